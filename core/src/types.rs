@@ -12,6 +12,9 @@ pub type Vector3f = na::Vector3<FScalar>;
 pub type Vector3i = na::Vector3<IScalar>;
 pub type Vector3u = na::Vector3<UScalar>;
 
+// 4D Vectors
+pub type Vector4f = na::Vector4<FScalar>;
+
 // Quaternion
 pub type UnitQuaternion = na::UnitQuaternion<FScalar>;
 
@@ -45,3 +48,7 @@ pub type Projection3 = na::Projective3<FScalar>;
 // Any transformation
 pub type Transform2 = na::Transform2<FScalar>;
 pub type Transform3 = na::Transform3<FScalar>;
+
+// Raw homogeneous matrices
+pub type Matrix3f = na::Matrix3<FScalar>;
+pub type Matrix4f = na::Matrix4<FScalar>;
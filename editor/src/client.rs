@@ -1,5 +1,5 @@
 use winit::{
-    event::{DeviceEvent, DeviceId, Event, StartCause, WindowEvent},
+    event::{DeviceEvent, DeviceId, ElementState, Event, MouseButton, StartCause, WindowEvent},
     event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
     window::WindowId,
 };
@@ -7,8 +7,17 @@ use winit::{
 use std::time::Instant;
 
 use polyengine::Engine;
-use polyengine_core::log;
-use polyengine_graphics::RenderingSystem;
+use polyengine_core::*;
+use polyengine_graphics::{
+    Camera,
+    DebugDrawOptions,
+    Light,
+    LightShadow,
+    Material,
+    ObjectId,
+    PickId,
+    RenderingSystem,
+};
 
 use crate::primitives;
 
@@ -17,18 +26,66 @@ pub struct ClientApp {
     engine: Engine,
 
     last_tick_instant: Instant,
+    // Last cursor position in physical pixels
+    cursor_position: [u32; 2],
+    // Click waiting for its pick result
+    pending_pick: Option<PickId>,
+    selected_object: Option<ObjectId>,
 }
 
 impl ClientApp {
     pub fn new(event_loop: &EventLoop<()>) -> Self {
         let engine = Engine::new();
         let mut rendering_system = RenderingSystem::new(&event_loop);
-        rendering_system.open_window(event_loop, "Rustcraft client");
-        rendering_system.create_geometry(&primitives::generate_box(1.0));
+        let window_id = rendering_system.open_window(event_loop, "Rustcraft client");
+        let box_geometry = rendering_system.create_geometry(&primitives::generate_box(1.0));
+        let box_object = rendering_system
+            .create_object(
+                box_geometry,
+                Similarity3::from_parts(
+                    Translation3f::identity(),
+                    UnitQuaternion::from_euler_angles(0.4, 0.6, 0.0),
+                    1.0,
+                ),
+            )
+            .unwrap();
+        let box_material = rendering_system.create_material(Material {
+            base_color: Vector4f::new(0.8, 0.1, 0.1, 1.0),
+            ..Material::default()
+        });
+        rendering_system
+            .set_object_material(box_object, box_material)
+            .unwrap();
+        // Large box below the scene to receive shadows
+        rendering_system
+            .create_object(
+                box_geometry,
+                Similarity3::from_parts(
+                    Translation3f::new(0.0, -5.75, 0.0),
+                    UnitQuaternion::identity(),
+                    10.0,
+                ),
+            )
+            .unwrap();
+        rendering_system.create_light(
+            Light::directional(
+                Vector3f::new(-0.5, -1.0, -0.7),
+                Vector3f::new(1.0, 1.0, 1.0),
+                3.0,
+            )
+            .with_shadow(LightShadow::default()),
+        );
+
+        let mut camera = Camera::default();
+        camera.transform = Isometry3::translation(0.0, 0.0, 2.0);
+        rendering_system.set_camera(window_id, camera).unwrap();
         return ClientApp {
             engine,
             rendering_system,
             last_tick_instant: Instant::now(),
+            cursor_position: [0, 0],
+            pending_pick: None,
+            selected_object: None,
         };
     }
 
@@ -57,6 +114,20 @@ impl ClientApp {
     fn update(&mut self, dt: std::time::Duration) {
         log::trace!("Update: dt={:?}", dt);
         self.engine.update(dt);
+
+        if let Some(pick_id) = self.pending_pick {
+            if let Some(object_ids) = self.rendering_system.pick_result(pick_id) {
+                self.pending_pick = None;
+                self.selected_object = object_ids.first().cloned();
+                log::info!("Selected object: {:?}", self.selected_object);
+            }
+        }
+
+        self.rendering_system.debug_draw().axes(
+            &Isometry3::identity(),
+            0.5,
+            &DebugDrawOptions::default(),
+        );
     }
 
     // Event handling
@@ -137,6 +208,19 @@ impl ClientApp {
             WindowEvent::Resized(size) => {
                 self.rendering_system.window_resized(window_id, size);
             }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = [position.x.max(0.0) as u32, position.y.max(0.0) as u32];
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.pending_pick = self
+                    .rendering_system
+                    .pick(window_id, self.cursor_position)
+                    .ok();
+            }
             // WindowEvent::Moved(position) => {},
             // WindowEvent::Destroyed => {},
             // WindowEvent::DroppedFile(path_buf) => {},
@@ -146,11 +230,9 @@ impl ClientApp {
             // WindowEvent::Focused(gained) => {},
             // WindowEvent::KeyboardInput{device_id, input, is_synthetic} => {},
             // WindowEvent::ModifiersChanged(ModifiersState) => {},
-            // WindowEvent::CursorMoved{device_id, position, modifiers} => {},
             // WindowEvent::CursorEntered{device_id} => {},
             // WindowEvent::CursorLeft{device_id} => {},
             // WindowEvent::MouseWheel { device_id, delta, phase, modifiers } => {},
-            // WindowEvent::MouseInput { device_id, state, button, modifiers } => {},
             // WindowEvent::TouchpadPressure { device_id, pressure, stage } => {},
             // WindowEvent::AxisMotion { device_id, axis, value } => {},
             // WindowEvent::Touch(touch) => {},
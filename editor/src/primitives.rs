@@ -1,12 +1,56 @@
 use polyengine_core::*;
 
+// Unit cube triangle list with counter-clockwise, outward facing triangles.
 pub fn generate_box(scale: FScalar) -> Vec<na::Vector3<FScalar>> {
-    return vec![
-        na::Vector3::<FScalar>::new(-0.5, -0.5, 0.0) * scale,
-        na::Vector3::<FScalar>::new(0.5, 0.5, 0.0) * scale,
-        na::Vector3::<FScalar>::new(0.5, -0.5, 0.0) * scale,
-        na::Vector3::<FScalar>::new(-0.5, -0.5, 0.0) * scale,
-        na::Vector3::<FScalar>::new(-0.5, 0.5, 0.0) * scale,
-        na::Vector3::<FScalar>::new(0.5, 0.5, 0.0) * scale,
+    let corner = |x: FScalar, y: FScalar, z: FScalar| na::Vector3::<FScalar>::new(x, y, z) * scale;
+    let faces = [
+        // +X, -X
+        [
+            (0.5, -0.5, 0.5),
+            (0.5, -0.5, -0.5),
+            (0.5, 0.5, -0.5),
+            (0.5, 0.5, 0.5),
+        ],
+        [
+            (-0.5, -0.5, -0.5),
+            (-0.5, -0.5, 0.5),
+            (-0.5, 0.5, 0.5),
+            (-0.5, 0.5, -0.5),
+        ],
+        // +Y, -Y
+        [
+            (-0.5, 0.5, 0.5),
+            (0.5, 0.5, 0.5),
+            (0.5, 0.5, -0.5),
+            (-0.5, 0.5, -0.5),
+        ],
+        [
+            (-0.5, -0.5, -0.5),
+            (0.5, -0.5, -0.5),
+            (0.5, -0.5, 0.5),
+            (-0.5, -0.5, 0.5),
+        ],
+        // +Z, -Z
+        [
+            (-0.5, -0.5, 0.5),
+            (0.5, -0.5, 0.5),
+            (0.5, 0.5, 0.5),
+            (-0.5, 0.5, 0.5),
+        ],
+        [
+            (0.5, -0.5, -0.5),
+            (-0.5, -0.5, -0.5),
+            (-0.5, 0.5, -0.5),
+            (0.5, 0.5, -0.5),
+        ],
     ];
+
+    let mut vertices = Vec::with_capacity(36);
+    for face in faces.iter() {
+        for &i in &[0, 1, 2, 0, 2, 3] {
+            let (x, y, z) = face[i];
+            vertices.push(corner(x, y, z));
+        }
+    }
+    return vertices;
 }
@@ -0,0 +1,333 @@
+use crate::{
+    antialiasing::AntiAliasing,
+    common::BufferlessPipeline,
+    config,
+    debug_utils::set_image_name,
+};
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, PipelineLayoutAbstract},
+    device::Device,
+    format::ClearValue,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{AttachmentImage, ImageUsage},
+    pipeline::{
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        viewport::Viewport,
+        GraphicsPipeline,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+mod fullscreen_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/fullscreen.vert"
+    }
+}
+
+mod taa_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/taa.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod fxaa_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/fxaa.frag",
+        include: ["src/shaders"]
+    }
+}
+
+// Temporal history, two images swapped every frame so the previous result can
+// be read while the new one is written.
+pub struct TaaHistory {
+    pub images: [Arc<AttachmentImage>; 2],
+    framebuffers: [Arc<dyn FramebufferAbstract + Send + Sync>; 2],
+    // Whether the history holds a resolved frame, it is undefined before
+    valid: bool,
+    written: bool,
+}
+
+// Tonemapped image FXAA reads from.
+pub struct FxaaTarget {
+    pub image: Arc<AttachmentImage>,
+    pub framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+// Window size dependent anti-aliasing images.
+pub struct AaTargets {
+    depth_buffer: Arc<AttachmentImage>,
+    mode: AntiAliasing,
+    pub history: Option<TaaHistory>,
+    pub fxaa: Option<FxaaTarget>,
+}
+
+impl AaTargets {
+    // Whether the targets can be used for the window depth buffer and mode.
+    pub fn matches(&self, depth_buffer: &Arc<AttachmentImage>, mode: &AntiAliasing) -> bool {
+        return Arc::ptr_eq(&self.depth_buffer, depth_buffer) && self.mode.same_kind(mode);
+    }
+
+    // Has to be called once per frame before recording it.
+    pub fn begin_frame(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.valid = history.written;
+            history.written = true;
+        }
+    }
+}
+
+pub struct AaRenderer {
+    device: Arc<Device>,
+    taa_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    taa_pipeline: Arc<BufferlessPipeline>,
+    fxaa_pipeline: Arc<BufferlessPipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl AaRenderer {
+    // `window_render_pass` is the pass FXAA writes the final image in.
+    pub fn new(
+        device: Arc<Device>,
+        window_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let taa_render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let fullscreen_vs = fullscreen_vs::Shader::load(device.clone()).unwrap();
+        let taa_fs = taa_fs::Shader::load(device.clone()).unwrap();
+        let fxaa_fs = fxaa_fs::Shader::load(device.clone()).unwrap();
+
+        let taa_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(fullscreen_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(taa_fs.main_entry_point(), ())
+                .render_pass(Subpass::from(taa_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let fxaa_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(fullscreen_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fxaa_fs.main_entry_point(), ())
+                .render_pass(Subpass::from(window_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        return AaRenderer {
+            device,
+            taa_render_pass,
+            taa_pipeline,
+            fxaa_pipeline,
+            sampler,
+        };
+    }
+
+    // `ldr_render_pass` is the offscreen composite pass of the post-process
+    // stack, FXAA runs on its output.
+    pub fn create_targets(
+        &self,
+        depth_buffer: Arc<AttachmentImage>,
+        mode: &AntiAliasing,
+        ldr_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> AaTargets {
+        let dimensions = depth_buffer.dimensions();
+        // The resolved history is copied out by EXR captures
+        let usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+        let attachment = |name, format, render_pass: Arc<dyn RenderPassAbstract + Send + Sync>| {
+            let image = AttachmentImage::with_usage(self.device.clone(), dimensions, format, usage)
+                .unwrap();
+            set_image_name(&image, name);
+            let framebuffer = Arc::new(
+                Framebuffer::start(render_pass)
+                    .add(image.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>;
+            return (image, framebuffer);
+        };
+
+        let mut history = None;
+        let mut fxaa = None;
+        match mode {
+            AntiAliasing::None => {}
+            AntiAliasing::Fxaa => {
+                let (image, framebuffer) =
+                    attachment("fxaa input", config::DEFAULT_WINDOW_FORMAT, ldr_render_pass);
+                fxaa = Some(FxaaTarget { image, framebuffer });
+            }
+            AntiAliasing::Taa(_) => {
+                let (image_a, framebuffer_a) = attachment(
+                    "taa history a",
+                    config::HDR_FORMAT,
+                    self.taa_render_pass.clone(),
+                );
+                let (image_b, framebuffer_b) = attachment(
+                    "taa history b",
+                    config::HDR_FORMAT,
+                    self.taa_render_pass.clone(),
+                );
+                history = Some(TaaHistory {
+                    images: [image_a, image_b],
+                    framebuffers: [framebuffer_a, framebuffer_b],
+                    valid: false,
+                    written: false,
+                });
+            }
+        }
+
+        return AaTargets {
+            depth_buffer,
+            mode: *mode,
+            history,
+            fxaa,
+        };
+    }
+
+    // Resolves the jittered `current` image against the history and returns the
+    // resolved image, which becomes the history of the next frame.
+    pub fn record_taa(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        targets: &AaTargets,
+        current: Arc<AttachmentImage>,
+        velocity: Arc<AttachmentImage>,
+        feedback: f32,
+        frame_index: u64,
+    ) -> (AutoCommandBufferBuilder, Arc<AttachmentImage>) {
+        let history = targets
+            .history
+            .as_ref()
+            .expect("temporal anti-aliasing targets weren't created");
+        let write = (frame_index % 2) as usize;
+        let read = 1 - write;
+        let dimensions = current.dimensions();
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.taa_pipeline.descriptor_set_layout(0).unwrap().clone(),
+            )
+            .add_sampled_image(current, self.sampler.clone())
+            .unwrap()
+            .add_sampled_image(history.images[read].clone(), self.sampler.clone())
+            .unwrap()
+            .add_sampled_image(velocity, self.sampler.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        let push_constants = taa_fs::ty::PushConstants {
+            params: [feedback, if history.valid { 1.0 } else { 0.0 }, 0.0, 0.0],
+        };
+
+        let builder = builder
+            .begin_render_pass(
+                history.framebuffers[write].clone(),
+                false,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.taa_pipeline.clone(),
+                &DynamicState {
+                    viewports: Some(vec![Viewport {
+                        origin: [0.0, 0.0],
+                        dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                        depth_range: 0.0..1.0,
+                    }]),
+                    ..DynamicState::none()
+                },
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                set,
+                push_constants,
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+        return (builder, history.images[write].clone());
+    }
+
+    // Draws the anti-aliased tonemapped image, has to be recorded inside of the
+    // window render pass.
+    pub fn record_fxaa(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        targets: &AaTargets,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder {
+        let fxaa = targets.fxaa.as_ref().expect("FXAA targets weren't created");
+        let set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.fxaa_pipeline.descriptor_set_layout(0).unwrap().clone(),
+            )
+            .add_sampled_image(fxaa.image.clone(), self.sampler.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        return builder
+            .draw(
+                self.fxaa_pipeline.clone(),
+                dynamic_state,
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                set,
+                (),
+            )
+            .unwrap();
+    }
+}
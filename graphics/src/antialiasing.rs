@@ -0,0 +1,134 @@
+use polyengine_core::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TaaSettings {
+    // Weight of the reprojected history in the resolved image
+    pub feedback: FScalar,
+    // Length of the camera jitter sequence
+    pub jitter_samples: usize,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        return TaaSettings {
+            feedback: 0.9,
+            jitter_samples: 8,
+        };
+    }
+}
+
+// Screen space anti-aliasing applied by the window.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AntiAliasing {
+    None,
+    // Edge blur on the tonemapped image, cheap but softens detail
+    Fxaa,
+    // Jittered rendering accumulated over frames with history reprojection
+    Taa(TaaSettings),
+}
+
+impl AntiAliasing {
+    // Whether both modes need the same window targets.
+    pub fn same_kind(&self, other: &AntiAliasing) -> bool {
+        return std::mem::discriminant(self) == std::mem::discriminant(other);
+    }
+}
+
+// Element of the Halton low discrepancy sequence, in <0, 1).
+pub fn halton(mut index: u64, base: u64) -> FScalar {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as FScalar;
+        result += fraction * (index % base) as FScalar;
+        index /= base;
+    }
+    return result;
+}
+
+// Sub-pixel camera offset of the frame, in pixels within <-0.5, 0.5).
+pub fn jitter_offset(frame_index: u64, sample_count: usize) -> Vector2f {
+    // Halton sequence starts at 0, skip it so the offsets stay centered.
+    let index = frame_index % sample_count.max(1) as u64 + 1;
+    return Vector2f::new(halton(index, 2) - 0.5, halton(index, 3) - 0.5);
+}
+
+// Clip space translation moving the image by `offset` pixels.
+pub fn jitter_matrix(offset: &Vector2f, dimensions: [u32; 2]) -> Matrix4f {
+    let mut matrix = Matrix4f::identity();
+    matrix[(0, 3)] = 2.0 * offset.x / dimensions[0] as FScalar;
+    matrix[(1, 3)] = 2.0 * offset.y / dimensions[1] as FScalar;
+    return matrix;
+}
+
+// Camera matrices of consecutive frames of a window, used to compute motion
+// vectors.
+#[derive(Debug, Copy, Clone)]
+pub struct TemporalState {
+    pub frame_index: u64,
+    pub view_projection: Matrix4f,
+    pub previous_view_projection: Matrix4f,
+}
+
+impl Default for TemporalState {
+    fn default() -> Self {
+        return TemporalState {
+            frame_index: 0,
+            view_projection: Matrix4f::identity(),
+            previous_view_projection: Matrix4f::identity(),
+        };
+    }
+}
+
+impl TemporalState {
+    // Starts a new frame rendered with the unjittered `view_projection`.
+    pub fn advance(&mut self, view_projection: Matrix4f) {
+        if self.frame_index == 0 {
+            self.previous_view_projection = view_projection;
+        } else {
+            self.previous_view_projection = self.view_projection;
+        }
+        self.view_projection = view_projection;
+        self.frame_index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halton_test() {
+        assert_eq!(halton(1, 2), 0.5);
+        assert_eq!(halton(2, 2), 0.25);
+        assert_eq!(halton(3, 2), 0.75);
+        assert!((halton(2, 3) - 2.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn jitter_test() {
+        for frame in 0..16 {
+            let offset = jitter_offset(frame, 8);
+            assert!(offset.x.abs() < 0.5 && offset.y.abs() < 0.5);
+        }
+        assert_eq!(jitter_offset(3, 8), jitter_offset(11, 8));
+
+        let matrix = jitter_matrix(&Vector2f::new(0.5, -0.5), [100, 50]);
+        let clip = matrix * Vector4f::new(0.0, 0.0, 0.5, 2.0);
+        assert_eq!(clip.x / clip.w, 0.01);
+        assert_eq!(clip.y / clip.w, -0.02);
+    }
+
+    #[test]
+    fn temporal_state_test() {
+        let mut state = TemporalState::default();
+        let a = Matrix4f::new_scaling(2.0);
+        let b = Matrix4f::new_scaling(3.0);
+        state.advance(a);
+        assert_eq!(state.previous_view_projection, a);
+        state.advance(b);
+        assert_eq!(state.previous_view_projection, a);
+        assert_eq!(state.view_projection, b);
+        assert_eq!(state.frame_index, 2);
+    }
+}
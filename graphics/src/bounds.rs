@@ -0,0 +1,136 @@
+use polyengine_core::*;
+
+// Axis aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3f,
+    pub max: Vector3f,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3f, max: Vector3f) -> Self { return Aabb { min, max }; }
+
+    pub fn empty() -> Self {
+        return Aabb {
+            min: Vector3f::repeat(FScalar::MAX),
+            max: Vector3f::repeat(FScalar::MIN),
+        };
+    }
+
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vector3f>>(points: I) -> Self {
+        let mut aabb = Aabb::empty();
+        for p in points {
+            aabb.extend(p);
+        }
+        return aabb;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z;
+    }
+
+    pub fn extend(&mut self, p: &Vector3f) {
+        self.min = self.min.zip_map(p, FScalar::min);
+        self.max = self.max.zip_map(p, FScalar::max);
+    }
+
+    pub fn merged(&self, other: &Aabb) -> Aabb {
+        return Aabb {
+            min: self.min.zip_map(&other.min, FScalar::min),
+            max: self.max.zip_map(&other.max, FScalar::max),
+        };
+    }
+
+    pub fn center(&self) -> Vector3f { return (self.min + self.max) * 0.5; }
+
+    // Half of the box size along each axis
+    pub fn half_extents(&self) -> Vector3f { return (self.max - self.min) * 0.5; }
+
+    pub fn corners(&self) -> [Vector3f; 8] {
+        let (a, b) = (self.min, self.max);
+        return [
+            Vector3f::new(a.x, a.y, a.z),
+            Vector3f::new(b.x, a.y, a.z),
+            Vector3f::new(a.x, b.y, a.z),
+            Vector3f::new(b.x, b.y, a.z),
+            Vector3f::new(a.x, a.y, b.z),
+            Vector3f::new(b.x, a.y, b.z),
+            Vector3f::new(a.x, b.y, b.z),
+            Vector3f::new(b.x, b.y, b.z),
+        ];
+    }
+
+    // Smallest axis aligned box containing this box after the transformation.
+    pub fn transformed(&self, transform: &Similarity3) -> Aabb {
+        let center = transform.transform_point(&self.center().into()).coords;
+        let rotation = transform.isometry.rotation.to_rotation_matrix();
+        let abs_rotation = rotation.matrix().abs();
+        let half_extents = abs_rotation * self.half_extents() * transform.scaling().abs();
+        return Aabb {
+            min: center - half_extents,
+            max: center + half_extents,
+        };
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        return BoundingSphere {
+            center: self.center(),
+            radius: self.half_extents().norm(),
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vector3f,
+    pub radius: FScalar,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Vector3f, radius: FScalar) -> Self {
+        return BoundingSphere { center, radius };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Aabb;
+    use polyengine_core::{approx::assert_relative_eq, *};
+
+    #[test]
+    fn from_points_test() {
+        let points = vec![
+            Vector3f::new(-1.0, 2.0, 0.5),
+            Vector3f::new(3.0, -2.0, 0.0),
+            Vector3f::new(0.0, 0.0, -4.0),
+        ];
+        let aabb = Aabb::from_points(&points);
+        assert_eq!(aabb.min, Vector3f::new(-1.0, -2.0, -4.0));
+        assert_eq!(aabb.max, Vector3f::new(3.0, 2.0, 0.5));
+        assert!(!aabb.is_empty());
+        assert!(Aabb::empty().is_empty());
+    }
+
+    #[test]
+    fn transformed_test() {
+        let aabb = Aabb::new(
+            Vector3f::new(-1.0, -1.0, -1.0),
+            Vector3f::new(1.0, 1.0, 1.0),
+        );
+        let transform = Similarity3::new(
+            Vector3f::new(10.0, 0.0, 0.0),
+            Vector3f::z() * std::f32::consts::FRAC_PI_4,
+            2.0,
+        );
+        let moved = aabb.transformed(&transform);
+        let expected = 2.0 * std::f32::consts::SQRT_2;
+        assert_relative_eq!(
+            moved.center(),
+            Vector3f::new(10.0, 0.0, 0.0),
+            epsilon = 1e-5
+        );
+        assert_relative_eq!(moved.half_extents().x, expected, epsilon = 1e-5);
+        assert_relative_eq!(moved.half_extents().y, expected, epsilon = 1e-5);
+        assert_relative_eq!(moved.half_extents().z, 2.0, epsilon = 1e-5);
+    }
+}
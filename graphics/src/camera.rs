@@ -0,0 +1,91 @@
+use crate::frustum::Frustum;
+use polyengine_core::*;
+
+// Right handed perspective projection into Vulkan clip space: Y points down and
+// depth is in <0, 1>.
+pub fn perspective_matrix(
+    fov_y: FScalar,
+    aspect: FScalar,
+    z_near: FScalar,
+    z_far: FScalar,
+) -> Matrix4f {
+    let f = 1.0 / (fov_y * 0.5).tan();
+    let range = z_near - z_far;
+    #[rustfmt::skip]
+    let projection = Matrix4f::new(
+        f / aspect, 0.0, 0.0, 0.0,
+        0.0, -f, 0.0, 0.0,
+        0.0, 0.0, z_far / range, z_near * z_far / range,
+        0.0, 0.0, -1.0, 0.0,
+    );
+    return projection;
+}
+
+// Right handed orthographic counterpart of `perspective_matrix`.
+pub fn orthographic_matrix(
+    left: FScalar,
+    right: FScalar,
+    bottom: FScalar,
+    top: FScalar,
+    z_near: FScalar,
+    z_far: FScalar,
+) -> Matrix4f {
+    let (w, h, d) = (right - left, top - bottom, z_far - z_near);
+    #[rustfmt::skip]
+    let projection = Matrix4f::new(
+        2.0 / w, 0.0, 0.0, -(right + left) / w,
+        0.0, -2.0 / h, 0.0, (top + bottom) / h,
+        0.0, 0.0, -1.0 / d, -z_near / d,
+        0.0, 0.0, 0.0, 1.0,
+    );
+    return projection;
+}
+
+// Perspective camera looking down its local -Z axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+    // Camera to world transformation
+    pub transform: Isometry3,
+    // Vertical field of view in radians
+    pub fov_y: FScalar,
+    pub z_near: FScalar,
+    pub z_far: FScalar,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        return Camera::new(
+            Isometry3::identity(),
+            std::f32::consts::FRAC_PI_3,
+            0.1,
+            1000.0,
+        );
+    }
+}
+
+impl Camera {
+    pub fn new(transform: Isometry3, fov_y: FScalar, z_near: FScalar, z_far: FScalar) -> Self {
+        return Camera {
+            transform,
+            fov_y,
+            z_near,
+            z_far,
+        };
+    }
+
+    pub fn position(&self) -> Vector3f { return self.transform.translation.vector; }
+
+    pub fn view_matrix(&self) -> Matrix4f { return self.transform.inverse().to_homogeneous(); }
+
+    pub fn projection_matrix(&self, aspect: FScalar) -> Matrix4f {
+        return perspective_matrix(self.fov_y, aspect, self.z_near, self.z_far);
+    }
+
+    pub fn view_projection_matrix(&self, aspect: FScalar) -> Matrix4f {
+        return self.projection_matrix(aspect) * self.view_matrix();
+    }
+
+    pub fn frustum(&self, aspect: FScalar) -> Frustum {
+        return Frustum::from_matrix(&self.view_projection_matrix(aspect));
+    }
+}
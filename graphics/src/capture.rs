@@ -0,0 +1,554 @@
+use crate::{environment::f32_to_f16, error::RenderingError};
+use polyengine_core::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, sync_channel, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+pub type CaptureId = u32;
+
+// Captures waiting for the disk before rendering waits for the writer
+pub const CAPTURE_QUEUE_SIZE: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CaptureFormat {
+    // 8-bit sRGB image of what the window shows
+    Png,
+    // Half float linear image of the scene before exposure, bloom and
+    // tonemapping. 2D windows and windows without a rendered scene store the
+    // window image instead.
+    Exr,
+}
+
+impl CaptureFormat {
+    // Format matching the extension of the path, ignoring case.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => return Some(CaptureFormat::Png),
+            "exr" => return Some(CaptureFormat::Exr),
+            _ => return None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CaptureFormat::Png => return "png",
+            CaptureFormat::Exr => return "exr",
+        }
+    }
+}
+
+// Every frame of a window written to numbered files. While recording, time
+// advances by exactly `timestep` per rendered frame, no matter how long the
+// frame took, so animations play back at the intended speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSequence {
+    // Created when missing
+    pub directory: PathBuf,
+    pub format: CaptureFormat,
+    pub timestep: Duration,
+}
+
+impl FrameSequence {
+    // `frame_000000.png`, `frame_000001.png`... in the directory.
+    pub fn frame_path(&self, frame_index: u32) -> PathBuf {
+        return self.directory.join(format!(
+            "frame_{:06}.{}",
+            frame_index,
+            self.format.extension()
+        ));
+    }
+}
+
+// Clock of a frame sequence being recorded.
+pub struct SequenceRecording {
+    pub sequence: FrameSequence,
+    start: Instant,
+    frame_index: u32,
+}
+
+impl SequenceRecording {
+    pub fn new(sequence: FrameSequence, start: Instant) -> Self {
+        return SequenceRecording {
+            sequence,
+            start,
+            frame_index: 0,
+        };
+    }
+
+    // Time the current frame is rendered at.
+    pub fn frame_time(&self) -> Instant {
+        return self.start + self.sequence.timestep * self.frame_index;
+    }
+
+    // Path of the current frame, moves the clock to the next one.
+    pub fn advance(&mut self) -> PathBuf {
+        let path = self.sequence.frame_path(self.frame_index);
+        self.frame_index += 1;
+        return path;
+    }
+}
+
+// Capture copied back from the GPU, rows go from the top down.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CapturedImage {
+    // sRGB encoded RGBA
+    Ldr {
+        width: u32,
+        height: u32,
+        texels: Vec<[u8; 4]>,
+    },
+    // Half float bits of linear RGBA
+    Hdr {
+        width: u32,
+        height: u32,
+        texels: Vec<[u16; 4]>,
+    },
+}
+
+impl CapturedImage {
+    // HDR images can only be stored as EXR.
+    pub fn encode(&self, format: CaptureFormat) -> Result<Vec<u8>, RenderingError> {
+        match (self, format) {
+            (
+                CapturedImage::Ldr {
+                    width,
+                    height,
+                    texels,
+                },
+                CaptureFormat::Png,
+            ) => {
+                return Ok(encode_png(*width, *height, texels));
+            }
+            (
+                CapturedImage::Hdr {
+                    width,
+                    height,
+                    texels,
+                },
+                CaptureFormat::Exr,
+            ) => {
+                return Ok(encode_exr(*width, *height, texels));
+            }
+            (
+                CapturedImage::Ldr {
+                    width,
+                    height,
+                    texels,
+                },
+                CaptureFormat::Exr,
+            ) => {
+                let linear: Vec<[u16; 4]> = texels
+                    .iter()
+                    .map(|t| {
+                        return [
+                            f32_to_f16(srgb_to_linear(t[0])),
+                            f32_to_f16(srgb_to_linear(t[1])),
+                            f32_to_f16(srgb_to_linear(t[2])),
+                            f32_to_f16(t[3] as f32 / 255.0),
+                        ];
+                    })
+                    .collect();
+                return Ok(encode_exr(*width, *height, &linear));
+            }
+            (CapturedImage::Hdr { .. }, CaptureFormat::Png) => {
+                return Err(RenderingError::UnsupportedCaptureFormat);
+            }
+        }
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        return c / 12.92;
+    }
+    return ((c + 0.055) / 1.055).powf(2.4);
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    return !crc;
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    return b << 16 | a;
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(&crc.to_be_bytes());
+}
+
+// RGB PNG without compression, the alpha of the window is dropped. Writing is
+// fast enough for frame sequences, the files are meant to be compressed by a
+// video encoder afterwards.
+pub fn encode_png(width: u32, height: u32, texels: &[[u8; 4]]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(((width * 3 + 1) * height) as usize);
+    for row in texels.chunks(width as usize) {
+        // No filter
+        raw.push(0);
+        for texel in row {
+            raw.extend(&texel[..3]);
+        }
+    }
+
+    // Zlib stream of stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(0xffff);
+    let block_count = blocks.len();
+    for (i, block) in blocks.enumerate() {
+        zlib.push((i + 1 == block_count) as u8);
+        zlib.extend(&(block.len() as u16).to_le_bytes());
+        zlib.extend(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend(block);
+    }
+    if block_count == 0 {
+        zlib.extend(&[1, 0, 0, 0xff, 0xff]);
+    }
+    zlib.extend(&adler32(&raw).to_be_bytes());
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut header = Vec::with_capacity(13);
+    header.extend(&width.to_be_bytes());
+    header.extend(&height.to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering and no interlacing
+    header.extend(&[8, 2, 0, 0, 0]);
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &zlib);
+    png_chunk(&mut png, b"IEND", &[]);
+    return png;
+}
+
+fn exr_attribute(exr: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    exr.extend(name.as_bytes());
+    exr.push(0);
+    exr.extend(kind.as_bytes());
+    exr.push(0);
+    exr.extend(&(value.len() as i32).to_le_bytes());
+    exr.extend(value);
+}
+
+// Uncompressed scanline OpenEXR with half float RGBA channels.
+pub fn encode_exr(width: u32, height: u32, texels: &[[u16; 4]]) -> Vec<u8> {
+    let mut exr = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    // Channels are stored in alphabetical order
+    let channels = [("A", 3), ("B", 2), ("G", 1), ("R", 0)];
+    let mut channel_list = Vec::new();
+    for (name, _) in channels.iter() {
+        channel_list.extend(name.as_bytes());
+        channel_list.push(0);
+        // Half pixel type, not linear, reserved bytes and no subsampling
+        channel_list.extend(&1i32.to_le_bytes());
+        channel_list.extend(&[0, 0, 0, 0]);
+        channel_list.extend(&1i32.to_le_bytes());
+        channel_list.extend(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    let mut window = Vec::with_capacity(16);
+    for &value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend(&(value as i32).to_le_bytes());
+    }
+    exr_attribute(&mut exr, "channels", "chlist", &channel_list);
+    exr_attribute(&mut exr, "compression", "compression", &[0]);
+    exr_attribute(&mut exr, "dataWindow", "box2i", &window);
+    exr_attribute(&mut exr, "displayWindow", "box2i", &window);
+    exr_attribute(&mut exr, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut exr, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    exr_attribute(&mut exr, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut exr, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    exr.push(0);
+
+    // Offset table of the scanlines, one line per block
+    let line_size = width as usize * channels.len() * 2;
+    let table_end = exr.len() + height as usize * 8;
+    for y in 0..height as usize {
+        let offset = table_end + y * (line_size + 8);
+        exr.extend(&(offset as u64).to_le_bytes());
+    }
+    for (y, row) in texels.chunks(width as usize).enumerate() {
+        exr.extend(&(y as i32).to_le_bytes());
+        exr.extend(&(line_size as i32).to_le_bytes());
+        for &(_, channel) in channels.iter() {
+            for texel in row {
+                exr.extend(&texel[channel].to_le_bytes());
+            }
+        }
+    }
+    return exr;
+}
+
+struct CaptureJob {
+    capture_id: Option<CaptureId>,
+    path: PathBuf,
+    format: CaptureFormat,
+    image: CapturedImage,
+}
+
+// Encodes and writes captures on a background thread, so rendering doesn't wait
+// for the disk unless `CAPTURE_QUEUE_SIZE` captures are still queued.
+pub struct CaptureWriter {
+    // Taken on drop, so the thread writes the queued captures and exits
+    jobs: Option<SyncSender<CaptureJob>>,
+    thread: Option<JoinHandle<()>>,
+    results: Receiver<(CaptureId, Result<(), RenderingError>)>,
+}
+
+impl Default for CaptureWriter {
+    fn default() -> Self {
+        let (jobs, job_receiver) = sync_channel::<CaptureJob>(CAPTURE_QUEUE_SIZE);
+        let (result_sender, results) = channel();
+        let thread = thread::spawn(move || {
+            for job in job_receiver {
+                let result = write_capture(&job);
+                if let Some(capture_id) = job.capture_id {
+                    // The system may be gone already
+                    let _ = result_sender.send((capture_id, result));
+                }
+            }
+        });
+        return CaptureWriter {
+            jobs: Some(jobs),
+            thread: Some(thread),
+            results,
+        };
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl CaptureWriter {
+    // Only captures with an ID report their result, failed frames of sequences
+    // are just logged. Blocks while the queue is full.
+    pub fn write(
+        &self,
+        capture_id: Option<CaptureId>,
+        path: PathBuf,
+        format: CaptureFormat,
+        image: CapturedImage,
+    ) {
+        let job = CaptureJob {
+            capture_id,
+            path,
+            format,
+            image,
+        };
+        let sent = self.jobs.as_ref().map(|jobs| jobs.send(job));
+        if let Some(Err(_)) = sent {
+            log::error!("The capture writer thread is gone");
+        }
+    }
+
+    // Captures written since the last call.
+    pub fn finished(&self) -> Vec<(CaptureId, Result<(), RenderingError>)> {
+        return self.results.try_iter().collect();
+    }
+}
+
+fn write_capture(job: &CaptureJob) -> Result<(), RenderingError> {
+    let bytes = job.image.encode(job.format)?;
+    let written = job
+        .path
+        .parent()
+        .map(fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| fs::write(&job.path, bytes));
+    if let Err(e) = written {
+        log::warn!("Failed to write the capture {:?}: {}", job.path, e);
+        return Err(RenderingError::CaptureFailed);
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_test() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn png_test() {
+        let texels = vec![[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 0]];
+        let png = encode_png(3, 1, &texels);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..20], &3u32.to_be_bytes());
+        assert_eq!(&png[20..24], &1u32.to_be_bytes());
+
+        // IDAT follows the 25 byte IHDR chunk
+        let idat = 8 + 25;
+        let length = u32::from_be_bytes([png[idat], png[idat + 1], png[idat + 2], png[idat + 3]]);
+        assert_eq!(&png[idat + 4..idat + 8], b"IDAT");
+        let zlib = &png[idat + 8..idat + 8 + length as usize];
+        // Header, a single final stored block, filter byte and RGB texels
+        assert_eq!(&zlib[..7], &[0x78, 0x01, 1, 10, 0, 0xf5, 0xff]);
+        assert_eq!(&zlib[7..17], &[0, 255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        assert_eq!(&zlib[17..], &adler32(&zlib[7..17]).to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn exr_test() {
+        let one = f32_to_f16(1.0);
+        let texels = vec![[one, 0, 0, one], [0, one, 0, one]];
+        let exr = encode_exr(2, 1, &texels);
+        assert_eq!(&exr[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        // A single scanline with four channels of two halfs each, at the end
+        let line_size = 2 * 4 * 2;
+        let line_start = exr.len() - line_size - 8;
+        let table = line_start - 8;
+        assert_eq!(exr[table - 1], 0);
+        assert_eq!(&exr[table..line_start], &(line_start as u64).to_le_bytes());
+        assert_eq!(&exr[line_start..line_start + 8], &[0, 0, 0, 0, 16, 0, 0, 0]);
+        let halfs: Vec<u16> = exr[line_start + 8..]
+            .chunks(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        // A, B, G and R of both texels
+        assert_eq!(halfs, vec![one, one, 0, 0, 0, one, one, 0]);
+    }
+
+    #[test]
+    fn ldr_exr_test() {
+        let image = CapturedImage::Ldr {
+            width: 1,
+            height: 1,
+            texels: vec![[255, 0, 188, 255]],
+        };
+        let exr = image.encode(CaptureFormat::Exr).unwrap();
+        let halfs: Vec<u16> = exr[exr.len() - 8..]
+            .chunks(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(halfs[0], f32_to_f16(1.0));
+        assert_eq!(halfs[2], 0);
+        // sRGB 188 is about half of the linear intensity
+        assert!((srgb_to_linear(188) - 0.5).abs() < 0.01);
+        assert_eq!(halfs[3], f32_to_f16(1.0));
+
+        let hdr = CapturedImage::Hdr {
+            width: 1,
+            height: 1,
+            texels: vec![[0; 4]],
+        };
+        assert_eq!(
+            hdr.encode(CaptureFormat::Png),
+            Err(RenderingError::UnsupportedCaptureFormat)
+        );
+    }
+
+    #[test]
+    fn writer_test() {
+        // Unique per run, concurrent test runs would remove each other's files
+        let directory =
+            std::env::temp_dir().join(format!("polyengine_writer_test_{}", std::process::id()));
+        let image = CapturedImage::Hdr {
+            width: 1,
+            height: 1,
+            texels: vec![[0; 4]],
+        };
+        let writer = CaptureWriter::default();
+        // More captures than fit into the queue
+        for capture_id in 0..CAPTURE_QUEUE_SIZE as u32 + 2 {
+            writer.write(
+                Some(capture_id),
+                directory.join(format!("{}.exr", capture_id)),
+                CaptureFormat::Exr,
+                image.clone(),
+            );
+        }
+        writer.write(
+            Some(100),
+            directory.join("hdr.png"),
+            CaptureFormat::Png,
+            image,
+        );
+        // Results arrive in order, the last one once everything was written
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut results = Vec::new();
+        while results.len() < CAPTURE_QUEUE_SIZE + 3 {
+            assert!(
+                Instant::now() < deadline,
+                "captures weren't written in time"
+            );
+            results.extend(writer.finished());
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(results[..CAPTURE_QUEUE_SIZE + 2]
+            .iter()
+            .all(|(_, result)| result.is_ok()));
+        assert_eq!(
+            results[CAPTURE_QUEUE_SIZE + 2],
+            (100, Err(RenderingError::UnsupportedCaptureFormat))
+        );
+        assert!(directory.join("0.exr").exists());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn sequence_test() {
+        let sequence = FrameSequence {
+            directory: PathBuf::from("trailer"),
+            format: CaptureFormat::Exr,
+            timestep: Duration::from_millis(40),
+        };
+        assert_eq!(
+            sequence.frame_path(12),
+            PathBuf::from("trailer").join("frame_000012.exr")
+        );
+        assert_eq!(
+            CaptureFormat::from_path(Path::new("shots/a.PNG")),
+            Some(CaptureFormat::Png)
+        );
+        assert_eq!(CaptureFormat::from_path(Path::new("shot.jpg")), None);
+        assert_eq!(CaptureFormat::from_path(Path::new("shot")), None);
+
+        let start = Instant::now();
+        let mut recording = SequenceRecording::new(sequence, start);
+        assert_eq!(recording.frame_time(), start);
+        assert_eq!(
+            recording.advance(),
+            PathBuf::from("trailer").join("frame_000000.exr")
+        );
+        recording.advance();
+        assert_eq!(recording.frame_time(), start + Duration::from_millis(80));
+    }
+}
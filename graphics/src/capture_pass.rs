@@ -0,0 +1,125 @@
+use crate::capture::{CaptureFormat, CaptureId, CapturedImage};
+use std::{path::PathBuf, sync::Arc};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::AutoCommandBufferBuilder,
+    device::Device,
+    image::{swapchain::SwapchainImage, AttachmentImage, ImageAccess},
+};
+use winit::window::Window;
+
+// Capture of a window, recorded with its next frame.
+#[derive(Debug, Clone)]
+pub struct CaptureRequest {
+    // None for frames of a sequence
+    pub capture_id: Option<CaptureId>,
+    pub path: PathBuf,
+    pub format: CaptureFormat,
+}
+
+enum ReadbackBuffer {
+    // B8G8R8A8 window image
+    Window(Arc<CpuAccessibleBuffer<[[u8; 4]]>>),
+    // R16G16B16A16 scene image
+    Scene(Arc<CpuAccessibleBuffer<[[u16; 4]]>>),
+}
+
+// Capture copied to host memory, read once the GPU is done with it.
+pub struct CaptureReadback {
+    pub request: CaptureRequest,
+    dimensions: [u32; 2],
+    buffer: ReadbackBuffer,
+}
+
+impl CaptureReadback {
+    // Copied image, None while the GPU still uses the buffer.
+    pub fn try_read(&self) -> Option<CapturedImage> {
+        let [width, height] = self.dimensions;
+        match &self.buffer {
+            ReadbackBuffer::Window(buffer) => match buffer.read() {
+                Ok(texels) => {
+                    return Some(CapturedImage::Ldr {
+                        width,
+                        height,
+                        texels: texels.iter().map(|t| [t[2], t[1], t[0], t[3]]).collect(),
+                    });
+                }
+                Err(_) => return None,
+            },
+            ReadbackBuffer::Scene(buffer) => match buffer.read() {
+                Ok(texels) => {
+                    return Some(CapturedImage::Hdr {
+                        width,
+                        height,
+                        texels: texels.to_vec(),
+                    });
+                }
+                Err(_) => return None,
+            },
+        }
+    }
+}
+
+pub struct CaptureRenderer {
+    device: Arc<Device>,
+}
+
+impl CaptureRenderer {
+    pub fn new(device: Arc<Device>) -> Self { return CaptureRenderer { device }; }
+
+    // Copies the window image into host visible buffers, has to be recorded after
+    // the last render pass of the frame. EXR captures copy `scene` instead when
+    // given, the HDR image before exposure and tonemapping.
+    pub fn record(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        requests: &[CaptureRequest],
+        window_image: &Arc<SwapchainImage<Window>>,
+        scene: Option<&Arc<AttachmentImage>>,
+    ) -> (AutoCommandBufferBuilder, Vec<CaptureReadback>) {
+        let mut readbacks = Vec::with_capacity(requests.len());
+        for request in requests {
+            let readback = match (request.format, scene) {
+                (CaptureFormat::Exr, Some(scene)) => {
+                    let dimensions = scene.dimensions().width_height();
+                    let buffer = self.readback_buffer(dimensions, [0u16; 4]);
+                    builder = builder
+                        .copy_image_to_buffer(scene.clone(), buffer.clone())
+                        .unwrap();
+                    CaptureReadback {
+                        request: request.clone(),
+                        dimensions,
+                        buffer: ReadbackBuffer::Scene(buffer),
+                    }
+                }
+                _ => {
+                    let dimensions = window_image.dimensions().width_height();
+                    let buffer = self.readback_buffer(dimensions, [0u8; 4]);
+                    builder = builder
+                        .copy_image_to_buffer(window_image.clone(), buffer.clone())
+                        .unwrap();
+                    CaptureReadback {
+                        request: request.clone(),
+                        dimensions,
+                        buffer: ReadbackBuffer::Window(buffer),
+                    }
+                }
+            };
+            readbacks.push(readback);
+        }
+        return (builder, readbacks);
+    }
+
+    fn readback_buffer<T>(&self, dimensions: [u32; 2], texel: T) -> Arc<CpuAccessibleBuffer<[T]>>
+    where
+        T: Copy + Send + Sync + 'static,
+    {
+        return CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::transfer_destination(),
+            false,
+            std::iter::repeat(texel).take((dimensions[0] * dimensions[1]) as usize),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,553 @@
+use crate::font::{read_u16, read_u32, read_u8, Outline, OutlineBuilder};
+use polyengine_core::*;
+
+// Limits of the Type 2 charstring interpreter.
+const MAX_STACK: usize = 48;
+const MAX_SUBROUTINE_DEPTH: u32 = 10;
+
+// Array of variable sized objects.
+#[derive(Debug, Copy, Clone, Default)]
+struct Index<'a> {
+    data: &'a [u8],
+    offsets: &'a [u8],
+    offset_size: usize,
+    count: usize,
+}
+
+impl<'a> Index<'a> {
+    // Returns the index and the offset of the data following it.
+    fn parse(data: &'a [u8], offset: usize) -> Option<(Self, usize)> {
+        let count = read_u16(data, offset)? as usize;
+        if count == 0 {
+            return Some((Index::default(), offset + 2));
+        }
+        let offset_size = read_u8(data, offset + 2)? as usize;
+        if offset_size == 0 || offset_size > 4 {
+            return None;
+        }
+        let offsets = data.get(offset + 3..offset + 3 + (count + 1) * offset_size)?;
+        // Object offsets start at 1
+        let data_start = offset + 3 + (count + 1) * offset_size - 1;
+        let mut index = Index {
+            data: data.get(data_start..)?,
+            offsets,
+            offset_size,
+            count,
+        };
+        let end = index.offset(count)?;
+        index.data = index.data.get(..end)?;
+        return Some((index, data_start + end));
+    }
+
+    fn offset(&self, i: usize) -> Option<usize> {
+        let bytes = self
+            .offsets
+            .get(i * self.offset_size..(i + 1) * self.offset_size)?;
+        return Some(bytes.iter().fold(0, |acc, &b| acc << 8 | b as usize));
+    }
+
+    fn get(&self, i: usize) -> Option<&'a [u8]> {
+        if i >= self.count {
+            return None;
+        }
+        return self.data.get(self.offset(i)?..self.offset(i + 1)?);
+    }
+}
+
+// Operands of a DICT operator, only integer operands are needed.
+fn parse_dict(data: &[u8], operator: u16) -> Option<Vec<i32>> {
+    let mut operands = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let b0 = data[offset];
+        offset += 1;
+        match b0 {
+            0..=21 => {
+                let op = if b0 == 12 {
+                    offset += 1;
+                    1200 + read_u8(data, offset - 1)? as u16
+                } else {
+                    b0 as u16
+                };
+                if op == operator {
+                    return Some(operands);
+                }
+                operands.clear();
+            }
+            28 => {
+                operands.push(read_u16(data, offset)? as i16 as i32);
+                offset += 2;
+            }
+            29 => {
+                operands.push(read_u32(data, offset)? as i32);
+                offset += 4;
+            }
+            // Real numbers are only skipped, nibbles until the 0xf terminator
+            30 => {
+                loop {
+                    let b = read_u8(data, offset)?;
+                    offset += 1;
+                    if b & 0xf == 0xf || b >> 4 == 0xf {
+                        break;
+                    }
+                }
+                operands.push(0);
+            }
+            32..=246 => operands.push(b0 as i32 - 139),
+            247..=250 => {
+                operands.push((b0 as i32 - 247) * 256 + read_u8(data, offset)? as i32 + 108);
+                offset += 1;
+            }
+            251..=254 => {
+                operands.push(-(b0 as i32 - 251) * 256 - read_u8(data, offset)? as i32 - 108);
+                offset += 1;
+            }
+            _ => return None,
+        }
+    }
+    return None;
+}
+
+// Local subroutines of a private DICT.
+fn private_subroutines<'a>(cff: &'a [u8], font_dict: &[u8]) -> Option<Index<'a>> {
+    let private = match parse_dict(font_dict, 18) {
+        Some(private) if private.len() == 2 => private,
+        _ => return Some(Index::default()),
+    };
+    let (size, offset) = (private[0] as usize, private[1] as usize);
+    match parse_dict(cff.get(offset..offset + size)?, 19) {
+        Some(subrs) if subrs.len() == 1 => {
+            return Some(Index::parse(cff, offset + subrs[0] as usize)?.0)
+        }
+        _ => return Some(Index::default()),
+    }
+}
+
+// Glyph programs of a `CFF ` table, CID-keyed fonts select the local
+// subroutines per glyph.
+pub struct CffOutlines<'a> {
+    char_strings: Index<'a>,
+    global_subroutines: Index<'a>,
+    local_subroutines: Vec<Index<'a>>,
+    fd_select: Option<&'a [u8]>,
+}
+
+impl<'a> CffOutlines<'a> {
+    pub fn parse(cff: &'a [u8]) -> Option<Self> {
+        if read_u8(cff, 0)? != 1 {
+            return None;
+        }
+        let header_size = read_u8(cff, 2)? as usize;
+        let (_names, offset) = Index::parse(cff, header_size)?;
+        let (top_dicts, offset) = Index::parse(cff, offset)?;
+        let (_strings, offset) = Index::parse(cff, offset)?;
+        let (global_subroutines, _) = Index::parse(cff, offset)?;
+        let top_dict = top_dicts.get(0)?;
+
+        // Only Type 2 charstrings
+        if let Some(charstring_type) = parse_dict(top_dict, 1206) {
+            if charstring_type != [2] {
+                return None;
+            }
+        }
+        let char_strings = Index::parse(cff, *parse_dict(top_dict, 17)?.first()? as usize)?.0;
+        let (local_subroutines, fd_select) = match parse_dict(top_dict, 1236) {
+            Some(fd_array) => {
+                let fd_array = Index::parse(cff, *fd_array.first()? as usize)?.0;
+                let mut subroutines = Vec::with_capacity(fd_array.count);
+                for i in 0..fd_array.count {
+                    subroutines.push(private_subroutines(cff, fd_array.get(i)?)?);
+                }
+                let fd_select = *parse_dict(top_dict, 1237)?.first()? as usize;
+                (subroutines, Some(cff.get(fd_select..)?))
+            }
+            None => (vec![private_subroutines(cff, top_dict)?], None),
+        };
+
+        return Some(CffOutlines {
+            char_strings,
+            global_subroutines,
+            local_subroutines,
+            fd_select,
+        });
+    }
+
+    fn font_dict_index(&self, glyph: u16) -> Option<usize> {
+        let fd_select = match self.fd_select {
+            Some(fd_select) => fd_select,
+            None => return Some(0),
+        };
+        match read_u8(fd_select, 0)? {
+            0 => return read_u8(fd_select, 1 + glyph as usize).map(|fd| fd as usize),
+            3 => {
+                let ranges = read_u16(fd_select, 1)? as usize;
+                for i in 0..ranges {
+                    let range = 3 + i * 3;
+                    let first = read_u16(fd_select, range)?;
+                    let next = read_u16(fd_select, range + 3)?;
+                    if glyph >= first && glyph < next {
+                        return read_u8(fd_select, range + 2).map(|fd| fd as usize);
+                    }
+                }
+                return None;
+            }
+            _ => return None,
+        }
+    }
+
+    pub fn outline(&self, glyph: u16) -> Option<Outline> {
+        let program = self.char_strings.get(glyph as usize)?;
+        let local = self.local_subroutines.get(self.font_dict_index(glyph)?)?;
+        let mut interpreter = CharStringInterpreter {
+            global_subroutines: &self.global_subroutines,
+            local_subroutines: local,
+            builder: OutlineBuilder::default(),
+            stack: Vec::with_capacity(MAX_STACK),
+            stem_count: 0,
+            width_parsed: false,
+            finished: false,
+        };
+        interpreter.run(program, 0)?;
+        return Some(interpreter.builder.finish());
+    }
+}
+
+fn subroutine_bias(subroutines: &Index) -> i32 {
+    if subroutines.count < 1240 {
+        return 107;
+    } else if subroutines.count < 33900 {
+        return 1131;
+    }
+    return 32768;
+}
+
+struct CharStringInterpreter<'a, 'b> {
+    global_subroutines: &'b Index<'a>,
+    local_subroutines: &'b Index<'a>,
+    builder: OutlineBuilder,
+    stack: Vec<FScalar>,
+    stem_count: usize,
+    // The advance width may precede the arguments of the first stack clearing
+    // operator, it is taken from `hmtx` instead
+    width_parsed: bool,
+    finished: bool,
+}
+
+impl<'a, 'b> CharStringInterpreter<'a, 'b> {
+    // Drops the width when the operator got more arguments than it takes.
+    fn skip_width(&mut self, extra_arguments: bool) {
+        if !self.width_parsed && extra_arguments && !self.stack.is_empty() {
+            self.stack.remove(0);
+        }
+        self.width_parsed = true;
+    }
+
+    fn line(&mut self, dx: FScalar, dy: FScalar) {
+        let position = self.builder.position();
+        self.builder.line_to(position + Vector2f::new(dx, dy));
+    }
+
+    fn curve(&mut self, d: [FScalar; 6]) {
+        let p0 = self.builder.position();
+        let p1 = p0 + Vector2f::new(d[0], d[1]);
+        let p2 = p1 + Vector2f::new(d[2], d[3]);
+        let p3 = p2 + Vector2f::new(d[4], d[5]);
+        self.builder.curve_to(p1, p2, p3);
+    }
+
+    fn run(&mut self, program: &[u8], depth: u32) -> Option<()> {
+        if depth > MAX_SUBROUTINE_DEPTH {
+            return None;
+        }
+        let mut offset = 0;
+        while offset < program.len() && !self.finished {
+            let b0 = program[offset];
+            offset += 1;
+            match b0 {
+                // Stem hints only matter for counting the hint mask bytes
+                1 | 3 | 18 | 23 => {
+                    let odd = self.stack.len() % 2 == 1;
+                    self.skip_width(odd);
+                    self.stem_count += self.stack.len() / 2;
+                    self.stack.clear();
+                }
+                19 | 20 => {
+                    let odd = self.stack.len() % 2 == 1;
+                    self.skip_width(odd);
+                    self.stem_count += self.stack.len() / 2;
+                    self.stack.clear();
+                    offset += (self.stem_count + 7) / 8;
+                }
+                21 => {
+                    let extra = self.stack.len() > 2;
+                    self.skip_width(extra);
+                    let position = self.builder.position();
+                    let (dx, dy) = (*self.stack.get(0)?, *self.stack.get(1)?);
+                    self.builder.move_to(position + Vector2f::new(dx, dy));
+                    self.stack.clear();
+                }
+                22 | 4 => {
+                    let extra = self.stack.len() > 1;
+                    self.skip_width(extra);
+                    let position = self.builder.position();
+                    let d = *self.stack.get(0)?;
+                    let delta = match b0 {
+                        22 => Vector2f::new(d, 0.0),
+                        _ => Vector2f::new(0.0, d),
+                    };
+                    self.builder.move_to(position + delta);
+                    self.stack.clear();
+                }
+                5 => {
+                    for pair in self.stack.clone().chunks_exact(2) {
+                        self.line(pair[0], pair[1]);
+                    }
+                    self.stack.clear();
+                }
+                // Alternating horizontal and vertical lines
+                6 | 7 => {
+                    let mut horizontal = b0 == 6;
+                    for d in self.stack.clone() {
+                        match horizontal {
+                            true => self.line(d, 0.0),
+                            false => self.line(0.0, d),
+                        }
+                        horizontal = !horizontal;
+                    }
+                    self.stack.clear();
+                }
+                8 => {
+                    for d in self.stack.clone().chunks_exact(6) {
+                        self.curve([d[0], d[1], d[2], d[3], d[4], d[5]]);
+                    }
+                    self.stack.clear();
+                }
+                // Curves followed by a line, lines followed by a curve
+                24 | 25 => {
+                    let stack = self.stack.clone();
+                    let (curves, lines) = match b0 {
+                        24 => ((stack.len().saturating_sub(2)) / 6, 0),
+                        _ => (1, (stack.len().saturating_sub(6)) / 2),
+                    };
+                    let mut i = 0;
+                    for _ in 0..lines {
+                        self.line(*stack.get(i)?, *stack.get(i + 1)?);
+                        i += 2;
+                    }
+                    for _ in 0..curves {
+                        let d = stack.get(i..i + 6)?;
+                        self.curve([d[0], d[1], d[2], d[3], d[4], d[5]]);
+                        i += 6;
+                    }
+                    if b0 == 24 {
+                        self.line(*stack.get(i)?, *stack.get(i + 1)?);
+                    }
+                    self.stack.clear();
+                }
+                // Curves starting and ending vertical or horizontal
+                26 | 27 => {
+                    let stack = self.stack.clone();
+                    let mut i = stack.len() % 4;
+                    let mut first = match i {
+                        1 => stack[0],
+                        _ => 0.0,
+                    };
+                    while i + 4 <= stack.len() {
+                        let d = &stack[i..i + 4];
+                        match b0 {
+                            26 => self.curve([first, d[0], d[1], d[2], 0.0, d[3]]),
+                            _ => self.curve([d[0], first, d[1], d[2], d[3], 0.0]),
+                        }
+                        first = 0.0;
+                        i += 4;
+                    }
+                    self.stack.clear();
+                }
+                // Curves alternating between horizontal and vertical tangents, the
+                // last one may end with an extra delta
+                30 | 31 => {
+                    let stack = self.stack.clone();
+                    let mut horizontal = b0 == 31;
+                    let mut i = 0;
+                    while i + 4 <= stack.len() {
+                        let d = &stack[i..i + 4];
+                        let last = match stack.len() - i == 5 {
+                            true => stack[i + 4],
+                            false => 0.0,
+                        };
+                        match horizontal {
+                            true => self.curve([d[0], 0.0, d[1], d[2], last, d[3]]),
+                            false => self.curve([0.0, d[0], d[1], d[2], d[3], last]),
+                        }
+                        horizontal = !horizontal;
+                        i += 4;
+                    }
+                    self.stack.clear();
+                }
+                10 | 29 => {
+                    let subroutines = match b0 {
+                        10 => self.local_subroutines,
+                        _ => self.global_subroutines,
+                    };
+                    let index = self.stack.pop()? as i32 + subroutine_bias(subroutines);
+                    if index < 0 {
+                        return None;
+                    }
+                    let subroutine = subroutines.get(index as usize)?;
+                    self.run(subroutine, depth + 1)?;
+                }
+                11 => return Some(()),
+                14 => {
+                    let extra = !self.stack.is_empty() && self.stack.len() != 4;
+                    self.skip_width(extra);
+                    self.stack.clear();
+                    self.finished = true;
+                }
+                12 => {
+                    let op = read_u8(program, offset)?;
+                    offset += 1;
+                    let s = self.stack.clone();
+                    match op {
+                        35 if s.len() >= 12 => {
+                            self.curve([s[0], s[1], s[2], s[3], s[4], s[5]]);
+                            self.curve([s[6], s[7], s[8], s[9], s[10], s[11]]);
+                        }
+                        34 if s.len() >= 7 => {
+                            self.curve([s[0], 0.0, s[1], s[2], s[3], 0.0]);
+                            self.curve([s[4], 0.0, s[5], -s[2], s[6], 0.0]);
+                        }
+                        36 if s.len() >= 9 => {
+                            self.curve([s[0], s[1], s[2], s[3], s[4], 0.0]);
+                            let dy = -(s[1] + s[3] + s[7]);
+                            self.curve([s[5], 0.0, s[6], s[7], s[8], dy]);
+                        }
+                        37 if s.len() >= 11 => {
+                            let dx = s[0] + s[2] + s[4] + s[6] + s[8];
+                            let dy = s[1] + s[3] + s[5] + s[7] + s[9];
+                            let (dx6, dy6) = match dx.abs() > dy.abs() {
+                                true => (s[10], -dy),
+                                false => (-dx, s[10]),
+                            };
+                            self.curve([s[0], s[1], s[2], s[3], s[4], s[5]]);
+                            self.curve([s[6], s[7], s[8], s[9], dx6, dy6]);
+                        }
+                        // Arithmetic and storage operators are not used by outlines
+                        _ => return None,
+                    }
+                    self.stack.clear();
+                }
+                28 => {
+                    let value = read_u16(program, offset)? as i16;
+                    offset += 2;
+                    self.stack.push(value as FScalar);
+                }
+                32..=246 => self.stack.push(b0 as FScalar - 139.0),
+                247..=250 => {
+                    let b1 = read_u8(program, offset)? as FScalar;
+                    offset += 1;
+                    self.stack
+                        .push((b0 as FScalar - 247.0) * 256.0 + b1 + 108.0);
+                }
+                251..=254 => {
+                    let b1 = read_u8(program, offset)? as FScalar;
+                    offset += 1;
+                    self.stack
+                        .push(-(b0 as FScalar - 251.0) * 256.0 - b1 - 108.0);
+                }
+                // 16.16 fixed point
+                255 => {
+                    let value = read_u32(program, offset)? as i32;
+                    offset += 4;
+                    self.stack.push(value as FScalar / 65536.0);
+                }
+                _ => return None,
+            }
+            if self.stack.len() > MAX_STACK {
+                return None;
+            }
+        }
+        return Some(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::OutlineSegment;
+
+    fn run(program: &[u8], local: &Index) -> Option<Outline> {
+        let global = Index::default();
+        let mut interpreter = CharStringInterpreter {
+            global_subroutines: &global,
+            local_subroutines: local,
+            builder: OutlineBuilder::default(),
+            stack: Vec::new(),
+            stem_count: 0,
+            width_parsed: false,
+            finished: false,
+        };
+        interpreter.run(program, 0)?;
+        return Some(interpreter.builder.finish());
+    }
+
+    #[test]
+    fn index_test() {
+        let data = [0, 2, 1, 1, 3, 4, 10, 20, 30, 99];
+        let (index, end) = Index::parse(&data, 0).unwrap();
+        assert_eq!(index.get(0), Some(&[10u8, 20][..]));
+        assert_eq!(index.get(1), Some(&[30u8][..]));
+        assert_eq!(index.get(2), None);
+        assert_eq!(end, 9);
+        assert_eq!(Index::parse(&[0, 0], 0).unwrap().1, 2);
+    }
+
+    #[test]
+    fn dict_test() {
+        // 100 17 (CharStrings), -200 20 (ignored) 1000 12 36 (FDArray)
+        let dict = [239, 17, 251, 92, 20, 28, 3, 232, 12, 36];
+        assert_eq!(parse_dict(&dict, 17), Some(vec![100]));
+        assert_eq!(parse_dict(&dict, 1236), Some(vec![1000]));
+        assert_eq!(parse_dict(&dict, 18), None);
+    }
+
+    #[test]
+    fn charstring_test() {
+        // Width 50, rmoveto 10 10, hlineto 100, vlineto 100, hlineto -100 from a
+        // subroutine, endchar
+        let subroutine = [39, 11];
+        let local_data = [0, 1, 1, 1, 3, subroutine[0], subroutine[1]];
+        let local = Index::parse(&local_data, 0).unwrap().0;
+        let program = [189, 149, 149, 21, 239, 6, 239, 7, 32, 10, 6, 14];
+        let outline = run(&program, &local).unwrap();
+        let points: Vec<Vector2f> = outline[0].iter().map(|s| s.start()).collect();
+        assert_eq!(
+            points,
+            vec![
+                Vector2f::new(10.0, 10.0),
+                Vector2f::new(110.0, 10.0),
+                Vector2f::new(110.0, 110.0),
+                Vector2f::new(10.0, 110.0),
+            ]
+        );
+        assert_eq!(
+            outline[0][3],
+            OutlineSegment::Line(Vector2f::new(10.0, 110.0), Vector2f::new(10.0, 10.0))
+        );
+    }
+
+    #[test]
+    fn curve_operators_test() {
+        // rmoveto 0 0, hvcurveto 10 10 10 10 with a final delta of 5, endchar
+        let program = [139, 139, 21, 149, 149, 149, 149, 144, 31, 14];
+        let outline = run(&program, &Index::default()).unwrap();
+        assert_eq!(
+            outline[0][0],
+            OutlineSegment::Cubic(
+                Vector2f::new(0.0, 0.0),
+                Vector2f::new(10.0, 0.0),
+                Vector2f::new(20.0, 10.0),
+                Vector2f::new(25.0, 20.0)
+            )
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    bounds::{Aabb, BoundingSphere},
+    camera::Camera,
+    light::{Light, LightKind},
+};
+use polyengine_core::*;
+
+// Number of clusters the view frustum is split into.
+pub const CLUSTER_TILES_X: usize = 16;
+pub const CLUSTER_TILES_Y: usize = 9;
+pub const CLUSTER_SLICES: usize = 24;
+pub const CLUSTER_COUNT: usize = CLUSTER_TILES_X * CLUSTER_TILES_Y * CLUSTER_SLICES;
+
+// Result of binning lights into the view frustum clusters. Indices refer to the
+// light list passed to `assign_lights`.
+pub struct LightClusters {
+    // Offset into `light_indices` and light count for every cluster
+    pub ranges: Vec<[u32; 2]>,
+    pub light_indices: Vec<u32>,
+    // Multiplier and bias mapping log of view depth to the slice index
+    pub slice_scale: FScalar,
+    pub slice_bias: FScalar,
+}
+
+pub fn cluster_index(x: usize, y: usize, slice: usize) -> usize {
+    return (slice * CLUSTER_TILES_Y + y) * CLUSTER_TILES_X + x;
+}
+
+// Distance from the camera at which given slice begins. Slices are distributed
+// exponentially so they keep roughly cubic shape.
+fn slice_depth(camera: &Camera, slice: usize) -> FScalar {
+    let ratio = camera.z_far / camera.z_near;
+    return camera.z_near * ratio.powf(slice as FScalar / CLUSTER_SLICES as FScalar);
+}
+
+// View space bounds of a cluster.
+fn cluster_bounds(camera: &Camera, aspect: FScalar, x: usize, y: usize, slice: usize) -> Aabb {
+    let f = 1.0 / (camera.fov_y * 0.5).tan();
+    let ndc_x = |tile: usize| tile as FScalar / CLUSTER_TILES_X as FScalar * 2.0 - 1.0;
+    let ndc_y = |tile: usize| tile as FScalar / CLUSTER_TILES_Y as FScalar * 2.0 - 1.0;
+
+    let mut bounds = Aabb::empty();
+    for depth in &[slice_depth(camera, slice), slice_depth(camera, slice + 1)] {
+        for nx in &[ndc_x(x), ndc_x(x + 1)] {
+            // Window Y axis points down
+            for ny in &[ndc_y(y), ndc_y(y + 1)] {
+                bounds.extend(&Vector3f::new(
+                    nx * aspect * depth / f,
+                    -ny * depth / f,
+                    -depth,
+                ));
+            }
+        }
+    }
+    return bounds;
+}
+
+fn sphere_intersects_aabb(sphere: &BoundingSphere, aabb: &Aabb) -> bool {
+    let closest = sphere
+        .center
+        .zip_zip_map(&aabb.min, &aabb.max, |c, min, max| c.max(min).min(max));
+    return (closest - sphere.center).norm_squared() <= sphere.radius * sphere.radius;
+}
+
+// Precomputed cluster volumes for a camera projection.
+pub struct ClusterVolumes {
+    fov_y: FScalar,
+    aspect: FScalar,
+    z_near: FScalar,
+    z_far: FScalar,
+    bounds: Vec<Aabb>,
+}
+
+impl ClusterVolumes {
+    pub fn new(camera: &Camera, aspect: FScalar) -> Self {
+        let mut bounds = Vec::with_capacity(CLUSTER_COUNT);
+        for slice in 0..CLUSTER_SLICES {
+            for y in 0..CLUSTER_TILES_Y {
+                for x in 0..CLUSTER_TILES_X {
+                    bounds.push(cluster_bounds(camera, aspect, x, y, slice));
+                }
+            }
+        }
+        return ClusterVolumes {
+            fov_y: camera.fov_y,
+            aspect,
+            z_near: camera.z_near,
+            z_far: camera.z_far,
+            bounds,
+        };
+    }
+
+    // Whether the volumes are still valid for the camera projection.
+    pub fn matches(&self, camera: &Camera, aspect: FScalar) -> bool {
+        return self.fov_y == camera.fov_y
+            && self.aspect == aspect
+            && self.z_near == camera.z_near
+            && self.z_far == camera.z_far;
+    }
+
+    // Bins point and spot lights into clusters. Directional lights affect every
+    // cluster and are skipped.
+    pub fn assign_lights(&self, camera: &Camera, lights: &[Light]) -> LightClusters {
+        let view = camera.view_matrix();
+        let log_ratio = (camera.z_far / camera.z_near).ln();
+        let slice_scale = CLUSTER_SLICES as FScalar / log_ratio;
+        let slice_bias = -(CLUSTER_SLICES as FScalar) * camera.z_near.ln() / log_ratio;
+
+        let mut cluster_lights: Vec<Vec<u32>> = vec![Vec::new(); CLUSTER_COUNT];
+        for (light_index, light) in lights.iter().enumerate() {
+            if light.kind == LightKind::Directional {
+                continue;
+            }
+
+            let center = view.transform_point(&light.position.into()).coords;
+            let sphere = BoundingSphere::new(center, light.range);
+            let near_depth = -center.z - light.range;
+            let far_depth = -center.z + light.range;
+            if far_depth < camera.z_near || near_depth > camera.z_far {
+                continue;
+            }
+
+            let slice_of = |depth: FScalar| {
+                let slice = depth.max(camera.z_near).ln() * slice_scale + slice_bias;
+                return (slice.max(0.0) as usize).min(CLUSTER_SLICES - 1);
+            };
+            for slice in slice_of(near_depth)..=slice_of(far_depth) {
+                for y in 0..CLUSTER_TILES_Y {
+                    for x in 0..CLUSTER_TILES_X {
+                        let index = cluster_index(x, y, slice);
+                        if sphere_intersects_aabb(&sphere, &self.bounds[index]) {
+                            cluster_lights[index].push(light_index as u32);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut ranges = Vec::with_capacity(CLUSTER_COUNT);
+        let mut light_indices = Vec::new();
+        for indices in cluster_lights {
+            ranges.push([light_indices.len() as u32, indices.len() as u32]);
+            light_indices.extend(indices);
+        }
+
+        return LightClusters {
+            ranges,
+            light_indices,
+            slice_scale,
+            slice_bias,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_camera() -> Camera {
+        return Camera::new(
+            Isometry3::identity(),
+            std::f32::consts::FRAC_PI_2,
+            0.1,
+            100.0,
+        );
+    }
+
+    #[test]
+    fn slice_mapping_test() {
+        let camera = test_camera();
+        let clusters = ClusterVolumes::new(&camera, 1.0).assign_lights(&camera, &[]);
+        for slice in 0..CLUSTER_SLICES {
+            let depth = slice_depth(&camera, slice) * 1.001;
+            let mapped = depth.ln() * clusters.slice_scale + clusters.slice_bias;
+            assert_eq!(mapped.floor() as usize, slice);
+        }
+    }
+
+    #[test]
+    fn assign_test() {
+        let camera = test_camera();
+        let volumes = ClusterVolumes::new(&camera, 1.0);
+        let lights = [
+            Light::point(
+                Vector3f::new(0.0, 0.0, -10.0),
+                Vector3f::repeat(1.0),
+                1.0,
+                1.0,
+            ),
+            Light::point(
+                Vector3f::new(0.0, 0.0, 10.0),
+                Vector3f::repeat(1.0),
+                1.0,
+                1.0,
+            ),
+            Light::directional(-Vector3f::y(), Vector3f::repeat(1.0), 1.0),
+        ];
+        let clusters = volumes.assign_lights(&camera, &lights);
+
+        assert_eq!(clusters.ranges.len(), CLUSTER_COUNT);
+        assert!(!clusters.light_indices.is_empty());
+        assert!(clusters.light_indices.iter().all(|&i| i == 0));
+
+        // Light sits in the middle of the screen, at 10 units depth.
+        let slice = (10f32.ln() * clusters.slice_scale + clusters.slice_bias) as usize;
+        let center = cluster_index(CLUSTER_TILES_X / 2, CLUSTER_TILES_Y / 2, slice);
+        assert_eq!(clusters.ranges[center][1], 1);
+        let corner = cluster_index(0, 0, slice);
+        assert_eq!(clusters.ranges[corner][1], 0);
+    }
+}
@@ -1,18 +1,33 @@
 use vulkano::{
     command_buffer::DynamicState,
+    descriptor::PipelineLayoutAbstract,
+    device::DeviceOwned,
     framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract},
-    image::swapchain::SwapchainImage,
-    pipeline::viewport::Viewport,
+    image::{swapchain::SwapchainImage, AttachmentImage, ImageUsage},
+    pipeline::{vertex::BufferlessDefinition, viewport::Viewport, GraphicsPipeline},
 };
 use winit::window::Window;
 
+use crate::{config, debug_utils::set_image_name};
 use std::{sync::Arc, vec::Vec};
 
+// Fullscreen and procedural draws generate their vertices in the shader,
+// drawing with `BufferlessVertices` needs the concrete pipeline type rather
+// than a trait object.
+pub type BufferlessPipeline = GraphicsPipeline<
+    BufferlessDefinition,
+    Box<dyn PipelineLayoutAbstract + Send + Sync>,
+    Arc<dyn RenderPassAbstract + Send + Sync>,
+>;
+
 pub fn window_size_dependent_setup(
     images: &[Arc<SwapchainImage<Window>>],
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
     dynamic_state: &mut DynamicState,
-) -> Vec<Arc<dyn FramebufferAbstract + Send + Sync>> {
+) -> (
+    Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    Arc<AttachmentImage>,
+) {
     let dimensions = images[0].dimensions();
 
     let viewport = Viewport {
@@ -22,16 +37,35 @@ pub fn window_size_dependent_setup(
     };
     dynamic_state.viewports = Some(vec![viewport]);
 
-    images
+    let device = images[0].swapchain().device().clone();
+    // Depth is written by the scene pass and reused for debug drawing in the window
+    // pass, the deferred lighting pass samples it.
+    let usage = ImageUsage {
+        depth_stencil_attachment: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+    let depth_buffer =
+        AttachmentImage::with_usage(device, dimensions, config::DEFAULT_DEPTH_FORMAT, usage)
+            .unwrap();
+    set_image_name(&depth_buffer, "window depth");
+    for (i, image) in images.iter().enumerate() {
+        set_image_name(image, &format!("swapchain image {}", i));
+    }
+
+    let framebuffers = images
         .iter()
         .map(|image| {
             Arc::new(
                 Framebuffer::start(render_pass.clone())
                     .add(image.clone())
                     .unwrap()
+                    .add(depth_buffer.clone())
+                    .unwrap()
                     .build()
                     .unwrap(),
             ) as Arc<dyn FramebufferAbstract + Send + Sync>
         })
-        .collect::<Vec<_>>()
+        .collect::<Vec<_>>();
+    return (framebuffers, depth_buffer);
 }
@@ -0,0 +1,86 @@
+// Capabilities of a device queue family, used to choose the compute queue.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QueueFamilyCaps {
+    pub graphics: bool,
+    pub compute: bool,
+    pub queue_count: u32,
+}
+
+// Where compute work is submitted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ComputeQueueChoice {
+    // Queue of a family without graphics support, runs asynchronously
+    Dedicated(usize),
+    // Second queue of the graphics family, runs asynchronously
+    SecondGraphics,
+    // The graphics queue itself, compute work is serialised with rendering
+    Shared,
+}
+
+// Prefers a dedicated compute family, then another queue of the graphics
+// family.
+pub fn select_compute_queue(
+    families: &[QueueFamilyCaps],
+    graphics_family: usize,
+) -> ComputeQueueChoice {
+    let dedicated = families
+        .iter()
+        .position(|family| family.compute && !family.graphics && family.queue_count > 0);
+    if let Some(index) = dedicated {
+        return ComputeQueueChoice::Dedicated(index);
+    }
+    let graphics = &families[graphics_family];
+    if graphics.compute && graphics.queue_count > 1 {
+        return ComputeQueueChoice::SecondGraphics;
+    }
+    return ComputeQueueChoice::Shared;
+}
+
+// Workgroups covering `work_size` invocations with workgroups of `local_size`.
+pub fn dispatch_groups(work_size: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    let groups = |size: u32, local: u32| (size + local - 1) / local;
+    return [
+        groups(work_size[0], local_size[0]),
+        groups(work_size[1], local_size[1]),
+        groups(work_size[2], local_size[2]),
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_compute_queue_test() {
+        let family = |graphics, compute, queue_count| QueueFamilyCaps {
+            graphics,
+            compute,
+            queue_count,
+        };
+        let families = [
+            family(true, true, 1),
+            family(false, false, 2),
+            family(false, true, 4),
+        ];
+        assert_eq!(
+            select_compute_queue(&families, 0),
+            ComputeQueueChoice::Dedicated(2)
+        );
+        assert_eq!(
+            select_compute_queue(&[family(true, true, 16)], 0),
+            ComputeQueueChoice::SecondGraphics
+        );
+        assert_eq!(
+            select_compute_queue(&[family(true, true, 1)], 0),
+            ComputeQueueChoice::Shared
+        );
+    }
+
+    #[test]
+    fn dispatch_groups_test() {
+        assert_eq!(dispatch_groups([1, 1, 1], [64, 1, 1]), [1, 1, 1]);
+        assert_eq!(dispatch_groups([64, 1, 1], [64, 1, 1]), [1, 1, 1]);
+        assert_eq!(dispatch_groups([65, 17, 6], [64, 8, 1]), [2, 3, 6]);
+        assert_eq!(dispatch_groups([0, 1, 1], [64, 1, 1]), [0, 1, 1]);
+    }
+}
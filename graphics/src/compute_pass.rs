@@ -0,0 +1,189 @@
+use crate::compute::dispatch_groups;
+use polyengine_core::*;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferUsage, DeviceLocalBuffer},
+    command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder},
+    descriptor::{
+        descriptor_set::{DescriptorSetsCollection, UnsafeDescriptorSetLayout},
+        PipelineLayoutAbstract,
+    },
+    device::{Device, Queue},
+    format::Format,
+    image::{Dimensions, ImageUsage, StorageImage},
+    instance::QueueFamily,
+    pipeline::ComputePipelineAbstract,
+    sync,
+    sync::GpuFuture,
+};
+
+// Compute pipeline with the workgroup size of its shader, so dispatches can be
+// given in invocations. Resources are bound with descriptor sets built from
+// `set_layout`, storage buffers with `add_buffer` and storage images with
+// `add_image`. Kernels are recorded on the graphics queue by the renderer, or
+// submitted through `ComputeQueue` to run asynchronously.
+pub struct ComputeKernel {
+    pub pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    // Has to match `local_size` of the shader
+    local_size: [u32; 3],
+}
+
+impl ComputeKernel {
+    pub fn new(
+        pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+        local_size: [u32; 3],
+    ) -> Self {
+        return ComputeKernel {
+            pipeline,
+            local_size,
+        };
+    }
+
+    pub fn set_layout(&self, index: usize) -> Arc<UnsafeDescriptorSetLayout> {
+        return self.pipeline.descriptor_set_layout(index).unwrap().clone();
+    }
+
+    // Dispatches enough workgroups for `work_size` invocations, the shader has to
+    // skip the ones past the end.
+    pub fn dispatch<S, Pc>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        work_size: [u32; 3],
+        sets: S,
+        push_constants: Pc,
+    ) -> AutoCommandBufferBuilder
+    where
+        S: DescriptorSetsCollection,
+    {
+        let groups = dispatch_groups(work_size, self.local_size);
+        if groups.contains(&0) {
+            return builder;
+        }
+        return builder
+            .dispatch(groups, self.pipeline.clone(), sets, push_constants)
+            .unwrap();
+    }
+}
+
+// Queue compute work is submitted to, asynchronous to rendering when the device
+// has a separate compute queue. Work submitted during a frame is waited for by
+// the next graphics submission.
+pub struct ComputeQueue {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    graphics_queue: Arc<Queue>,
+    // Submitted work the graphics queue hasn't waited for yet
+    pending: Option<Box<dyn GpuFuture>>,
+}
+
+impl ComputeQueue {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, graphics_queue: Arc<Queue>) -> Self {
+        return ComputeQueue {
+            device,
+            queue,
+            graphics_queue,
+            pending: None,
+        };
+    }
+
+    pub fn device(&self) -> &Arc<Device> { return &self.device; }
+
+    pub fn queue(&self) -> &Arc<Queue> { return &self.queue; }
+
+    // Whether compute work can overlap with rendering.
+    pub fn is_async(&self) -> bool { return !Arc::ptr_eq(&self.queue, &self.graphics_queue); }
+
+    // Families resources used by both compute and graphics work have to be shared
+    // between.
+    pub fn queue_families(&self) -> Vec<QueueFamily<'_>> {
+        let mut families = vec![self.graphics_queue.family()];
+        if self.queue.family().id() != self.graphics_queue.family().id() {
+            families.push(self.queue.family());
+        }
+        return families;
+    }
+
+    // Device local buffer of `len` elements readable and writable by compute and
+    // graphics work.
+    pub fn storage_buffer<T>(&self, len: usize, usage: BufferUsage) -> Arc<DeviceLocalBuffer<[T]>>
+    where
+        T: Send + Sync + 'static,
+    {
+        return DeviceLocalBuffer::array(
+            self.device.clone(),
+            len,
+            BufferUsage {
+                storage_buffer: true,
+                ..usage
+            },
+            self.queue_families(),
+        )
+        .unwrap();
+    }
+
+    // 2D image writable by compute work and sampled by graphics work.
+    pub fn storage_image(&self, dimensions: [u32; 2], format: Format) -> Arc<StorageImage<Format>> {
+        return StorageImage::with_usage(
+            self.device.clone(),
+            Dimensions::Dim2d {
+                width: dimensions[0],
+                height: dimensions[1],
+            },
+            format,
+            ImageUsage {
+                storage: true,
+                sampled: true,
+                ..ImageUsage::none()
+            },
+            self.queue_families(),
+        )
+        .unwrap();
+    }
+
+    pub fn start(&self) -> AutoCommandBufferBuilder {
+        return AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            self.queue.family(),
+        )
+        .unwrap();
+    }
+
+    // Submits after the previously submitted compute work, without waiting for it.
+    pub fn submit(&mut self, command_buffer: AutoCommandBuffer) {
+        let previous = match self.pending.take() {
+            Some(future) => future,
+            None => Box::new(sync::now(self.device.clone())) as Box<dyn GpuFuture>,
+        };
+        let future = previous
+            .then_execute(self.queue.clone(), command_buffer)
+            .unwrap()
+            .then_signal_semaphore_and_flush();
+        match future {
+            Ok(future) => self.pending = Some(Box::new(future)),
+            Err(e) => log::error!("Failed to flush compute work: {:?}", e),
+        }
+    }
+
+    // Submits and blocks until the work has finished, for one-off work outside of
+    // frames.
+    pub fn submit_and_wait(&mut self, command_buffer: AutoCommandBuffer) {
+        self.submit(command_buffer);
+        if let Some(future) = self.pending.take() {
+            future
+                .then_signal_fence_and_flush()
+                .unwrap()
+                .wait(None)
+                .unwrap();
+        }
+    }
+
+    // Compute work the next graphics submission has to wait for. Later graphics
+    // submissions on the same queue are ordered after it as well.
+    pub fn take_pending(&mut self) -> Option<Box<dyn GpuFuture>> { return self.pending.take(); }
+
+    // Puts back work taken by `take_pending` that no submission waited for.
+    pub fn restore_pending(&mut self, future: Box<dyn GpuFuture>) {
+        debug_assert!(self.pending.is_none());
+        self.pending = Some(future);
+    }
+}
@@ -7,3 +7,23 @@ pub const DEFAULT_COLOR_SPACE: ColorSpace = ColorSpace::SrgbNonLinear;
 
 pub const DEFAULT_WINDOW_FORMAT: Format = Format::B8G8R8A8Unorm;
 pub const DEFAULT_WINDOW_ALPHA: CompositeAlpha = CompositeAlpha::Opaque;
+pub const DEFAULT_DEPTH_FORMAT: Format = Format::D32Sfloat;
+// Scene colour is rendered in linear HDR and resolved into the window image.
+pub const HDR_FORMAT: Format = Format::R16G16B16A16Sfloat;
+// Screen space motion vectors written by the scene pass.
+pub const VELOCITY_FORMAT: Format = Format::R16G16Sfloat;
+// Deferred shading G-buffer, albedo is stored sRGB encoded to keep precision in
+// dark colours.
+pub const GBUFFER_ALBEDO_FORMAT: Format = Format::R8G8B8A8Srgb;
+pub const GBUFFER_NORMAL_FORMAT: Format = Format::R16G16B16A16Sfloat;
+pub const GBUFFER_MATERIAL_FORMAT: Format = Format::R8G8B8A8Unorm;
+// Screen space ambient occlusion, 1 means unoccluded.
+pub const AMBIENT_OCCLUSION_FORMAT: Format = Format::R8Unorm;
+// Weighted blended transparency, summed weighted colour and product of (1 -
+// alpha).
+pub const OIT_ACCUMULATION_FORMAT: Format = Format::R16G16B16A16Sfloat;
+pub const OIT_REVEALAGE_FORMAT: Format = Format::R16Sfloat;
+// Object IDs rendered for picking.
+pub const PICKING_FORMAT: Format = Format::R32Uint;
+// Hierarchical depth used for occlusion culling.
+pub const HIZ_FORMAT: Format = Format::R32Sfloat;
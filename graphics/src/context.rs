@@ -5,8 +5,34 @@ use vulkano::{
 };
 
 use super::{config, error::RenderingError, window::WindowContext};
-use crate::geometry::{Geometry, GeometryId};
-use std::{collections::HashMap, sync::Arc};
+use crate::{
+    capture::{CaptureFormat, CaptureId},
+    capture_pass::CaptureRequest,
+    compute::{select_compute_queue, ComputeQueueChoice, QueueFamilyCaps},
+    decal::{Decal, DecalId, DecalTextureId},
+    environment::EnvironmentId,
+    environment_pass::EnvironmentMap,
+    geometry::{Geometry, GeometryId},
+    gpu_driven_pass::GeometryArena,
+    light::{Light, LightId},
+    lod::LodGroup,
+    material::{Material, MaterialId},
+    object::{ObjectId, RenderObject},
+    particles::{ParticleEmitter, ParticleEmitterId},
+    picking::{PickId, PickRegion},
+    skinning::{JointPose, SkinnedMeshId, SkinnedObject, SkinnedObjectId},
+    skinning_pass::SkinnedMesh,
+    sprite_pass::SpriteAtlasImage,
+    sprites::{Sprite, SpriteAtlasId, SpriteId, TileMap, TileMapId},
+    terrain::TerrainId,
+    terrain_pass::RenderTerrain,
+    text::{FontId, Text, TextId},
+    text_pass::FontImage,
+    voxel::VoxelWorld,
+    voxel_pass::ChunkMesh,
+};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use vulkano::{format::Format, image::ImmutableImage};
 use winit::{event_loop::EventLoopWindowTarget, window::WindowId};
 
 use polyengine_core::*;
@@ -14,11 +40,73 @@ use polyengine_core::*;
 pub struct RenderContext {
     pub device: Arc<Device>,
     pub queue: Arc<vulkano::device::Queue>,
+    // Same as `queue` when the device has no other queue supporting compute
+    pub compute_queue: Arc<vulkano::device::Queue>,
     pub default_window_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
     pub windows: HashMap<WindowId, WindowContext>,
 
     geometry_id_counter: GeometryId,
     pub geometries: HashMap<GeometryId, Geometry>,
+    // Only built for GPU driven rendering
+    pub geometry_arena: Option<GeometryArena>,
+
+    object_id_counter: ObjectId,
+    pub objects: HashMap<ObjectId, RenderObject>,
+
+    material_id_counter: MaterialId,
+    pub materials: HashMap<MaterialId, Material>,
+
+    light_id_counter: LightId,
+    pub lights: HashMap<LightId, Light>,
+
+    environment_id_counter: EnvironmentId,
+    pub environments: HashMap<EnvironmentId, EnvironmentMap>,
+
+    particle_emitter_id_counter: ParticleEmitterId,
+    pub particle_emitters: HashMap<ParticleEmitterId, ParticleEmitter>,
+
+    sprite_atlas_id_counter: SpriteAtlasId,
+    pub sprite_atlases: HashMap<SpriteAtlasId, SpriteAtlasImage>,
+
+    sprite_id_counter: SpriteId,
+    pub sprites: HashMap<SpriteId, Sprite>,
+
+    tile_map_id_counter: TileMapId,
+    pub tile_maps: HashMap<TileMapId, TileMap>,
+
+    font_id_counter: FontId,
+    pub fonts: HashMap<FontId, FontImage>,
+
+    text_id_counter: TextId,
+    pub texts: HashMap<TextId, Text>,
+
+    skinned_mesh_id_counter: SkinnedMeshId,
+    pub skinned_meshes: HashMap<SkinnedMeshId, SkinnedMesh>,
+
+    skinned_object_id_counter: SkinnedObjectId,
+    pub skinned_objects: HashMap<SkinnedObjectId, SkinnedObject>,
+
+    pub voxel_world: VoxelWorld,
+    // Meshes of the chunks with visible faces
+    pub chunk_meshes: HashMap<Vector3i, ChunkMesh>,
+    pub block_atlas: Option<Arc<ImmutableImage<Format>>>,
+
+    terrain_id_counter: TerrainId,
+    pub terrains: HashMap<TerrainId, RenderTerrain>,
+
+    decal_texture_id_counter: DecalTextureId,
+    pub decal_textures: HashMap<DecalTextureId, Arc<ImmutableImage<Format>>>,
+
+    decal_id_counter: DecalId,
+    pub decals: HashMap<DecalId, Decal>,
+
+    pick_id_counter: PickId,
+    // Finished pick requests, until their result is taken
+    pub pick_results: HashMap<PickId, Vec<ObjectId>>,
+
+    capture_id_counter: CaptureId,
+    // Written or failed captures, until their result is taken
+    pub capture_results: HashMap<CaptureId, Result<(), RenderingError>>,
 }
 
 impl RenderContext {
@@ -56,6 +144,25 @@ impl RenderContext {
                 |&q| q.supports_graphics(), // && surface.is_supported(q).unwrap_or(false)
             )
             .expect("couldn't find a graphical queue family");
+        let family_caps: Vec<QueueFamilyCaps> = physical
+            .queue_families()
+            .map(|family| QueueFamilyCaps {
+                graphics: family.supports_graphics(),
+                compute: family.supports_compute(),
+                queue_count: family.queues_count() as u32,
+            })
+            .collect();
+        let compute_choice = select_compute_queue(&family_caps, queue_family.id() as usize);
+        let mut queue_families = vec![(queue_family, 0.5)];
+        match compute_choice {
+            ComputeQueueChoice::Dedicated(index) => {
+                let family = physical.queue_family_by_id(index as u32).unwrap();
+                queue_families.push((family, 0.5));
+            }
+            ComputeQueueChoice::SecondGraphics => queue_families.push((queue_family, 0.5)),
+            ComputeQueueChoice::Shared => (),
+        }
+        log::info!("Compute queue: {:?}", compute_choice);
 
         // Device + queues
         let (device, mut queues) = {
@@ -68,11 +175,15 @@ impl RenderContext {
                 physical,
                 physical.supported_features(),
                 &device_ext,
-                [(queue_family, 0.5)].iter().cloned(),
+                queue_families.iter().cloned(),
             )
             .expect("failed to create device")
         };
         let queue = queues.next().unwrap();
+        let compute_queue = match compute_choice {
+            ComputeQueueChoice::Shared => queue.clone(),
+            _ => queues.next().unwrap(),
+        };
 
         let default_window_render_pass = Arc::new(
             vulkano::single_pass_renderpass!(
@@ -80,9 +191,9 @@ impl RenderContext {
                 attachments: {
                     // `color` is a custom name we give to the first and only attachment.
                     color: {
-                        // `load: Clear` means that we ask the GPU to clear the content of this
-                        // attachment at the start of the drawing.
-                        load: Clear,
+                        // `load: DontCare` means that the previous content of the attachment is
+                        // discarded, the post-process composite overwrites every pixel.
+                        load: DontCare,
                         // `store: Store` means that we ask the GPU to store the output of the draw
                         // in the actual image. We could also ask it to discard the result.
                         store: Store,
@@ -93,13 +204,18 @@ impl RenderContext {
                         format: config::DEFAULT_WINDOW_FORMAT,
                         // TODO:
                         samples: 1,
+                    },
+                    // Scene depth, used to depth test debug primitives drawn after the composite.
+                    depth: {
+                        load: Load,
+                        store: DontCare,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
                     }
                 },
                 pass: {
-                    // We use the attachment named `color` as the one and only color attachment.
                     color: [color],
-                    // No depth-stencil attachment is indicated with empty brackets.
-                    depth_stencil: {}
+                    depth_stencil: {depth}
                 }
             )
             .unwrap(),
@@ -108,10 +224,49 @@ impl RenderContext {
         return RenderContext {
             device,
             queue,
+            compute_queue,
             default_window_render_pass,
             windows: HashMap::new(),
             geometry_id_counter: 0,
             geometries: HashMap::new(),
+            geometry_arena: None,
+            object_id_counter: 0,
+            objects: HashMap::new(),
+            material_id_counter: 0,
+            materials: HashMap::new(),
+            light_id_counter: 0,
+            lights: HashMap::new(),
+            environment_id_counter: 0,
+            environments: HashMap::new(),
+            particle_emitter_id_counter: 0,
+            particle_emitters: HashMap::new(),
+            sprite_atlas_id_counter: 0,
+            sprite_atlases: HashMap::new(),
+            sprite_id_counter: 0,
+            sprites: HashMap::new(),
+            tile_map_id_counter: 0,
+            tile_maps: HashMap::new(),
+            font_id_counter: 0,
+            fonts: HashMap::new(),
+            text_id_counter: 0,
+            texts: HashMap::new(),
+            skinned_mesh_id_counter: 0,
+            skinned_meshes: HashMap::new(),
+            skinned_object_id_counter: 0,
+            skinned_objects: HashMap::new(),
+            voxel_world: VoxelWorld::new(),
+            chunk_meshes: HashMap::new(),
+            block_atlas: None,
+            terrain_id_counter: 0,
+            terrains: HashMap::new(),
+            decal_texture_id_counter: 0,
+            decal_textures: HashMap::new(),
+            decal_id_counter: 0,
+            decals: HashMap::new(),
+            pick_id_counter: 0,
+            pick_results: HashMap::new(),
+            capture_id_counter: 0,
+            capture_results: HashMap::new(),
         };
     }
 
@@ -139,10 +294,457 @@ impl RenderContext {
     pub fn window_count(&self) -> usize { return self.windows.len(); }
 
     pub fn create_geometry(&mut self, data: &Vec<na::Vector3<FScalar>>) -> GeometryId {
+        return self.add_geometry(Geometry::from_data(self.device.clone(), data));
+    }
+
+    // Rebuilds the geometry arena when geometries were added since it was built.
+    pub fn update_geometry_arena(&mut self) {
+        let up_to_date = match &self.geometry_arena {
+            Some(arena) => arena.is_current(&self.geometries),
+            None => false,
+        };
+        if !up_to_date {
+            self.geometry_arena = Some(GeometryArena::new(self.device.clone(), &self.geometries));
+        }
+    }
+
+    // Remeshes the chunks changed since the last update.
+    pub fn update_chunk_meshes(&mut self) {
+        for chunk_position in self.voxel_world.take_dirty() {
+            let vertices = self.voxel_world.mesh_chunk(&chunk_position);
+            if vertices.is_empty() {
+                self.chunk_meshes.remove(&chunk_position);
+                continue;
+            }
+            let mesh = ChunkMesh::new(self.device.clone(), &chunk_position, &vertices);
+            self.chunk_meshes.insert(chunk_position, mesh);
+        }
+    }
+
+    pub fn add_geometry(&mut self, geometry: Geometry) -> GeometryId {
         let geometry_id = self.geometry_id_counter;
         self.geometry_id_counter += 1;
-        self.geometries
-            .insert(geometry_id, Geometry::from_data(self.device.clone(), data));
+        self.geometries.insert(geometry_id, geometry);
         return geometry_id;
     }
+
+    pub fn create_object(
+        &mut self,
+        lod_group: LodGroup,
+        transform: Similarity3,
+    ) -> Result<ObjectId, RenderingError> {
+        if !lod_group
+            .levels
+            .iter()
+            .all(|level| self.geometries.contains_key(&level.geometry))
+        {
+            return Err(RenderingError::GeometryNotFound);
+        }
+        let object_id = self.object_id_counter;
+        self.object_id_counter += 1;
+        self.objects
+            .insert(object_id, RenderObject::new(lod_group, transform));
+        return Ok(object_id);
+    }
+
+    pub fn set_object_transform(
+        &mut self,
+        object_id: ObjectId,
+        transform: Similarity3,
+    ) -> Result<(), RenderingError> {
+        match self.objects.get_mut(&object_id) {
+            Some(object) => {
+                object.transform = transform;
+                return Ok(());
+            }
+            None => return Err(RenderingError::ObjectNotFound),
+        }
+    }
+
+    pub fn remove_object(&mut self, object_id: ObjectId) -> Result<(), RenderingError> {
+        match self.objects.remove(&object_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::ObjectNotFound),
+        }
+    }
+
+    pub fn set_object_material(
+        &mut self,
+        object_id: ObjectId,
+        material_id: MaterialId,
+    ) -> Result<(), RenderingError> {
+        if !self.materials.contains_key(&material_id) {
+            return Err(RenderingError::MaterialNotFound);
+        }
+        match self.objects.get_mut(&object_id) {
+            Some(object) => {
+                object.material = Some(material_id);
+                return Ok(());
+            }
+            None => return Err(RenderingError::ObjectNotFound),
+        }
+    }
+
+    pub fn create_material(&mut self, material: Material) -> MaterialId {
+        let material_id = self.material_id_counter;
+        self.material_id_counter += 1;
+        self.materials.insert(material_id, material);
+        return material_id;
+    }
+
+    pub fn update_material(
+        &mut self,
+        material_id: MaterialId,
+        material: Material,
+    ) -> Result<(), RenderingError> {
+        match self.materials.get_mut(&material_id) {
+            Some(m) => {
+                *m = material;
+                return Ok(());
+            }
+            None => return Err(RenderingError::MaterialNotFound),
+        }
+    }
+
+    pub fn create_light(&mut self, light: Light) -> LightId {
+        let light_id = self.light_id_counter;
+        self.light_id_counter += 1;
+        self.lights.insert(light_id, light);
+        return light_id;
+    }
+
+    pub fn update_light(&mut self, light_id: LightId, light: Light) -> Result<(), RenderingError> {
+        match self.lights.get_mut(&light_id) {
+            Some(l) => {
+                *l = light;
+                return Ok(());
+            }
+            None => return Err(RenderingError::LightNotFound),
+        }
+    }
+
+    pub fn remove_light(&mut self, light_id: LightId) -> Result<(), RenderingError> {
+        match self.lights.remove(&light_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::LightNotFound),
+        }
+    }
+
+    pub fn create_particle_emitter(&mut self, emitter: ParticleEmitter) -> ParticleEmitterId {
+        let emitter_id = self.particle_emitter_id_counter;
+        self.particle_emitter_id_counter += 1;
+        self.particle_emitters.insert(emitter_id, emitter);
+        return emitter_id;
+    }
+
+    pub fn update_particle_emitter(
+        &mut self,
+        emitter_id: ParticleEmitterId,
+        emitter: ParticleEmitter,
+    ) -> Result<(), RenderingError> {
+        match self.particle_emitters.get_mut(&emitter_id) {
+            Some(e) => {
+                *e = emitter;
+                return Ok(());
+            }
+            None => return Err(RenderingError::ParticleEmitterNotFound),
+        }
+    }
+
+    pub fn remove_particle_emitter(
+        &mut self,
+        emitter_id: ParticleEmitterId,
+    ) -> Result<(), RenderingError> {
+        match self.particle_emitters.remove(&emitter_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::ParticleEmitterNotFound),
+        }
+    }
+
+    pub fn add_sprite_atlas(&mut self, atlas: SpriteAtlasImage) -> SpriteAtlasId {
+        let atlas_id = self.sprite_atlas_id_counter;
+        self.sprite_atlas_id_counter += 1;
+        self.sprite_atlases.insert(atlas_id, atlas);
+        return atlas_id;
+    }
+
+    pub fn remove_sprite_atlas(&mut self, atlas_id: SpriteAtlasId) -> Result<(), RenderingError> {
+        match self.sprite_atlases.remove(&atlas_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::SpriteAtlasNotFound),
+        }
+    }
+
+    pub fn create_sprite(&mut self, sprite: Sprite) -> SpriteId {
+        let sprite_id = self.sprite_id_counter;
+        self.sprite_id_counter += 1;
+        self.sprites.insert(sprite_id, sprite);
+        return sprite_id;
+    }
+
+    pub fn update_sprite(
+        &mut self,
+        sprite_id: SpriteId,
+        sprite: Sprite,
+    ) -> Result<(), RenderingError> {
+        match self.sprites.get_mut(&sprite_id) {
+            Some(s) => {
+                *s = sprite;
+                return Ok(());
+            }
+            None => return Err(RenderingError::SpriteNotFound),
+        }
+    }
+
+    pub fn remove_sprite(&mut self, sprite_id: SpriteId) -> Result<(), RenderingError> {
+        match self.sprites.remove(&sprite_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::SpriteNotFound),
+        }
+    }
+
+    pub fn create_tile_map(&mut self, tile_map: TileMap) -> TileMapId {
+        let tile_map_id = self.tile_map_id_counter;
+        self.tile_map_id_counter += 1;
+        self.tile_maps.insert(tile_map_id, tile_map);
+        return tile_map_id;
+    }
+
+    pub fn update_tile_map(
+        &mut self,
+        tile_map_id: TileMapId,
+        tile_map: TileMap,
+    ) -> Result<(), RenderingError> {
+        match self.tile_maps.get_mut(&tile_map_id) {
+            Some(t) => {
+                *t = tile_map;
+                return Ok(());
+            }
+            None => return Err(RenderingError::TileMapNotFound),
+        }
+    }
+
+    pub fn remove_tile_map(&mut self, tile_map_id: TileMapId) -> Result<(), RenderingError> {
+        match self.tile_maps.remove(&tile_map_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::TileMapNotFound),
+        }
+    }
+
+    pub fn add_font(&mut self, font: FontImage) -> FontId {
+        let font_id = self.font_id_counter;
+        self.font_id_counter += 1;
+        self.fonts.insert(font_id, font);
+        return font_id;
+    }
+
+    pub fn remove_font(&mut self, font_id: FontId) -> Result<(), RenderingError> {
+        match self.fonts.remove(&font_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::FontNotFound),
+        }
+    }
+
+    pub fn create_text(&mut self, text: Text) -> TextId {
+        let text_id = self.text_id_counter;
+        self.text_id_counter += 1;
+        self.texts.insert(text_id, text);
+        return text_id;
+    }
+
+    pub fn update_text(&mut self, text_id: TextId, text: Text) -> Result<(), RenderingError> {
+        match self.texts.get_mut(&text_id) {
+            Some(t) => {
+                *t = text;
+                return Ok(());
+            }
+            None => return Err(RenderingError::TextNotFound),
+        }
+    }
+
+    pub fn remove_text(&mut self, text_id: TextId) -> Result<(), RenderingError> {
+        match self.texts.remove(&text_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::TextNotFound),
+        }
+    }
+
+    pub fn add_skinned_mesh(&mut self, mesh: SkinnedMesh) -> SkinnedMeshId {
+        let mesh_id = self.skinned_mesh_id_counter;
+        self.skinned_mesh_id_counter += 1;
+        self.skinned_meshes.insert(mesh_id, mesh);
+        return mesh_id;
+    }
+
+    pub fn remove_skinned_mesh(&mut self, mesh_id: SkinnedMeshId) -> Result<(), RenderingError> {
+        match self.skinned_meshes.remove(&mesh_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::SkinnedMeshNotFound),
+        }
+    }
+
+    pub fn create_skinned_object(
+        &mut self,
+        mesh_id: SkinnedMeshId,
+        transform: Similarity3,
+    ) -> Result<SkinnedObjectId, RenderingError> {
+        let joint_count = match self.skinned_meshes.get(&mesh_id) {
+            Some(mesh) => mesh.skeleton.joints.len(),
+            None => return Err(RenderingError::SkinnedMeshNotFound),
+        };
+        let object_id = self.skinned_object_id_counter;
+        self.skinned_object_id_counter += 1;
+        self.skinned_objects.insert(
+            object_id,
+            SkinnedObject::new(mesh_id, joint_count, transform),
+        );
+        return Ok(object_id);
+    }
+
+    pub fn skinned_object_mut(
+        &mut self,
+        object_id: SkinnedObjectId,
+    ) -> Result<&mut SkinnedObject, RenderingError> {
+        return self
+            .skinned_objects
+            .get_mut(&object_id)
+            .ok_or(RenderingError::SkinnedObjectNotFound);
+    }
+
+    pub fn set_skinned_pose(
+        &mut self,
+        object_id: SkinnedObjectId,
+        poses: &[JointPose],
+    ) -> Result<(), RenderingError> {
+        let object = match self.skinned_objects.get_mut(&object_id) {
+            Some(object) => object,
+            None => return Err(RenderingError::SkinnedObjectNotFound),
+        };
+        match self.skinned_meshes.get(&object.mesh) {
+            Some(mesh) => {
+                object.set_pose(&mesh.skeleton, poses);
+                return Ok(());
+            }
+            None => return Err(RenderingError::SkinnedMeshNotFound),
+        }
+    }
+
+    pub fn remove_skinned_object(
+        &mut self,
+        object_id: SkinnedObjectId,
+    ) -> Result<(), RenderingError> {
+        match self.skinned_objects.remove(&object_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::SkinnedObjectNotFound),
+        }
+    }
+
+    pub fn add_terrain(&mut self, terrain: RenderTerrain) -> TerrainId {
+        let terrain_id = self.terrain_id_counter;
+        self.terrain_id_counter += 1;
+        self.terrains.insert(terrain_id, terrain);
+        return terrain_id;
+    }
+
+    pub fn remove_terrain(&mut self, terrain_id: TerrainId) -> Result<(), RenderingError> {
+        match self.terrains.remove(&terrain_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::TerrainNotFound),
+        }
+    }
+
+    pub fn add_decal_texture(&mut self, texture: Arc<ImmutableImage<Format>>) -> DecalTextureId {
+        let texture_id = self.decal_texture_id_counter;
+        self.decal_texture_id_counter += 1;
+        self.decal_textures.insert(texture_id, texture);
+        return texture_id;
+    }
+
+    pub fn remove_decal_texture(
+        &mut self,
+        texture_id: DecalTextureId,
+    ) -> Result<(), RenderingError> {
+        match self.decal_textures.remove(&texture_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::DecalTextureNotFound),
+        }
+    }
+
+    pub fn create_decal(&mut self, decal: Decal) -> DecalId {
+        let decal_id = self.decal_id_counter;
+        self.decal_id_counter += 1;
+        self.decals.insert(decal_id, decal);
+        return decal_id;
+    }
+
+    pub fn update_decal(&mut self, decal_id: DecalId, decal: Decal) -> Result<(), RenderingError> {
+        match self.decals.get_mut(&decal_id) {
+            Some(d) => {
+                *d = decal;
+                return Ok(());
+            }
+            None => return Err(RenderingError::DecalNotFound),
+        }
+    }
+
+    pub fn remove_decal(&mut self, decal_id: DecalId) -> Result<(), RenderingError> {
+        match self.decals.remove(&decal_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::DecalNotFound),
+        }
+    }
+
+    pub fn add_environment(&mut self, environment: EnvironmentMap) -> EnvironmentId {
+        let environment_id = self.environment_id_counter;
+        self.environment_id_counter += 1;
+        self.environments.insert(environment_id, environment);
+        return environment_id;
+    }
+
+    pub fn remove_environment(
+        &mut self,
+        environment_id: EnvironmentId,
+    ) -> Result<(), RenderingError> {
+        match self.environments.remove(&environment_id) {
+            Some(_) => return Ok(()),
+            None => return Err(RenderingError::EnvironmentNotFound),
+        }
+    }
+
+    pub fn request_pick(
+        &mut self,
+        window_id: WindowId,
+        region: PickRegion,
+    ) -> Result<PickId, RenderingError> {
+        match self.windows.get_mut(&window_id) {
+            Some(window) => {
+                let pick_id = self.pick_id_counter;
+                self.pick_id_counter += 1;
+                window.pick_requests.push((pick_id, region));
+                return Ok(pick_id);
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    pub fn request_capture(
+        &mut self,
+        window_id: WindowId,
+        path: PathBuf,
+        format: CaptureFormat,
+    ) -> Result<CaptureId, RenderingError> {
+        match self.windows.get_mut(&window_id) {
+            Some(window) => {
+                let capture_id = self.capture_id_counter;
+                self.capture_id_counter += 1;
+                window.capture_requests.push(CaptureRequest {
+                    capture_id: Some(capture_id),
+                    path,
+                    format,
+                });
+                return Ok(capture_id);
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
 }
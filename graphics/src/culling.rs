@@ -0,0 +1,156 @@
+use crate::{
+    bounds::Aabb,
+    camera::Camera,
+    frustum::Frustum,
+    geometry::{Geometry, GeometryId},
+    material::MaterialId,
+    object::{ObjectId, RenderObject},
+};
+use polyengine_core::*;
+use std::collections::HashMap;
+
+// Single geometry submission that survived culling.
+#[derive(Debug, Copy, Clone)]
+pub struct DrawItem {
+    pub object_id: ObjectId,
+    pub geometry_id: GeometryId,
+    pub material_id: Option<MaterialId>,
+    pub model: Matrix4f,
+    // Model matrix used in the previous frame, equal to `model` for objects that
+    // weren't drawn then
+    pub previous_model: Matrix4f,
+    // Distance from the camera to the object bounds center
+    pub distance: FScalar,
+    // False for objects outside the camera frustum, which are only kept for
+    // culling on the GPU
+    pub in_frustum: bool,
+}
+
+// Per camera culling state, keeps the LOD levels and transforms of objects
+// drawn in the previous frame.
+#[derive(Default)]
+pub struct CullingState {
+    lod_levels: HashMap<ObjectId, usize>,
+    previous_models: HashMap<ObjectId, Matrix4f>,
+}
+
+impl CullingState {
+    pub fn new() -> Self { return CullingState::default(); }
+}
+
+fn world_bounds(object: &RenderObject, geometries: &HashMap<GeometryId, Geometry>) -> Option<Aabb> {
+    let local = object
+        .lod_group
+        .levels
+        .iter()
+        .filter_map(|level| geometries.get(&level.geometry))
+        .fold(Aabb::empty(), |acc, geometry| acc.merged(&geometry.bounds));
+
+    if local.is_empty() {
+        return None;
+    }
+    return Some(local.transformed(&object.transform));
+}
+
+// Returns objects visible from the camera with their LOD levels resolved.
+// With `keep_hidden` objects outside the frustum are returned too, for GPU
+// driven rendering culling the opaque ones itself.
+pub fn cull_objects(
+    objects: &HashMap<ObjectId, RenderObject>,
+    geometries: &HashMap<GeometryId, Geometry>,
+    camera: &Camera,
+    aspect: FScalar,
+    keep_hidden: bool,
+    state: &mut CullingState,
+) -> Vec<DrawItem> {
+    let frustum = camera.frustum(aspect);
+    let camera_position = camera.position();
+    let mut visible = Vec::new();
+
+    state.lod_levels.retain(|id, _| objects.contains_key(id));
+    let previous_models = std::mem::replace(&mut state.previous_models, HashMap::new());
+
+    for (&object_id, object) in objects {
+        let bounds = match world_bounds(object, geometries) {
+            Some(bounds) => bounds,
+            None => continue,
+        };
+
+        let in_frustum = frustum.intersects_sphere(&bounds.bounding_sphere())
+            && frustum.intersects_aabb(&bounds);
+        if !in_frustum && !keep_hidden {
+            continue;
+        }
+
+        let distance = (bounds.center() - camera_position).norm();
+        let previous = state.lod_levels.get(&object_id).cloned();
+        let level = match object.lod_group.select(distance, previous) {
+            Some(level) => level,
+            None => {
+                state.lod_levels.remove(&object_id);
+                continue;
+            }
+        };
+        state.lod_levels.insert(object_id, level);
+
+        let model = object.transform.to_homogeneous();
+        let previous_model = previous_models.get(&object_id).cloned().unwrap_or(model);
+        state.previous_models.insert(object_id, model);
+
+        visible.push(DrawItem {
+            object_id,
+            geometry_id: object.lod_group.geometry(level),
+            material_id: object.material,
+            model,
+            previous_model,
+            distance,
+            in_frustum,
+        });
+    }
+
+    return visible;
+}
+
+// Returns objects intersecting a shadow view frustum. LOD levels are chosen by
+// distance to `lod_origin` (usually the main camera) without hysteresis so
+// casters match the geometry seen on screen closely enough.
+pub fn cull_shadow_casters(
+    objects: &HashMap<ObjectId, RenderObject>,
+    geometries: &HashMap<GeometryId, Geometry>,
+    frustum: &Frustum,
+    lod_origin: &Vector3f,
+) -> Vec<DrawItem> {
+    let mut casters = Vec::new();
+
+    for (&object_id, object) in objects {
+        let bounds = match world_bounds(object, geometries) {
+            Some(bounds) => bounds,
+            None => continue,
+        };
+
+        if !frustum.intersects_sphere(&bounds.bounding_sphere())
+            || !frustum.intersects_aabb(&bounds)
+        {
+            continue;
+        }
+
+        let distance = (bounds.center() - lod_origin).norm();
+        let level = match object.lod_group.select(distance, None) {
+            Some(level) => level,
+            None => continue,
+        };
+
+        let model = object.transform.to_homogeneous();
+        casters.push(DrawItem {
+            object_id,
+            geometry_id: object.lod_group.geometry(level),
+            material_id: object.material,
+            model,
+            previous_model: model,
+            distance,
+            in_frustum: true,
+        });
+    }
+
+    return casters;
+}
@@ -0,0 +1,562 @@
+use crate::{bounds::Aabb, camera::Camera, debug_font, vertex::DebugVertex};
+use polyengine_core::*;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use vulkano::{
+    buffer::{BufferAccess, BufferSlice, BufferUsage, CpuBufferPool},
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+};
+
+pub mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+            #version 450
+            layout(location = 0) in vec3 position;
+            layout(location = 1) in vec4 color;
+            layout(location = 0) out vec4 v_color;
+            layout(push_constant) uniform PushConstants {
+                mat4 view_projection;
+            } pc;
+            void main() {
+                v_color = color;
+                gl_Position = pc.view_projection * vec4(position, 1.0);
+            }
+        "
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+            layout(location = 0) in vec4 v_color;
+            layout(location = 0) out vec4 f_color;
+            void main() {
+                f_color = v_color;
+            }
+        "
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugDepthMode {
+    // Hidden behind scene geometry
+    DepthTested,
+    // Drawn on top of everything
+    Overlay,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DebugDrawOptions {
+    pub color: Vector4f,
+    // Zero duration draws the primitive for the current frame only.
+    pub duration: Duration,
+    pub depth_mode: DebugDepthMode,
+}
+
+impl Default for DebugDrawOptions {
+    fn default() -> Self {
+        return DebugDrawOptions {
+            color: Vector4f::new(1.0, 1.0, 1.0, 1.0),
+            duration: Duration::from_secs(0),
+            depth_mode: DebugDepthMode::DepthTested,
+        };
+    }
+}
+
+impl DebugDrawOptions {
+    pub fn color(color: Vector4f) -> Self {
+        return DebugDrawOptions {
+            color,
+            ..DebugDrawOptions::default()
+        };
+    }
+}
+
+struct DebugLine {
+    from: Vector3f,
+    to: Vector3f,
+    color: Vector4f,
+    depth_mode: DebugDepthMode,
+    expires_at: Option<Instant>,
+}
+
+struct DebugText {
+    position: Vector3f,
+    text: String,
+    height: FScalar,
+    color: Vector4f,
+    depth_mode: DebugDepthMode,
+    expires_at: Option<Instant>,
+}
+
+// Immediate mode debug primitives. Everything is decomposed into lines that
+// are submitted once per frame per window.
+#[derive(Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+    texts: Vec<DebugText>,
+    // Clock of the last `end_frame`, recorded sequences don't follow the wall
+    // clock
+    now: Option<Instant>,
+}
+
+fn orthonormal_basis(n: &Vector3f) -> (Vector3f, Vector3f) {
+    let helper = if n.x.abs() < 0.9 {
+        Vector3f::x()
+    } else {
+        Vector3f::y()
+    };
+    let u = n.cross(&helper).normalize();
+    let v = n.cross(&u);
+    return (u, v);
+}
+
+impl DebugDraw {
+    pub fn new() -> Self { return DebugDraw::default(); }
+
+    fn expiration(&self, duration: Duration) -> Option<Instant> {
+        if duration == Duration::from_secs(0) {
+            return None;
+        }
+        return Some(self.now.unwrap_or_else(Instant::now) + duration);
+    }
+
+    pub fn line(&mut self, from: Vector3f, to: Vector3f, options: &DebugDrawOptions) {
+        self.lines.push(DebugLine {
+            from,
+            to,
+            color: options.color,
+            depth_mode: options.depth_mode,
+            expires_at: self.expiration(options.duration),
+        });
+    }
+
+    // Connects consecutive points with lines.
+    pub fn path(&mut self, points: &[Vector3f], options: &DebugDrawOptions) {
+        for w in points.windows(2) {
+            self.line(w[0], w[1], options);
+        }
+    }
+
+    pub fn arrow(&mut self, from: Vector3f, to: Vector3f, options: &DebugDrawOptions) {
+        self.line(from, to, options);
+
+        let dir = to - from;
+        let length = dir.norm();
+        if length <= FScalar::EPSILON {
+            return;
+        }
+        let dir = dir / length;
+        let head_length = length * 0.2;
+        let head_width = head_length * 0.4;
+        let (u, v) = orthonormal_basis(&dir);
+        let base = to - dir * head_length;
+        for side in &[u, -u, v, -v] {
+            self.line(to, base + side * head_width, options);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, options: &DebugDrawOptions) {
+        let c = aabb.corners();
+        self.box_edges(&c, options);
+    }
+
+    pub fn sphere(&mut self, center: Vector3f, radius: FScalar, options: &DebugDrawOptions) {
+        self.circle(center, Vector3f::x(), radius, options);
+        self.circle(center, Vector3f::y(), radius, options);
+        self.circle(center, Vector3f::z(), radius, options);
+    }
+
+    pub fn circle(
+        &mut self,
+        center: Vector3f,
+        normal: Vector3f,
+        radius: FScalar,
+        options: &DebugDrawOptions,
+    ) {
+        const SEGMENTS: usize = 32;
+        let length = normal.norm();
+        if length <= FScalar::EPSILON {
+            return;
+        }
+        let (u, v) = orthonormal_basis(&(normal / length));
+        let point = |i: usize| {
+            let angle = i as FScalar / SEGMENTS as FScalar * 2.0 * std::f32::consts::PI;
+            return center + (u * angle.cos() + v * angle.sin()) * radius;
+        };
+        for i in 0..SEGMENTS {
+            self.line(point(i), point(i + 1), options);
+        }
+    }
+
+    // Draws the volume visible through given view-projection matrix.
+    pub fn frustum(&mut self, view_projection: &Matrix4f, options: &DebugDrawOptions) {
+        let inverse = match view_projection.try_inverse() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        let ndc = Aabb::new(Vector3f::new(-1.0, -1.0, 0.0), Vector3f::new(1.0, 1.0, 1.0));
+        let mut corners = ndc.corners();
+        for corner in corners.iter_mut() {
+            let world = inverse * Vector4f::new(corner.x, corner.y, corner.z, 1.0);
+            *corner = world.xyz() / world.w;
+        }
+        self.box_edges(&corners, options);
+    }
+
+    pub fn camera(&mut self, camera: &Camera, aspect: FScalar, options: &DebugDrawOptions) {
+        self.frustum(&camera.view_projection_matrix(aspect), options);
+    }
+
+    // Square grid lying in the local XZ plane of the transform.
+    pub fn grid(
+        &mut self,
+        transform: &Isometry3,
+        cells: u32,
+        cell_size: FScalar,
+        options: &DebugDrawOptions,
+    ) {
+        let half = cells as FScalar * cell_size * 0.5;
+        let point = |x: FScalar, z: FScalar| (transform * na::Point3::new(x, 0.0, z)).coords;
+        for i in 0..=cells {
+            let offset = i as FScalar * cell_size - half;
+            self.line(point(offset, -half), point(offset, half), options);
+            self.line(point(-half, offset), point(half, offset), options);
+        }
+    }
+
+    // Coordinate frame with X, Y and Z axes drawn in red, green and blue. Color
+    // from options is ignored.
+    pub fn axes(&mut self, transform: &Isometry3, size: FScalar, options: &DebugDrawOptions) {
+        let origin = transform.translation.vector;
+        let axes = [
+            (Vector3f::x(), Vector4f::new(1.0, 0.0, 0.0, 1.0)),
+            (Vector3f::y(), Vector4f::new(0.0, 1.0, 0.0, 1.0)),
+            (Vector3f::z(), Vector4f::new(0.0, 0.0, 1.0, 1.0)),
+        ];
+        for (axis, color) in axes.iter() {
+            let axis_options = DebugDrawOptions {
+                color: *color,
+                ..*options
+            };
+            self.arrow(
+                origin,
+                origin + transform.rotation * axis * size,
+                &axis_options,
+            );
+        }
+    }
+
+    // Camera facing text label, `height` is the glyph height in world units.
+    pub fn text(
+        &mut self,
+        position: Vector3f,
+        text: &str,
+        height: FScalar,
+        options: &DebugDrawOptions,
+    ) {
+        self.texts.push(DebugText {
+            position,
+            text: text.to_owned(),
+            height,
+            color: options.color,
+            depth_mode: options.depth_mode,
+            expires_at: self.expiration(options.duration),
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.texts.clear();
+    }
+
+    // Removes primitives that should not be drawn in the next frame.
+    pub fn end_frame(&mut self, now: Instant) {
+        let alive = |expires_at: &Option<Instant>| match expires_at {
+            Some(t) => *t > now,
+            None => false,
+        };
+        self.lines.retain(|l| alive(&l.expires_at));
+        self.texts.retain(|t| alive(&t.expires_at));
+        self.now = Some(now);
+    }
+
+    fn box_edges(&mut self, c: &[Vector3f; 8], options: &DebugDrawOptions) {
+        const EDGES: [(usize, usize); 12] = [
+            (0, 1),
+            (2, 3),
+            (4, 5),
+            (6, 7),
+            (0, 2),
+            (1, 3),
+            (4, 6),
+            (5, 7),
+            (0, 4),
+            (1, 5),
+            (2, 6),
+            (3, 7),
+        ];
+        for (a, b) in EDGES.iter() {
+            self.line(c[*a], c[*b], options);
+        }
+    }
+
+    // Builds line list vertices for given camera. Depth tested lines are placed
+    // first, returns their vertex count.
+    pub fn build_vertices(&self, camera: &Camera) -> (Vec<DebugVertex>, usize) {
+        let right = camera.transform.rotation * Vector3f::x();
+        let up = camera.transform.rotation * Vector3f::y();
+        let mut vertices = Vec::with_capacity(self.lines.len() * 2);
+        let mut vertices_split = 0;
+
+        for mode in &[DebugDepthMode::DepthTested, DebugDepthMode::Overlay] {
+            let mut push = |p: &Vector3f, color: &Vector4f| {
+                vertices.push(DebugVertex {
+                    position: [p.x, p.y, p.z],
+                    color: [color.x, color.y, color.z, color.w],
+                });
+            };
+
+            for line in self.lines.iter().filter(|l| l.depth_mode == *mode) {
+                push(&line.from, &line.color);
+                push(&line.to, &line.color);
+            }
+
+            for text in self.texts.iter().filter(|t| t.depth_mode == *mode) {
+                let to_world =
+                    |(x, y): (f32, f32)| text.position + (right * x + up * y) * text.height;
+                debug_font::for_each_stroke(&text.text, |a, b| {
+                    push(&to_world(a), &text.color);
+                    push(&to_world(b), &text.color);
+                });
+            }
+
+            if *mode == DebugDepthMode::DepthTested {
+                vertices_split = vertices.len();
+            }
+        }
+
+        return (vertices, vertices_split);
+    }
+}
+
+// Vertices of a single frame, already uploaded.
+pub struct DebugBatch {
+    pub depth_tested: Option<Arc<dyn BufferAccess + Send + Sync>>,
+    pub overlay: Option<Arc<dyn BufferAccess + Send + Sync>>,
+}
+
+pub struct DebugRenderer {
+    pub depth_tested_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub overlay_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    vertex_pool: CpuBufferPool<DebugVertex>,
+}
+
+impl DebugRenderer {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+
+        let depth_tested_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<DebugVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .line_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                // Test against the scene but don't occlude other debug lines.
+                .depth_stencil(DepthStencil {
+                    depth_write: false,
+                    depth_compare: Compare::LessOrEqual,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .blend_alpha_blending()
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        let overlay_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<DebugVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .line_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil_disabled()
+                .blend_alpha_blending()
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        return DebugRenderer {
+            depth_tested_pipeline,
+            overlay_pipeline,
+            vertex_pool: CpuBufferPool::new(device, BufferUsage::vertex_buffer()),
+        };
+    }
+
+    // Uploads all primitives visible from the camera into one buffer.
+    pub fn prepare(&self, debug_draw: &DebugDraw, camera: &Camera) -> DebugBatch {
+        let (vertices, split) = debug_draw.build_vertices(camera);
+        let total = vertices.len();
+        if total == 0 {
+            return DebugBatch {
+                depth_tested: None,
+                overlay: None,
+            };
+        }
+
+        let chunk = Arc::new(self.vertex_pool.chunk(vertices).unwrap());
+        let slice = |range: std::ops::Range<usize>| {
+            if range.start == range.end {
+                return None;
+            }
+            let slice = BufferSlice::from_typed_buffer_access(chunk.clone())
+                .slice(range)
+                .unwrap();
+            return Some(Arc::new(slice) as Arc<dyn BufferAccess + Send + Sync>);
+        };
+
+        return DebugBatch {
+            depth_tested: slice(0..split),
+            overlay: slice(split..total),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugDraw, DebugDrawOptions};
+    use crate::bounds::Aabb;
+    use polyengine_core::*;
+    use std::time::{Duration, Instant};
+
+    fn options(duration: Duration) -> DebugDrawOptions {
+        return DebugDrawOptions {
+            duration,
+            ..DebugDrawOptions::default()
+        };
+    }
+
+    #[test]
+    fn circle_lies_on_radius() {
+        let mut debug_draw = DebugDraw::new();
+        let center = Vector3f::new(1.0, 2.0, 3.0);
+        let normal = Vector3f::new(0.0, 0.0, 2.0);
+        debug_draw.circle(center, normal, 0.5, &DebugDrawOptions::default());
+
+        assert_eq!(debug_draw.lines.len(), 32);
+        for line in &debug_draw.lines {
+            assert!(((line.from - center).norm() - 0.5).abs() < 1e-5);
+            assert!((line.from - center).dot(&normal).abs() < 1e-5);
+        }
+        assert!((debug_draw.lines[31].to - debug_draw.lines[0].from).norm() < 1e-5);
+    }
+
+    #[test]
+    fn circle_skips_zero_normal() {
+        let mut debug_draw = DebugDraw::new();
+        let options = DebugDrawOptions::default();
+        debug_draw.circle(Vector3f::zeros(), Vector3f::zeros(), 1.0, &options);
+        debug_draw.circle(
+            Vector3f::zeros(),
+            Vector3f::new(0.0, 1e-9, 0.0),
+            1.0,
+            &options,
+        );
+        assert!(debug_draw.lines.is_empty());
+    }
+
+    #[test]
+    fn primitive_line_counts() {
+        let mut debug_draw = DebugDraw::new();
+        let options = DebugDrawOptions::default();
+        debug_draw.sphere(Vector3f::zeros(), 1.0, &options);
+        assert_eq!(debug_draw.lines.len(), 96);
+
+        debug_draw.clear();
+        debug_draw.arrow(Vector3f::zeros(), Vector3f::x(), &options);
+        assert_eq!(debug_draw.lines.len(), 5);
+
+        // Zero length arrows have no head
+        debug_draw.clear();
+        debug_draw.arrow(Vector3f::x(), Vector3f::x(), &options);
+        assert_eq!(debug_draw.lines.len(), 1);
+
+        debug_draw.clear();
+        debug_draw.aabb(
+            &Aabb::new(Vector3f::zeros(), Vector3f::repeat(1.0)),
+            &options,
+        );
+        assert_eq!(debug_draw.lines.len(), 12);
+
+        debug_draw.clear();
+        debug_draw.path(&[Vector3f::zeros(), Vector3f::x(), Vector3f::y()], &options);
+        debug_draw.grid(&Isometry3::identity(), 4, 1.0, &options);
+        assert_eq!(debug_draw.lines.len(), 2 + 10);
+    }
+
+    #[test]
+    fn primitives_expire() {
+        let mut debug_draw = DebugDraw::new();
+        debug_draw.line(
+            Vector3f::zeros(),
+            Vector3f::x(),
+            &options(Duration::from_secs(0)),
+        );
+        debug_draw.line(
+            Vector3f::zeros(),
+            Vector3f::y(),
+            &options(Duration::from_secs(60)),
+        );
+        debug_draw.text(
+            Vector3f::zeros(),
+            "a",
+            1.0,
+            &options(Duration::from_secs(60)),
+        );
+
+        let now = Instant::now();
+        debug_draw.end_frame(now);
+        assert_eq!(debug_draw.lines.len(), 1);
+        assert_eq!(debug_draw.lines[0].to, Vector3f::y());
+        assert_eq!(debug_draw.texts.len(), 1);
+
+        debug_draw.end_frame(now + Duration::from_secs(120));
+        assert!(debug_draw.lines.is_empty());
+        assert!(debug_draw.texts.is_empty());
+    }
+
+    #[test]
+    fn expiry_follows_frame_clock() {
+        // Far away from the wall clock, like a recorded sequence
+        let start = Instant::now() + Duration::from_secs(3600);
+        let timestep = Duration::from_millis(40);
+        let mut debug_draw = DebugDraw::new();
+        debug_draw.end_frame(start);
+        debug_draw.line(Vector3f::zeros(), Vector3f::x(), &options(timestep * 3));
+
+        for frame in 1..3 {
+            debug_draw.end_frame(start + timestep * frame);
+            assert_eq!(debug_draw.lines.len(), 1);
+        }
+        debug_draw.end_frame(start + timestep * 3);
+        assert!(debug_draw.lines.is_empty());
+    }
+}
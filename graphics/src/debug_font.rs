@@ -0,0 +1,126 @@
+// Minimal 16-segment stroke font used for debug text labels. Coordinates are in
+// the <0, 1> glyph box with Y pointing up.
+const SEGMENTS: [[(f32, f32); 2]; 16] = [
+    [(0.0, 1.0), (0.5, 1.0)], // 0: top left
+    [(0.5, 1.0), (1.0, 1.0)], // 1: top right
+    [(1.0, 1.0), (1.0, 0.5)], // 2: right upper
+    [(1.0, 0.5), (1.0, 0.0)], // 3: right lower
+    [(1.0, 0.0), (0.5, 0.0)], // 4: bottom right
+    [(0.5, 0.0), (0.0, 0.0)], // 5: bottom left
+    [(0.0, 0.0), (0.0, 0.5)], // 6: left lower
+    [(0.0, 0.5), (0.0, 1.0)], // 7: left upper
+    [(0.0, 1.0), (0.5, 0.5)], // 8: diagonal top left
+    [(0.5, 1.0), (0.5, 0.5)], // 9: center upper
+    [(1.0, 1.0), (0.5, 0.5)], // 10: diagonal top right
+    [(0.5, 0.5), (1.0, 0.5)], // 11: middle right
+    [(1.0, 0.0), (0.5, 0.5)], // 12: diagonal bottom right
+    [(0.5, 0.0), (0.5, 0.5)], // 13: center lower
+    [(0.0, 0.0), (0.5, 0.5)], // 14: diagonal bottom left
+    [(0.0, 0.5), (0.5, 0.5)], // 15: middle left
+];
+
+fn mask(segments: &[u16]) -> u16 { return segments.iter().fold(0, |acc, s| acc | 1 << s); }
+
+fn glyph_mask(c: char) -> u16 {
+    return match c.to_ascii_uppercase() {
+        '0' => mask(&[0, 1, 2, 3, 4, 5, 6, 7, 10, 14]),
+        '1' => mask(&[2, 3, 10]),
+        '2' => mask(&[0, 1, 2, 11, 15, 6, 5, 4]),
+        '3' => mask(&[0, 1, 2, 3, 4, 5, 11]),
+        '4' => mask(&[7, 15, 11, 2, 3]),
+        '5' => mask(&[0, 1, 7, 15, 11, 3, 4, 5]),
+        '6' => mask(&[0, 1, 7, 6, 5, 4, 3, 11, 15]),
+        '7' => mask(&[0, 1, 2, 3]),
+        '8' => mask(&[0, 1, 2, 3, 4, 5, 6, 7, 11, 15]),
+        '9' => mask(&[0, 1, 2, 3, 4, 5, 7, 11, 15]),
+        'A' => mask(&[0, 1, 2, 3, 6, 7, 11, 15]),
+        'B' => mask(&[0, 1, 2, 3, 4, 5, 9, 11, 13]),
+        'C' => mask(&[0, 1, 4, 5, 6, 7]),
+        'D' => mask(&[0, 1, 2, 3, 4, 5, 9, 13]),
+        'E' => mask(&[0, 1, 4, 5, 6, 7, 15]),
+        'F' => mask(&[0, 1, 6, 7, 15]),
+        'G' => mask(&[0, 1, 3, 4, 5, 6, 7, 11]),
+        'H' => mask(&[2, 3, 6, 7, 11, 15]),
+        'I' => mask(&[0, 1, 4, 5, 9, 13]),
+        'J' => mask(&[2, 3, 4, 5, 6]),
+        'K' => mask(&[6, 7, 15, 10, 12]),
+        'L' => mask(&[4, 5, 6, 7]),
+        'M' => mask(&[2, 3, 6, 7, 8, 10]),
+        'N' => mask(&[2, 3, 6, 7, 8, 12]),
+        'O' => mask(&[0, 1, 2, 3, 4, 5, 6, 7]),
+        'P' => mask(&[0, 1, 2, 6, 7, 11, 15]),
+        'Q' => mask(&[0, 1, 2, 3, 4, 5, 6, 7, 12]),
+        'R' => mask(&[0, 1, 2, 6, 7, 11, 15, 12]),
+        'S' => mask(&[0, 1, 7, 15, 11, 3, 4, 5]),
+        'T' => mask(&[0, 1, 9, 13]),
+        'U' => mask(&[2, 3, 4, 5, 6, 7]),
+        'V' => mask(&[6, 7, 14, 10]),
+        'W' => mask(&[2, 3, 6, 7, 12, 14]),
+        'X' => mask(&[8, 10, 12, 14]),
+        'Y' => mask(&[8, 10, 13]),
+        'Z' => mask(&[0, 1, 4, 5, 10, 14]),
+        '-' => mask(&[11, 15]),
+        '+' => mask(&[9, 11, 13, 15]),
+        '=' => mask(&[4, 5, 11, 15]),
+        '_' => mask(&[4, 5]),
+        '.' | ',' => mask(&[5]),
+        ':' | '|' => mask(&[9, 13]),
+        '/' => mask(&[10, 14]),
+        '\\' => mask(&[8, 12]),
+        '(' | '<' | '[' => mask(&[10, 12]),
+        ')' | '>' | ']' => mask(&[8, 14]),
+        '*' => mask(&[8, 9, 10, 11, 12, 13, 14, 15]),
+        _ => 0,
+    };
+}
+
+// Width of a glyph relative to its height.
+pub const GLYPH_ASPECT: f32 = 0.6;
+// Horizontal distance between glyph origins relative to glyph width.
+pub const GLYPH_ADVANCE: f32 = 1.5;
+
+// Calls `emit` with every stroke of the text, in glyph box units (a single
+// glyph spans <0, 1> on both axes before applying the aspect ratio). Newlines
+// move the pen one line down.
+pub fn for_each_stroke<F: FnMut((f32, f32), (f32, f32))>(text: &str, mut emit: F) {
+    let mut pen_x = 0.0;
+    let mut pen_y = 0.0;
+    for c in text.chars() {
+        if c == '\n' {
+            pen_x = 0.0;
+            pen_y -= 1.5;
+            continue;
+        }
+
+        let glyph = glyph_mask(c);
+        for (i, segment) in SEGMENTS.iter().enumerate() {
+            if glyph & (1 << i) != 0 {
+                let [(x0, y0), (x1, y1)] = *segment;
+                emit(
+                    ((pen_x + x0) * GLYPH_ASPECT, pen_y + y0),
+                    ((pen_x + x1) * GLYPH_ASPECT, pen_y + y1),
+                );
+            }
+        }
+        pen_x += GLYPH_ADVANCE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{for_each_stroke, GLYPH_ADVANCE, GLYPH_ASPECT};
+
+    #[test]
+    fn stroke_count_test() {
+        let mut count = 0;
+        for_each_stroke("H1 ?", |_, _| count += 1);
+        assert_eq!(count, 6 + 3);
+    }
+
+    #[test]
+    fn advance_test() {
+        let mut max_x: f32 = 0.0;
+        for_each_stroke("-- ", |a, b| max_x = max_x.max(a.0).max(b.0));
+        assert_eq!(max_x, (GLYPH_ADVANCE + 1.0) * GLYPH_ASPECT);
+    }
+}
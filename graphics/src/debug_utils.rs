@@ -0,0 +1,138 @@
+use polyengine_core::*;
+use std::{ffi::CString, sync::Arc};
+use vulkano::{
+    image::{sys::UnsafeImage, ImageAccess},
+    instance::{
+        debug::{DebugCallback, Message, MessageSeverity, MessageType},
+        layers_list,
+        Instance,
+        InstanceExtensions,
+    },
+    VulkanHandle,
+    VulkanObject,
+};
+
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+// Creates the instance with the extensions needed by the windows. In debug mode
+// the validation layer and the debug utils extension are enabled when
+// available, the returned callback logs their messages until dropped.
+pub fn create_instance(debug: bool) -> (Arc<Instance>, Option<DebugCallback>) {
+    let mut extensions = vulkano_win::required_extensions();
+    let mut layers = Vec::new();
+    if debug {
+        match InstanceExtensions::supported_by_core() {
+            Ok(supported) if supported.ext_debug_utils => extensions.ext_debug_utils = true,
+            _ => log::warn!("VK_EXT_debug_utils isn't supported, debug messages are disabled"),
+        }
+        let available = layers_list()
+            .map(|mut layers| layers.any(|layer| layer.name() == VALIDATION_LAYER))
+            .unwrap_or(false);
+        if available {
+            layers.push(VALIDATION_LAYER);
+        } else {
+            log::warn!(
+                "{} isn't installed, validation is disabled",
+                VALIDATION_LAYER
+            );
+        }
+    }
+
+    let instance = Instance::new(None, &extensions, layers).expect("failed to create instance");
+    if !extensions.ext_debug_utils {
+        return (instance, None);
+    }
+    let callback = DebugCallback::new(
+        &instance,
+        MessageSeverity {
+            error: true,
+            warning: true,
+            information: true,
+            verbose: true,
+        },
+        MessageType::all(),
+        log_message,
+    )
+    .map_err(|e| log::warn!("Failed to create the debug messenger: {:?}", e))
+    .ok();
+    return (instance, callback);
+}
+
+fn log_message(message: &Message) {
+    let kind = if message.ty.validation {
+        "validation"
+    } else if message.ty.performance {
+        "performance"
+    } else {
+        "general"
+    };
+    log::log!(
+        message_level(&message.severity),
+        "Vulkan {}: {}",
+        kind,
+        message.description
+    );
+}
+
+fn message_level(severity: &MessageSeverity) -> log::Level {
+    if severity.error {
+        return log::Level::Error;
+    } else if severity.warning {
+        return log::Level::Warn;
+    } else if severity.information {
+        return log::Level::Info;
+    }
+    return log::Level::Trace;
+}
+
+// Names the image in validation messages and graphics debuggers like
+// RenderDoc. Does nothing outside of debug mode.
+pub fn set_image_name<I>(image: &I, name: &str)
+where
+    I: ImageAccess,
+{
+    // Images aren't `DeviceOwned`, the raw image knows its device.
+    let image = image.inner().image;
+    let device = image.device();
+    if !device.instance().loaded_extensions().ext_debug_utils {
+        return;
+    }
+    let name = CString::new(name).unwrap();
+    let result = unsafe {
+        device.set_object_name_raw(UnsafeImage::TYPE, image.internal_object().value(), &name)
+    };
+    if let Err(e) = result {
+        log::warn!("Failed to name {:?}: {:?}", name, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_level_test() {
+        let severity = |error, warning, information| MessageSeverity {
+            error,
+            warning,
+            information,
+            verbose: true,
+        };
+        assert_eq!(
+            message_level(&severity(true, false, false)),
+            log::Level::Error
+        );
+        assert_eq!(
+            message_level(&severity(false, true, false)),
+            log::Level::Warn
+        );
+        assert_eq!(
+            message_level(&severity(false, false, true)),
+            log::Level::Info
+        );
+        assert_eq!(
+            message_level(&severity(false, false, false)),
+            log::Level::Trace
+        );
+    }
+}
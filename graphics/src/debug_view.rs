@@ -0,0 +1,30 @@
+// Diagnostic render modes of a window, drawn over the final image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugView {
+    None,
+    // Triangle edges over the shaded scene, needs the `fill_mode_non_solid`
+    // device feature
+    Wireframe,
+    // World space vertex normals as colours
+    Normals,
+    // Checker pattern of the texture coordinates
+    UvChecker,
+    // Brighter where more surfaces are drawn over each other
+    Overdraw,
+    // Logarithmic distance from the camera, white is near
+    Depth,
+}
+
+impl DebugView {
+    // Value of the view in debug_view.frag.
+    pub fn shader_index(&self) -> u32 {
+        match self {
+            DebugView::None => return 0,
+            DebugView::Wireframe => return 1,
+            DebugView::Normals => return 2,
+            DebugView::UvChecker => return 3,
+            DebugView::Overdraw => return 4,
+            DebugView::Depth => return 5,
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{
+    camera::Camera,
+    common::BufferlessPipeline,
+    culling::DrawItem,
+    debug_view::DebugView,
+    geometry::{Geometry, GeometryId},
+    vertex::Vertex,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::DescriptorSetsCollection,
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    pipeline::{
+        blend::{AttachmentBlend, BlendFactor, BlendOp},
+        depth_stencil::{Compare, DepthStencil},
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/debug_view.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/debug_view.frag"
+    }
+}
+
+mod fullscreen_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/fullscreen.vert"
+    }
+}
+
+mod solid_color_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/solid_color.frag"
+    }
+}
+
+// Pulls wireframe lines in front of the surfaces they outline.
+const WIREFRAME_DEPTH_OFFSET: f32 = 1e-5;
+
+pub struct DebugViewRenderer {
+    // Replaces visible surfaces, tested against the scene depth. Its layout is
+    // used for the draw set of every view.
+    pub surface_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // Adds up every fragment without depth testing
+    overdraw_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // Clears the image before the overdraw view
+    background_pipeline: Arc<BufferlessPipeline>,
+    // None when the device can't rasterize lines from triangles
+    wireframe_pipeline: Option<Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+}
+
+impl DebugViewRenderer {
+    // `window_render_pass` is the pass the views are drawn in, after the composite.
+    pub fn new(
+        device: Arc<Device>,
+        window_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+        let fullscreen_vs = fullscreen_vs::Shader::load(device.clone()).unwrap();
+        let solid_color_fs = solid_color_fs::Shader::load(device.clone()).unwrap();
+
+        let depth_tested = DepthStencil {
+            depth_write: false,
+            depth_compare: Compare::LessOrEqual,
+            ..DepthStencil::simple_depth_test()
+        };
+        let surface_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil(depth_tested.clone())
+                .render_pass(Subpass::from(window_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let overdraw_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil_disabled()
+                .blend_collective(AttachmentBlend {
+                    enabled: true,
+                    color_op: BlendOp::Add,
+                    color_source: BlendFactor::One,
+                    color_destination: BlendFactor::One,
+                    alpha_op: BlendOp::Add,
+                    alpha_source: BlendFactor::One,
+                    alpha_destination: BlendFactor::One,
+                    mask_red: true,
+                    mask_green: true,
+                    mask_blue: true,
+                    mask_alpha: true,
+                })
+                .render_pass(Subpass::from(window_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let background_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(fullscreen_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(solid_color_fs.main_entry_point(), ())
+                .depth_stencil_disabled()
+                .render_pass(Subpass::from(window_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        let wireframe_pipeline = if device.enabled_features().fill_mode_non_solid {
+            Some(Arc::new(
+                GraphicsPipeline::start()
+                    .vertex_input_single_buffer::<Vertex>()
+                    .vertex_shader(vs.main_entry_point(), ())
+                    .triangle_list()
+                    .polygon_mode_line()
+                    .viewports_dynamic_scissors_irrelevant(1)
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .depth_stencil(depth_tested)
+                    .render_pass(Subpass::from(window_render_pass, 0).unwrap())
+                    .build(device)
+                    .unwrap(),
+            )
+                as Arc<dyn GraphicsPipelineAbstract + Send + Sync>)
+        } else {
+            log::warn!("fill_mode_non_solid isn't supported, the wireframe view is disabled");
+            None
+        };
+
+        return DebugViewRenderer {
+            surface_pipeline,
+            overdraw_pipeline,
+            background_pipeline,
+            wireframe_pipeline,
+        };
+    }
+
+    // Draws the view over the composited image and returns the number of draws,
+    // has to be recorded inside of the window render pass. `set` contains the
+    // frame data and draw data.
+    pub fn record<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        view: DebugView,
+        set: S,
+        draws: &[DrawItem],
+        geometries: &HashMap<GeometryId, Geometry>,
+        camera: &Camera,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSetsCollection + Clone,
+    {
+        let mut draw_calls = draws.iter().filter(|item| item.in_frustum).count() as u32;
+        let pipeline = match view {
+            DebugView::None => return (builder, 0),
+            DebugView::Wireframe => match &self.wireframe_pipeline {
+                Some(pipeline) => pipeline,
+                None => return (builder, 0),
+            },
+            DebugView::Overdraw => {
+                builder = builder
+                    .draw(
+                        self.background_pipeline.clone(),
+                        dynamic_state,
+                        BufferlessVertices {
+                            vertices: 3,
+                            instances: 1,
+                        },
+                        (),
+                        solid_color_fs::ty::PushConstants {
+                            color: [0.0, 0.0, 0.0, 1.0],
+                        },
+                    )
+                    .unwrap();
+                draw_calls += 1;
+                &self.overdraw_pipeline
+            }
+            DebugView::Normals | DebugView::UvChecker | DebugView::Depth => &self.surface_pipeline,
+        };
+        let depth_offset = if view == DebugView::Wireframe {
+            WIREFRAME_DEPTH_OFFSET
+        } else {
+            0.0
+        };
+
+        for (index, item) in draws.iter().enumerate() {
+            if !item.in_frustum {
+                continue;
+            }
+            let geometry = &geometries[&item.geometry_id];
+            let push_constants = vs::ty::PushConstants {
+                draw: [index as u32, view.shader_index(), 0, 0],
+                params: [depth_offset, 0.0, camera.z_near, camera.z_far],
+            };
+            builder = builder
+                .draw(
+                    pipeline.clone(),
+                    dynamic_state,
+                    geometry.vertex_buffer.clone(),
+                    set.clone(),
+                    push_constants,
+                )
+                .unwrap();
+        }
+        return (builder, draw_calls);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{bounds::Aabb, frustum::Frustum, material::MaterialId};
+use polyengine_core::*;
+use std::collections::HashMap;
+
+pub type DecalId = u32;
+pub type DecalTextureId = u32;
+
+// Decals drawn per window and frame unless set otherwise
+pub const DEFAULT_DECAL_BUDGET: usize = 256;
+
+// Texture projected by decals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecalTexture {
+    pub width: u32,
+    pub height: u32,
+    // sRGB texels with straight alpha, row by row
+    pub texels: Vec<[u8; 4]>,
+}
+
+impl DecalTexture {
+    // Returns None when the data doesn't match the size.
+    pub fn new(width: u32, height: u32, texels: Vec<[u8; 4]>) -> Option<Self> {
+        if width == 0 || height == 0 || texels.len() != (width * height) as usize {
+            return None;
+        }
+        return Some(DecalTexture {
+            width,
+            height,
+            texels,
+        });
+    }
+}
+
+// Box projecting a texture onto the opaque surfaces inside of it, along its -Y
+// axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Decal {
+    // Centre and orientation of the box
+    pub transform: Isometry3,
+    // Extents of the box along its axes
+    pub size: Vector3f,
+    // Mapped onto the XZ plane of the box with +X right and -Z up, the
+    // material colour alone is projected when not set
+    pub texture: Option<DecalTextureId>,
+    // Base colour, metallic and roughness replace the surface's where the
+    // texture is opaque. Uses the default material when not set.
+    pub material: Option<MaterialId>,
+    // Angles in radians between the surface normal and the +Y axis of the box
+    // where the decal starts fading and where it's gone, so it doesn't smear
+    // over steep surfaces
+    pub angle_fade: [FScalar; 2],
+    // Decals with a higher order are drawn over lower ones, newer decals over
+    // older ones of the same order
+    pub order: i32,
+}
+
+impl Decal {
+    pub fn new(transform: Isometry3, size: Vector3f) -> Self {
+        return Decal {
+            transform,
+            size,
+            texture: None,
+            material: None,
+            angle_fade: [FScalar::to_radians(60.0), FScalar::to_radians(80.0)],
+            order: 0,
+        };
+    }
+
+    // Whether the box has a volume and the fade angles are ordered.
+    pub fn is_valid(&self) -> bool {
+        let [start, end] = self.angle_fade;
+        return self.size.iter().all(|&s| s > 0.0) && start >= 0.0 && start <= end;
+    }
+
+    // Maps the unit cube centred on the origin onto the box.
+    pub fn model(&self) -> Matrix4f {
+        return self.transform.to_homogeneous() * Matrix4f::new_nonuniform_scaling(&self.size);
+    }
+
+    // Cosines of the fade angles, the decal is opaque above the first and gone
+    // below the second.
+    pub fn angle_fade_cosines(&self) -> [FScalar; 2] {
+        let [start, end] = self.angle_fade;
+        return [start.cos(), end.cos()];
+    }
+
+    // World space bounds of the box.
+    pub fn bounds(&self) -> Aabb {
+        let half = self.size * 0.5;
+        let corners: Vec<Vector3f> = Aabb::new(-half, half)
+            .corners()
+            .iter()
+            .map(|corner| self.transform.transform_point(&(*corner).into()).coords)
+            .collect();
+        return Aabb::from_points(&corners);
+    }
+}
+
+// Decals intersecting the frustum in drawing order. Beyond `budget` decals
+// the lowest ordered and then the oldest ones are left out.
+pub fn select_decals(
+    decals: &HashMap<DecalId, Decal>,
+    frustum: &Frustum,
+    budget: usize,
+) -> Vec<DecalId> {
+    let mut visible: Vec<DecalId> = decals
+        .iter()
+        .filter(|(_, decal)| frustum.intersects_aabb(&decal.bounds()))
+        .map(|(&id, _)| id)
+        .collect();
+    visible.sort_by_key(|id| (decals[id].order, *id));
+    let skipped = visible.len().saturating_sub(budget);
+    return visible.split_off(skipped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+
+    fn decal_at(x: FScalar) -> Decal {
+        return Decal::new(
+            Isometry3::translation(x, 0.0, -10.0),
+            Vector3f::new(1.0, 1.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn decal_test() {
+        assert!(DecalTexture::new(2, 2, vec![[255; 4]; 4]).is_some());
+        assert!(DecalTexture::new(2, 2, vec![[255; 4]; 3]).is_none());
+
+        let mut decal = Decal::new(
+            Isometry3::new(
+                Vector3f::new(1.0, 2.0, 3.0),
+                Vector3f::y() * std::f32::consts::FRAC_PI_2,
+            ),
+            Vector3f::new(2.0, 1.0, 4.0),
+        );
+        assert!(decal.is_valid());
+        // Rotated a quarter turn around +Y, the long side lies along X
+        let bounds = decal.bounds();
+        assert!((bounds.min - Vector3f::new(-1.0, 1.5, 2.0)).norm() < 1e-5);
+        assert!((bounds.max - Vector3f::new(3.0, 2.5, 4.0)).norm() < 1e-5);
+        let corner = decal
+            .model()
+            .transform_point(&na::Point3::new(0.5, 0.5, 0.5));
+        assert!((corner.coords - Vector3f::new(3.0, 2.5, 2.0)).norm() < 1e-5);
+
+        let [start, end] = decal.angle_fade_cosines();
+        assert!(start > end);
+        decal.angle_fade = [1.0, 0.5];
+        assert!(!decal.is_valid());
+        decal.angle_fade = [0.5, 1.0];
+        decal.size.y = 0.0;
+        assert!(!decal.is_valid());
+    }
+
+    #[test]
+    fn selection_test() {
+        let camera = Camera::new(
+            Isometry3::identity(),
+            std::f32::consts::FRAC_PI_2,
+            0.1,
+            100.0,
+        );
+        let frustum = camera.frustum(1.0);
+        let mut decals = HashMap::new();
+        decals.insert(0, decal_at(0.0));
+        decals.insert(1, decal_at(1.0));
+        decals.insert(
+            2,
+            Decal {
+                order: 1,
+                ..decal_at(-1.0)
+            },
+        );
+        decals.insert(3, decal_at(2.0));
+        // Outside of the frustum
+        decals.insert(4, decal_at(100.0));
+
+        assert_eq!(select_decals(&decals, &frustum, 10), vec![0, 1, 3, 2]);
+        // The oldest decals of the lowest order are dropped first
+        assert_eq!(select_decals(&decals, &frustum, 2), vec![3, 2]);
+        assert!(select_decals(&decals, &frustum, 0).is_empty());
+    }
+}
@@ -0,0 +1,494 @@
+use crate::{
+    common::BufferlessPipeline,
+    config,
+    decal::{select_decals, Decal, DecalId, DecalTexture, DecalTextureId},
+    deferred_pass::DeferredTargets,
+    frustum::Frustum,
+    material::{Material, MaterialId},
+    post_pass::PostTargets,
+};
+use std::{collections::HashMap, iter, sync::Arc};
+use vulkano::{
+    buffer::BufferAccess,
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet, PipelineLayoutAbstract},
+    device::{Device, Queue},
+    format::{ClearValue, Format},
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{AttachmentImage, Dimensions, ImmutableImage},
+    pipeline::{
+        blend::AttachmentBlend,
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        GraphicsPipeline,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/decal.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod gbuffer_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/decal_gbuffer.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod forward_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/decal_forward.frag",
+        include: ["src/shaders"]
+    }
+}
+
+// Decal drawn in a frame.
+pub struct DecalDraw {
+    push_constants: vs::ty::PushConstants,
+    texture: Arc<ImmutableImage<Format>>,
+}
+
+// Framebuffers the decals of a window are drawn into, built around its scene
+// targets and G-buffer.
+pub struct DecalTargets {
+    hdr_image: Arc<AttachmentImage>,
+    depth_buffer: Arc<AttachmentImage>,
+    // G-buffer albedo and normals of the deferred path, the normals fade the
+    // decals on steep surfaces
+    gbuffer: Option<(Arc<AttachmentImage>, Arc<AttachmentImage>)>,
+    // G-buffer albedo and material in the deferred path, HDR colour in the
+    // forward path
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    // Loads the HDR colour, motion vectors and depth to continue the forward
+    // scene pass after the decals
+    pub resume_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+impl DecalTargets {
+    // Whether the targets were created for the current scene targets and G-buffer.
+    pub fn matches(
+        &self,
+        post_targets: &PostTargets,
+        deferred_targets: Option<&DeferredTargets>,
+    ) -> bool {
+        let gbuffer_matches = match (&self.gbuffer, deferred_targets) {
+            (Some((albedo, _)), Some(deferred)) => Arc::ptr_eq(albedo, &deferred.albedo),
+            (None, None) => true,
+            _ => false,
+        };
+        return Arc::ptr_eq(&self.hdr_image, &post_targets.hdr_image) && gbuffer_matches;
+    }
+}
+
+pub struct DecalRenderer {
+    queue: Arc<Queue>,
+    gbuffer_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    forward_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    // Compatible with the forward scene pass, so forward pipelines can be used in
+    // it
+    resume_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    gbuffer_pipeline: Arc<BufferlessPipeline>,
+    // Lit like the deferred lighting pass, uses its frame set layout
+    pub forward_pipeline: Arc<BufferlessPipeline>,
+    sampler: Arc<Sampler>,
+    // Depth and G-buffer texels are fetched directly
+    target_sampler: Arc<Sampler>,
+    // Bound for decals without a texture
+    white: Arc<ImmutableImage<Format>>,
+}
+
+impl DecalRenderer {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        let gbuffer_render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    albedo: {
+                        load: Load,
+                        store: Store,
+                        format: config::GBUFFER_ALBEDO_FORMAT,
+                        samples: 1,
+                    },
+                    material: {
+                        load: Load,
+                        store: Store,
+                        format: config::GBUFFER_MATERIAL_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [albedo, material],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+        let forward_render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+        let resume_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    },
+                    velocity: {
+                        load: Load,
+                        store: Store,
+                        format: config::VELOCITY_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Load,
+                        store: Store,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color, velocity],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let gbuffer_fs = gbuffer_fs::Shader::load(device.clone()).unwrap();
+        let forward_fs = forward_fs::Shader::load(device.clone()).unwrap();
+        // Alpha of the targets is kept, the G-buffer and HDR colour don't store
+        // coverage
+        let blend = AttachmentBlend {
+            mask_alpha: false,
+            ..AttachmentBlend::alpha_blending()
+        };
+        let gbuffer_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(gbuffer_fs.main_entry_point(), ())
+                .blend_collective(blend.clone())
+                .render_pass(Subpass::from(gbuffer_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let forward_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(forward_fs.main_entry_point(), ())
+                .blend_collective(blend)
+                .render_pass(Subpass::from(forward_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        let sampler = |filter| {
+            return Sampler::new(
+                device.clone(),
+                filter,
+                filter,
+                MipmapMode::Nearest,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+            )
+            .unwrap();
+        };
+        let white = Self::upload(&queue, iter::once([255u8; 4]), 1, 1);
+
+        return DecalRenderer {
+            queue,
+            gbuffer_render_pass,
+            forward_render_pass,
+            resume_render_pass,
+            gbuffer_pipeline,
+            forward_pipeline,
+            sampler: sampler(Filter::Linear),
+            target_sampler: sampler(Filter::Nearest),
+            white,
+        };
+    }
+
+    // Uploads the texels of a decal texture. Blocks until the GPU is done.
+    pub fn create_texture(&self, texture: &DecalTexture) -> Arc<ImmutableImage<Format>> {
+        return Self::upload(
+            &self.queue,
+            texture.texels.iter().cloned(),
+            texture.width,
+            texture.height,
+        );
+    }
+
+    fn upload<I>(
+        queue: &Arc<Queue>,
+        texels: I,
+        width: u32,
+        height: u32,
+    ) -> Arc<ImmutableImage<Format>>
+    where
+        I: ExactSizeIterator<Item = [u8; 4]>,
+    {
+        let (image, upload) = ImmutableImage::from_iter(
+            texels,
+            Dimensions::Dim2d { width, height },
+            Format::R8G8B8A8Srgb,
+            queue.clone(),
+        )
+        .unwrap();
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        return image;
+    }
+
+    // Creates the targets of the forward path, or of the deferred path when the
+    // window has a G-buffer.
+    pub fn create_targets(
+        &self,
+        post_targets: &PostTargets,
+        deferred_targets: Option<&DeferredTargets>,
+        depth_buffer: Arc<AttachmentImage>,
+    ) -> DecalTargets {
+        let (gbuffer, framebuffer): (_, Arc<dyn FramebufferAbstract + Send + Sync>) =
+            match deferred_targets {
+                Some(deferred) => (
+                    Some((deferred.albedo.clone(), deferred.normal.clone())),
+                    Arc::new(
+                        Framebuffer::start(self.gbuffer_render_pass.clone())
+                            .add(deferred.albedo.clone())
+                            .unwrap()
+                            .add(deferred.material.clone())
+                            .unwrap()
+                            .build()
+                            .unwrap(),
+                    ),
+                ),
+                None => (
+                    None,
+                    Arc::new(
+                        Framebuffer::start(self.forward_render_pass.clone())
+                            .add(post_targets.hdr_image.clone())
+                            .unwrap()
+                            .build()
+                            .unwrap(),
+                    ),
+                ),
+            };
+        let resume_framebuffer = Arc::new(
+            Framebuffer::start(self.resume_render_pass.clone())
+                .add(post_targets.hdr_image.clone())
+                .unwrap()
+                .add(post_targets.velocity_image.clone())
+                .unwrap()
+                .add(depth_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        return DecalTargets {
+            hdr_image: post_targets.hdr_image.clone(),
+            depth_buffer,
+            gbuffer,
+            framebuffer,
+            resume_framebuffer,
+        };
+    }
+
+    // Visible decals in drawing order, at most `budget` of them. Decals whose
+    // texture was removed are skipped.
+    pub fn prepare(
+        &self,
+        decals: &HashMap<DecalId, Decal>,
+        textures: &HashMap<DecalTextureId, Arc<ImmutableImage<Format>>>,
+        materials: &HashMap<MaterialId, Material>,
+        default_material: &Material,
+        frustum: &Frustum,
+        budget: usize,
+    ) -> Vec<DecalDraw> {
+        return select_decals(decals, frustum, budget)
+            .into_iter()
+            .filter_map(|id| {
+                let decal = &decals[&id];
+                let texture = match decal.texture {
+                    Some(texture_id) => textures.get(&texture_id)?.clone(),
+                    None => self.white.clone(),
+                };
+                let material = decal
+                    .material
+                    .and_then(|material_id| materials.get(&material_id))
+                    .unwrap_or(default_material);
+                let c = material.base_color;
+                let [fade_start, fade_end] = decal.angle_fade_cosines();
+                return Some(DecalDraw {
+                    push_constants: vs::ty::PushConstants {
+                        inverse_model: decal.model().try_inverse().unwrap().into(),
+                        color: [c.x, c.y, c.z, c.w],
+                        params: [material.metallic, material.roughness, fade_start, fade_end],
+                    },
+                    texture,
+                });
+            })
+            .collect();
+    }
+
+    // Blends the decals into the G-buffer, after the G-buffer pass and before
+    // the lighting pass.
+    pub fn record_gbuffer<B>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        targets: &DecalTargets,
+        draws: &[DecalDraw],
+        frame_data: B,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        B: BufferAccess + Send + Sync + 'static,
+    {
+        let (_, normal) = targets
+            .gbuffer
+            .as_ref()
+            .expect("decal targets created without a G-buffer");
+        let pipeline = &self.gbuffer_pipeline;
+        let frame_set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.descriptor_set_layout(0).unwrap().clone())
+                .add_buffer(frame_data)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let scene_set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.descriptor_set_layout(2).unwrap().clone())
+                .add_sampled_image(targets.depth_buffer.clone(), self.target_sampler.clone())
+                .unwrap()
+                .add_sampled_image(normal.clone(), self.target_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        return self.record(
+            builder,
+            pipeline,
+            targets,
+            draws,
+            frame_set,
+            scene_set,
+            dynamic_state,
+        );
+    }
+
+    // Lights the decals and blends them over the opaque surfaces of the forward
+    // path, outside of the scene pass. `frame_set` is the set of
+    // `forward_pipeline`.
+    pub fn record_forward<S>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        targets: &DecalTargets,
+        draws: &[DecalDraw],
+        frame_set: S,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        let pipeline = &self.forward_pipeline;
+        let scene_set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.descriptor_set_layout(2).unwrap().clone())
+                .add_sampled_image(targets.depth_buffer.clone(), self.target_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        return self.record(
+            builder,
+            pipeline,
+            targets,
+            draws,
+            frame_set,
+            scene_set,
+            dynamic_state,
+        );
+    }
+
+    fn record<S, T>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<BufferlessPipeline>,
+        targets: &DecalTargets,
+        draws: &[DecalDraw],
+        frame_set: S,
+        scene_set: T,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+        T: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        let clear_values = match targets.gbuffer {
+            Some(_) => vec![ClearValue::None, ClearValue::None],
+            None => vec![ClearValue::None],
+        };
+        let mut builder = builder
+            .begin_render_pass(targets.framebuffer.clone(), false, clear_values)
+            .unwrap();
+        for draw in draws {
+            let texture_set = Arc::new(
+                PersistentDescriptorSet::start(pipeline.descriptor_set_layout(1).unwrap().clone())
+                    .add_sampled_image(draw.texture.clone(), self.sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            builder = builder
+                .draw(
+                    pipeline.clone(),
+                    dynamic_state,
+                    BufferlessVertices {
+                        vertices: 36,
+                        instances: 1,
+                    },
+                    (frame_set.clone(), texture_set, scene_set.clone()),
+                    draw.push_constants,
+                )
+                .unwrap();
+        }
+        return builder.end_render_pass().unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use crate::{
+    common::BufferlessPipeline,
+    config,
+    debug_utils::set_image_name,
+    post_pass::PostTargets,
+    vertex::Vertex,
+};
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet, PipelineLayoutAbstract},
+    device::Device,
+    format::ClearValue,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{AttachmentImage, ImageUsage},
+    pipeline::{
+        blend::{AttachmentBlend, BlendFactor, BlendOp},
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/forward.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod gbuffer_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/gbuffer.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod fullscreen_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/fullscreen.vert"
+    }
+}
+
+mod lighting_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/deferred_lighting.frag",
+        include: ["src/shaders"]
+    }
+}
+
+// G-buffer of a window, built around its HDR scene targets.
+pub struct DeferredTargets {
+    hdr_image: Arc<AttachmentImage>,
+    pub albedo: Arc<AttachmentImage>,
+    pub normal: Arc<AttachmentImage>,
+    pub material: Arc<AttachmentImage>,
+    depth_buffer: Arc<AttachmentImage>,
+    // Clears all attachments, opaque geometry is drawn into it
+    pub gbuffer_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    lighting_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    // Loads the lit scene, for the skybox and transparent geometry
+    pub forward_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+impl DeferredTargets {
+    // Whether the targets were created for the current scene targets.
+    pub fn matches(&self, post_targets: &PostTargets) -> bool {
+        return Arc::ptr_eq(&self.hdr_image, &post_targets.hdr_image);
+    }
+}
+
+// Blending of pipelines writing only the G-buffer normal, for surfaces shaded
+// forward after the lighting pass.
+pub fn gbuffer_normal_blend() -> Vec<AttachmentBlend> {
+    let masked = AttachmentBlend {
+        mask_red: false,
+        mask_green: false,
+        mask_blue: false,
+        mask_alpha: false,
+        ..AttachmentBlend::pass_through()
+    };
+    return vec![
+        masked.clone(),
+        masked.clone(),
+        masked.clone(),
+        AttachmentBlend::pass_through(),
+        masked,
+    ];
+}
+
+pub struct DeferredRenderer {
+    device: Arc<Device>,
+    pub gbuffer_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    lighting_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    forward_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    pub gbuffer_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub lighting_pipeline: Arc<BufferlessPipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl DeferredRenderer {
+    pub fn new(device: Arc<Device>) -> Self {
+        let gbuffer_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    },
+                    velocity: {
+                        load: Clear,
+                        store: Store,
+                        format: config::VELOCITY_FORMAT,
+                        samples: 1,
+                    },
+                    albedo: {
+                        load: Clear,
+                        store: Store,
+                        format: config::GBUFFER_ALBEDO_FORMAT,
+                        samples: 1,
+                    },
+                    normal: {
+                        load: Clear,
+                        store: Store,
+                        format: config::GBUFFER_NORMAL_FORMAT,
+                        samples: 1,
+                    },
+                    material: {
+                        load: Clear,
+                        store: Store,
+                        format: config::GBUFFER_MATERIAL_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color, velocity, albedo, normal, material],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+        let lighting_render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+        // Compatible with the forward scene pass, so forward pipelines can be used in
+        // it.
+        let forward_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    },
+                    velocity: {
+                        load: Load,
+                        store: Store,
+                        format: config::VELOCITY_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Load,
+                        store: Store,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color, velocity],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let gbuffer_fs = gbuffer_fs::Shader::load(device.clone()).unwrap();
+        let fullscreen_vs = fullscreen_vs::Shader::load(device.clone()).unwrap();
+        let lighting_fs = lighting_fs::Shader::load(device.clone()).unwrap();
+
+        let gbuffer_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(gbuffer_fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(gbuffer_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let additive = AttachmentBlend {
+            enabled: true,
+            color_op: BlendOp::Add,
+            color_source: BlendFactor::One,
+            color_destination: BlendFactor::One,
+            alpha_op: BlendOp::Add,
+            alpha_source: BlendFactor::One,
+            alpha_destination: BlendFactor::One,
+            mask_red: true,
+            mask_green: true,
+            mask_blue: true,
+            mask_alpha: true,
+        };
+        let lighting_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(fullscreen_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(lighting_fs.main_entry_point(), ())
+                .blend_collective(additive)
+                .render_pass(Subpass::from(lighting_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        // G-buffer texels are fetched directly, filtering is never used.
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        return DeferredRenderer {
+            device,
+            gbuffer_render_pass,
+            lighting_render_pass,
+            forward_render_pass,
+            gbuffer_pipeline,
+            lighting_pipeline,
+            sampler,
+        };
+    }
+
+    pub fn create_targets(
+        &self,
+        post_targets: &PostTargets,
+        depth_buffer: Arc<AttachmentImage>,
+    ) -> DeferredTargets {
+        let dimensions = depth_buffer.dimensions();
+        let usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+        let attachment = |name, format| {
+            let image = AttachmentImage::with_usage(self.device.clone(), dimensions, format, usage)
+                .unwrap();
+            set_image_name(&image, name);
+            return image;
+        };
+        let albedo = attachment("g-buffer albedo", config::GBUFFER_ALBEDO_FORMAT);
+        let normal = attachment("g-buffer normal", config::GBUFFER_NORMAL_FORMAT);
+        let material = attachment("g-buffer material", config::GBUFFER_MATERIAL_FORMAT);
+
+        let gbuffer_framebuffer = Arc::new(
+            Framebuffer::start(self.gbuffer_render_pass.clone())
+                .add(post_targets.hdr_image.clone())
+                .unwrap()
+                .add(post_targets.velocity_image.clone())
+                .unwrap()
+                .add(albedo.clone())
+                .unwrap()
+                .add(normal.clone())
+                .unwrap()
+                .add(material.clone())
+                .unwrap()
+                .add(depth_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let lighting_framebuffer = Arc::new(
+            Framebuffer::start(self.lighting_render_pass.clone())
+                .add(post_targets.hdr_image.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let forward_framebuffer = Arc::new(
+            Framebuffer::start(self.forward_render_pass.clone())
+                .add(post_targets.hdr_image.clone())
+                .unwrap()
+                .add(post_targets.velocity_image.clone())
+                .unwrap()
+                .add(depth_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        return DeferredTargets {
+            hdr_image: post_targets.hdr_image.clone(),
+            albedo,
+            normal,
+            material,
+            depth_buffer,
+            gbuffer_framebuffer,
+            lighting_framebuffer,
+            forward_framebuffer,
+        };
+    }
+
+    // Records the lighting pass, after the G-buffer pass and before the forward
+    // pass. `frame_set` is the frame set of `lighting_pipeline`.
+    pub fn record_lighting<S>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        targets: &DeferredTargets,
+        frame_set: S,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        S: DescriptorSet + Send + Sync + 'static,
+    {
+        let gbuffer_set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.lighting_pipeline
+                    .descriptor_set_layout(1)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_sampled_image(targets.albedo.clone(), self.sampler.clone())
+            .unwrap()
+            .add_sampled_image(targets.normal.clone(), self.sampler.clone())
+            .unwrap()
+            .add_sampled_image(targets.material.clone(), self.sampler.clone())
+            .unwrap()
+            .add_sampled_image(targets.depth_buffer.clone(), self.sampler.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        return builder
+            .begin_render_pass(
+                targets.lighting_framebuffer.clone(),
+                false,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.lighting_pipeline.clone(),
+                dynamic_state,
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                (frame_set, gbuffer_set),
+                (),
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+}
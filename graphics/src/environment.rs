@@ -0,0 +1,274 @@
+use crate::error::RenderingError;
+use polyengine_core::*;
+
+pub type EnvironmentId = u32;
+
+// Number of prefiltered specular cubemaps, from mirror-like to fully rough
+// reflections. Fixed, the shaders declare an array of this size.
+pub const SPECULAR_LEVELS: usize = 5;
+
+// Linear HDR image in the equirectangular (latitude-longitude) projection, rows
+// go from the top (+Y) down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    // Returns None when the data doesn't match the size.
+    pub fn from_rgb(width: u32, height: u32, texels: Vec<[f32; 3]>) -> Option<Self> {
+        if width == 0 || height == 0 || texels.len() != (width * height) as usize {
+            return None;
+        }
+        return Some(HdrImage {
+            width,
+            height,
+            texels,
+        });
+    }
+
+    // Decodes a Radiance RGBE (.hdr) file, flat or run length encoded. Only the
+    // standard `-Y height +X width` orientation is supported.
+    pub fn from_radiance(data: &[u8]) -> Result<Self, RenderingError> {
+        let mut position = 0;
+        let mut read_line = || -> Option<&[u8]> {
+            let start = position;
+            let length = data[start..].iter().position(|&b| b == b'\n')?;
+            position = start + length + 1;
+            return Some(&data[start..start + length]);
+        };
+
+        match read_line() {
+            Some(b"#?RADIANCE") | Some(b"#?RGBE") => {}
+            _ => return Err(RenderingError::InvalidImage),
+        }
+        loop {
+            match read_line() {
+                Some(b"") => break,
+                Some(line) if line.starts_with(b"FORMAT=") => {
+                    if line != b"FORMAT=32-bit_rle_rgbe" {
+                        return Err(RenderingError::InvalidImage);
+                    }
+                }
+                Some(_) => {}
+                None => return Err(RenderingError::InvalidImage),
+            }
+        }
+        let resolution = read_line()
+            .and_then(|line| std::str::from_utf8(line).ok())
+            .ok_or(RenderingError::InvalidImage)?;
+        let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (width.parse::<u32>(), height.parse::<u32>()),
+            _ => return Err(RenderingError::InvalidImage),
+        };
+        let (width, height) = match (width, height) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+            _ => return Err(RenderingError::InvalidImage),
+        };
+
+        let mut bytes = data[position..].iter().cloned();
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        let mut scanline = vec![[0u8; 4]; width as usize];
+        for _ in 0..height {
+            read_scanline(&mut bytes, &mut scanline).ok_or(RenderingError::InvalidImage)?;
+            texels.extend(scanline.iter().map(rgbe_to_rgb));
+        }
+
+        return Ok(HdrImage {
+            width,
+            height,
+            texels,
+        });
+    }
+}
+
+fn read_scanline(bytes: &mut impl Iterator<Item = u8>, scanline: &mut [[u8; 4]]) -> Option<()> {
+    let width = scanline.len();
+    let first = [bytes.next()?, bytes.next()?, bytes.next()?, bytes.next()?];
+    let encoded_width = (first[2] as usize) << 8 | first[3] as usize;
+    if !(8..=0x7fff).contains(&width) || first[0] != 2 || first[1] != 2 || encoded_width != width {
+        // Flat scanline, the first texel was already read.
+        scanline[0] = first;
+        for texel in scanline.iter_mut().skip(1) {
+            *texel = [bytes.next()?, bytes.next()?, bytes.next()?, bytes.next()?];
+        }
+        return Some(());
+    }
+
+    // Channels are stored one after another, each as runs and literal spans.
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = bytes.next()? as usize;
+            if count > 128 {
+                let count = count - 128;
+                let value = bytes.next()?;
+                if count == 0 || x + count > width {
+                    return None;
+                }
+                for texel in &mut scanline[x..x + count] {
+                    texel[channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return None;
+                }
+                for texel in &mut scanline[x..x + count] {
+                    texel[channel] = bytes.next()?;
+                }
+                x += count;
+            }
+        }
+    }
+    return Some(());
+}
+
+// Shared exponent texel to linear RGB.
+pub fn rgbe_to_rgb(rgbe: &[u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0; 3];
+    }
+    let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    return [
+        rgbe[0] as f32 * scale,
+        rgbe[1] as f32 * scale,
+        rgbe[2] as f32 * scale,
+    ];
+}
+
+// Half precision bits of the value, used to upload HDR texels.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent <= 0 {
+        // Subnormal or flushed to zero
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        return sign | (mantissa >> (14 - half_exponent) as u32) as u16;
+    }
+    // Rounding may carry into the exponent, which is still the closest value.
+    let half = ((half_exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1);
+    if half >= 0x7c00 {
+        return sign | 0x7c00;
+    }
+    return sign | half as u16;
+}
+
+// Roughness the specular cubemap `level` is prefiltered for.
+pub fn specular_level_roughness(level: usize) -> FScalar {
+    return level as FScalar / (SPECULAR_LEVELS - 1) as FScalar;
+}
+
+// Resolutions of the cubemaps generated from environment images.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IblSettings {
+    // Face size of the skybox cubemap
+    pub cubemap_size: u32,
+    // Face size of the diffuse irradiance cubemap, irradiance is very smooth
+    pub irradiance_size: u32,
+    // Face size of the sharpest specular level, each next level is half of it
+    pub specular_size: u32,
+    // Importance samples per texel of the specular levels
+    pub sample_count: u32,
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        return IblSettings {
+            cubemap_size: 512,
+            irradiance_size: 32,
+            specular_size: 128,
+            sample_count: 512,
+        };
+    }
+}
+
+impl IblSettings {
+    pub fn specular_level_size(&self, level: usize) -> u32 {
+        return (self.specular_size >> level).max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn radiance_header(width: u32, height: u32) -> Vec<u8> {
+        return format!(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            height, width
+        )
+        .into_bytes();
+    }
+
+    #[test]
+    fn rgbe_test() {
+        assert_eq!(rgbe_to_rgb(&[128, 64, 0, 129]), [1.0, 0.5, 0.0]);
+        assert_eq!(rgbe_to_rgb(&[255, 255, 255, 0]), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn flat_radiance_test() {
+        let mut data = radiance_header(2, 1);
+        data.extend(&[128, 0, 0, 129, 0, 128, 0, 130]);
+        let image = HdrImage::from_radiance(&data).unwrap();
+        assert_eq!(image.texels, vec![[1.0, 0.0, 0.0], [0.0, 2.0, 0.0]]);
+
+        assert_eq!(
+            HdrImage::from_radiance(&data[..data.len() - 1]),
+            Err(RenderingError::InvalidImage)
+        );
+        assert_eq!(
+            HdrImage::from_radiance(b"P6\n2 1\n"),
+            Err(RenderingError::InvalidImage)
+        );
+    }
+
+    #[test]
+    fn rle_radiance_test() {
+        let mut data = radiance_header(8, 1);
+        data.extend(&[2, 2, 0, 8]);
+        // Red: run of 8, green: 8 literals, blue: two runs, exponent: run of 8
+        data.extend(&[136, 128]);
+        data.extend(&[8, 0, 0, 0, 0, 0, 0, 0, 128]);
+        data.extend(&[132, 0, 132, 64]);
+        data.extend(&[136, 129]);
+        let image = HdrImage::from_radiance(&data).unwrap();
+        assert_eq!(image.texels.len(), 8);
+        assert_eq!(image.texels[0], [1.0, 0.0, 0.0]);
+        assert_eq!(image.texels[7], [1.0, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn f16_test() {
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(1e-10), 0);
+    }
+
+    #[test]
+    fn specular_levels_test() {
+        let settings = IblSettings::default();
+        assert_eq!(specular_level_roughness(0), 0.0);
+        assert_eq!(specular_level_roughness(SPECULAR_LEVELS - 1), 1.0);
+        assert_eq!(settings.specular_level_size(2), 32);
+        assert_eq!(settings.specular_level_size(12), 1);
+    }
+}
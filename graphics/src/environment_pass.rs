@@ -0,0 +1,457 @@
+use crate::{
+    common::BufferlessPipeline,
+    config,
+    environment::{f32_to_f16, specular_level_roughness, HdrImage, IblSettings, SPECULAR_LEVELS},
+};
+use std::sync::Arc;
+use vulkano::{
+    buffer::BufferAccess,
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, PipelineLayoutAbstract},
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::{Dimensions, ImageUsage, ImageViewAccess, ImmutableImage, StorageImage},
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        ComputePipeline,
+        ComputePipelineAbstract,
+        GraphicsPipeline,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod equirect_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/equirect_to_cube.comp",
+        include: ["src/shaders"]
+    }
+}
+
+mod irradiance_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/irradiance.comp",
+        include: ["src/shaders"]
+    }
+}
+
+mod prefilter_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/prefilter.comp",
+        include: ["src/shaders"]
+    }
+}
+
+mod brdf_lut_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/brdf_lut.comp",
+        include: ["src/shaders"]
+    }
+}
+
+mod skybox_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/skybox.vert"
+    }
+}
+
+mod skybox_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/skybox.frag",
+        include: ["src/shaders"]
+    }
+}
+
+const BRDF_LUT_SIZE: u32 = 256;
+// Compute shader workgroup size along X and Y
+const GROUP_SIZE: u32 = 8;
+
+// Cubemaps generated from an environment image, used for the skybox and image
+// based lighting.
+pub struct EnvironmentMap {
+    pub cubemap: Arc<StorageImage<Format>>,
+    pub irradiance: Arc<StorageImage<Format>>,
+    // Prefiltered for increasing roughness, see `specular_level_roughness`
+    pub specular_levels: Vec<Arc<StorageImage<Format>>>,
+}
+
+pub struct EnvironmentRenderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    equirect_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    irradiance_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    prefilter_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    skybox_pipeline: Arc<BufferlessPipeline>,
+    pub sampler: Arc<Sampler>,
+    // Wraps around horizontally, the equirectangular image covers all longitudes
+    equirect_sampler: Arc<Sampler>,
+    // Environment independent part of the specular lighting
+    pub brdf_lut: Arc<StorageImage<Format>>,
+    // Bound in place of the environment cubemaps when there is no environment
+    fallback_cubemap: Arc<ImmutableImage<Format>>,
+}
+
+impl EnvironmentRenderer {
+    // `scene_render_pass` is the HDR pass the skybox is drawn in.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        scene_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let equirect_cs = equirect_cs::Shader::load(device.clone()).unwrap();
+        let irradiance_cs = irradiance_cs::Shader::load(device.clone()).unwrap();
+        let prefilter_cs = prefilter_cs::Shader::load(device.clone()).unwrap();
+        let brdf_lut_cs = brdf_lut_cs::Shader::load(device.clone()).unwrap();
+        let skybox_vs = skybox_vs::Shader::load(device.clone()).unwrap();
+        let skybox_fs = skybox_fs::Shader::load(device.clone()).unwrap();
+
+        let equirect_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &equirect_cs.main_entry_point(), &()).unwrap(),
+        );
+        let irradiance_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &irradiance_cs.main_entry_point(), &()).unwrap(),
+        );
+        let prefilter_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &prefilter_cs.main_entry_point(), &()).unwrap(),
+        );
+        let brdf_lut_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &brdf_lut_cs.main_entry_point(), &()).unwrap(),
+        );
+
+        let skybox_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(skybox_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(skybox_fs.main_entry_point(), ())
+                // Drawn after the scene, only where the depth buffer is still cleared.
+                .depth_stencil(DepthStencil {
+                    depth_write: false,
+                    depth_compare: Compare::LessOrEqual,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .render_pass(Subpass::from(scene_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+        let equirect_sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::Repeat,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let usage = ImageUsage {
+            storage: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+        let brdf_lut = StorageImage::with_usage(
+            device.clone(),
+            Dimensions::Dim2d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+            },
+            config::HDR_FORMAT,
+            usage,
+            Some(queue.family()),
+        )
+        .unwrap();
+        let (fallback_cubemap, fallback_upload) = ImmutableImage::from_iter(
+            std::iter::repeat([0u16; 4]).take(6),
+            Dimensions::Cubemap { size: 1 },
+            config::HDR_FORMAT,
+            queue.clone(),
+        )
+        .unwrap();
+
+        // Both images are needed by every frame, so they are finished here.
+        let set = Arc::new(
+            PersistentDescriptorSet::start(
+                brdf_lut_pipeline.descriptor_set_layout(0).unwrap().clone(),
+            )
+            .add_image(brdf_lut.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        let groups = group_count(BRDF_LUT_SIZE);
+        let command_buffer =
+            AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
+                .unwrap()
+                .dispatch([groups, groups, 1], brdf_lut_pipeline, set, ())
+                .unwrap()
+                .build()
+                .unwrap();
+        fallback_upload
+            .then_execute(queue.clone(), command_buffer)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        return EnvironmentRenderer {
+            device,
+            queue,
+            equirect_pipeline,
+            irradiance_pipeline,
+            prefilter_pipeline,
+            skybox_pipeline,
+            sampler,
+            equirect_sampler,
+            brdf_lut,
+            fallback_cubemap,
+        };
+    }
+
+    fn create_cubemap(&self, size: u32) -> Arc<StorageImage<Format>> {
+        let usage = ImageUsage {
+            storage: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+        return StorageImage::with_usage(
+            self.device.clone(),
+            Dimensions::Cubemap { size },
+            config::HDR_FORMAT,
+            usage,
+            Some(self.queue.family()),
+        )
+        .unwrap();
+    }
+
+    // Converts the image into the skybox cubemap and precomputes its diffuse
+    // irradiance and prefiltered specular cubemaps. Blocks until the GPU is done,
+    // environments are expected to be loaded rarely.
+    pub fn create_environment(&self, image: &HdrImage, settings: &IblSettings) -> EnvironmentMap {
+        let texels: Vec<[u16; 4]> = image
+            .texels
+            .iter()
+            .map(|t| {
+                [
+                    f32_to_f16(t[0]),
+                    f32_to_f16(t[1]),
+                    f32_to_f16(t[2]),
+                    f32_to_f16(1.0),
+                ]
+            })
+            .collect();
+        let (equirect, upload) = ImmutableImage::from_iter(
+            texels.into_iter(),
+            Dimensions::Dim2d {
+                width: image.width,
+                height: image.height,
+            },
+            config::HDR_FORMAT,
+            self.queue.clone(),
+        )
+        .unwrap();
+
+        let cubemap = self.create_cubemap(settings.cubemap_size);
+        let irradiance = self.create_cubemap(settings.irradiance_size);
+        let specular_levels: Vec<_> = (0..SPECULAR_LEVELS)
+            .map(|level| self.create_cubemap(settings.specular_level_size(level)))
+            .collect();
+
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            self.queue.family(),
+        )
+        .unwrap();
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.equirect_pipeline
+                    .descriptor_set_layout(0)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_sampled_image(equirect, self.equirect_sampler.clone())
+            .unwrap()
+            .add_image(cubemap.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        let groups = group_count(settings.cubemap_size);
+        builder = builder
+            .dispatch([groups, groups, 6], self.equirect_pipeline.clone(), set, ())
+            .unwrap();
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.irradiance_pipeline
+                    .descriptor_set_layout(0)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_sampled_image(cubemap.clone(), self.sampler.clone())
+            .unwrap()
+            .add_image(irradiance.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        let groups = group_count(settings.irradiance_size);
+        builder = builder
+            .dispatch(
+                [groups, groups, 6],
+                self.irradiance_pipeline.clone(),
+                set,
+                (),
+            )
+            .unwrap();
+
+        for (level, target) in specular_levels.iter().enumerate() {
+            let set = Arc::new(
+                PersistentDescriptorSet::start(
+                    self.prefilter_pipeline
+                        .descriptor_set_layout(0)
+                        .unwrap()
+                        .clone(),
+                )
+                .add_sampled_image(cubemap.clone(), self.sampler.clone())
+                .unwrap()
+                .add_image(target.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+            );
+            let push_constants = prefilter_cs::ty::PushConstants {
+                params: [
+                    specular_level_roughness(level),
+                    settings.sample_count as f32,
+                    0.0,
+                    0.0,
+                ],
+            };
+            let groups = group_count(settings.specular_level_size(level));
+            builder = builder
+                .dispatch(
+                    [groups, groups, 6],
+                    self.prefilter_pipeline.clone(),
+                    set,
+                    push_constants,
+                )
+                .unwrap();
+        }
+
+        upload
+            .then_execute(self.queue.clone(), builder.build().unwrap())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        return EnvironmentMap {
+            cubemap,
+            irradiance,
+            specular_levels,
+        };
+    }
+
+    // Irradiance and specular cubemaps bound for image based lighting, fallback
+    // images when there is no environment.
+    pub fn lighting_images(
+        &self,
+        environment: Option<&EnvironmentMap>,
+    ) -> (
+        Arc<dyn ImageViewAccess + Send + Sync>,
+        Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
+    ) {
+        let irradiance: Arc<dyn ImageViewAccess + Send + Sync>;
+        let mut specular_levels: Vec<Arc<dyn ImageViewAccess + Send + Sync>> = Vec::new();
+        match environment {
+            Some(environment) => {
+                irradiance = environment.irradiance.clone();
+                for image in &environment.specular_levels {
+                    specular_levels.push(image.clone());
+                }
+            }
+            None => {
+                irradiance = self.fallback_cubemap.clone();
+                for _ in 0..SPECULAR_LEVELS {
+                    specular_levels.push(self.fallback_cubemap.clone());
+                }
+            }
+        }
+        return (irradiance, specular_levels);
+    }
+
+    // Draws the environment behind the scene, has to be recorded inside of the
+    // scene render pass after opaque geometry. `frame_data` is the frame uniform
+    // buffer of the forward pass.
+    pub fn record_skybox<B>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        environment: &EnvironmentMap,
+        frame_data: B,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        B: BufferAccess + Send + Sync + 'static,
+    {
+        let set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.skybox_pipeline
+                    .descriptor_set_layout(0)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_buffer(frame_data)
+            .unwrap()
+            .add_sampled_image(environment.cubemap.clone(), self.sampler.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        return builder
+            .draw(
+                self.skybox_pipeline.clone(),
+                dynamic_state,
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                set,
+                (),
+            )
+            .unwrap();
+    }
+}
+
+fn group_count(size: u32) -> u32 { return (size + GROUP_SIZE - 1) / GROUP_SIZE; }
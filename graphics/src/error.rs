@@ -3,4 +3,34 @@ pub enum RenderingError {
     RecreateSwapchainFailed,
     ImageAcquireFailed,
     WindowNotFound,
+    ObjectNotFound,
+    MaterialNotFound,
+    LightNotFound,
+    EnvironmentNotFound,
+    ParticleEmitterNotFound,
+    SpriteAtlasNotFound,
+    SpriteNotFound,
+    TileMapNotFound,
+    FontNotFound,
+    TextNotFound,
+    SkinnedMeshNotFound,
+    SkinnedObjectNotFound,
+    TerrainNotFound,
+    DecalNotFound,
+    DecalTextureNotFound,
+    GeometryNotFound,
+    // Image data couldn't be decoded
+    InvalidImage,
+    // Font data couldn't be parsed or uses unsupported features
+    InvalidFont,
+    // Skeleton parents come after their children, or vertices use missing joints
+    InvalidSkin,
+    // Terrain spacing isn't positive, or its layers are too many or differ in size
+    InvalidTerrain,
+    // Decal box has no volume, or its fade angles are out of order
+    InvalidDecal,
+    // Capture path has an extension other than png or exr
+    UnsupportedCaptureFormat,
+    // Capture couldn't be written to disk
+    CaptureFailed,
 }
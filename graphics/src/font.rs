@@ -0,0 +1,790 @@
+use crate::{cff::CffOutlines, error::RenderingError};
+use polyengine_core::*;
+use std::collections::HashMap;
+
+// Nested composite glyphs deeper than this are treated as broken.
+const MAX_COMPONENT_DEPTH: u32 = 8;
+
+// Part of a closed glyph contour in font units, Y pointing up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutlineSegment {
+    Line(Vector2f, Vector2f),
+    Quadratic(Vector2f, Vector2f, Vector2f),
+    Cubic(Vector2f, Vector2f, Vector2f, Vector2f),
+}
+
+impl OutlineSegment {
+    #[cfg(test)]
+    pub fn start(&self) -> Vector2f {
+        match *self {
+            OutlineSegment::Line(p0, _) => return p0,
+            OutlineSegment::Quadratic(p0, _, _) => return p0,
+            OutlineSegment::Cubic(p0, _, _, _) => return p0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn end(&self) -> Vector2f {
+        match *self {
+            OutlineSegment::Line(_, p1) => return p1,
+            OutlineSegment::Quadratic(_, _, p2) => return p2,
+            OutlineSegment::Cubic(_, _, _, p3) => return p3,
+        }
+    }
+
+    pub fn point(&self, t: FScalar) -> Vector2f {
+        let s = 1.0 - t;
+        match *self {
+            OutlineSegment::Line(p0, p1) => return p0 * s + p1 * t,
+            OutlineSegment::Quadratic(p0, p1, p2) => {
+                return p0 * (s * s) + p1 * (2.0 * s * t) + p2 * (t * t);
+            }
+            OutlineSegment::Cubic(p0, p1, p2, p3) => {
+                return p0 * (s * s * s)
+                    + p1 * (3.0 * s * s * t)
+                    + p2 * (3.0 * s * t * t)
+                    + p3 * (t * t * t);
+            }
+        }
+    }
+
+    pub fn transform<F>(&self, f: F) -> OutlineSegment
+    where
+        F: Fn(Vector2f) -> Vector2f,
+    {
+        match *self {
+            OutlineSegment::Line(p0, p1) => return OutlineSegment::Line(f(p0), f(p1)),
+            OutlineSegment::Quadratic(p0, p1, p2) => {
+                return OutlineSegment::Quadratic(f(p0), f(p1), f(p2));
+            }
+            OutlineSegment::Cubic(p0, p1, p2, p3) => {
+                return OutlineSegment::Cubic(f(p0), f(p1), f(p2), f(p3));
+            }
+        }
+    }
+}
+
+// Closed contours of a glyph. Filled areas follow the non-zero winding rule.
+pub type Outline = Vec<Vec<OutlineSegment>>;
+
+// Collects pen movements of the glyph programs into closed contours.
+#[derive(Debug, Clone)]
+pub struct OutlineBuilder {
+    contours: Outline,
+    contour: Vec<OutlineSegment>,
+    start: Vector2f,
+    position: Vector2f,
+}
+
+impl Default for OutlineBuilder {
+    fn default() -> Self {
+        return OutlineBuilder {
+            contours: Vec::new(),
+            contour: Vec::new(),
+            start: Vector2f::zeros(),
+            position: Vector2f::zeros(),
+        };
+    }
+}
+
+impl OutlineBuilder {
+    pub fn position(&self) -> Vector2f { return self.position; }
+
+    pub fn move_to(&mut self, point: Vector2f) {
+        self.close();
+        self.start = point;
+        self.position = point;
+    }
+
+    pub fn line_to(&mut self, point: Vector2f) {
+        if point != self.position {
+            self.contour
+                .push(OutlineSegment::Line(self.position, point));
+        }
+        self.position = point;
+    }
+
+    pub fn quad_to(&mut self, control: Vector2f, point: Vector2f) {
+        self.contour
+            .push(OutlineSegment::Quadratic(self.position, control, point));
+        self.position = point;
+    }
+
+    pub fn curve_to(&mut self, control_a: Vector2f, control_b: Vector2f, point: Vector2f) {
+        self.contour.push(OutlineSegment::Cubic(
+            self.position,
+            control_a,
+            control_b,
+            point,
+        ));
+        self.position = point;
+    }
+
+    pub fn close(&mut self) {
+        let start = self.start;
+        self.line_to(start);
+        if !self.contour.is_empty() {
+            self.contours
+                .push(std::mem::replace(&mut self.contour, Vec::new()));
+        }
+    }
+
+    pub fn finish(mut self) -> Outline {
+        self.close();
+        return self.contours;
+    }
+}
+
+pub fn read_u8(data: &[u8], offset: usize) -> Option<u8> { return data.get(offset).cloned(); }
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    return Some(u16::from_be_bytes([bytes[0], bytes[1]]));
+}
+
+pub fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    return read_u16(data, offset).map(|value| value as i16);
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    return Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+}
+
+enum GlyphOutlines<'a> {
+    TrueType { glyf: &'a [u8], loca: Vec<u32> },
+    Cff(CffOutlines<'a>),
+}
+
+// OpenType font with TrueType or CFF outlines, or the first font of a
+// collection. Only horizontal metrics and kerning pairs are read, there is no
+// shaping.
+pub struct FontFile<'a> {
+    // Font units per em square
+    pub units_per_em: u16,
+    // Distances of the tallest and lowest glyphs from the baseline, the
+    // descender is negative
+    pub ascender: i16,
+    pub descender: i16,
+    // Extra space between lines
+    pub line_gap: i16,
+    pub glyph_count: u16,
+    advances: Vec<u16>,
+    cmap: HashMap<char, u16>,
+    kerning: HashMap<(u16, u16), i16>,
+    gpos: Option<&'a [u8]>,
+    gpos_lookups: Vec<u16>,
+    outlines: GlyphOutlines<'a>,
+}
+
+impl<'a> FontFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, RenderingError> {
+        return parse_font(data).ok_or(RenderingError::InvalidFont);
+    }
+
+    pub fn glyph_index(&self, c: char) -> Option<u16> { return self.cmap.get(&c).cloned(); }
+
+    // Horizontal advance in font units.
+    pub fn advance(&self, glyph: u16) -> u16 {
+        let index = (glyph as usize).min(self.advances.len() - 1);
+        return self.advances[index];
+    }
+
+    // Adjustment of the advance between two glyphs in font units, from the
+    // pair positioning of `GPOS` or the legacy `kern` table.
+    pub fn kerning(&self, left: u16, right: u16) -> i16 {
+        if let Some(gpos) = self.gpos {
+            let mut total = 0;
+            for &lookup in &self.gpos_lookups {
+                total += gpos_pair_adjustment(gpos, lookup, left, right).unwrap_or(0);
+            }
+            if total != 0 {
+                return total;
+            }
+        }
+        return self.kerning.get(&(left, right)).cloned().unwrap_or(0);
+    }
+
+    // Contours of the glyph, empty for blank glyphs like the space.
+    pub fn outline(&self, glyph: u16) -> Result<Outline, RenderingError> {
+        if glyph >= self.glyph_count {
+            return Err(RenderingError::InvalidFont);
+        }
+        let outline = match &self.outlines {
+            GlyphOutlines::TrueType { glyf, loca } => {
+                let mut builder = OutlineBuilder::default();
+                glyf_outline(glyf, loca, glyph, 0, &mut builder).map(|_| builder.finish())
+            }
+            GlyphOutlines::Cff(cff) => cff.outline(glyph),
+        };
+        return outline.ok_or(RenderingError::InvalidFont);
+    }
+}
+
+fn parse_font(data: &[u8]) -> Option<FontFile<'_>> {
+    let mut offset = 0;
+    if data.get(0..4)? == b"ttcf" {
+        offset = read_u32(data, 12)? as usize;
+    }
+    let version = data.get(offset..offset + 4)?;
+    if version != [0, 1, 0, 0] && version != b"true" && version != b"OTTO" {
+        return None;
+    }
+    let table_count = read_u16(data, offset + 4)? as usize;
+    let mut tables = HashMap::new();
+    for i in 0..table_count {
+        let record = offset + 12 + i * 16;
+        let tag = data.get(record..record + 4)?;
+        let start = read_u32(data, record + 8)? as usize;
+        let length = read_u32(data, record + 12)? as usize;
+        tables.insert(tag, data.get(start..start + length)?);
+    }
+    let table = |tag: &[u8]| tables.get(tag).cloned();
+
+    let head = table(b"head")?;
+    let units_per_em = read_u16(head, 18)?;
+    let long_offsets = read_i16(head, 50)? != 0;
+    let glyph_count = read_u16(table(b"maxp")?, 4)?;
+    let hhea = table(b"hhea")?;
+    let metric_count = read_u16(hhea, 34)? as usize;
+    if units_per_em == 0 || glyph_count == 0 || metric_count == 0 {
+        return None;
+    }
+
+    // Glyphs past the last metric repeat its advance
+    let hmtx = table(b"hmtx")?;
+    let mut advances = Vec::with_capacity(metric_count);
+    for i in 0..metric_count {
+        advances.push(read_u16(hmtx, i * 4)?);
+    }
+
+    let outlines = match table(b"glyf") {
+        Some(glyf) => {
+            let loca = table(b"loca")?;
+            let mut offsets = Vec::with_capacity(glyph_count as usize + 1);
+            for i in 0..=glyph_count as usize {
+                let offset = match long_offsets {
+                    true => read_u32(loca, i * 4)?,
+                    false => read_u16(loca, i * 2)? as u32 * 2,
+                };
+                offsets.push(offset);
+            }
+            GlyphOutlines::TrueType {
+                glyf,
+                loca: offsets,
+            }
+        }
+        None => GlyphOutlines::Cff(CffOutlines::parse(table(b"CFF ")?)?),
+    };
+
+    let gpos = table(b"GPOS");
+    let gpos_lookups = gpos.and_then(gpos_kerning_lookups).unwrap_or_default();
+
+    return Some(FontFile {
+        units_per_em,
+        ascender: read_i16(hhea, 4)?,
+        descender: read_i16(hhea, 6)?,
+        line_gap: read_i16(hhea, 8)?,
+        glyph_count,
+        advances,
+        cmap: parse_cmap(table(b"cmap")?)?,
+        kerning: table(b"kern").and_then(parse_kern).unwrap_or_default(),
+        gpos,
+        gpos_lookups,
+        outlines,
+    });
+}
+
+// Reads the Unicode subtable, preferring full repertoire format 12 subtables
+// over the format 4 ones limited to the basic multilingual plane.
+fn parse_cmap(cmap: &[u8]) -> Option<HashMap<char, u16>> {
+    let mut best: Option<(u32, &[u8])> = None;
+    for i in 0..read_u16(cmap, 2)? as usize {
+        let record = 4 + i * 8;
+        let platform = read_u16(cmap, record)?;
+        let encoding = read_u16(cmap, record + 2)?;
+        let subtable = cmap.get(read_u32(cmap, record + 4)? as usize..)?;
+        let unicode = platform == 0 || (platform == 3 && (encoding == 1 || encoding == 10));
+        let rank = match read_u16(subtable, 0)? {
+            12 => 2,
+            4 => 1,
+            _ => 0,
+        };
+        if unicode && rank > best.map(|b| b.0).unwrap_or(0) {
+            best = Some((rank, subtable));
+        }
+    }
+
+    let mut map = HashMap::new();
+    let mut insert = |code: u32, glyph: u32| {
+        if let Some(c) = std::char::from_u32(code) {
+            if glyph != 0 {
+                map.insert(c, glyph as u16);
+            }
+        }
+    };
+    match best? {
+        (2, subtable) => {
+            for i in 0..read_u32(subtable, 12)? as usize {
+                let group = 16 + i * 12;
+                let first = read_u32(subtable, group)?;
+                let last = read_u32(subtable, group + 4)?;
+                let glyph = read_u32(subtable, group + 8)?;
+                for code in first..=last.min(0x10ffff) {
+                    insert(code, glyph + code - first);
+                }
+            }
+        }
+        (_, subtable) => {
+            let segments = read_u16(subtable, 6)? as usize / 2;
+            let ends = 14;
+            let starts = ends + segments * 2 + 2;
+            let deltas = starts + segments * 2;
+            let range_offsets = deltas + segments * 2;
+            for i in 0..segments {
+                let end = read_u16(subtable, ends + i * 2)? as u32;
+                let start = read_u16(subtable, starts + i * 2)? as u32;
+                let delta = read_u16(subtable, deltas + i * 2)? as u32;
+                let range_offset = read_u16(subtable, range_offsets + i * 2)? as usize;
+                for code in start..=end.min(0xfffe) {
+                    let glyph = if range_offset == 0 {
+                        (code + delta) & 0xffff
+                    } else {
+                        // Offset from the range offset entry into the glyph array
+                        let index =
+                            range_offsets + i * 2 + range_offset + (code - start) as usize * 2;
+                        match read_u16(subtable, index)? as u32 {
+                            0 => 0,
+                            glyph => (glyph + delta) & 0xffff,
+                        }
+                    };
+                    insert(code, glyph);
+                }
+            }
+        }
+    }
+    return Some(map);
+}
+
+// Pairs of the horizontal format 0 subtables of the `kern` table.
+fn parse_kern(kern: &[u8]) -> Option<HashMap<(u16, u16), i16>> {
+    let mut pairs = HashMap::new();
+    if read_u16(kern, 0)? != 0 {
+        return Some(pairs);
+    }
+    let mut offset = 4;
+    for _ in 0..read_u16(kern, 2)? {
+        let length = read_u16(kern, offset + 2)? as usize;
+        let coverage = read_u16(kern, offset + 4)?;
+        let horizontal = coverage & 0x1 != 0 && coverage & 0x4 == 0;
+        if coverage >> 8 == 0 && horizontal {
+            for i in 0..read_u16(kern, offset + 6)? as usize {
+                let pair = offset + 14 + i * 6;
+                let left = read_u16(kern, pair)?;
+                let right = read_u16(kern, pair + 2)?;
+                pairs.insert((left, right), read_i16(kern, pair + 4)?);
+            }
+        }
+        offset += length;
+    }
+    return Some(pairs);
+}
+
+// Lookups of the `kern` features of the `GPOS` table, of any script.
+fn gpos_kerning_lookups(gpos: &[u8]) -> Option<Vec<u16>> {
+    let features = read_u16(gpos, 6)? as usize;
+    let mut lookups = Vec::new();
+    for i in 0..read_u16(gpos, features)? as usize {
+        let record = features + 2 + i * 6;
+        if gpos.get(record..record + 4)? != b"kern" {
+            continue;
+        }
+        let feature = features + read_u16(gpos, record + 4)? as usize;
+        for j in 0..read_u16(gpos, feature + 2)? as usize {
+            let lookup = read_u16(gpos, feature + 4 + j * 2)?;
+            if !lookups.contains(&lookup) {
+                lookups.push(lookup);
+            }
+        }
+    }
+    lookups.sort();
+    return Some(lookups);
+}
+
+// X advance adjustment of the first glyph by a pair positioning lookup, None
+// when no subtable of the lookup covers the pair.
+fn gpos_pair_adjustment(gpos: &[u8], lookup_index: u16, left: u16, right: u16) -> Option<i16> {
+    let lookups = read_u16(gpos, 8)? as usize;
+    let lookup = lookups + read_u16(gpos, lookups + 2 + lookup_index as usize * 2)? as usize;
+    let lookup_type = read_u16(gpos, lookup)?;
+    for i in 0..read_u16(gpos, lookup + 4)? as usize {
+        let mut subtable = lookup + read_u16(gpos, lookup + 6 + i * 2)? as usize;
+        let mut subtable_type = lookup_type;
+        // Extension subtables point to the actual subtable with a 32-bit offset
+        if lookup_type == 9 {
+            subtable_type = read_u16(gpos, subtable + 2)?;
+            subtable += read_u32(gpos, subtable + 4)? as usize;
+        }
+        if subtable_type != 2 {
+            continue;
+        }
+        if let Some(value) = pair_pos_adjustment(gpos.get(subtable..)?, left, right) {
+            return Some(value);
+        }
+    }
+    return None;
+}
+
+fn pair_pos_adjustment(subtable: &[u8], left: u16, right: u16) -> Option<i16> {
+    let coverage_index = coverage_index(subtable.get(read_u16(subtable, 2)? as usize..)?, left)?;
+    let value_format_a = read_u16(subtable, 4)?;
+    let value_format_b = read_u16(subtable, 6)?;
+    let size_a = (value_format_a & 0xff).count_ones() as usize * 2;
+    let size_b = (value_format_b & 0xff).count_ones() as usize * 2;
+    // X advance follows the X and Y placement when they are present
+    let read_advance = |offset: usize| -> Option<i16> {
+        if value_format_a & 0x4 == 0 {
+            return Some(0);
+        }
+        return read_i16(
+            subtable,
+            offset + (value_format_a & 0x3).count_ones() as usize * 2,
+        );
+    };
+    match read_u16(subtable, 0)? {
+        1 => {
+            let pair_set_offset = read_u16(subtable, 10 + coverage_index as usize * 2)? as usize;
+            let record_size = 2 + size_a + size_b;
+            for i in 0..read_u16(subtable, pair_set_offset)? as usize {
+                let record = pair_set_offset + 2 + i * record_size;
+                if read_u16(subtable, record)? == right {
+                    return read_advance(record + 2);
+                }
+            }
+            return None;
+        }
+        2 => {
+            let class_a = class_value(subtable.get(read_u16(subtable, 8)? as usize..)?, left)?;
+            let class_b = class_value(subtable.get(read_u16(subtable, 10)? as usize..)?, right)?;
+            let class_b_count = read_u16(subtable, 14)? as usize;
+            let record =
+                16 + (class_a as usize * class_b_count + class_b as usize) * (size_a + size_b);
+            return read_advance(record);
+        }
+        _ => return None,
+    }
+}
+
+fn coverage_index(coverage: &[u8], glyph: u16) -> Option<u16> {
+    match read_u16(coverage, 0)? {
+        1 => {
+            for i in 0..read_u16(coverage, 2)? {
+                if read_u16(coverage, 4 + i as usize * 2)? == glyph {
+                    return Some(i);
+                }
+            }
+        }
+        2 => {
+            for i in 0..read_u16(coverage, 2)? as usize {
+                let range = 4 + i * 6;
+                let first = read_u16(coverage, range)?;
+                let last = read_u16(coverage, range + 2)?;
+                if (first..=last).contains(&glyph) {
+                    return Some(read_u16(coverage, range + 4)? + glyph - first);
+                }
+            }
+        }
+        _ => {}
+    }
+    return None;
+}
+
+// Glyphs not listed are in class 0.
+fn class_value(class_def: &[u8], glyph: u16) -> Option<u16> {
+    match read_u16(class_def, 0)? {
+        1 => {
+            let first = read_u16(class_def, 2)?;
+            let count = read_u16(class_def, 4)?;
+            if glyph >= first && glyph - first < count {
+                return read_u16(class_def, 6 + (glyph - first) as usize * 2);
+            }
+        }
+        2 => {
+            for i in 0..read_u16(class_def, 2)? as usize {
+                let range = 4 + i * 6;
+                let first = read_u16(class_def, range)?;
+                let last = read_u16(class_def, range + 2)?;
+                if (first..=last).contains(&glyph) {
+                    return read_u16(class_def, range + 4);
+                }
+            }
+        }
+        _ => return None,
+    }
+    return Some(0);
+}
+
+fn glyf_outline(
+    glyf: &[u8],
+    loca: &[u32],
+    glyph: u16,
+    depth: u32,
+    builder: &mut OutlineBuilder,
+) -> Option<()> {
+    let start = *loca.get(glyph as usize)? as usize;
+    let end = *loca.get(glyph as usize + 1)? as usize;
+    if start == end {
+        return Some(());
+    }
+    let data = glyf.get(start..end)?;
+    let contour_count = read_i16(data, 0)?;
+    if contour_count >= 0 {
+        return simple_glyph_outline(data, contour_count as usize, builder);
+    }
+    if depth >= MAX_COMPONENT_DEPTH {
+        return None;
+    }
+
+    // Composite glyph, components are transformed copies of other glyphs
+    let mut offset = 10;
+    loop {
+        let flags = read_u16(data, offset)?;
+        let component = read_u16(data, offset + 2)?;
+        offset += 4;
+        let (dx, dy) = if flags & 0x1 != 0 {
+            let args = (read_i16(data, offset)?, read_i16(data, offset + 2)?);
+            offset += 4;
+            (args.0 as FScalar, args.1 as FScalar)
+        } else {
+            let args = (
+                read_u8(data, offset)? as i8,
+                read_u8(data, offset + 1)? as i8,
+            );
+            offset += 2;
+            (args.0 as FScalar, args.1 as FScalar)
+        };
+        // Components aligned by matching points are placed without an offset
+        let (dx, dy) = match flags & 0x2 != 0 {
+            true => (dx, dy),
+            false => (0.0, 0.0),
+        };
+        let f2dot14 = |offset: usize| read_i16(data, offset).map(|v| v as FScalar / 16384.0);
+        let mut matrix = [1.0, 0.0, 0.0, 1.0];
+        if flags & 0x8 != 0 {
+            let scale = f2dot14(offset)?;
+            matrix = [scale, 0.0, 0.0, scale];
+            offset += 2;
+        } else if flags & 0x40 != 0 {
+            matrix = [f2dot14(offset)?, 0.0, 0.0, f2dot14(offset + 2)?];
+            offset += 4;
+        } else if flags & 0x80 != 0 {
+            matrix = [
+                f2dot14(offset)?,
+                f2dot14(offset + 2)?,
+                f2dot14(offset + 4)?,
+                f2dot14(offset + 6)?,
+            ];
+            offset += 8;
+        }
+
+        let mut component_builder = OutlineBuilder::default();
+        glyf_outline(glyf, loca, component, depth + 1, &mut component_builder)?;
+        for contour in component_builder.finish() {
+            let transform = |p: Vector2f| {
+                return Vector2f::new(
+                    matrix[0] * p.x + matrix[2] * p.y + dx,
+                    matrix[1] * p.x + matrix[3] * p.y + dy,
+                );
+            };
+            builder.contours.push(
+                contour
+                    .iter()
+                    .map(|segment| segment.transform(transform))
+                    .collect(),
+            );
+        }
+        if flags & 0x20 == 0 {
+            return Some(());
+        }
+    }
+}
+
+fn simple_glyph_outline(
+    data: &[u8],
+    contour_count: usize,
+    builder: &mut OutlineBuilder,
+) -> Option<()> {
+    let mut contour_ends = Vec::with_capacity(contour_count);
+    for i in 0..contour_count {
+        contour_ends.push(read_u16(data, 10 + i * 2)? as usize);
+    }
+    let point_count = match contour_ends.last() {
+        Some(&last) => last + 1,
+        None => return Some(()),
+    };
+    let instructions = 10 + contour_count * 2;
+    let mut offset = instructions + 2 + read_u16(data, instructions)? as usize;
+
+    let mut flags = Vec::with_capacity(point_count);
+    while flags.len() < point_count {
+        let flag = read_u8(data, offset)?;
+        offset += 1;
+        flags.push(flag);
+        if flag & 0x8 != 0 {
+            let repeat = read_u8(data, offset)?;
+            offset += 1;
+            for _ in 0..repeat {
+                flags.push(flag);
+            }
+        }
+    }
+    flags.truncate(point_count);
+
+    // Coordinates are deltas, short ones store the sign in the flags
+    let mut read_coordinates = |short_bit: u8, same_bit: u8| -> Option<Vec<FScalar>> {
+        let mut value = 0i32;
+        let mut coordinates = Vec::with_capacity(point_count);
+        for &flag in &flags {
+            if flag & short_bit != 0 {
+                let delta = read_u8(data, offset)? as i32;
+                offset += 1;
+                value += if flag & same_bit != 0 { delta } else { -delta };
+            } else if flag & same_bit == 0 {
+                value += read_i16(data, offset)? as i32;
+                offset += 2;
+            }
+            coordinates.push(value as FScalar);
+        }
+        return Some(coordinates);
+    };
+    let xs = read_coordinates(0x2, 0x10)?;
+    let ys = read_coordinates(0x4, 0x20)?;
+
+    let mut start = 0;
+    for &end in &contour_ends {
+        if end < start || end >= point_count {
+            return None;
+        }
+        let points: Vec<(Vector2f, bool)> = (start..=end)
+            .map(|i| (Vector2f::new(xs[i], ys[i]), flags[i] & 0x1 != 0))
+            .collect();
+        add_quadratic_contour(&points, builder);
+        start = end + 1;
+    }
+    return Some(());
+}
+
+// Adds a contour of on-curve points and quadratic control points. Two control
+// points in a row imply an on-curve point halfway between them.
+fn add_quadratic_contour(points: &[(Vector2f, bool)], builder: &mut OutlineBuilder) {
+    let count = points.len();
+    if count == 0 {
+        return;
+    }
+    let first_on = points.iter().position(|p| p.1);
+    let (start, first) = match first_on {
+        Some(i) => (points[i].0, i),
+        // Only control points, start between the first two
+        None => ((points[0].0 + points[1 % count].0) * 0.5, 0),
+    };
+    builder.move_to(start);
+    let mut control: Option<Vector2f> = None;
+    for i in 1..=count {
+        let (point, on_curve) = points[(first + i) % count];
+        match (on_curve, control) {
+            (true, Some(c)) => {
+                builder.quad_to(c, point);
+                control = None;
+            }
+            (true, None) => builder.line_to(point),
+            (false, Some(c)) => {
+                builder.quad_to(c, (c + point) * 0.5);
+                control = Some(point);
+            }
+            (false, None) => control = Some(point),
+        }
+    }
+    if let Some(c) = control {
+        builder.quad_to(c, start);
+    }
+    builder.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_glyph_test() {
+        // Triangle with an off-curve point between the last and the first point
+        let mut data = vec![0, 1, 0, 0, 0, 0, 0, 100, 0, 100];
+        data.extend(&[0, 3]);
+        data.extend(&[0, 0]);
+        // On, on, on, off
+        data.extend(&[0x37, 0x33, 0x27, 0x06]);
+        // X: 0, +100, -50, -50
+        data.extend(&[0, 100, 50, 50]);
+        // Y: 0, same, +100, -50
+        data.extend(&[0, 100, 50]);
+        let mut builder = OutlineBuilder::default();
+        simple_glyph_outline(&data, 1, &mut builder).unwrap();
+        let outline = builder.finish();
+        assert_eq!(outline.len(), 1);
+        assert_eq!(
+            outline[0],
+            vec![
+                OutlineSegment::Line(Vector2f::new(0.0, 0.0), Vector2f::new(100.0, 0.0)),
+                OutlineSegment::Line(Vector2f::new(100.0, 0.0), Vector2f::new(50.0, 100.0)),
+                OutlineSegment::Quadratic(
+                    Vector2f::new(50.0, 100.0),
+                    Vector2f::new(0.0, 50.0),
+                    Vector2f::new(0.0, 0.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn implied_points_test() {
+        let points = [
+            (Vector2f::new(0.0, 0.0), false),
+            (Vector2f::new(2.0, 0.0), false),
+            (Vector2f::new(2.0, 2.0), false),
+            (Vector2f::new(0.0, 2.0), false),
+        ];
+        let mut builder = OutlineBuilder::default();
+        add_quadratic_contour(&points, &mut builder);
+        let outline = builder.finish();
+        assert_eq!(outline[0].len(), 4);
+        assert_eq!(outline[0][0].start(), Vector2f::new(1.0, 0.0));
+        assert_eq!(outline[0][3].end(), Vector2f::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn cmap_test() {
+        // Format 4 with 'A'..'C' mapped by delta and the terminating segment
+        let mut subtable = vec![0, 4, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0];
+        subtable.extend(&[0, 0x43, 0xff, 0xff, 0, 0]);
+        subtable.extend(&[0, 0x41, 0xff, 0xff]);
+        subtable.extend(&[0xff, 0xc2, 0, 1]);
+        subtable.extend(&[0, 0, 0, 0]);
+        let mut cmap = vec![0, 0, 0, 1, 0, 3, 0, 1, 0, 0, 0, 12];
+        cmap.extend(subtable);
+        let map = parse_cmap(&cmap).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&'A'], 3);
+        assert_eq!(map[&'C'], 5);
+    }
+
+    #[test]
+    fn kern_test() {
+        let mut kern = vec![0, 0, 0, 1];
+        kern.extend(&[0, 0, 0, 26, 0, 1]);
+        kern.extend(&[0, 2, 0, 0, 0, 0, 0, 0]);
+        kern.extend(&[0, 3, 0, 5, 0xff, 0xb0]);
+        kern.extend(&[0, 5, 0, 3, 0, 10]);
+        let pairs = parse_kern(&kern).unwrap();
+        assert_eq!(pairs[&(3, 5)], -80);
+        assert_eq!(pairs[&(5, 3)], 10);
+    }
+}
@@ -0,0 +1,534 @@
+use crate::{
+    error::RenderingError,
+    font::{FontFile, OutlineSegment},
+    sdf::generate_mtsdf,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, fs, path::PathBuf};
+
+// Changes whenever the generated atlases do, older cache files are ignored.
+const CACHE_VERSION: u32 = 1;
+const CACHE_MAGIC: &[u8; 4] = b"PSDF";
+
+// Empty texels between glyphs in the atlas.
+const GLYPH_SPACING: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontSettings {
+    // Atlas pixels per em, larger sizes keep small details and corners sharp
+    pub em_size: u32,
+    // Width of the distance field around the outlines in atlas pixels.
+    // Outlines and shadow softness are limited to half of it
+    pub distance_range: FScalar,
+    // Characters in the atlas, others are drawn as the missing glyph
+    pub characters: Vec<char>,
+    // Generated atlases are stored in and loaded from this directory
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for FontSettings {
+    fn default() -> Self {
+        return FontSettings {
+            em_size: 48,
+            distance_range: 8.0,
+            characters: (' '..='~').collect(),
+            cache_dir: None,
+        };
+    }
+}
+
+// Atlas rectangle of a glyph and the area it covers around the pen position
+// on the baseline, in em with Y pointing up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GlyphQuad {
+    pub plane_min: Vector2f,
+    pub plane_max: Vector2f,
+    pub atlas_offset: [u32; 2],
+    pub atlas_size: [u32; 2],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SdfGlyph {
+    // Horizontal advance in em
+    pub advance: FScalar,
+    // None for blank glyphs like the space
+    pub quad: Option<GlyphQuad>,
+}
+
+// Metrics of the atlas glyphs, distances in em.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontMetrics {
+    pub em_size: u32,
+    pub distance_range: FScalar,
+    pub ascender: FScalar,
+    // Negative, below the baseline
+    pub descender: FScalar,
+    // Baseline to baseline distance
+    pub line_height: FScalar,
+    pub atlas_size: [u32; 2],
+    pub glyphs: HashMap<char, SdfGlyph>,
+    // Drawn for characters without a glyph in the atlas
+    pub missing_glyph: SdfGlyph,
+    // Advance adjustment between two characters
+    pub kerning: HashMap<(char, char), FScalar>,
+}
+
+impl FontMetrics {
+    pub fn glyph(&self, c: char) -> &SdfGlyph {
+        return self.glyphs.get(&c).unwrap_or(&self.missing_glyph);
+    }
+
+    pub fn kerning(&self, left: char, right: char) -> FScalar {
+        return self.kerning.get(&(left, right)).cloned().unwrap_or(0.0);
+    }
+}
+
+// Font with a multi-channel signed distance field atlas of its glyphs, text
+// stays sharp at any size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdfFont {
+    pub metrics: FontMetrics,
+    // Linear RGBA texels, row by row
+    pub texels: Vec<[u8; 4]>,
+}
+
+impl SdfFont {
+    // Parses a TTF or OTF font and generates its atlas. With a cache directory
+    // the atlas is only generated the first time and loaded afterwards.
+    pub fn load(data: &[u8], settings: &FontSettings) -> Result<Self, RenderingError> {
+        let cache_path = settings
+            .cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{:016x}.sdf", cache_key(data, settings))));
+        if let Some(path) = &cache_path {
+            let cached = fs::read(path)
+                .ok()
+                .and_then(|bytes| SdfFont::from_bytes(&bytes));
+            if let Some(font) = cached {
+                return Ok(font);
+            }
+        }
+
+        let font = SdfFont::generate(&FontFile::parse(data)?, settings)?;
+        if let Some(path) = &cache_path {
+            let written = fs::create_dir_all(path.parent().unwrap())
+                .and_then(|_| fs::write(path, font.to_bytes()));
+            if let Err(e) = written {
+                log::warn!("Failed to cache the font atlas in {:?}: {}", path, e);
+            }
+        }
+        return Ok(font);
+    }
+
+    pub fn generate(font: &FontFile, settings: &FontSettings) -> Result<Self, RenderingError> {
+        let units_per_em = font.units_per_em as FScalar;
+        let scale = settings.em_size as FScalar / units_per_em;
+        let padding = (settings.distance_range / 2.0).ceil() as u32 + 1;
+        let mut characters = settings.characters.clone();
+        characters.sort();
+        characters.dedup();
+
+        // Glyph 0 is the missing glyph
+        let mut entries: Vec<(Option<char>, u16)> = characters
+            .iter()
+            .filter_map(|&c| font.glyph_index(c).map(|glyph| (Some(c), glyph)))
+            .collect();
+        entries.push((None, 0));
+
+        let mut outlines = Vec::with_capacity(entries.len());
+        let mut sizes = Vec::new();
+        for &(_, glyph) in &entries {
+            let outline = font.outline(glyph)?;
+            // Control points enclose the curves, the box may be slightly larger
+            let points = outline.iter().flatten().flat_map(|segment| {
+                return match *segment {
+                    OutlineSegment::Line(a, b) => vec![a, b],
+                    OutlineSegment::Quadratic(a, b, c) => vec![a, b, c],
+                    OutlineSegment::Cubic(a, b, c, d) => vec![a, b, c, d],
+                };
+            });
+            let bounds = points.fold(None, |bounds: Option<(Vector2f, Vector2f)>, p| {
+                return match bounds {
+                    Some((min, max)) => {
+                        Some((min.zip_map(&p, FScalar::min), max.zip_map(&p, FScalar::max)))
+                    }
+                    None => Some((p, p)),
+                };
+            });
+            if let Some((min, max)) = bounds {
+                let size = [
+                    ((max.x - min.x) * scale).ceil() as u32 + padding * 2,
+                    ((max.y - min.y) * scale).ceil() as u32 + padding * 2,
+                ];
+                sizes.push(size);
+                outlines.push(Some((outline, min, sizes.len() - 1)));
+            } else {
+                outlines.push(None);
+            }
+        }
+
+        let (atlas_size, offsets) = pack_rectangles(&sizes);
+        let mut texels = vec![[0u8; 4]; (atlas_size[0] * atlas_size[1]) as usize];
+        let mut glyphs = HashMap::new();
+        let mut missing_glyph = None;
+        for (&(c, glyph), outline) in entries.iter().zip(&outlines) {
+            let quad = outline.as_ref().map(|(outline, min, rectangle)| {
+                let [width, height] = sizes[*rectangle];
+                let offset = offsets[*rectangle];
+                let glyph_texels = generate_mtsdf(
+                    outline,
+                    width,
+                    height,
+                    |p| {
+                        let x = (p.x - min.x) * scale + padding as FScalar;
+                        let y = (p.y - min.y) * scale + padding as FScalar;
+                        return Vector2f::new(x, height as FScalar - y);
+                    },
+                    settings.distance_range,
+                );
+                for row in 0..height {
+                    let start = ((offset[1] + row) * atlas_size[0] + offset[0]) as usize;
+                    let source = (row * width) as usize;
+                    texels[start..start + width as usize]
+                        .copy_from_slice(&glyph_texels[source..source + width as usize]);
+                }
+
+                let em_size = settings.em_size as FScalar;
+                let plane_min = Vector2f::new(
+                    min.x / units_per_em - padding as FScalar / em_size,
+                    min.y / units_per_em - padding as FScalar / em_size,
+                );
+                return GlyphQuad {
+                    plane_min,
+                    plane_max: plane_min
+                        + Vector2f::new(width as FScalar / em_size, height as FScalar / em_size),
+                    atlas_offset: offset,
+                    atlas_size: [width, height],
+                };
+            });
+            let sdf_glyph = SdfGlyph {
+                advance: font.advance(glyph) as FScalar / units_per_em,
+                quad,
+            };
+            match c {
+                Some(c) => {
+                    glyphs.insert(c, sdf_glyph);
+                }
+                None => missing_glyph = Some(sdf_glyph),
+            }
+        }
+
+        let mut kerning = HashMap::new();
+        for &(left, left_glyph) in &entries {
+            for &(right, right_glyph) in &entries {
+                if let (Some(left), Some(right)) = (left, right) {
+                    let value = font.kerning(left_glyph, right_glyph);
+                    if value != 0 {
+                        kerning.insert((left, right), value as FScalar / units_per_em);
+                    }
+                }
+            }
+        }
+
+        let metrics = FontMetrics {
+            em_size: settings.em_size,
+            distance_range: settings.distance_range,
+            ascender: font.ascender as FScalar / units_per_em,
+            descender: font.descender as FScalar / units_per_em,
+            line_height: line_height(font.ascender, font.descender, font.line_gap) as FScalar
+                / units_per_em,
+            atlas_size,
+            glyphs,
+            missing_glyph: missing_glyph.unwrap(),
+            kerning,
+        };
+        return Ok(SdfFont { metrics, texels });
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let metrics = &self.metrics;
+        let mut bytes = CACHE_MAGIC.to_vec();
+        write_u32(&mut bytes, CACHE_VERSION);
+        write_u32(&mut bytes, metrics.em_size);
+        write_f32(&mut bytes, metrics.distance_range);
+        write_f32(&mut bytes, metrics.ascender);
+        write_f32(&mut bytes, metrics.descender);
+        write_f32(&mut bytes, metrics.line_height);
+        write_u32(&mut bytes, metrics.atlas_size[0]);
+        write_u32(&mut bytes, metrics.atlas_size[1]);
+        write_glyph(&mut bytes, &metrics.missing_glyph);
+        write_u32(&mut bytes, metrics.glyphs.len() as u32);
+        for (&c, glyph) in &metrics.glyphs {
+            write_u32(&mut bytes, c as u32);
+            write_glyph(&mut bytes, glyph);
+        }
+        write_u32(&mut bytes, metrics.kerning.len() as u32);
+        for (&(left, right), &value) in &metrics.kerning {
+            write_u32(&mut bytes, left as u32);
+            write_u32(&mut bytes, right as u32);
+            write_f32(&mut bytes, value);
+        }
+        bytes.extend(self.texels.iter().flatten());
+        return bytes;
+    }
+
+    // Returns None for data of another format or cache version.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader { bytes, position: 0 };
+        if reader.take(4)? != CACHE_MAGIC || reader.u32()? != CACHE_VERSION {
+            return None;
+        }
+        let em_size = reader.u32()?;
+        let distance_range = reader.f32()?;
+        let ascender = reader.f32()?;
+        let descender = reader.f32()?;
+        let line_height = reader.f32()?;
+        let atlas_size = [reader.u32()?, reader.u32()?];
+        let missing_glyph = read_glyph(&mut reader)?;
+        let mut glyphs = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let c = std::char::from_u32(reader.u32()?)?;
+            glyphs.insert(c, read_glyph(&mut reader)?);
+        }
+        let mut kerning = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let left = std::char::from_u32(reader.u32()?)?;
+            let right = std::char::from_u32(reader.u32()?)?;
+            kerning.insert((left, right), reader.f32()?);
+        }
+        // Sizes come from the file, so a corrupt header must not overflow
+        let texel_bytes = (atlas_size[0] as usize)
+            .checked_mul(atlas_size[1] as usize)?
+            .checked_mul(4)?;
+        if texel_bytes > reader.remaining() {
+            return None;
+        }
+        let texels = reader
+            .take(texel_bytes)?
+            .chunks_exact(4)
+            .map(|t| [t[0], t[1], t[2], t[3]])
+            .collect();
+
+        let metrics = FontMetrics {
+            em_size,
+            distance_range,
+            ascender,
+            descender,
+            line_height,
+            atlas_size,
+            glyphs,
+            missing_glyph,
+            kerning,
+        };
+        return Some(SdfFont { metrics, texels });
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(count)?;
+        let taken = self.bytes.get(self.position..end)?;
+        self.position = end;
+        return Some(taken);
+    }
+
+    fn remaining(&self) -> usize { return self.bytes.len() - self.position; }
+
+    fn u32(&mut self) -> Option<u32> {
+        let b = self.take(4)?;
+        return Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    }
+
+    fn f32(&mut self) -> Option<FScalar> { return self.u32().map(FScalar::from_bits); }
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) { bytes.extend(&value.to_le_bytes()); }
+
+fn write_f32(bytes: &mut Vec<u8>, value: FScalar) { write_u32(bytes, value.to_bits()); }
+
+fn write_glyph(bytes: &mut Vec<u8>, glyph: &SdfGlyph) {
+    write_f32(bytes, glyph.advance);
+    match glyph.quad {
+        Some(quad) => {
+            bytes.push(1);
+            for &value in quad.plane_min.iter().chain(quad.plane_max.iter()) {
+                write_f32(bytes, value);
+            }
+            for &value in quad.atlas_offset.iter().chain(&quad.atlas_size) {
+                write_u32(bytes, value);
+            }
+        }
+        None => bytes.push(0),
+    }
+}
+
+fn read_glyph(reader: &mut ByteReader) -> Option<SdfGlyph> {
+    let advance = reader.f32()?;
+    let quad = match reader.take(1)?[0] {
+        0 => None,
+        _ => Some(GlyphQuad {
+            plane_min: Vector2f::new(reader.f32()?, reader.f32()?),
+            plane_max: Vector2f::new(reader.f32()?, reader.f32()?),
+            atlas_offset: [reader.u32()?, reader.u32()?],
+            atlas_size: [reader.u32()?, reader.u32()?],
+        }),
+    };
+    return Some(SdfGlyph { advance, quad });
+}
+
+// Baseline to baseline distance in font units, widened as extreme hhea values
+// overflow i16.
+fn line_height(ascender: i16, descender: i16, line_gap: i16) -> i32 {
+    return ascender as i32 - descender as i32 + line_gap as i32;
+}
+
+// Shelf packing of the rectangles, tallest first. Returns the atlas size and
+// the offset of every rectangle.
+pub fn pack_rectangles(sizes: &[[u32; 2]]) -> ([u32; 2], Vec<[u32; 2]>) {
+    let area: u32 = sizes
+        .iter()
+        .map(|s| (s[0] + GLYPH_SPACING) * (s[1] + GLYPH_SPACING))
+        .sum();
+    let widest = sizes
+        .iter()
+        .map(|s| s[0] + GLYPH_SPACING)
+        .max()
+        .unwrap_or(1);
+    let width = ((area as f64 * 1.1).sqrt().ceil() as u32)
+        .max(widest)
+        .next_power_of_two();
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i][1]));
+    let mut offsets = vec![[0, 0]; sizes.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for i in order {
+        let [w, h] = sizes[i];
+        if x + w > width {
+            x = 0;
+            y += shelf_height + GLYPH_SPACING;
+            shelf_height = 0;
+        }
+        offsets[i] = [x, y];
+        x += w + GLYPH_SPACING;
+        shelf_height = shelf_height.max(h);
+    }
+    let height = ((y + shelf_height).max(1) + 3) / 4 * 4;
+    return ([width, height], offsets);
+}
+
+// FNV-1a hash of everything the atlas depends on.
+fn cache_key(data: &[u8], settings: &FontSettings) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |bytes: &[u8]| {
+        for &b in bytes {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    feed(&CACHE_VERSION.to_le_bytes());
+    feed(data);
+    feed(&settings.em_size.to_le_bytes());
+    feed(&settings.distance_range.to_bits().to_le_bytes());
+    for &c in &settings.characters {
+        feed(&(c as u32).to_le_bytes());
+    }
+    return hash;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_test() {
+        let sizes = [[10, 20], [30, 5], [8, 8], [12, 20], [40, 1]];
+        let (atlas, offsets) = pack_rectangles(&sizes);
+        assert!(atlas[0].is_power_of_two());
+        assert_eq!(atlas[1] % 4, 0);
+        for (i, (a, offset_a)) in sizes.iter().zip(&offsets).enumerate() {
+            assert!(offset_a[0] + a[0] <= atlas[0] && offset_a[1] + a[1] <= atlas[1]);
+            for (b, offset_b) in sizes.iter().zip(&offsets).skip(i + 1) {
+                let apart = offset_a[0] + a[0] <= offset_b[0]
+                    || offset_b[0] + b[0] <= offset_a[0]
+                    || offset_a[1] + a[1] <= offset_b[1]
+                    || offset_b[1] + b[1] <= offset_a[1];
+                assert!(apart);
+            }
+        }
+    }
+
+    #[test]
+    fn line_height_test() {
+        assert_eq!(line_height(800, -200, 100), 1100);
+        assert_eq!(line_height(i16::MAX, i16::MIN, i16::MAX), 98302);
+        assert_eq!(line_height(i16::MIN, i16::MAX, i16::MIN), -98303);
+    }
+
+    #[test]
+    fn cache_test() {
+        let glyph = SdfGlyph {
+            advance: 0.5,
+            quad: Some(GlyphQuad {
+                plane_min: Vector2f::new(-0.1, -0.2),
+                plane_max: Vector2f::new(0.6, 0.7),
+                atlas_offset: [0, 1],
+                atlas_size: [2, 1],
+            }),
+        };
+        let mut glyphs = HashMap::new();
+        glyphs.insert('a', glyph);
+        glyphs.insert(
+            ' ',
+            SdfGlyph {
+                advance: 0.25,
+                quad: None,
+            },
+        );
+        let mut kerning = HashMap::new();
+        kerning.insert(('a', 'a'), -0.05);
+        let font = SdfFont {
+            metrics: FontMetrics {
+                em_size: 32,
+                distance_range: 4.0,
+                ascender: 0.8,
+                descender: -0.2,
+                line_height: 1.2,
+                atlas_size: [2, 2],
+                glyphs,
+                missing_glyph: glyph,
+                kerning,
+            },
+            texels: vec![
+                [1, 2, 3, 4],
+                [5, 6, 7, 8],
+                [9, 10, 11, 12],
+                [13, 14, 15, 16],
+            ],
+        };
+        let bytes = font.to_bytes();
+        assert_eq!(SdfFont::from_bytes(&bytes), Some(font));
+        assert_eq!(SdfFont::from_bytes(&bytes[..bytes.len() - 1]), None);
+        // Atlas size in the header overflowing the texel count
+        let mut corrupt = bytes.clone();
+        corrupt[28..36].copy_from_slice(&[0xff; 8]);
+        assert_eq!(SdfFont::from_bytes(&corrupt), None);
+
+        let settings = FontSettings::default();
+        let larger = FontSettings {
+            em_size: 64,
+            ..settings.clone()
+        };
+        assert_eq!(cache_key(b"font", &settings), cache_key(b"font", &settings));
+        assert_ne!(cache_key(b"font", &settings), cache_key(b"font", &larger));
+        assert_ne!(
+            cache_key(b"font", &settings),
+            cache_key(b"other", &settings)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use polyengine_core::*;
+
+// Plane in the `dot(normal, p) + d = 0` form, normal points inside of the
+// frustum.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3f,
+    pub d: FScalar,
+}
+
+impl Plane {
+    fn from_row(row: na::RowVector4<FScalar>) -> Self {
+        let normal = Vector3f::new(row[0], row[1], row[2]);
+        let length = normal.norm();
+        return Plane {
+            normal: normal / length,
+            d: row[3] / length,
+        };
+    }
+
+    pub fn signed_distance(&self, p: &Vector3f) -> FScalar { return self.normal.dot(p) + self.d; }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    // Left, right, bottom, top, near, far
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Extracts planes from a view-projection matrix producing Vulkan clip space
+    // (depth in <0, 1>).
+    pub fn from_matrix(m: &Matrix4f) -> Self {
+        let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        return Frustum {
+            planes: [
+                Plane::from_row(r3 + r0),
+                Plane::from_row(r3 - r0),
+                Plane::from_row(r3 + r1),
+                Plane::from_row(r3 - r1),
+                Plane::from_row(r2.into_owned()),
+                Plane::from_row(r3 - r2),
+            ],
+        };
+    }
+
+    pub fn contains_point(&self, p: &Vector3f) -> bool {
+        return self
+            .planes
+            .iter()
+            .all(|plane| plane.signed_distance(p) >= 0.0);
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        return self
+            .planes
+            .iter()
+            .all(|plane| plane.signed_distance(&sphere.center) >= -sphere.radius);
+    }
+
+    // Conservative test, can report boxes near frustum corners as visible.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let half_extents = aabb.half_extents();
+        return self.planes.iter().all(|plane| {
+            let radius = half_extents.dot(&plane.normal.abs());
+            plane.signed_distance(&center) >= -radius
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Frustum;
+    use crate::{
+        bounds::{Aabb, BoundingSphere},
+        camera::Camera,
+    };
+    use polyengine_core::*;
+
+    fn test_frustum() -> Frustum {
+        let camera = Camera::new(
+            Isometry3::identity(),
+            std::f32::consts::FRAC_PI_2,
+            0.1,
+            100.0,
+        );
+        return Frustum::from_matrix(&camera.view_projection_matrix(1.0));
+    }
+
+    #[test]
+    fn point_test() {
+        let frustum = test_frustum();
+        assert!(frustum.contains_point(&Vector3f::new(0.0, 0.0, -1.0)));
+        assert!(frustum.contains_point(&Vector3f::new(0.9, -0.9, -1.0)));
+        assert!(!frustum.contains_point(&Vector3f::new(0.0, 0.0, 1.0)));
+        assert!(!frustum.contains_point(&Vector3f::new(0.0, 0.0, -0.05)));
+        assert!(!frustum.contains_point(&Vector3f::new(0.0, 0.0, -101.0)));
+        assert!(!frustum.contains_point(&Vector3f::new(1.1, 0.0, -1.0)));
+    }
+
+    #[test]
+    fn sphere_test() {
+        let frustum = test_frustum();
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(Vector3f::new(0.0, 0.0, 5.0), 5.5)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(Vector3f::new(0.0, 0.0, 5.0), 4.5)));
+        assert!(
+            !frustum.intersects_sphere(&BoundingSphere::new(Vector3f::new(-20.0, 0.0, -5.0), 1.0))
+        );
+    }
+
+    #[test]
+    fn aabb_test() {
+        let frustum = test_frustum();
+        let visible = Aabb::new(
+            Vector3f::new(-1.0, -1.0, -6.0),
+            Vector3f::new(1.0, 1.0, -4.0),
+        );
+        let behind = Aabb::new(Vector3f::new(-1.0, -1.0, 4.0), Vector3f::new(1.0, 1.0, 6.0));
+        let above = Aabb::new(
+            Vector3f::new(-1.0, 10.0, -6.0),
+            Vector3f::new(1.0, 12.0, -4.0),
+        );
+        assert!(frustum.intersects_aabb(&visible));
+        assert!(!frustum.intersects_aabb(&behind));
+        assert!(!frustum.intersects_aabb(&above));
+    }
+}
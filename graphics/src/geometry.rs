@@ -3,7 +3,7 @@ use vulkano::{
     device::Device,
 };
 
-use crate::vertex::Vertex;
+use crate::{bounds::Aabb, vertex::Vertex};
 pub use polyengine_core::*;
 use std::sync::Arc;
 
@@ -11,23 +11,54 @@ pub type GeometryId = u32;
 
 pub struct Geometry {
     pub vertex_buffer: Vec<Arc<dyn vulkano::buffer::BufferAccess + Send + Sync>>,
+    // Same buffer as `vertex_buffer`, copied into the shared geometry arena of
+    // GPU driven rendering
+    pub vertices: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    // Local space bounds
+    pub bounds: Aabb,
 }
 
 impl Geometry {
+    // Builds geometry from a triangle list, normals are generated per face.
     pub fn from_data(device: Arc<Device>, data: &Vec<na::Vector3<FScalar>>) -> Self {
-        let mapper = |v3: &na::Vector3<FScalar>| {
-            return Vertex {
-                position: [v3.x as f32, v3.y as f32, v3.z as f32],
-            };
-        };
-        let map_handle = data.into_iter().map(mapper);
+        let mut vertices = Vec::with_capacity(data.len());
+        for triangle in data.chunks(3) {
+            let normal = match triangle {
+                [a, b, c] => (b - a).cross(&(c - a)).try_normalize(FScalar::EPSILON),
+                _ => None,
+            }
+            .unwrap_or_else(Vector3f::z);
+
+            for v3 in triangle {
+                vertices.push(Vertex {
+                    position: [v3.x as f32, v3.y as f32, v3.z as f32],
+                    normal: [normal.x, normal.y, normal.z],
+                    uv: [0.0, 0.0],
+                });
+            }
+        }
+
+        return Geometry::from_vertices(device, &vertices);
+    }
+
+    pub fn from_vertices(device: Arc<Device>, vertices: &[Vertex]) -> Self {
+        let mut bounds = Aabb::empty();
+        for v in vertices {
+            bounds.extend(&Vector3f::from(v.position));
+        }
 
-        let vertex_buffer =
-            CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::all(), false, map_handle)
-                .unwrap();
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::all(),
+            false,
+            vertices.iter().cloned(),
+        )
+        .unwrap();
 
         return Geometry {
-            vertex_buffer: vec![vertex_buffer],
+            vertex_buffer: vec![vertex_buffer.clone()],
+            vertices: vertex_buffer,
+            bounds,
         };
     }
 }
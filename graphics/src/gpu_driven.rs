@@ -0,0 +1,68 @@
+// Push constant draw index of indirect draws, the scene vertex shader reads the
+// draw data at the instance index instead. Has to match `draw_data.glsl`.
+pub const INSTANCED_DRAW: u32 = u32::MAX;
+
+// Has to match `gpu_cull.comp`.
+pub const MAX_HIZ_LEVELS: usize = 16;
+
+// Region of a hierarchical depth level in the atlas, in texels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HizLevel {
+    pub offset: [u32; 2],
+    pub size: [u32; 2],
+}
+
+// Atlas size and levels of the hierarchical depth of a depth buffer. Level 0 is
+// half of the depth buffer resolution and sits in the top left corner, the
+// following levels are stacked to the right of it, down to 1x1.
+pub fn hiz_layout(depth_dimensions: [u32; 2]) -> ([u32; 2], Vec<HizLevel>) {
+    let half = |size: u32| ((size + 1) / 2).max(1);
+    let mut size = [half(depth_dimensions[0]), half(depth_dimensions[1])];
+    let mut levels = vec![HizLevel {
+        offset: [0, 0],
+        size,
+    }];
+    let column = size[0];
+    let mut row = 0;
+    while (size[0] > 1 || size[1] > 1) && levels.len() < MAX_HIZ_LEVELS {
+        size = [half(size[0]), half(size[1])];
+        levels.push(HizLevel {
+            offset: [column, row],
+            size,
+        });
+        row += size[1];
+    }
+
+    let width = column + levels.get(1).map_or(0, |level| level.size[0]);
+    let height = levels[0].size[1].max(row);
+    return ([width, height], levels);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hiz_layout_test() {
+        let (atlas, levels) = hiz_layout([1280, 720]);
+        assert_eq!(levels[0].size, [640, 360]);
+        assert_eq!(levels[1].offset, [640, 0]);
+        assert_eq!(levels[1].size, [320, 180]);
+        assert_eq!(levels[2].offset, [640, 180]);
+        assert_eq!(levels.last().unwrap().size, [1, 1]);
+        assert_eq!(atlas[0], 960);
+        for level in &levels {
+            assert!(level.offset[0] + level.size[0] <= atlas[0]);
+            assert!(level.offset[1] + level.size[1] <= atlas[1]);
+        }
+
+        // Odd sizes round up, so every depth texel is covered
+        let (_, levels) = hiz_layout([5, 3]);
+        let sizes: Vec<[u32; 2]> = levels.iter().map(|level| level.size).collect();
+        assert_eq!(sizes, vec![[3, 2], [2, 1], [1, 1]]);
+
+        let (atlas, levels) = hiz_layout([1, 1]);
+        assert_eq!(levels.len(), 1);
+        assert_eq!(atlas, [1, 1]);
+    }
+}
@@ -0,0 +1,474 @@
+use crate::{
+    compute_pass::ComputeKernel,
+    config,
+    culling::DrawItem,
+    frustum::Frustum,
+    geometry::{Geometry, GeometryId},
+    gpu_driven::{hiz_layout, HizLevel, INSTANCED_DRAW, MAX_HIZ_LEVELS},
+    object::{ObjectId, RenderObject},
+    vertex::Vertex,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    buffer::{
+        cpu_pool::CpuBufferPoolChunk,
+        BufferSlice,
+        BufferUsage,
+        CpuAccessibleBuffer,
+        CpuBufferPool,
+    },
+    command_buffer::{AutoCommandBufferBuilder, DrawIndexedIndirectCommand, DynamicState},
+    descriptor::descriptor_set::{DescriptorSetsCollection, PersistentDescriptorSet},
+    device::{Device, Queue},
+    format::Format,
+    image::{AttachmentImage, Dimensions, ImageUsage, StorageImage},
+    memory::pool::StdMemoryPool,
+    pipeline::{ComputePipeline, GraphicsPipelineAbstract},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/forward.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod cull_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/gpu_cull.comp"
+    }
+}
+
+mod hiz_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/hiz.comp"
+    }
+}
+
+// World space bounds of a GPU driven draw, layout has to match `CullObject` in
+// `gpu_cull.comp`.
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct GpuCullObject {
+    pub sphere: [f32; 4],
+    pub aabb_min: [f32; 4],
+    pub aabb_max: [f32; 4],
+}
+
+// Vertices of all geometries in a single buffer, so GPU driven draws of
+// different geometries only differ in their indirect commands.
+pub struct GeometryArena {
+    pub vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    // Geometries are unindexed triangle lists, a single index sequence serves all
+    // of them with a vertex offset
+    pub index_buffer: Arc<CpuAccessibleBuffer<[u32]>>,
+    // First vertex and vertex count of every geometry
+    ranges: HashMap<GeometryId, (u32, u32)>,
+}
+
+impl GeometryArena {
+    pub fn new(device: Arc<Device>, geometries: &HashMap<GeometryId, Geometry>) -> Self {
+        let mut ids: Vec<GeometryId> = geometries.keys().cloned().collect();
+        ids.sort_unstable();
+
+        let mut vertices = Vec::new();
+        let mut ranges = HashMap::with_capacity(ids.len());
+        let mut max_count = 1;
+        for id in ids {
+            let geometry_vertices = geometries[&id].vertices.read().unwrap();
+            let count = geometry_vertices.len() as u32;
+            ranges.insert(id, (vertices.len() as u32, count));
+            max_count = max_count.max(count);
+            vertices.extend_from_slice(&geometry_vertices);
+        }
+        if vertices.is_empty() {
+            vertices.push(Vertex::default());
+        }
+
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::vertex_buffer(),
+            false,
+            vertices.into_iter(),
+        )
+        .unwrap();
+        let index_buffer = CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage::index_buffer(),
+            false,
+            0..max_count,
+        )
+        .unwrap();
+
+        return GeometryArena {
+            vertex_buffer,
+            index_buffer,
+            ranges,
+        };
+    }
+
+    // Geometries are never removed, so the arena is current while it has all of
+    // them.
+    pub fn is_current(&self, geometries: &HashMap<GeometryId, Geometry>) -> bool {
+        return self.ranges.len() == geometries.len();
+    }
+
+    pub fn range(&self, geometry_id: GeometryId) -> Option<(u32, u32)> {
+        return self.ranges.get(&geometry_id).cloned();
+    }
+}
+
+// Hierarchical depth of a window, the farthest depth of screen regions at
+// decreasing resolutions. Built after the scene pass and used to cull the next
+// frame.
+pub struct HizTargets {
+    window_depth_buffer: Arc<AttachmentImage>,
+    atlas: Arc<StorageImage<Format>>,
+    levels: Vec<HizLevel>,
+    // Whether a previous frame built the atlas
+    pub valid: bool,
+}
+
+impl HizTargets {
+    // Whether the targets were created for the window depth buffer.
+    pub fn matches(&self, depth_buffer: &Arc<AttachmentImage>) -> bool {
+        return Arc::ptr_eq(&self.window_depth_buffer, depth_buffer);
+    }
+}
+
+// Culled indirect commands of a frame, one per GPU driven draw.
+pub struct IndirectDraws {
+    commands: Arc<CpuBufferPoolChunk<DrawIndexedIndirectCommand, Arc<StdMemoryPool>>>,
+    count: usize,
+}
+
+pub struct GpuDrivenRenderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    cull_kernel: ComputeKernel,
+    hiz_kernel: ComputeKernel,
+    depth_sampler: Arc<Sampler>,
+    params_pool: CpuBufferPool<cull_cs::ty::CullParams>,
+    object_pool: CpuBufferPool<GpuCullObject>,
+    command_pool: CpuBufferPool<DrawIndexedIndirectCommand>,
+    // All commands are submitted with a single draw when supported
+    multi_draw: bool,
+}
+
+impl GpuDrivenRenderer {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        let cull_cs = cull_cs::Shader::load(device.clone()).unwrap();
+        let hiz_cs = hiz_cs::Shader::load(device.clone()).unwrap();
+        let cull_kernel = ComputeKernel::new(
+            Arc::new(
+                ComputePipeline::new(device.clone(), &cull_cs.main_entry_point(), &()).unwrap(),
+            ),
+            [64, 1, 1],
+        );
+        let hiz_kernel = ComputeKernel::new(
+            Arc::new(
+                ComputePipeline::new(device.clone(), &hiz_cs.main_entry_point(), &()).unwrap(),
+            ),
+            [8, 8, 1],
+        );
+        let depth_sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let multi_draw = device.enabled_features().multi_draw_indirect;
+        if !multi_draw {
+            log::warn!(
+                "multi_draw_indirect isn't supported, GPU driven draws are submitted one by one"
+            );
+        }
+
+        return GpuDrivenRenderer {
+            device: device.clone(),
+            queue,
+            cull_kernel,
+            hiz_kernel,
+            depth_sampler,
+            params_pool: CpuBufferPool::uniform_buffer(device.clone()),
+            object_pool: CpuBufferPool::new(
+                device.clone(),
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::none()
+                },
+            ),
+            command_pool: CpuBufferPool::new(
+                device,
+                BufferUsage {
+                    storage_buffer: true,
+                    indirect_buffer: true,
+                    ..BufferUsage::none()
+                },
+            ),
+            multi_draw,
+        };
+    }
+
+    pub fn create_hiz_targets(&self, window_depth_buffer: Arc<AttachmentImage>) -> HizTargets {
+        let (size, levels) = hiz_layout(window_depth_buffer.dimensions());
+        let atlas = StorageImage::with_usage(
+            self.device.clone(),
+            Dimensions::Dim2d {
+                width: size[0],
+                height: size[1],
+            },
+            config::HIZ_FORMAT,
+            ImageUsage {
+                storage: true,
+                ..ImageUsage::none()
+            },
+            Some(self.queue.family()),
+        )
+        .unwrap();
+
+        return HizTargets {
+            window_depth_buffer,
+            atlas,
+            levels,
+            valid: false,
+        };
+    }
+
+    // Draw calls recorded for `count` GPU driven draws.
+    pub fn draw_calls(&self, count: usize) -> u32 {
+        if self.multi_draw {
+            return count.min(1) as u32;
+        }
+        return count as u32;
+    }
+
+    // Uploads the bounds and indirect commands of the draws at `indices` of the
+    // frame draw list and culls them against the camera frustum and the
+    // hierarchical depth of the previous frame.
+    pub fn record_cull(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        hiz: &HizTargets,
+        arena: &GeometryArena,
+        view_projection: &Matrix4f,
+        previous_view_projection: &Matrix4f,
+        draws: &[DrawItem],
+        indices: &[usize],
+        objects: &HashMap<ObjectId, RenderObject>,
+        geometries: &HashMap<GeometryId, Geometry>,
+    ) -> (AutoCommandBufferBuilder, IndirectDraws) {
+        let mut cull_objects = Vec::with_capacity(indices.len());
+        let mut commands = Vec::with_capacity(indices.len());
+        for &index in indices {
+            let item = &draws[index];
+            let (first_vertex, vertex_count) = match arena.range(item.geometry_id) {
+                Some(range) => range,
+                None => continue,
+            };
+            let bounds = geometries[&item.geometry_id]
+                .bounds
+                .transformed(&objects[&item.object_id].transform);
+            let sphere = bounds.bounding_sphere();
+            cull_objects.push(GpuCullObject {
+                sphere: [
+                    sphere.center.x,
+                    sphere.center.y,
+                    sphere.center.z,
+                    sphere.radius,
+                ],
+                aabb_min: [bounds.min.x, bounds.min.y, bounds.min.z, 0.0],
+                aabb_max: [bounds.max.x, bounds.max.y, bounds.max.z, 0.0],
+            });
+            // Visible instances are set by the culling shader
+            commands.push(DrawIndexedIndirectCommand {
+                index_count: vertex_count,
+                instance_count: 0,
+                first_index: 0,
+                vertex_offset: first_vertex,
+                first_instance: index as u32,
+            });
+        }
+        let count = commands.len();
+        // Storage buffers can't be empty
+        if count == 0 {
+            cull_objects.push(GpuCullObject::default());
+            commands.push(DrawIndexedIndirectCommand {
+                index_count: 0,
+                instance_count: 0,
+                first_index: 0,
+                vertex_offset: 0,
+                first_instance: 0,
+            });
+        }
+
+        let frustum = Frustum::from_matrix(view_projection);
+        let mut planes = [[0.0; 4]; 6];
+        for (plane, frustum_plane) in planes.iter_mut().zip(frustum.planes.iter()) {
+            let normal = frustum_plane.normal;
+            *plane = [normal.x, normal.y, normal.z, frustum_plane.d];
+        }
+        let mut hiz_levels = [[0; 4]; MAX_HIZ_LEVELS];
+        for (rect, level) in hiz_levels.iter_mut().zip(&hiz.levels) {
+            *rect = [
+                level.offset[0] as i32,
+                level.offset[1] as i32,
+                level.size[0] as i32,
+                level.size[1] as i32,
+            ];
+        }
+        let level_count = if hiz.valid {
+            hiz.levels.len() as u32
+        } else {
+            0
+        };
+        let params = self
+            .params_pool
+            .next(cull_cs::ty::CullParams {
+                planes,
+                previous_view_projection: (*previous_view_projection).into(),
+                hiz_levels,
+                info: [count as u32, level_count, 0, 0],
+            })
+            .unwrap();
+        let object_buffer = self.object_pool.chunk(cull_objects).unwrap();
+        let commands = Arc::new(self.command_pool.chunk(commands).unwrap());
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(self.cull_kernel.set_layout(0))
+                .add_buffer(params)
+                .unwrap()
+                .add_buffer(object_buffer)
+                .unwrap()
+                .add_buffer(commands.clone())
+                .unwrap()
+                .add_image(hiz.atlas.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let builder = self
+            .cull_kernel
+            .dispatch(builder, [count as u32, 1, 1], set, ());
+        return (builder, IndirectDraws { commands, count });
+    }
+
+    // Draws the culled commands with `pipeline`, a scene pipeline using
+    // `forward.vert`. `set` has to contain the draw data at binding 6.
+    pub fn record_draws<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        set: S,
+        draws: &IndirectDraws,
+        arena: &GeometryArena,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        S: DescriptorSetsCollection + Clone,
+    {
+        let push_constants = vs::ty::PushConstants {
+            draw: [INSTANCED_DRAW, 0, 0, 0],
+        };
+        if draws.count == 0 {
+            return builder;
+        }
+        if self.multi_draw {
+            return builder
+                .draw_indexed_indirect(
+                    pipeline.clone(),
+                    dynamic_state,
+                    vec![arena.vertex_buffer.clone()],
+                    arena.index_buffer.clone(),
+                    draws.commands.clone(),
+                    set,
+                    push_constants,
+                )
+                .unwrap();
+        }
+        for i in 0..draws.count {
+            let command = BufferSlice::from_typed_buffer_access(draws.commands.clone())
+                .slice(i..i + 1)
+                .unwrap();
+            builder = builder
+                .draw_indexed_indirect(
+                    pipeline.clone(),
+                    dynamic_state,
+                    vec![arena.vertex_buffer.clone()],
+                    arena.index_buffer.clone(),
+                    command,
+                    set.clone(),
+                    push_constants,
+                )
+                .unwrap();
+        }
+        return builder;
+    }
+
+    // Builds the hierarchical depth from the window depth buffer, has to be
+    // recorded after the scene pass.
+    pub fn record_hiz(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        targets: &HizTargets,
+    ) -> AutoCommandBufferBuilder {
+        let set = Arc::new(
+            PersistentDescriptorSet::start(self.hiz_kernel.set_layout(0))
+                .add_sampled_image(
+                    targets.window_depth_buffer.clone(),
+                    self.depth_sampler.clone(),
+                )
+                .unwrap()
+                .add_image(targets.atlas.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let depth_size = targets.window_depth_buffer.dimensions();
+        for (i, level) in targets.levels.iter().enumerate() {
+            let source = match i {
+                0 => [0, 0, depth_size[0] as i32, depth_size[1] as i32],
+                _ => {
+                    let source = &targets.levels[i - 1];
+                    [
+                        source.offset[0] as i32,
+                        source.offset[1] as i32,
+                        source.size[0] as i32,
+                        source.size[1] as i32,
+                    ]
+                }
+            };
+            let push_constants = hiz_cs::ty::PushConstants {
+                source,
+                target: [
+                    level.offset[0] as i32,
+                    level.offset[1] as i32,
+                    level.size[0] as i32,
+                    level.size[1] as i32,
+                ],
+                mode: [(i == 0) as u32, 0, 0, 0],
+            };
+            builder = self.hiz_kernel.dispatch(
+                builder,
+                [level.size[0], level.size[1], 1],
+                set.clone(),
+                push_constants,
+            );
+        }
+        return builder;
+    }
+}
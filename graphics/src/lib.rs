@@ -1,14 +1,130 @@
+mod aa_pass;
+mod antialiasing;
+mod bounds;
+mod camera;
+mod capture;
+mod capture_pass;
+mod cff;
+mod clusters;
 mod common;
+mod compute;
+mod compute_pass;
 mod config;
 mod context;
+mod culling;
+mod debug_draw;
+mod debug_font;
+mod debug_utils;
+mod debug_view;
+mod debug_view_pass;
+mod decal;
+mod decal_pass;
+mod deferred_pass;
+mod environment;
+mod environment_pass;
 mod error;
+mod font;
+mod font_atlas;
+mod frustum;
 mod geometry;
+mod gpu_driven;
+mod gpu_driven_pass;
+mod light;
+mod lod;
 mod material;
+mod object;
+mod oit_pass;
+mod particles;
+mod particles_pass;
+mod picking;
+mod picking_pass;
+mod post;
+mod post_pass;
+mod profiling;
+mod profiling_pass;
+mod render_queue;
 mod renderer;
+mod sdf;
+mod shadow;
+mod shadow_pass;
+mod skinning;
+mod skinning_pass;
+mod sprite_pass;
+mod sprites;
+mod ssao;
+mod ssao_pass;
 mod system;
 mod target;
+mod terrain;
+mod terrain_pass;
+mod text;
+mod text_pass;
 mod vertex;
+mod voxel;
+mod voxel_pass;
 mod window;
 
+pub use antialiasing::{AntiAliasing, TaaSettings};
+pub use bounds::{Aabb, BoundingSphere};
+pub use camera::Camera;
+pub use capture::{CaptureFormat, CaptureId, FrameSequence};
+pub use compute_pass::{ComputeKernel, ComputeQueue};
+pub use debug_draw::{DebugDepthMode, DebugDraw, DebugDrawOptions};
+pub use debug_view::DebugView;
+pub use decal::{Decal, DecalId, DecalTexture, DecalTextureId, DEFAULT_DECAL_BUDGET};
+pub use environment::{EnvironmentId, HdrImage, IblSettings};
+pub use error::RenderingError;
+pub use font_atlas::FontSettings;
+pub use frustum::{Frustum, Plane};
 pub use geometry::GeometryId;
+pub use light::{Light, LightId, LightKind};
+pub use lod::{LodGroup, LodLevel};
+pub use material::{BlendMode, Material, MaterialId};
+pub use object::ObjectId;
+pub use particles::{
+    CurlNoise,
+    Curve,
+    ParticleAtlas,
+    ParticleCollision,
+    ParticleEmitter,
+    ParticleEmitterId,
+};
+pub use picking::PickId;
+pub use post::{
+    BloomSettings,
+    ColorGradingLut,
+    ExposureMode,
+    PostProcessSettings,
+    Tonemapper,
+    VignetteSettings,
+};
+pub use profiling::{PassStats, ProfiledPass, RenderStats};
+pub use render_queue::TransparencyMode;
+pub use renderer::RenderPath;
+pub use shadow::{LightShadow, ShadowSettings};
+pub use skinning::{
+    normalize_weights,
+    Joint,
+    JointPose,
+    Skeleton,
+    SkinnedMeshId,
+    SkinnedObjectId,
+    SkinningMethod,
+};
+pub use sprites::{
+    Camera2d,
+    NineSlice,
+    Sprite,
+    SpriteAtlas,
+    SpriteAtlasId,
+    SpriteId,
+    SpriteRegion,
+    TileMap,
+    TileMapId,
+};
+pub use ssao::SsaoSettings;
 pub use system::RenderingSystem;
+pub use terrain::{Heightmap, SplatMap, Terrain, TerrainId, TerrainLayer, MAX_TERRAIN_LAYERS};
+pub use text::{FontId, Text, TextAlign, TextId, TextOutline, TextShadow, TextSpace};
+pub use vertex::{SkinnedVertex, Vertex};
+pub use voxel::{BlockAtlas, BlockId, BlockTextures, Chunk, AIR, CHUNK_SIZE};
@@ -0,0 +1,129 @@
+use crate::shadow::LightShadow;
+use polyengine_core::*;
+
+pub type LightId = u32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    // Cone angles in radians, measured from the light direction. Intensity fades
+    // out between the inner and the outer angle.
+    Spot {
+        inner_angle: FScalar,
+        outer_angle: FScalar,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vector3f,
+    pub intensity: FScalar,
+    // Distance at which point and spot lights fade out completely
+    pub range: FScalar,
+    // Ignored by directional lights
+    pub position: Vector3f,
+    // Direction the light is travelling in, ignored by point lights
+    pub direction: Vector3f,
+    // Lights without shadow settings don't cast shadows
+    pub shadow: Option<LightShadow>,
+}
+
+impl Light {
+    pub fn directional(direction: Vector3f, color: Vector3f, intensity: FScalar) -> Self {
+        return Light {
+            kind: LightKind::Directional,
+            color,
+            intensity,
+            range: FScalar::INFINITY,
+            position: Vector3f::zeros(),
+            direction: direction.normalize(),
+            shadow: None,
+        };
+    }
+
+    pub fn point(position: Vector3f, color: Vector3f, intensity: FScalar, range: FScalar) -> Self {
+        return Light {
+            kind: LightKind::Point,
+            color,
+            intensity,
+            range,
+            position,
+            direction: -Vector3f::y(),
+            shadow: None,
+        };
+    }
+
+    pub fn spot(
+        position: Vector3f,
+        direction: Vector3f,
+        color: Vector3f,
+        intensity: FScalar,
+        range: FScalar,
+        inner_angle: FScalar,
+        outer_angle: FScalar,
+    ) -> Self {
+        return Light {
+            kind: LightKind::Spot {
+                inner_angle,
+                outer_angle,
+            },
+            color,
+            intensity,
+            range,
+            position,
+            direction: direction.normalize(),
+            shadow: None,
+        };
+    }
+
+    pub fn with_shadow(self, shadow: LightShadow) -> Self {
+        return Light {
+            shadow: Some(shadow),
+            ..self
+        };
+    }
+}
+
+// Light layout used by shaders, has to match `Light` in
+// `shaders/lighting.glsl`.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct GpuLight {
+    // xyz: position, w: range
+    pub position_range: [f32; 4],
+    // rgb: color, a: intensity
+    pub color_intensity: [f32; 4],
+    // xyz: direction, w: light type
+    pub direction_type: [f32; 4],
+    // x: cos of inner angle, y: cos of outer angle
+    pub spot_angles: [f32; 4],
+    // x: first shadow view or -1, y: depth bias, z: normal bias, w: view count
+    pub shadow_params: [f32; 4],
+}
+
+const LIGHT_DIRECTIONAL: f32 = 0.0;
+const LIGHT_POINT: f32 = 1.0;
+const LIGHT_SPOT: f32 = 2.0;
+
+impl GpuLight {
+    pub fn new(light: &Light, shadow_params: [f32; 4]) -> Self {
+        let (light_type, spot_angles) = match light.kind {
+            LightKind::Directional => (LIGHT_DIRECTIONAL, [0.0; 4]),
+            LightKind::Point => (LIGHT_POINT, [0.0; 4]),
+            LightKind::Spot {
+                inner_angle,
+                outer_angle,
+            } => (LIGHT_SPOT, [inner_angle.cos(), outer_angle.cos(), 0.0, 0.0]),
+        };
+        let (p, c, d) = (light.position, light.color, light.direction);
+        return GpuLight {
+            position_range: [p.x, p.y, p.z, light.range],
+            color_intensity: [c.x, c.y, c.z, light.intensity],
+            direction_type: [d.x, d.y, d.z, light_type],
+            spot_angles,
+            shadow_params,
+        };
+    }
+}
@@ -0,0 +1,112 @@
+use crate::geometry::GeometryId;
+use polyengine_core::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LodLevel {
+    pub geometry: GeometryId,
+    // Camera distance up to which this level is used.
+    pub switch_distance: FScalar,
+}
+
+// Set of geometries representing the same object at decreasing detail. Levels
+// have to be sorted by switch distance. Objects further than the last switch
+// distance are not drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodGroup {
+    pub levels: Vec<LodLevel>,
+    // Distance margin that has to be crossed before switching back to a previously used
+    // level. Prevents flickering when the camera hovers around a switch distance.
+    pub hysteresis: FScalar,
+}
+
+impl LodGroup {
+    pub fn new(levels: Vec<LodLevel>, hysteresis: FScalar) -> Self {
+        debug_assert!(levels
+            .windows(2)
+            .all(|w| w[0].switch_distance <= w[1].switch_distance));
+        return LodGroup { levels, hysteresis };
+    }
+
+    // Group with single level that is always visible.
+    pub fn single(geometry: GeometryId) -> Self {
+        return LodGroup::new(
+            vec![LodLevel {
+                geometry,
+                switch_distance: FScalar::INFINITY,
+            }],
+            0.0,
+        );
+    }
+
+    // Returns index of the level to use at given distance. `previous` is the level
+    // selected last time, used to apply hysteresis.
+    pub fn select(&self, distance: FScalar, previous: Option<usize>) -> Option<usize> {
+        let selected = self
+            .levels
+            .iter()
+            .position(|level| distance <= level.switch_distance);
+
+        return match (previous, selected) {
+            // Moving away, stay on the finer level until we are past the margin.
+            (Some(prev), Some(sel)) if sel > prev => {
+                if distance <= self.levels[prev].switch_distance + self.hysteresis {
+                    Some(prev)
+                } else {
+                    selected
+                }
+            }
+            (Some(prev), None) if prev < self.levels.len() => {
+                if distance <= self.levels[prev].switch_distance + self.hysteresis {
+                    Some(prev)
+                } else {
+                    None
+                }
+            }
+            _ => selected,
+        };
+    }
+
+    pub fn geometry(&self, level: usize) -> GeometryId { return self.levels[level].geometry; }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LodGroup, LodLevel};
+
+    fn test_group() -> LodGroup {
+        return LodGroup::new(
+            vec![
+                LodLevel {
+                    geometry: 0,
+                    switch_distance: 10.0,
+                },
+                LodLevel {
+                    geometry: 1,
+                    switch_distance: 50.0,
+                },
+            ],
+            2.0,
+        );
+    }
+
+    #[test]
+    fn select_test() {
+        let group = test_group();
+        assert_eq!(group.select(5.0, None), Some(0));
+        assert_eq!(group.select(10.0, None), Some(0));
+        assert_eq!(group.select(20.0, None), Some(1));
+        assert_eq!(group.select(60.0, None), None);
+    }
+
+    #[test]
+    fn hysteresis_test() {
+        let group = test_group();
+        // Moving away keeps finer level within the margin.
+        assert_eq!(group.select(11.0, Some(0)), Some(0));
+        assert_eq!(group.select(13.0, Some(0)), Some(1));
+        assert_eq!(group.select(51.0, Some(1)), Some(1));
+        assert_eq!(group.select(53.0, Some(1)), None);
+        // Moving closer switches immediately.
+        assert_eq!(group.select(9.0, Some(1)), Some(0));
+    }
+}
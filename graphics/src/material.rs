@@ -1 +1,51 @@
+use polyengine_core::*;
 
+pub type MaterialId = u32;
+
+// How a material's surfaces are combined with what is behind them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlendMode {
+    Opaque,
+    // Opaque, fragments with alpha below the cutoff are discarded
+    AlphaTest(FScalar),
+    // Transparent, blended with straight alpha
+    AlphaBlend,
+    // Transparent, colour weighted by alpha is added to the background
+    Additive,
+    // Transparent, the base colour is already multiplied by alpha
+    Premultiplied,
+}
+
+impl BlendMode {
+    // Transparent surfaces don't write depth and are drawn after opaque ones.
+    pub fn is_transparent(&self) -> bool {
+        match self {
+            BlendMode::Opaque | BlendMode::AlphaTest(_) => return false,
+            _ => return true,
+        }
+    }
+}
+
+// Metallic-roughness PBR material.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Material {
+    // Linear RGB color with alpha
+    pub base_color: Vector4f,
+    pub metallic: FScalar,
+    pub roughness: FScalar,
+    // Linear RGB radiance added on top of lighting
+    pub emissive: Vector3f,
+    pub blend_mode: BlendMode,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        return Material {
+            base_color: Vector4f::new(1.0, 1.0, 1.0, 1.0),
+            metallic: 0.0,
+            roughness: 0.5,
+            emissive: Vector3f::zeros(),
+            blend_mode: BlendMode::Opaque,
+        };
+    }
+}
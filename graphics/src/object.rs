@@ -0,0 +1,22 @@
+use crate::{lod::LodGroup, material::MaterialId};
+use polyengine_core::*;
+
+pub type ObjectId = u32;
+
+// Instance of geometry placed in the world.
+pub struct RenderObject {
+    pub lod_group: LodGroup,
+    pub transform: Similarity3,
+    // Uses the default material when not set
+    pub material: Option<MaterialId>,
+}
+
+impl RenderObject {
+    pub fn new(lod_group: LodGroup, transform: Similarity3) -> Self {
+        return RenderObject {
+            lod_group,
+            transform,
+            material: None,
+        };
+    }
+}
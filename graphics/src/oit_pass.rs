@@ -0,0 +1,302 @@
+use crate::{common::BufferlessPipeline, config, post_pass::PostTargets, vertex::Vertex};
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, PipelineLayoutAbstract},
+    device::Device,
+    format::ClearValue,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{AttachmentImage, ImageUsage},
+    pipeline::{
+        blend::{AttachmentBlend, BlendFactor, BlendOp},
+        depth_stencil::DepthStencil,
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/forward.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod oit_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/oit.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod fullscreen_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/fullscreen.vert"
+    }
+}
+
+mod composite_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/oit_composite.frag"
+    }
+}
+
+// Order independent transparency images of a window, built around its HDR scene
+// targets.
+pub struct OitTargets {
+    hdr_image: Arc<AttachmentImage>,
+    accumulation: Arc<AttachmentImage>,
+    revealage: Arc<AttachmentImage>,
+    // Tests against the scene depth, transparent surfaces are drawn into it
+    pub accumulation_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    composite_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+impl OitTargets {
+    // Whether the targets were created for the current scene targets.
+    pub fn matches(&self, post_targets: &PostTargets) -> bool {
+        return Arc::ptr_eq(&self.hdr_image, &post_targets.hdr_image);
+    }
+}
+
+pub struct OitRenderer {
+    device: Arc<Device>,
+    accumulation_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    composite_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    // Uses the frame set of the forward pipeline
+    pub accumulation_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    composite_pipeline: Arc<BufferlessPipeline>,
+    sampler: Arc<Sampler>,
+}
+
+impl OitRenderer {
+    pub fn new(device: Arc<Device>) -> Self {
+        let accumulation_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    accumulation: {
+                        load: Clear,
+                        store: Store,
+                        format: config::OIT_ACCUMULATION_FORMAT,
+                        samples: 1,
+                    },
+                    revealage: {
+                        load: Clear,
+                        store: Store,
+                        format: config::OIT_REVEALAGE_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Load,
+                        store: Store,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [accumulation, revealage],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+        let composite_render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let oit_fs = oit_fs::Shader::load(device.clone()).unwrap();
+        let fullscreen_vs = fullscreen_vs::Shader::load(device.clone()).unwrap();
+        let composite_fs = composite_fs::Shader::load(device.clone()).unwrap();
+
+        let accumulation_blend = AttachmentBlend {
+            enabled: true,
+            color_op: BlendOp::Add,
+            color_source: BlendFactor::One,
+            color_destination: BlendFactor::One,
+            alpha_op: BlendOp::Add,
+            alpha_source: BlendFactor::One,
+            alpha_destination: BlendFactor::One,
+            mask_red: true,
+            mask_green: true,
+            mask_blue: true,
+            mask_alpha: true,
+        };
+        // Multiplies the revealage by (1 - alpha) of every surface.
+        let revealage_blend = AttachmentBlend {
+            color_source: BlendFactor::Zero,
+            color_destination: BlendFactor::OneMinusSrcColor,
+            alpha_source: BlendFactor::Zero,
+            alpha_destination: BlendFactor::OneMinusSrcAlpha,
+            ..accumulation_blend
+        };
+        let accumulation_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(oit_fs.main_entry_point(), ())
+                .depth_stencil(DepthStencil {
+                    depth_write: false,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .blend_individual(vec![accumulation_blend, revealage_blend].into_iter())
+                .render_pass(Subpass::from(accumulation_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let composite_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(fullscreen_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(composite_fs.main_entry_point(), ())
+                .blend_alpha_blending()
+                .render_pass(Subpass::from(composite_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        return OitRenderer {
+            device,
+            accumulation_render_pass,
+            composite_render_pass,
+            accumulation_pipeline,
+            composite_pipeline,
+            sampler,
+        };
+    }
+
+    pub fn create_targets(
+        &self,
+        post_targets: &PostTargets,
+        depth_buffer: Arc<AttachmentImage>,
+    ) -> OitTargets {
+        let dimensions = depth_buffer.dimensions();
+        let usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+        let attachment = |format| {
+            return AttachmentImage::with_usage(self.device.clone(), dimensions, format, usage)
+                .unwrap();
+        };
+        let accumulation = attachment(config::OIT_ACCUMULATION_FORMAT);
+        let revealage = attachment(config::OIT_REVEALAGE_FORMAT);
+
+        let accumulation_framebuffer = Arc::new(
+            Framebuffer::start(self.accumulation_render_pass.clone())
+                .add(accumulation.clone())
+                .unwrap()
+                .add(revealage.clone())
+                .unwrap()
+                .add(depth_buffer)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let composite_framebuffer = Arc::new(
+            Framebuffer::start(self.composite_render_pass.clone())
+                .add(post_targets.hdr_image.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        return OitTargets {
+            hdr_image: post_targets.hdr_image.clone(),
+            accumulation,
+            revealage,
+            accumulation_framebuffer,
+            composite_framebuffer,
+        };
+    }
+
+    // Clear values of `accumulation_framebuffer`.
+    pub fn clear_values(&self) -> Vec<ClearValue> {
+        return vec![[0.0, 0.0, 0.0, 0.0].into(), [1.0].into(), ClearValue::None];
+    }
+
+    // Blends the accumulated surfaces over the scene, after the accumulation pass.
+    pub fn record_composite(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        targets: &OitTargets,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder {
+        let set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.composite_pipeline
+                    .descriptor_set_layout(0)
+                    .unwrap()
+                    .clone(),
+            )
+            .add_sampled_image(targets.accumulation.clone(), self.sampler.clone())
+            .unwrap()
+            .add_sampled_image(targets.revealage.clone(), self.sampler.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        return builder
+            .begin_render_pass(
+                targets.composite_framebuffer.clone(),
+                false,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.composite_pipeline.clone(),
+                dynamic_state,
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                set,
+                (),
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+}
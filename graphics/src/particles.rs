@@ -0,0 +1,314 @@
+use polyengine_core::*;
+use std::{
+    ops::{Add, Mul},
+    sync::Arc,
+};
+
+pub type ParticleEmitterId = u32;
+
+// Samples of the over-life curves uploaded per emitter, has to match
+// `particles.glsl`.
+pub const CURVE_SAMPLES: usize = 32;
+
+// Longest simulation step in seconds, longer frames slow the particles down
+// instead of letting them tunnel through colliders.
+pub const MAX_TIME_STEP: FScalar = 0.1;
+
+// Piecewise linear curve over the normalised particle age. Keys are sorted by
+// time, values before the first and after the last key are clamped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve<T> {
+    keys: Vec<(FScalar, T)>,
+}
+
+impl<T> Curve<T>
+where
+    T: Copy + Add<Output = T> + Mul<FScalar, Output = T>,
+{
+    // Returns None without keys.
+    pub fn new(mut keys: Vec<(FScalar, T)>) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        return Some(Curve { keys });
+    }
+
+    pub fn constant(value: T) -> Self {
+        return Curve {
+            keys: vec![(0.0, value)],
+        };
+    }
+
+    pub fn linear(start: T, end: T) -> Self {
+        return Curve {
+            keys: vec![(0.0, start), (1.0, end)],
+        };
+    }
+
+    pub fn sample(&self, time: FScalar) -> T {
+        let after = self.keys.iter().position(|key| key.0 > time);
+        match after {
+            Some(0) => return self.keys[0].1,
+            Some(i) => {
+                let (t0, v0) = self.keys[i - 1];
+                let (t1, v1) = self.keys[i];
+                let f = (time - t0) / (t1 - t0);
+                return v0 * (1.0 - f) + v1 * f;
+            }
+            None => return self.keys[self.keys.len() - 1].1,
+        }
+    }
+
+    // Evenly spaced samples from age 0 to 1, interpolated by the shaders.
+    pub fn bake(&self) -> [T; CURVE_SAMPLES] {
+        let mut samples = [self.keys[0].1; CURVE_SAMPLES];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = self.sample(i as FScalar / (CURVE_SAMPLES - 1) as FScalar);
+        }
+        return samples;
+    }
+}
+
+// Divergence free noise added to the particle velocity, makes particles swirl
+// without clumping.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CurlNoise {
+    // Acceleration in units per second squared
+    pub strength: FScalar,
+    // Noise cells per world unit
+    pub frequency: FScalar,
+    // How fast the noise field changes over time
+    pub speed: FScalar,
+}
+
+// Particles bounce off the scene depth of the previous frame. Only surfaces
+// visible in the window collide.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ParticleCollision {
+    // Kept fraction of the velocity along the surface normal
+    pub bounce: FScalar,
+    // Lost fraction of the velocity along the surface
+    pub friction: FScalar,
+    // Particles farther behind a surface than this are hidden, not colliding
+    pub thickness: FScalar,
+}
+
+impl Default for ParticleCollision {
+    fn default() -> Self {
+        return ParticleCollision {
+            bounce: 0.5,
+            friction: 0.1,
+            thickness: 0.5,
+        };
+    }
+}
+
+// Flipbook texture of an emitter. Frames are laid out in rows of `columns`
+// and played once over the particle lifetime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleAtlas {
+    pub width: u32,
+    pub height: u32,
+    // sRGB texels with straight alpha, row by row
+    pub texels: Vec<[u8; 4]>,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl ParticleAtlas {
+    // Returns None when the data doesn't match the size or there are no frames.
+    pub fn new(
+        width: u32,
+        height: u32,
+        texels: Vec<[u8; 4]>,
+        columns: u32,
+        rows: u32,
+    ) -> Option<Self> {
+        if texels.len() != (width * height) as usize || columns == 0 || rows == 0 {
+            return None;
+        }
+        return Some(ParticleAtlas {
+            width,
+            height,
+            texels,
+            columns,
+            rows,
+        });
+    }
+
+    pub fn frame_count(&self) -> u32 { return self.columns * self.rows; }
+}
+
+// Emitter of GPU simulated particles, drawn as camera facing quads sorted back
+// to front.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleEmitter {
+    pub position: Vector3f,
+    // Particles spawn inside of a sphere around the position
+    pub spawn_radius: FScalar,
+    // Particles spawned per second
+    pub spawn_rate: FScalar,
+    // Upper bound of live particles, the oldest ones are replaced when exceeded
+    pub max_particles: u32,
+    // Lifetime in seconds, picked between the two values per particle
+    pub lifetime: [FScalar; 2],
+    // Initial velocity, randomised inside of a cone around it
+    pub velocity: Vector3f,
+    // Half angle of the velocity cone in radians
+    pub velocity_spread: FScalar,
+    pub gravity: Vector3f,
+    // Fraction of the velocity lost per second
+    pub drag: FScalar,
+    pub curl_noise: Option<CurlNoise>,
+    pub collision: Option<ParticleCollision>,
+    // Linear RGB colour with alpha, multiplied with the atlas
+    pub color_over_life: Curve<Vector4f>,
+    // Quad size in world units
+    pub size_over_life: Curve<FScalar>,
+    pub atlas: Option<Arc<ParticleAtlas>>,
+}
+
+impl Default for ParticleEmitter {
+    fn default() -> Self {
+        return ParticleEmitter {
+            position: Vector3f::zeros(),
+            spawn_radius: 0.0,
+            spawn_rate: 50.0,
+            max_particles: 1024,
+            lifetime: [1.0, 2.0],
+            velocity: Vector3f::new(0.0, 2.0, 0.0),
+            velocity_spread: 0.3,
+            gravity: Vector3f::new(0.0, -9.81, 0.0),
+            drag: 0.0,
+            curl_noise: None,
+            collision: None,
+            color_over_life: Curve::linear(
+                Vector4f::new(1.0, 1.0, 1.0, 1.0),
+                Vector4f::new(1.0, 1.0, 1.0, 0.0),
+            ),
+            size_over_life: Curve::constant(0.1),
+            atlas: None,
+        };
+    }
+}
+
+// Slots of the particle ring buffer spawned into this frame.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct SpawnRange {
+    pub first: u32,
+    pub count: u32,
+}
+
+// Spawns particles at a steady rate into a ring buffer, carrying fractional
+// particles over to the next frames.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ParticleSpawner {
+    carry: FScalar,
+    cursor: u32,
+    // Changes every frame, randomises the spawned particles
+    pub seed: u32,
+}
+
+impl ParticleSpawner {
+    pub fn advance(
+        &mut self,
+        spawn_rate: FScalar,
+        capacity: u32,
+        time_step: FScalar,
+    ) -> SpawnRange {
+        let amount = self.carry + spawn_rate.max(0.0) * time_step;
+        let whole = amount.floor();
+        self.carry = amount - whole;
+        let count = (whole as u32).min(capacity);
+        let first = self.cursor;
+        self.cursor = (self.cursor + count) % capacity.max(1);
+        self.seed = self.seed.wrapping_add(1);
+        return SpawnRange { first, count };
+    }
+}
+
+// Compared distances of the bitonic sort of `size` keys, `size` has to be a
+// power of two. Every step compares elements `j` apart in blocks of `k`.
+pub fn bitonic_steps(size: u32) -> Vec<[u32; 2]> {
+    let mut steps = Vec::new();
+    let mut k = 2;
+    while k <= size {
+        let mut j = k / 2;
+        while j > 0 {
+            steps.push([k, j]);
+            j /= 2;
+        }
+        k *= 2;
+    }
+    return steps;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_test() {
+        let curve = Curve::new(vec![(1.0, 4.0), (0.0, 0.0), (0.5, 1.0)]).unwrap();
+        assert_eq!(curve.sample(-1.0), 0.0);
+        assert_eq!(curve.sample(0.25), 0.5);
+        assert_eq!(curve.sample(0.75), 2.5);
+        assert_eq!(curve.sample(2.0), 4.0);
+        assert!(Curve::<FScalar>::new(Vec::new()).is_none());
+
+        let baked = Curve::linear(0.0, 1.0).bake();
+        assert_eq!(baked[0], 0.0);
+        assert_eq!(baked[CURVE_SAMPLES - 1], 1.0);
+        assert_eq!(Curve::constant(2.0).bake(), [2.0; CURVE_SAMPLES]);
+    }
+
+    #[test]
+    fn spawner_test() {
+        let mut spawner = ParticleSpawner::default();
+        // 2.5 particles per frame alternate between 2 and 3
+        assert_eq!(
+            spawner.advance(25.0, 8, 0.1),
+            SpawnRange { first: 0, count: 2 }
+        );
+        assert_eq!(
+            spawner.advance(25.0, 8, 0.1),
+            SpawnRange { first: 2, count: 3 }
+        );
+        // Wraps around the ring
+        assert_eq!(
+            spawner.advance(40.0, 8, 0.1),
+            SpawnRange { first: 5, count: 4 }
+        );
+        assert_eq!(
+            spawner.advance(0.0, 8, 0.1),
+            SpawnRange { first: 1, count: 0 }
+        );
+        // Never more than the capacity
+        assert_eq!(spawner.advance(1000.0, 8, 0.1).count, 8);
+    }
+
+    #[test]
+    fn bitonic_steps_test() {
+        assert!(bitonic_steps(1).is_empty());
+        assert_eq!(bitonic_steps(4), vec![[2, 1], [4, 2], [4, 1]]);
+
+        // Runs the compare and swap of `particle_sort.comp`
+        let mut keys = vec![7, 3, 9, 0, 5, 5, 1, 8, 2, 6, 4, 11, 15, 13, 12, 10];
+        for [k, j] in bitonic_steps(keys.len() as u32) {
+            for i in 0..keys.len() {
+                let other = i ^ j as usize;
+                if other <= i {
+                    continue;
+                }
+                let ascending = i & k as usize == 0;
+                if (keys[i] > keys[other]) == ascending {
+                    keys.swap(i, other);
+                }
+            }
+        }
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+}
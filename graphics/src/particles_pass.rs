@@ -0,0 +1,549 @@
+use crate::{
+    common::BufferlessPipeline,
+    compute_pass::ComputeKernel,
+    particles::{
+        bitonic_steps,
+        ParticleAtlas,
+        ParticleEmitter,
+        ParticleEmitterId,
+        ParticleSpawner,
+        SpawnRange,
+        CURVE_SAMPLES,
+        MAX_TIME_STEP,
+    },
+};
+use polyengine_core::*;
+use std::{collections::HashMap, iter, sync::Arc, time::Instant};
+use vulkano::{
+    buffer::{
+        cpu_pool::CpuBufferPoolChunk,
+        BufferAccess,
+        BufferUsage,
+        CpuBufferPool,
+        DeviceLocalBuffer,
+    },
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, PipelineLayoutAbstract},
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::{AttachmentImage, Dimensions, ImmutableImage},
+    memory::pool::StdMemoryPool,
+    pipeline::{
+        blend::AttachmentBlend,
+        depth_stencil::DepthStencil,
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        ComputePipeline,
+        GraphicsPipeline,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod simulate_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/particle_simulate.comp",
+        include: ["src/shaders"]
+    }
+}
+
+mod sort_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/particle_sort.comp",
+        include: ["src/shaders"]
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/particle.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/particle.frag"
+    }
+}
+
+// Layout has to match `Particle` in `particles.glsl`.
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct GpuParticle {
+    pub position: [f32; 4],
+    pub velocity: [f32; 4],
+}
+
+// Particle ring buffer of an emitter.
+struct EmitterTargets {
+    particles: Arc<DeviceLocalBuffer<[GpuParticle]>>,
+    capacity: u32,
+    spawner: ParticleSpawner,
+    // Slots spawned into this frame
+    spawn: SpawnRange,
+    // New buffers have undefined content, the first simulation kills every
+    // particle it doesn't spawn
+    reset: bool,
+    atlas: Option<(Arc<ParticleAtlas>, Arc<ImmutableImage<Format>>)>,
+}
+
+// Particles of a window. Every window simulates its own copy, so particles
+// collide with the depth of the window they are drawn in.
+pub struct ParticleTargets {
+    // Depth buffer of the previous frame, collided with before it is cleared
+    depth_buffer: Arc<AttachmentImage>,
+    // False when the depth buffer was created since the previous frame
+    depth_valid: bool,
+    emitters: HashMap<ParticleEmitterId, EmitterTargets>,
+    last_frame: Option<Instant>,
+    // Seconds simulated this frame
+    time_step: FScalar,
+    // Seconds simulated in total, animates the curl noise
+    time: FScalar,
+}
+
+impl ParticleTargets {
+    pub fn is_empty(&self) -> bool { return self.emitters.is_empty(); }
+}
+
+// Back to front order of the particles of an emitter.
+pub struct SortedParticles {
+    emitter_id: ParticleEmitterId,
+    keys: Arc<CpuBufferPoolChunk<[u32; 2], Arc<StdMemoryPool>>>,
+}
+
+pub struct ParticleRenderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    simulate_kernel: ComputeKernel,
+    sort_kernel: ComputeKernel,
+    pipeline: Arc<BufferlessPipeline>,
+    depth_sampler: Arc<Sampler>,
+    atlas_sampler: Arc<Sampler>,
+    // Bound for emitters without an atlas
+    white_atlas: Arc<ImmutableImage<Format>>,
+    simulation_pool: CpuBufferPool<simulate_cs::ty::SimulationParams>,
+    emitter_pool: CpuBufferPool<vs::ty::EmitterParams>,
+    key_pool: CpuBufferPool<[u32; 2]>,
+}
+
+impl ParticleRenderer {
+    // Particles are drawn in the scene pass `scene_render_pass`.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        scene_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let simulate_cs = simulate_cs::Shader::load(device.clone()).unwrap();
+        let sort_cs = sort_cs::Shader::load(device.clone()).unwrap();
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+        let simulate_kernel = ComputeKernel::new(
+            Arc::new(
+                ComputePipeline::new(device.clone(), &simulate_cs.main_entry_point(), &()).unwrap(),
+            ),
+            [64, 1, 1],
+        );
+        let sort_kernel = ComputeKernel::new(
+            Arc::new(
+                ComputePipeline::new(device.clone(), &sort_cs.main_entry_point(), &()).unwrap(),
+            ),
+            [64, 1, 1],
+        );
+
+        // Particles don't have motion vectors, TAA reprojects what is behind them.
+        let velocity_blend = AttachmentBlend {
+            mask_red: false,
+            mask_green: false,
+            mask_blue: false,
+            mask_alpha: false,
+            ..AttachmentBlend::pass_through()
+        };
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil(DepthStencil {
+                    depth_write: false,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .blend_individual(
+                    vec![AttachmentBlend::alpha_blending(), velocity_blend].into_iter(),
+                )
+                .render_pass(Subpass::from(scene_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        let sampler = |filter: Filter| {
+            return Sampler::new(
+                device.clone(),
+                filter,
+                filter,
+                MipmapMode::Nearest,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+            )
+            .unwrap();
+        };
+        let depth_sampler = sampler(Filter::Nearest);
+        let atlas_sampler = sampler(Filter::Linear);
+
+        let (white_atlas, upload) = ImmutableImage::from_iter(
+            iter::once([255u8; 4]),
+            Dimensions::Dim2d {
+                width: 1,
+                height: 1,
+            },
+            Format::R8G8B8A8Srgb,
+            queue.clone(),
+        )
+        .unwrap();
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        return ParticleRenderer {
+            device: device.clone(),
+            queue,
+            simulate_kernel,
+            sort_kernel,
+            pipeline,
+            depth_sampler,
+            atlas_sampler,
+            white_atlas,
+            simulation_pool: CpuBufferPool::uniform_buffer(device.clone()),
+            emitter_pool: CpuBufferPool::uniform_buffer(device.clone()),
+            key_pool: CpuBufferPool::new(
+                device,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::none()
+                },
+            ),
+        };
+    }
+
+    // Matches the window particles with the emitters and advances their time.
+    // Returns the upload of new atlases, which has to finish before rendering.
+    pub fn prepare(
+        &self,
+        targets: &mut Option<ParticleTargets>,
+        depth_buffer: &Arc<AttachmentImage>,
+        emitters: &HashMap<ParticleEmitterId, ParticleEmitter>,
+        now: Instant,
+    ) -> Option<Box<dyn GpuFuture>> {
+        match targets {
+            Some(targets) => {
+                targets.depth_valid = Arc::ptr_eq(&targets.depth_buffer, depth_buffer);
+                targets.depth_buffer = depth_buffer.clone();
+            }
+            None => {
+                *targets = Some(ParticleTargets {
+                    depth_buffer: depth_buffer.clone(),
+                    depth_valid: false,
+                    emitters: HashMap::new(),
+                    last_frame: None,
+                    time_step: 0.0,
+                    time: 0.0,
+                });
+            }
+        }
+        let targets = targets.as_mut().unwrap();
+        targets.time_step = targets
+            .last_frame
+            .map_or(0.0, |last| (now - last).as_secs_f32())
+            .min(MAX_TIME_STEP);
+        targets.time += targets.time_step;
+        targets.last_frame = Some(now);
+
+        targets
+            .emitters
+            .retain(|emitter_id, _| emitters.contains_key(emitter_id));
+        let mut uploads: Option<Box<dyn GpuFuture>> = None;
+        for (&emitter_id, emitter) in emitters {
+            let capacity = emitter.max_particles.max(1);
+            let up_to_date = match targets.emitters.get(&emitter_id) {
+                Some(emitter_targets) => emitter_targets.capacity == capacity,
+                None => false,
+            };
+            if !up_to_date {
+                let particles = DeviceLocalBuffer::array(
+                    self.device.clone(),
+                    capacity as usize,
+                    BufferUsage {
+                        storage_buffer: true,
+                        ..BufferUsage::none()
+                    },
+                    Some(self.queue.family()),
+                )
+                .unwrap();
+                targets.emitters.insert(
+                    emitter_id,
+                    EmitterTargets {
+                        particles,
+                        capacity,
+                        spawner: ParticleSpawner::default(),
+                        spawn: SpawnRange::default(),
+                        reset: true,
+                        atlas: None,
+                    },
+                );
+            } else {
+                targets.emitters.get_mut(&emitter_id).unwrap().reset = false;
+            }
+
+            let emitter_targets = targets.emitters.get_mut(&emitter_id).unwrap();
+            emitter_targets.spawn =
+                emitter_targets
+                    .spawner
+                    .advance(emitter.spawn_rate, capacity, targets.time_step);
+
+            let up_to_date = match (&emitter.atlas, &emitter_targets.atlas) {
+                (Some(atlas), Some((uploaded, _))) => Arc::ptr_eq(atlas, uploaded),
+                (None, None) => true,
+                _ => false,
+            };
+            if up_to_date {
+                continue;
+            }
+            emitter_targets.atlas = match &emitter.atlas {
+                Some(atlas) => {
+                    let (image, upload) = ImmutableImage::from_iter(
+                        atlas.texels.iter().cloned(),
+                        Dimensions::Dim2d {
+                            width: atlas.width,
+                            height: atlas.height,
+                        },
+                        Format::R8G8B8A8Srgb,
+                        self.queue.clone(),
+                    )
+                    .unwrap();
+                    uploads = Some(match uploads {
+                        Some(previous) => Box::new(previous.join(upload)),
+                        None => Box::new(upload),
+                    });
+                    Some((atlas.clone(), image))
+                }
+                None => None,
+            };
+        }
+        return uploads;
+    }
+
+    // Spawns and moves the particles of every emitter. Has to be recorded before
+    // the depth buffer is cleared, `previous_view_projection` is the matrix it was
+    // rendered with.
+    pub fn record_simulation(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        targets: &ParticleTargets,
+        emitters: &HashMap<ParticleEmitterId, ParticleEmitter>,
+        previous_view_projection: &Matrix4f,
+    ) -> AutoCommandBufferBuilder {
+        let inverse_view_projection = previous_view_projection
+            .try_inverse()
+            .unwrap_or_else(Matrix4f::identity);
+        for (emitter_id, emitter_targets) in &targets.emitters {
+            let emitter = match emitters.get(emitter_id) {
+                Some(emitter) => emitter,
+                None => continue,
+            };
+            let (p, v, g) = (emitter.position, emitter.velocity, emitter.gravity);
+            let noise = match emitter.curl_noise {
+                Some(noise) => [noise.strength, noise.frequency, noise.speed, 0.0],
+                None => [0.0; 4],
+            };
+            let collision = match emitter.collision {
+                Some(collision) if targets.depth_valid => [
+                    collision.bounce,
+                    collision.friction,
+                    collision.thickness,
+                    1.0,
+                ],
+                _ => [0.0; 4],
+            };
+            let spawn = emitter_targets.spawn;
+            let params = self
+                .simulation_pool
+                .next(simulate_cs::ty::SimulationParams {
+                    view_projection: (*previous_view_projection).into(),
+                    inverse_view_projection: inverse_view_projection.into(),
+                    origin: [p.x, p.y, p.z, emitter.spawn_radius],
+                    velocity: [v.x, v.y, v.z, emitter.velocity_spread],
+                    gravity: [g.x, g.y, g.z, emitter.drag],
+                    time: [
+                        emitter.lifetime[0],
+                        emitter.lifetime[1],
+                        targets.time_step,
+                        targets.time,
+                    ],
+                    noise,
+                    collision,
+                    spawn: [
+                        spawn.first,
+                        spawn.count,
+                        emitter_targets.capacity,
+                        emitter_targets.spawner.seed,
+                    ],
+                    flags: [emitter_targets.reset as u32, 0, 0, 0],
+                })
+                .unwrap();
+
+            let set = Arc::new(
+                PersistentDescriptorSet::start(self.simulate_kernel.set_layout(0))
+                    .add_buffer(params)
+                    .unwrap()
+                    .add_buffer(emitter_targets.particles.clone())
+                    .unwrap()
+                    .add_sampled_image(targets.depth_buffer.clone(), self.depth_sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            builder =
+                self.simulate_kernel
+                    .dispatch(builder, [emitter_targets.capacity, 1, 1], set, ());
+        }
+        return builder;
+    }
+
+    // Sorts the particles of every emitter back to front, has to be recorded
+    // outside of a render pass.
+    pub fn record_sort(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        targets: &ParticleTargets,
+        camera_position: &Vector3f,
+    ) -> (AutoCommandBufferBuilder, Vec<SortedParticles>) {
+        let mut sorted = Vec::with_capacity(targets.emitters.len());
+        for (&emitter_id, emitter_targets) in &targets.emitters {
+            // Bitonic sorts need a power of two, the padding sorts last
+            let size = emitter_targets.capacity.next_power_of_two();
+            let keys = Arc::new(
+                self.key_pool
+                    .chunk(iter::repeat([0, 0]).take(size as usize))
+                    .unwrap(),
+            );
+            let set = Arc::new(
+                PersistentDescriptorSet::start(self.sort_kernel.set_layout(0))
+                    .add_buffer(emitter_targets.particles.clone())
+                    .unwrap()
+                    .add_buffer(keys.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            let c = camera_position;
+            let mut push_constants = sort_cs::ty::PushConstants {
+                camera_position: [c.x, c.y, c.z, 1.0],
+                step: [0, 0, 0, emitter_targets.capacity],
+            };
+            builder = self
+                .sort_kernel
+                .dispatch(builder, [size, 1, 1], set.clone(), push_constants);
+            for [k, j] in bitonic_steps(size) {
+                push_constants.step = [1, k, j, emitter_targets.capacity];
+                builder =
+                    self.sort_kernel
+                        .dispatch(builder, [size, 1, 1], set.clone(), push_constants);
+            }
+            sorted.push(SortedParticles { emitter_id, keys });
+        }
+        return (builder, sorted);
+    }
+
+    // Draws the sorted particles, has to be recorded inside of the scene render
+    // pass after opaque geometry. `frame_data` is the frame uniform buffer of the
+    // forward pass. Returns the recorded draw calls.
+    pub fn record_draws<B>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        targets: &ParticleTargets,
+        sorted: &[SortedParticles],
+        emitters: &HashMap<ParticleEmitterId, ParticleEmitter>,
+        frame_data: B,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        B: BufferAccess + Clone + Send + Sync + 'static,
+    {
+        let mut draw_calls = 0;
+        for particles in sorted {
+            let (emitter, emitter_targets) = match (
+                emitters.get(&particles.emitter_id),
+                targets.emitters.get(&particles.emitter_id),
+            ) {
+                (Some(emitter), Some(emitter_targets)) => (emitter, emitter_targets),
+                _ => continue,
+            };
+            let mut colors = [[0.0; 4]; CURVE_SAMPLES];
+            for (color, c) in colors.iter_mut().zip(&emitter.color_over_life.bake()) {
+                *color = [c.x, c.y, c.z, c.w];
+            }
+            let mut sizes = [[0.0; 4]; CURVE_SAMPLES / 4];
+            for (i, size) in emitter.size_over_life.bake().iter().enumerate() {
+                sizes[i / 4][i % 4] = *size;
+            }
+            let (atlas_grid, atlas_image) = match &emitter_targets.atlas {
+                Some((atlas, image)) => ([atlas.columns, atlas.rows], image.clone()),
+                None => ([1, 1], self.white_atlas.clone()),
+            };
+            let params = self
+                .emitter_pool
+                .next(vs::ty::EmitterParams {
+                    colors,
+                    sizes,
+                    atlas: [atlas_grid[0], atlas_grid[1], 0, 0],
+                })
+                .unwrap();
+
+            let set = Arc::new(
+                PersistentDescriptorSet::start(
+                    self.pipeline.descriptor_set_layout(0).unwrap().clone(),
+                )
+                .add_buffer(frame_data.clone())
+                .unwrap()
+                .add_buffer(emitter_targets.particles.clone())
+                .unwrap()
+                .add_buffer(particles.keys.clone())
+                .unwrap()
+                .add_buffer(params)
+                .unwrap()
+                .add_sampled_image(atlas_image, self.atlas_sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+            );
+            builder = builder
+                .draw(
+                    self.pipeline.clone(),
+                    dynamic_state,
+                    BufferlessVertices {
+                        vertices: 6,
+                        instances: emitter_targets.capacity as usize,
+                    },
+                    set,
+                    (),
+                )
+                .unwrap();
+            draw_calls += 1;
+        }
+        return (builder, draw_calls);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::object::ObjectId;
+
+pub type PickId = u32;
+
+// Window area objects are picked in, in physical pixels from the top left
+// corner.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PickRegion {
+    Pixel([u32; 2]),
+    // Both corners are included, in any order
+    Rectangle([u32; 2], [u32; 2]),
+}
+
+impl PickRegion {
+    // Offset and size of the region within an image, None when it is outside.
+    pub fn clamp(&self, dimensions: [u32; 2]) -> Option<([u32; 2], [u32; 2])> {
+        let (a, b) = match *self {
+            PickRegion::Pixel(pixel) => (pixel, pixel),
+            PickRegion::Rectangle(a, b) => (a, b),
+        };
+        let min = [a[0].min(b[0]), a[1].min(b[1])];
+        let max = [
+            a[0].max(b[0]).min(dimensions[0].saturating_sub(1)),
+            a[1].max(b[1]).min(dimensions[1].saturating_sub(1)),
+        ];
+        if dimensions[0] == 0 || dimensions[1] == 0 || min[0] > max[0] || min[1] > max[1] {
+            return None;
+        }
+        return Some((min, [max[0] - min[0] + 1, max[1] - min[1] + 1]));
+    }
+}
+
+// Value written into the picking target, 0 is left for the background.
+pub fn encode_object_id(object_id: ObjectId) -> u32 { return object_id + 1; }
+
+// Objects found in picking target texels, sorted and without duplicates.
+pub fn decode_object_ids(texels: &[u32]) -> Vec<ObjectId> {
+    let mut object_ids: Vec<ObjectId> = texels
+        .iter()
+        .filter(|&&texel| texel != 0)
+        .map(|&texel| texel - 1)
+        .collect();
+    object_ids.sort_unstable();
+    object_ids.dedup();
+    return object_ids;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamp_test() {
+        let dimensions = [100, 50];
+        assert_eq!(
+            PickRegion::Pixel([10, 20]).clamp(dimensions),
+            Some(([10, 20], [1, 1]))
+        );
+        assert_eq!(PickRegion::Pixel([100, 20]).clamp(dimensions), None);
+        assert_eq!(
+            PickRegion::Rectangle([30, 40], [10, 80]).clamp(dimensions),
+            Some(([10, 40], [21, 10]))
+        );
+        assert_eq!(PickRegion::Pixel([0, 0]).clamp([0, 0]), None);
+    }
+
+    #[test]
+    fn decode_test() {
+        let texels = [
+            0,
+            encode_object_id(4),
+            encode_object_id(0),
+            0,
+            encode_object_id(4),
+        ];
+        assert_eq!(decode_object_ids(&texels), vec![0, 4]);
+        assert!(decode_object_ids(&[0, 0]).is_empty());
+    }
+}
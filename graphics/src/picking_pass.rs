@@ -0,0 +1,241 @@
+use crate::{
+    config,
+    culling::DrawItem,
+    debug_utils::set_image_name,
+    geometry::{Geometry, GeometryId},
+    object::ObjectId,
+    picking::{decode_object_ids, encode_object_id, PickId, PickRegion},
+    vertex::Vertex,
+};
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::DescriptorSetsCollection,
+    device::Device,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{AttachmentImage, ImageUsage},
+    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract},
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/forward.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/picking.frag"
+    }
+}
+
+// Object ID image of a window, with its own depth so transparent objects can be
+// picked too.
+pub struct PickingTargets {
+    window_depth_buffer: Arc<AttachmentImage>,
+    id_image: Arc<AttachmentImage>,
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+impl PickingTargets {
+    // Whether the targets were created for the window depth buffer.
+    pub fn matches(&self, depth_buffer: &Arc<AttachmentImage>) -> bool {
+        return Arc::ptr_eq(&self.window_depth_buffer, depth_buffer);
+    }
+}
+
+// Pick request copied to host memory, read once the GPU is done with it.
+pub struct PickReadback {
+    pub pick_id: PickId,
+    // None when the region was outside of the window
+    buffer: Option<Arc<CpuAccessibleBuffer<[u32]>>>,
+}
+
+impl PickReadback {
+    // Picked objects, None while the GPU still uses the buffer.
+    pub fn try_read(&self) -> Option<Vec<ObjectId>> {
+        match &self.buffer {
+            Some(buffer) => match buffer.read() {
+                Ok(texels) => return Some(decode_object_ids(&texels)),
+                Err(_) => return None,
+            },
+            None => return Some(Vec::new()),
+        }
+    }
+}
+
+pub struct PickingRenderer {
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    // Uses the draw set of the depth prepass
+    pub pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+}
+
+impl PickingRenderer {
+    pub fn new(device: Arc<Device>) -> Self {
+        let render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    object_id: {
+                        load: Clear,
+                        store: Store,
+                        format: config::PICKING_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [object_id],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        return PickingRenderer {
+            device,
+            render_pass,
+            pipeline,
+        };
+    }
+
+    pub fn create_targets(&self, window_depth_buffer: Arc<AttachmentImage>) -> PickingTargets {
+        let dimensions = window_depth_buffer.dimensions();
+        let id_image = AttachmentImage::with_usage(
+            self.device.clone(),
+            dimensions,
+            config::PICKING_FORMAT,
+            ImageUsage {
+                color_attachment: true,
+                transfer_source: true,
+                ..ImageUsage::none()
+            },
+        )
+        .unwrap();
+        set_image_name(&id_image, "picking object ids");
+        let depth_buffer = AttachmentImage::transient(
+            self.device.clone(),
+            dimensions,
+            config::DEFAULT_DEPTH_FORMAT,
+        )
+        .unwrap();
+        let framebuffer = Arc::new(
+            Framebuffer::start(self.render_pass.clone())
+                .add(id_image.clone())
+                .unwrap()
+                .add(depth_buffer)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        return PickingTargets {
+            window_depth_buffer,
+            id_image,
+            framebuffer,
+        };
+    }
+
+    // Renders object IDs of `draws` and copies the requested regions into host
+    // visible buffers. `set` contains the frame data and draw data.
+    pub fn record<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        targets: &PickingTargets,
+        set: S,
+        draws: &[DrawItem],
+        geometries: &HashMap<GeometryId, Geometry>,
+        requests: &[(PickId, PickRegion)],
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, Vec<PickReadback>)
+    where
+        S: DescriptorSetsCollection + Clone,
+    {
+        let clear_values = vec![[0u32; 4].into(), 1f32.into()];
+        builder = builder
+            .begin_render_pass(targets.framebuffer.clone(), false, clear_values)
+            .unwrap();
+        for (index, item) in draws.iter().enumerate() {
+            if !item.in_frustum {
+                continue;
+            }
+            let geometry = &geometries[&item.geometry_id];
+            let push_constants = vs::ty::PushConstants {
+                draw: [index as u32, encode_object_id(item.object_id), 0, 0],
+            };
+            builder = builder
+                .draw(
+                    self.pipeline.clone(),
+                    dynamic_state,
+                    geometry.vertex_buffer.clone(),
+                    set.clone(),
+                    push_constants,
+                )
+                .unwrap();
+        }
+        builder = builder.end_render_pass().unwrap();
+
+        let dimensions = targets.id_image.dimensions();
+        let mut readbacks = Vec::with_capacity(requests.len());
+        for &(pick_id, region) in requests {
+            let (offset, size) = match region.clamp(dimensions) {
+                Some(bounds) => bounds,
+                None => {
+                    readbacks.push(PickReadback {
+                        pick_id,
+                        buffer: None,
+                    });
+                    continue;
+                }
+            };
+            let buffer = CpuAccessibleBuffer::from_iter(
+                self.device.clone(),
+                BufferUsage::transfer_destination(),
+                false,
+                std::iter::repeat(0u32).take((size[0] * size[1]) as usize),
+            )
+            .unwrap();
+            builder = builder
+                .copy_image_to_buffer_dimensions(
+                    targets.id_image.clone(),
+                    buffer.clone(),
+                    [offset[0], offset[1], 0],
+                    [size[0], size[1], 1],
+                    0,
+                    1,
+                    0,
+                )
+                .unwrap();
+            readbacks.push(PickReadback {
+                pick_id,
+                buffer: Some(buffer),
+            });
+        }
+        return (builder, readbacks);
+    }
+}
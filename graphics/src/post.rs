@@ -0,0 +1,201 @@
+use polyengine_core::*;
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Tonemapper {
+    // Values above 1 are clipped
+    None,
+    Reinhard,
+    // Narkowicz fit of the ACES filmic curve
+    Aces,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExposureMode {
+    // Constant multiplier applied to the scene color
+    Manual(FScalar),
+    // Exposure follows the average scene luminance
+    Auto {
+        // Average luminance is mapped to this value
+        key_value: FScalar,
+        // Luminance range taken into account, extreme values are clamped
+        min_luminance: FScalar,
+        max_luminance: FScalar,
+        // Adaptation rates per second, when the scene gets brighter and darker
+        speed_up: FScalar,
+        speed_down: FScalar,
+    },
+}
+
+impl ExposureMode {
+    pub fn auto() -> Self {
+        return ExposureMode::Auto {
+            key_value: 0.18,
+            min_luminance: 0.001,
+            max_luminance: 64.0,
+            speed_up: 3.0,
+            speed_down: 1.0,
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BloomSettings {
+    // Luminance above which pixels start to bloom
+    pub threshold: FScalar,
+    // Width of the soft transition around the threshold, relative to it
+    pub soft_knee: FScalar,
+    pub intensity: FScalar,
+    // Number of half resolution steps, more gives wider glow
+    pub mip_count: usize,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        return BloomSettings {
+            threshold: 1.0,
+            soft_knee: 0.5,
+            intensity: 0.05,
+            mip_count: 6,
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VignetteSettings {
+    pub intensity: FScalar,
+    // Distance from the center, relative to the half diagonal, where darkening starts
+    pub radius: FScalar,
+    pub smoothness: FScalar,
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        return VignetteSettings {
+            intensity: 0.4,
+            radius: 0.6,
+            smoothness: 0.5,
+        };
+    }
+}
+
+// 3D colour grading table stored as a horizontal strip of `size` slices, each
+// `size` x `size` texels. Red grows along X within a slice, green along Y and
+// blue selects the slice. Inputs and outputs are display (sRGB encoded) values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorGradingLut {
+    pub size: u32,
+    pub texels: Vec<[u8; 4]>,
+}
+
+impl ColorGradingLut {
+    // Returns None for sizes below 2, which can't span the colour range.
+    pub fn identity(size: u32) -> Option<Self> {
+        if size < 2 {
+            return None;
+        }
+        let max = (size - 1) as f32;
+        let mut texels = Vec::with_capacity((size * size * size) as usize);
+        for g in 0..size {
+            for b in 0..size {
+                for r in 0..size {
+                    let channel = |v: u32| (v as f32 / max * 255.0).round() as u8;
+                    texels.push([channel(r), channel(g), channel(b), 255]);
+                }
+            }
+        }
+        return Some(ColorGradingLut { size, texels });
+    }
+
+    // Texels in the strip layout, row by row. Returns None when the data doesn't
+    // match the size.
+    pub fn from_strip(size: u32, texels: Vec<[u8; 4]>) -> Option<Self> {
+        if size < 2 || texels.len() != (size * size * size) as usize {
+            return None;
+        }
+        return Some(ColorGradingLut { size, texels });
+    }
+
+    pub fn dimensions(&self) -> [u32; 2] { return [self.size * self.size, self.size]; }
+}
+
+// Post-process stack applied when resolving the HDR image into the window.
+// Effects run in a fixed order: exposure, bloom, tonemapping, colour grading,
+// vignette.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcessSettings {
+    pub exposure: ExposureMode,
+    // Exposure adjustment in stops, applied on top of the exposure mode
+    pub exposure_compensation: FScalar,
+    pub tonemapper: Tonemapper,
+    pub bloom: Option<BloomSettings>,
+    pub color_grading: Option<Arc<ColorGradingLut>>,
+    pub vignette: Option<VignetteSettings>,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        return PostProcessSettings {
+            exposure: ExposureMode::Manual(1.0),
+            exposure_compensation: 0.0,
+            tonemapper: Tonemapper::Aces,
+            bloom: Some(BloomSettings::default()),
+            color_grading: None,
+            vignette: None,
+        };
+    }
+}
+
+// Sizes of the bloom chain, starting at half of the image size. The chain stops
+// early when the next level would be smaller than 2 texels.
+pub fn bloom_mip_sizes(dimensions: [u32; 2], mip_count: usize) -> Vec<[u32; 2]> {
+    let mut sizes = Vec::with_capacity(mip_count);
+    let mut size = dimensions;
+    for _ in 0..mip_count {
+        size = [size[0] / 2, size[1] / 2];
+        if size[0] < 2 || size[1] < 2 {
+            break;
+        }
+        sizes.push(size);
+    }
+    return sizes;
+}
+
+// Fraction of the remaining difference covered by exponential adaptation during
+// `dt` seconds.
+pub fn adaptation_rate(dt: FScalar, speed: FScalar) -> FScalar { return 1.0 - (-dt * speed).exp(); }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_lut_test() {
+        assert!(ColorGradingLut::identity(0).is_none());
+        assert!(ColorGradingLut::identity(1).is_none());
+        assert_eq!(ColorGradingLut::identity(2).unwrap().texels[7], [255; 4]);
+        let lut = ColorGradingLut::identity(4).unwrap();
+        assert_eq!(lut.dimensions(), [16, 4]);
+        assert_eq!(lut.texels.len(), 64);
+        // Row 1 (green = 85), slice 2 (blue = 170), red = 255
+        let texel = lut.texels[16 + 2 * 4 + 3];
+        assert_eq!(texel, [255, 85, 170, 255]);
+        assert!(ColorGradingLut::from_strip(4, lut.texels.clone()).is_some());
+        assert!(ColorGradingLut::from_strip(5, lut.texels).is_none());
+    }
+
+    #[test]
+    fn bloom_mip_sizes_test() {
+        assert_eq!(
+            bloom_mip_sizes([1280, 720], 3),
+            vec![[640, 360], [320, 180], [160, 90]]
+        );
+        assert_eq!(bloom_mip_sizes([16, 8], 6), vec![[8, 4], [4, 2]]);
+    }
+
+    #[test]
+    fn adaptation_rate_test() {
+        assert_eq!(adaptation_rate(0.0, 2.0), 0.0);
+        assert!(adaptation_rate(10.0, 2.0) > 0.999);
+    }
+}
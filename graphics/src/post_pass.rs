@@ -0,0 +1,679 @@
+use crate::{
+    common::BufferlessPipeline,
+    config,
+    debug_utils::set_image_name,
+    post::{
+        adaptation_rate,
+        bloom_mip_sizes,
+        ColorGradingLut,
+        ExposureMode,
+        PostProcessSettings,
+        Tonemapper,
+    },
+};
+use polyengine_core::*;
+use std::{sync::Arc, time::Instant};
+use vulkano::{
+    buffer::{BufferUsage, DeviceLocalBuffer},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, PipelineLayoutAbstract},
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{AttachmentImage, Dimensions, ImageUsage, ImmutableImage},
+    pipeline::{
+        blend::{AttachmentBlend, BlendFactor, BlendOp},
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        viewport::Viewport,
+        ComputePipeline,
+        ComputePipelineAbstract,
+        GraphicsPipeline,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod fullscreen_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/fullscreen.vert"
+    }
+}
+
+mod prefilter_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/bloom_prefilter.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod downsample_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/bloom_downsample.frag"
+    }
+}
+
+mod upsample_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/bloom_upsample.frag"
+    }
+}
+
+mod composite_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/composite.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod luminance_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/luminance.comp",
+        include: ["src/shaders"]
+    }
+}
+
+// Size of the colour grading table used when grading is disabled.
+const IDENTITY_LUT_SIZE: u32 = 2;
+
+// Has to match the ids in color.glsl.
+fn tonemapper_id(tonemapper: Tonemapper) -> f32 {
+    return match tonemapper {
+        Tonemapper::None => 0.0,
+        Tonemapper::Reinhard => 1.0,
+        Tonemapper::Aces => 2.0,
+    };
+}
+
+struct BloomLevel {
+    image: Arc<AttachmentImage>,
+    // Overwrites the level, used while downsampling
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    // Keeps the content, upsampled levels are blended on top
+    blend_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+// Per window HDR targets and post-process state. Recreated together with the
+// window depth buffer.
+pub struct PostTargets {
+    depth_buffer: Arc<AttachmentImage>,
+    pub hdr_image: Arc<AttachmentImage>,
+    pub velocity_image: Arc<AttachmentImage>,
+    pub scene_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    bloom_mip_count: usize,
+    bloom_levels: Vec<BloomLevel>,
+    // x: adapted luminance, y: exposure, kept on the GPU between frames
+    exposure_buffer: Arc<DeviceLocalBuffer<[f32; 4]>>,
+    auto_exposure: bool,
+    // Skips adaptation in the next measurement
+    exposure_reset: bool,
+    last_frame: Option<Instant>,
+    // Seconds since the previous frame of the window
+    frame_delta: FScalar,
+    color_grading: Option<(Arc<ColorGradingLut>, Arc<ImmutableImage<Format>>)>,
+}
+
+impl PostTargets {
+    pub fn matches(
+        &self,
+        depth_buffer: &Arc<AttachmentImage>,
+        settings: &PostProcessSettings,
+    ) -> bool {
+        let bloom_mip_count = settings.bloom.map_or(0, |bloom| bloom.mip_count);
+        return Arc::ptr_eq(&self.depth_buffer, depth_buffer)
+            && self.bloom_mip_count == bloom_mip_count;
+    }
+}
+
+pub struct PostRenderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    // Single HDR colour attachment, used by bloom levels
+    hdr_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    hdr_blend_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    // Tonemapped image in the window format, for passes running after the composite
+    pub ldr_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    prefilter_pipeline: Arc<BufferlessPipeline>,
+    downsample_pipeline: Arc<BufferlessPipeline>,
+    upsample_pipeline: Arc<BufferlessPipeline>,
+    composite_pipeline: Arc<BufferlessPipeline>,
+    composite_ldr_pipeline: Arc<BufferlessPipeline>,
+    luminance_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    identity_lut: Arc<ImmutableImage<Format>>,
+}
+
+impl PostRenderer {
+    // `window_render_pass` is the final pass writing into the swapchain image.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        window_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let hdr_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+        let hdr_blend_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let ldr_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: config::DEFAULT_WINDOW_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let fullscreen_vs = fullscreen_vs::Shader::load(device.clone()).unwrap();
+        let prefilter_fs = prefilter_fs::Shader::load(device.clone()).unwrap();
+        let downsample_fs = downsample_fs::Shader::load(device.clone()).unwrap();
+        let upsample_fs = upsample_fs::Shader::load(device.clone()).unwrap();
+        let composite_fs = composite_fs::Shader::load(device.clone()).unwrap();
+        let luminance_cs = luminance_cs::Shader::load(device.clone()).unwrap();
+
+        // All passes draw a single fullscreen triangle.
+        macro_rules! fullscreen_pipeline {
+            ($fs:expr, $render_pass:expr) => {
+                fullscreen_pipeline!($fs, $render_pass, AttachmentBlend::pass_through())
+            };
+            ($fs:expr, $render_pass:expr, $blend:expr) => {
+                Arc::new(
+                    GraphicsPipeline::start()
+                        .vertex_input(BufferlessDefinition {})
+                        .vertex_shader(fullscreen_vs.main_entry_point(), ())
+                        .triangle_list()
+                        .viewports_dynamic_scissors_irrelevant(1)
+                        .fragment_shader($fs.main_entry_point(), ())
+                        .blend_collective($blend)
+                        .render_pass(
+                            Subpass::from(
+                                $render_pass.clone() as Arc<dyn RenderPassAbstract + Send + Sync>,
+                                0,
+                            )
+                            .unwrap(),
+                        )
+                        .build(device.clone())
+                        .unwrap(),
+                )
+            };
+        }
+
+        let additive = AttachmentBlend {
+            enabled: true,
+            color_op: BlendOp::Add,
+            color_source: BlendFactor::One,
+            color_destination: BlendFactor::One,
+            alpha_op: BlendOp::Add,
+            alpha_source: BlendFactor::One,
+            alpha_destination: BlendFactor::One,
+            mask_red: true,
+            mask_green: true,
+            mask_blue: true,
+            mask_alpha: true,
+        };
+        let prefilter_pipeline = fullscreen_pipeline!(prefilter_fs, hdr_render_pass);
+        let downsample_pipeline = fullscreen_pipeline!(downsample_fs, hdr_render_pass);
+        let upsample_pipeline = fullscreen_pipeline!(upsample_fs, hdr_blend_render_pass, additive);
+        let composite_pipeline = fullscreen_pipeline!(composite_fs, window_render_pass);
+        let composite_ldr_pipeline = fullscreen_pipeline!(composite_fs, ldr_render_pass);
+
+        let luminance_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &luminance_cs.main_entry_point(), &()).unwrap(),
+        );
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        // Bound in place of the grading table when grading is disabled, there is
+        // nothing to wait for in later frames so the upload is finished here.
+        let identity = ColorGradingLut::identity(IDENTITY_LUT_SIZE).unwrap();
+        let (identity_lut, upload) = upload_lut(&identity, queue.clone());
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        return PostRenderer {
+            device,
+            queue,
+            hdr_render_pass,
+            hdr_blend_render_pass,
+            ldr_render_pass,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+            composite_ldr_pipeline,
+            luminance_pipeline,
+            sampler,
+            identity_lut,
+        };
+    }
+
+    // Creates the HDR scene targets around the window depth buffer.
+    pub fn create_targets(
+        &self,
+        scene_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        depth_buffer: Arc<AttachmentImage>,
+        settings: &PostProcessSettings,
+    ) -> PostTargets {
+        let dimensions = depth_buffer.dimensions();
+        // Scene images are copied out by EXR captures
+        let usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+
+        let hdr_image =
+            AttachmentImage::with_usage(self.device.clone(), dimensions, config::HDR_FORMAT, usage)
+                .unwrap();
+        let velocity_image = AttachmentImage::with_usage(
+            self.device.clone(),
+            dimensions,
+            config::VELOCITY_FORMAT,
+            usage,
+        )
+        .unwrap();
+        set_image_name(&hdr_image, "scene colour");
+        set_image_name(&velocity_image, "scene velocity");
+        let scene_framebuffer = Arc::new(
+            Framebuffer::start(scene_render_pass)
+                .add(hdr_image.clone())
+                .unwrap()
+                .add(velocity_image.clone())
+                .unwrap()
+                .add(depth_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let bloom_mip_count = settings.bloom.map_or(0, |bloom| bloom.mip_count);
+        let bloom_levels = bloom_mip_sizes(dimensions, bloom_mip_count)
+            .into_iter()
+            .map(|size| {
+                let image = AttachmentImage::with_usage(
+                    self.device.clone(),
+                    size,
+                    config::HDR_FORMAT,
+                    usage,
+                )
+                .unwrap();
+                set_image_name(&image, "bloom level");
+                let framebuffer = Arc::new(
+                    Framebuffer::start(self.hdr_render_pass.clone())
+                        .add(image.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                );
+                let blend_framebuffer = Arc::new(
+                    Framebuffer::start(self.hdr_blend_render_pass.clone())
+                        .add(image.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                );
+                return BloomLevel {
+                    image,
+                    framebuffer,
+                    blend_framebuffer,
+                };
+            })
+            .collect();
+
+        let exposure_buffer = DeviceLocalBuffer::new(
+            self.device.clone(),
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            Some(self.queue.family()),
+        )
+        .unwrap();
+
+        return PostTargets {
+            depth_buffer,
+            hdr_image,
+            velocity_image,
+            scene_framebuffer,
+            bloom_mip_count,
+            bloom_levels,
+            exposure_buffer,
+            auto_exposure: false,
+            exposure_reset: true,
+            last_frame: None,
+            frame_delta: 0.0,
+            color_grading: None,
+        };
+    }
+
+    // Updates per frame state of the targets. Returns the colour grading upload
+    // the next submission has to wait for, if a new table was set.
+    pub fn prepare(
+        &self,
+        targets: &mut PostTargets,
+        settings: &PostProcessSettings,
+        now: Instant,
+    ) -> Option<Box<dyn GpuFuture>> {
+        targets.frame_delta = targets
+            .last_frame
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        targets.last_frame = Some(now);
+
+        let auto_exposure = match settings.exposure {
+            ExposureMode::Auto { .. } => true,
+            ExposureMode::Manual(_) => false,
+        };
+        // Adaptation restarts from the current scene when auto exposure gets enabled.
+        targets.exposure_reset = auto_exposure && !targets.auto_exposure;
+        targets.auto_exposure = auto_exposure;
+
+        let up_to_date = match (&settings.color_grading, &targets.color_grading) {
+            (Some(lut), Some((uploaded, _))) => Arc::ptr_eq(lut, uploaded),
+            (None, None) => true,
+            _ => false,
+        };
+        if up_to_date {
+            return None;
+        }
+
+        return match &settings.color_grading {
+            Some(lut) => {
+                let (image, upload) = upload_lut(lut, self.queue.clone());
+                targets.color_grading = Some((lut.clone(), image));
+                Some(upload)
+            }
+            None => {
+                targets.color_grading = None;
+                None
+            }
+        };
+    }
+
+    fn fullscreen_pass<Pc>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+        pipeline: &Arc<BufferlessPipeline>,
+        source: Arc<AttachmentImage>,
+        dimensions: [u32; 2],
+        push_constants: Pc,
+    ) -> AutoCommandBufferBuilder {
+        let set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.descriptor_set_layout(0).unwrap().clone())
+                .add_sampled_image(source, self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        return builder
+            .begin_render_pass(framebuffer, false, vec![vulkano::format::ClearValue::None])
+            .unwrap()
+            .draw(
+                pipeline.clone(),
+                &viewport_state(dimensions),
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                set,
+                push_constants,
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+
+    // Records exposure measurement and the bloom chain on the `scene` image, the
+    // HDR target or its anti-aliased copy, returns the number of draws. Has to be
+    // recorded after the scene pass and before `record_composite`.
+    pub fn record_effects(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        targets: &PostTargets,
+        scene: &Arc<AttachmentImage>,
+        settings: &PostProcessSettings,
+    ) -> (AutoCommandBufferBuilder, u32) {
+        if let ExposureMode::Auto {
+            key_value,
+            min_luminance,
+            max_luminance,
+            speed_up,
+            speed_down,
+        } = settings.exposure
+        {
+            let set = Arc::new(
+                PersistentDescriptorSet::start(
+                    self.luminance_pipeline
+                        .descriptor_set_layout(0)
+                        .unwrap()
+                        .clone(),
+                )
+                .add_sampled_image(scene.clone(), self.sampler.clone())
+                .unwrap()
+                .add_buffer(targets.exposure_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+            );
+            let push_constants = luminance_cs::ty::PushConstants {
+                luminance_range: [min_luminance.log2(), max_luminance.log2(), 0.0, 0.0],
+                adaptation: [
+                    adaptation_rate(targets.frame_delta, speed_up),
+                    adaptation_rate(targets.frame_delta, speed_down),
+                    key_value,
+                    if targets.exposure_reset { 1.0 } else { 0.0 },
+                ],
+            };
+            builder = builder
+                .dispatch(
+                    [1, 1, 1],
+                    self.luminance_pipeline.clone(),
+                    set,
+                    push_constants,
+                )
+                .unwrap();
+        }
+
+        let bloom = match settings.bloom {
+            Some(bloom) if !targets.bloom_levels.is_empty() => bloom,
+            _ => return (builder, 0),
+        };
+
+        let levels = &targets.bloom_levels;
+        builder = self.fullscreen_pass(
+            builder,
+            levels[0].framebuffer.clone(),
+            &self.prefilter_pipeline,
+            scene.clone(),
+            levels[0].image.dimensions(),
+            prefilter_fs::ty::PushConstants {
+                threshold: [bloom.threshold, bloom.threshold * bloom.soft_knee, 0.0, 0.0],
+            },
+        );
+        for i in 1..levels.len() {
+            builder = self.fullscreen_pass(
+                builder,
+                levels[i].framebuffer.clone(),
+                &self.downsample_pipeline,
+                levels[i - 1].image.clone(),
+                levels[i].image.dimensions(),
+                (),
+            );
+        }
+        for i in (0..levels.len() - 1).rev() {
+            builder = self.fullscreen_pass(
+                builder,
+                levels[i].blend_framebuffer.clone(),
+                &self.upsample_pipeline,
+                levels[i + 1].image.clone(),
+                levels[i].image.dimensions(),
+                (),
+            );
+        }
+
+        // Prefilter, downsample and upsample draws
+        return (builder, 2 * levels.len() as u32 - 1);
+    }
+
+    // Draws the resolved image, has to be recorded inside of the window render
+    // pass or, when `offscreen` is set, inside of `ldr_render_pass`.
+    pub fn record_composite(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        targets: &PostTargets,
+        scene: &Arc<AttachmentImage>,
+        settings: &PostProcessSettings,
+        dynamic_state: &DynamicState,
+        aspect: FScalar,
+        offscreen: bool,
+    ) -> AutoCommandBufferBuilder {
+        let pipeline = if offscreen {
+            &self.composite_ldr_pipeline
+        } else {
+            &self.composite_pipeline
+        };
+        let (bloom_image, bloom_intensity) = match (settings.bloom, targets.bloom_levels.first()) {
+            (Some(bloom), Some(level)) => (level.image.clone(), bloom.intensity),
+            _ => (scene.clone(), 0.0),
+        };
+        let (lut_image, lut_size, grading) = match &targets.color_grading {
+            Some((lut, image)) => (image.clone(), lut.size, 1.0),
+            None => (self.identity_lut.clone(), IDENTITY_LUT_SIZE, 0.0),
+        };
+        let (manual_exposure, auto_exposure) = match settings.exposure {
+            ExposureMode::Manual(exposure) => (exposure, 0.0),
+            ExposureMode::Auto { .. } => (1.0, 1.0),
+        };
+        let vignette = match settings.vignette {
+            Some(v) => [v.intensity, v.radius, v.smoothness, aspect],
+            None => [0.0, 0.0, 0.0, aspect],
+        };
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.descriptor_set_layout(0).unwrap().clone())
+                .add_sampled_image(scene.clone(), self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(bloom_image, self.sampler.clone())
+                .unwrap()
+                .add_sampled_image(lut_image, self.sampler.clone())
+                .unwrap()
+                .add_buffer(targets.exposure_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let push_constants = composite_fs::ty::PushConstants {
+            exposure: [
+                manual_exposure,
+                auto_exposure,
+                settings.exposure_compensation.exp2(),
+                tonemapper_id(settings.tonemapper),
+            ],
+            effects: [bloom_intensity, lut_size as f32, grading, 0.0],
+            vignette,
+        };
+
+        return builder
+            .draw(
+                pipeline.clone(),
+                dynamic_state,
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                set,
+                push_constants,
+            )
+            .unwrap();
+    }
+}
+
+fn viewport_state(dimensions: [u32; 2]) -> DynamicState {
+    return DynamicState {
+        viewports: Some(vec![Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+            depth_range: 0.0..1.0,
+        }]),
+        ..DynamicState::none()
+    };
+}
+
+fn upload_lut(
+    lut: &ColorGradingLut,
+    queue: Arc<Queue>,
+) -> (Arc<ImmutableImage<Format>>, Box<dyn GpuFuture>) {
+    let [width, height] = lut.dimensions();
+    let (image, future) = ImmutableImage::from_iter(
+        lut.texels.iter().cloned(),
+        Dimensions::Dim2d { width, height },
+        Format::R8G8B8A8Unorm,
+        queue,
+    )
+    .unwrap();
+    return (image, Box::new(future));
+}
@@ -0,0 +1,160 @@
+use std::{ffi::CStr, fmt, time::Duration};
+
+// Upper bound of profiled passes in a frame, sizes the query pools.
+pub const MAX_PROFILED_PASSES: usize = 16;
+
+// Render passes reported in the frame statistics, in recording order.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProfiledPass {
+    Shadows,
+    // Culling of GPU driven draws
+    GpuCulling,
+    // Simulation and sorting of particles
+    Particles,
+    DepthPrepass,
+    AmbientOcclusion,
+    GBuffer,
+    // Projected decals, in the forward path between the opaque surfaces and the
+    // rest of the scene
+    Decals,
+    Lighting,
+    // Opaque, skinned, voxel and terrain surfaces, skybox, particles and sorted
+    // or additive transparent surfaces. Split in two by forward decals.
+    Scene,
+    // Weighted blended accumulation and composite
+    Transparency,
+    // Hierarchical depth for the GPU culling of the next frame
+    DepthPyramid,
+    Picking,
+    // Temporal anti-aliasing, exposure and bloom
+    PostProcess,
+    // Composite into the swapchain image, FXAA, text and debug drawing
+    Window,
+    // Batched sprites, tile maps and screen text of 2D windows
+    Sprites,
+}
+
+impl ProfiledPass {
+    pub fn name(&self) -> &'static str { return self.label().to_str().unwrap(); }
+
+    // Nul terminated name, for the debug labels of graphics debuggers.
+    pub fn label(&self) -> &'static CStr {
+        let name: &'static [u8] = match self {
+            ProfiledPass::Shadows => b"shadows\0",
+            ProfiledPass::GpuCulling => b"gpu culling\0",
+            ProfiledPass::Particles => b"particles\0",
+            ProfiledPass::DepthPrepass => b"depth prepass\0",
+            ProfiledPass::AmbientOcclusion => b"ambient occlusion\0",
+            ProfiledPass::GBuffer => b"g-buffer\0",
+            ProfiledPass::Decals => b"decals\0",
+            ProfiledPass::Lighting => b"lighting\0",
+            ProfiledPass::Scene => b"scene\0",
+            ProfiledPass::Transparency => b"transparency\0",
+            ProfiledPass::DepthPyramid => b"depth pyramid\0",
+            ProfiledPass::Picking => b"picking\0",
+            ProfiledPass::PostProcess => b"post-process\0",
+            ProfiledPass::Window => b"window\0",
+            ProfiledPass::Sprites => b"sprites\0",
+        };
+        return CStr::from_bytes_with_nul(name).unwrap();
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PassStats {
+    pub pass: ProfiledPass,
+    pub gpu_time: Duration,
+    pub draw_calls: u32,
+}
+
+// Statistics of a finished frame of a window.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RenderStats {
+    pub passes: Vec<PassStats>,
+}
+
+impl RenderStats {
+    pub fn gpu_time(&self) -> Duration {
+        return self.passes.iter().map(|pass| pass.gpu_time).sum();
+    }
+
+    pub fn draw_calls(&self) -> u32 { return self.passes.iter().map(|pass| pass.draw_calls).sum(); }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GPU {:.3} ms, {} draws",
+            self.gpu_time().as_secs_f64() * 1000.0,
+            self.draw_calls()
+        )?;
+        for pass in &self.passes {
+            write!(
+                f,
+                "\n\t{}: {:.3} ms, {} draws",
+                pass.pass.name(),
+                pass.gpu_time.as_secs_f64() * 1000.0,
+                pass.draw_calls
+            )?;
+        }
+        return Ok(());
+    }
+}
+
+// Time between two timestamps of a queue with `valid_bits` significant bits,
+// `period` is the length of a tick in nanoseconds.
+pub fn timestamp_delta(begin: u64, end: u64, valid_bits: u32, period: f32) -> Duration {
+    let mask = if valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << valid_bits) - 1
+    };
+    // The counter may have wrapped around between the timestamps
+    let ticks = (end & mask).wrapping_sub(begin & mask) & mask;
+    return Duration::from_nanos((ticks as f64 * period as f64).round() as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_delta_test() {
+        assert_eq!(
+            timestamp_delta(100, 350, 64, 1.0),
+            Duration::from_nanos(250)
+        );
+        assert_eq!(timestamp_delta(10, 20, 64, 2.5), Duration::from_nanos(25));
+        // 36 valid bits, the counter wrapped
+        let max = (1u64 << 36) - 1;
+        assert_eq!(
+            timestamp_delta(max - 4, 5, 36, 1.0),
+            Duration::from_nanos(10)
+        );
+    }
+
+    #[test]
+    fn totals_test() {
+        let pass = |pass, milliseconds, draw_calls| PassStats {
+            pass,
+            gpu_time: Duration::from_millis(milliseconds),
+            draw_calls,
+        };
+        let stats = RenderStats {
+            passes: vec![
+                pass(ProfiledPass::Shadows, 2, 10),
+                pass(ProfiledPass::Scene, 5, 20),
+            ],
+        };
+        assert_eq!(stats.gpu_time(), Duration::from_millis(7));
+        assert_eq!(stats.draw_calls(), 30);
+        assert!(stats.to_string().contains("scene: 5.000 ms, 20 draws"));
+    }
+
+    #[test]
+    fn label_test() {
+        assert_eq!(ProfiledPass::PostProcess.name(), "post-process");
+        assert_eq!(ProfiledPass::GBuffer.label().to_bytes(), b"g-buffer");
+    }
+}
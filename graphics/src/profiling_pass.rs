@@ -0,0 +1,379 @@
+use crate::profiling::{
+    timestamp_delta,
+    PassStats,
+    ProfiledPass,
+    RenderStats,
+    MAX_PROFILED_PASSES,
+};
+use polyengine_core::*;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{
+        pool::standard::{StandardCommandPoolAlloc, StandardCommandPoolBuilder},
+        sys::{
+            Flags,
+            Kind,
+            KindOcclusionQuery,
+            UnsafeCommandBuffer,
+            UnsafeCommandBufferBuilder,
+            UnsafeCommandBufferBuilderPipelineBarrier,
+        },
+        AutoCommandBufferBuilder,
+        CommandBuffer,
+        CommandBufferExecError,
+    },
+    device::{Device, DeviceOwned, Queue},
+    image::{ImageAccess, ImageLayout},
+    query::{QueryPipelineStatisticFlags, QueryType, UnsafeQueryPool},
+    sync::{AccessCheckError, AccessFlagBits, GpuFuture, PipelineStages},
+};
+
+// Two timestamps per pass. `queries_range` only accepts ranges ending before
+// the last slot, so the pool has a spare one.
+const TIMESTAMP_COUNT: u32 = MAX_PROFILED_PASSES as u32 * 2;
+const TIMESTAMP_SLOTS: u32 = TIMESTAMP_COUNT + 1;
+
+// Query pool and results of a frame, reused once the results were read.
+pub struct FrameQueries {
+    // Begin and end of every pass
+    timestamps: Arc<UnsafeQueryPool>,
+    // Timestamps copied at the end of the frame. The frame also writes the last
+    // element, which keeps the buffer locked until the GPU finished it.
+    results: Arc<CpuAccessibleBuffer<[u32]>>,
+    // Passes recorded into the pool and their draw calls
+    passes: Vec<(ProfiledPass, u32)>,
+    active: Option<ProfiledPass>,
+    timestamp_period: f32,
+    valid_bits: u32,
+}
+
+impl FrameQueries {
+    // Statistics of the frame, None while the GPU hasn't finished it.
+    pub fn try_read(&self) -> Option<RenderStats> {
+        if self.passes.is_empty() {
+            return Some(RenderStats::default());
+        }
+        let timestamps = match self.results.read() {
+            Ok(timestamps) => timestamps,
+            Err(_) => return None,
+        };
+        let passes = self
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(i, &(pass, draw_calls))| PassStats {
+                pass,
+                gpu_time: timestamp_delta(
+                    timestamps[i * 2] as u64,
+                    timestamps[i * 2 + 1] as u64,
+                    self.valid_bits,
+                    self.timestamp_period,
+                ),
+                draw_calls,
+            })
+            .collect();
+        return Some(RenderStats { passes });
+    }
+}
+
+// Secondary command buffer with the query and label commands vulkano only
+// offers on unsafe command buffers. It isn't tracked by vulkano, so it keeps
+// the pool and results it writes alive itself.
+struct RawCommands {
+    inner: UnsafeCommandBuffer<StandardCommandPoolAlloc>,
+    _queries: Option<(Arc<UnsafeQueryPool>, Arc<CpuAccessibleBuffer<[u32]>>)>,
+}
+
+unsafe impl DeviceOwned for RawCommands {
+    fn device(&self) -> &Arc<Device> { return self.inner.device(); }
+}
+
+unsafe impl CommandBuffer for RawCommands {
+    type PoolAlloc = StandardCommandPoolAlloc;
+
+    fn inner(&self) -> &UnsafeCommandBuffer<StandardCommandPoolAlloc> { return &self.inner; }
+
+    // Only executed by primary command buffers, never submitted on its own.
+    fn lock_submit(&self, _: &dyn GpuFuture, _: &Queue) -> Result<(), CommandBufferExecError> {
+        return Ok(());
+    }
+
+    unsafe fn unlock(&self) {}
+
+    fn check_buffer_access(
+        &self,
+        _: &dyn BufferAccess,
+        _: bool,
+        _: &Queue,
+    ) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        return Err(AccessCheckError::Unknown);
+    }
+
+    fn check_image_access(
+        &self,
+        _: &dyn ImageAccess,
+        _: ImageLayout,
+        _: bool,
+        _: &Queue,
+    ) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        return Err(AccessCheckError::Unknown);
+    }
+}
+
+pub struct GpuProfiler {
+    device: Arc<Device>,
+    // Family of the queue the frames are submitted to
+    queue_family: u32,
+    // Nanoseconds per timestamp tick
+    timestamp_period: f32,
+    // 0 when the queue can't write timestamps
+    valid_bits: u32,
+    // Passes are labeled for graphics debuggers, only in debug mode
+    labels: bool,
+}
+
+// Colour of the pass labels in graphics debuggers
+const LABEL_COLOR: [f32; 4] = [0.35, 0.6, 0.9, 1.0];
+
+impl GpuProfiler {
+    pub fn new(device: Arc<Device>, queue: &Arc<Queue>) -> Self {
+        let timestamp_period = device.physical_device().limits().timestamp_period();
+        let valid_bits = queue.family().timestamp_valid_bits().unwrap_or(0);
+        let labels = device.instance().loaded_extensions().ext_debug_utils;
+        if valid_bits == 0 {
+            log::warn!("Timestamp queries aren't supported, render stats are disabled");
+        }
+
+        return GpuProfiler {
+            device,
+            queue_family: queue.family().id(),
+            timestamp_period,
+            // Results are copied as 32 bit values
+            valid_bits: valid_bits.min(32),
+            labels,
+        };
+    }
+
+    // Resets the pool of `recycled` or a new one for the next frame, None when
+    // the device can't profile.
+    pub fn begin_frame(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        recycled: Option<FrameQueries>,
+    ) -> (AutoCommandBufferBuilder, Option<FrameQueries>) {
+        if self.valid_bits == 0 {
+            return (builder, None);
+        }
+        let mut queries = match recycled {
+            Some(queries) => queries,
+            None => self.create_queries(),
+        };
+        queries.passes.clear();
+        queries.active = None;
+
+        let mut raw = self.raw_commands();
+        unsafe {
+            raw.reset_query_pool(
+                queries
+                    .timestamps
+                    .queries_range(0, TIMESTAMP_COUNT)
+                    .unwrap(),
+            );
+        }
+        let builder = self.execute_raw(builder, raw, Some(&queries));
+        return (builder, Some(queries));
+    }
+
+    fn create_queries(&self) -> FrameQueries {
+        let timestamps = Arc::new(
+            UnsafeQueryPool::new(self.device.clone(), QueryType::Timestamp, TIMESTAMP_SLOTS)
+                .unwrap(),
+        );
+        let usage = BufferUsage {
+            transfer_destination: true,
+            ..BufferUsage::none()
+        };
+        let results = CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            usage,
+            false,
+            (0..TIMESTAMP_SLOTS).map(|_| 0u32),
+        )
+        .unwrap();
+
+        return FrameQueries {
+            timestamps,
+            results,
+            passes: Vec::with_capacity(MAX_PROFILED_PASSES),
+            active: None,
+            timestamp_period: self.timestamp_period,
+            valid_bits: self.valid_bits,
+        };
+    }
+
+    // Starts measuring `pass`, and labels it in debug mode. Passes can't be
+    // nested and have to begin and end outside of render passes.
+    pub fn begin_pass(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        queries: &mut Option<FrameQueries>,
+        pass: ProfiledPass,
+    ) -> AutoCommandBufferBuilder {
+        let queries = match queries {
+            Some(queries) if queries.passes.len() < MAX_PROFILED_PASSES => Some(queries),
+            _ => None,
+        };
+        if queries.is_none() && !self.labels {
+            return builder;
+        }
+        let mut raw = self.raw_commands();
+        // Label regions can't span command buffers, the label marks where the
+        // pass begins.
+        if self.labels {
+            unsafe { raw.debug_marker_insert(pass.label(), LABEL_COLOR) };
+        }
+        let queries = match queries {
+            Some(queries) => queries,
+            None => return self.execute_raw(builder, raw, None),
+        };
+        assert!(queries.active.is_none(), "profiled passes can't be nested");
+        let index = queries.passes.len() as u32;
+        // Both timestamps wait for the previous commands, so the passes don't overlap
+        unsafe {
+            raw.write_timestamp(
+                queries.timestamps.query(index * 2).unwrap(),
+                PipelineStages {
+                    bottom_of_pipe: true,
+                    ..PipelineStages::none()
+                },
+            );
+        }
+        queries.active = Some(pass);
+        return self.execute_raw(builder, raw, Some(queries));
+    }
+
+    // Ends the pass started last, `draw_calls` were recorded since then.
+    pub fn end_pass(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        queries: &mut Option<FrameQueries>,
+        draw_calls: u32,
+    ) -> AutoCommandBufferBuilder {
+        let (queries, pass) = match queries {
+            Some(queries) => match queries.active.take() {
+                Some(pass) => (queries, pass),
+                None => return builder,
+            },
+            None => return builder,
+        };
+        let index = queries.passes.len() as u32;
+        let mut raw = self.raw_commands();
+        unsafe {
+            raw.write_timestamp(
+                queries.timestamps.query(index * 2 + 1).unwrap(),
+                PipelineStages {
+                    bottom_of_pipe: true,
+                    ..PipelineStages::none()
+                },
+            );
+        }
+        queries.passes.push((pass, draw_calls));
+        return self.execute_raw(builder, raw, Some(queries));
+    }
+
+    // Copies the timestamps of the frame into its results, has to be recorded
+    // after the last pass.
+    pub fn end_frame(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        queries: &Option<FrameQueries>,
+    ) -> AutoCommandBufferBuilder {
+        let queries = match queries {
+            Some(queries) if !queries.passes.is_empty() => queries,
+            _ => return builder,
+        };
+        let count = queries.passes.len() as u32 * 2;
+        let mut raw = self.raw_commands();
+        unsafe {
+            // The copy doesn't wait for the timestamps on its own
+            let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
+            barrier.add_execution_dependency(
+                PipelineStages {
+                    all_commands: true,
+                    ..PipelineStages::none()
+                },
+                PipelineStages {
+                    transfer: true,
+                    ..PipelineStages::none()
+                },
+                false,
+            );
+            raw.pipeline_barrier(&barrier);
+            raw.copy_query_pool_results(
+                queries.timestamps.queries_range(0, count).unwrap(),
+                &queries.results,
+                std::mem::size_of::<u32>(),
+            );
+            let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
+            barrier.add_memory_barrier(
+                PipelineStages {
+                    transfer: true,
+                    ..PipelineStages::none()
+                },
+                AccessFlagBits {
+                    transfer_write: true,
+                    ..AccessFlagBits::none()
+                },
+                PipelineStages {
+                    host: true,
+                    ..PipelineStages::none()
+                },
+                AccessFlagBits {
+                    host_read: true,
+                    ..AccessFlagBits::none()
+                },
+                false,
+            );
+            raw.pipeline_barrier(&barrier);
+        }
+        let builder = self.execute_raw(builder, raw, Some(queries));
+        // The raw copy isn't tracked, this write locks the results for the frame.
+        let marker = queries
+            .results
+            .clone()
+            .into_buffer_slice()
+            .index(TIMESTAMP_COUNT as usize)
+            .unwrap();
+        return builder.update_buffer(marker, 1u32).unwrap();
+    }
+
+    fn raw_commands(&self) -> UnsafeCommandBufferBuilder<StandardCommandPoolBuilder> {
+        let family = self
+            .device
+            .physical_device()
+            .queue_family_by_id(self.queue_family)
+            .unwrap();
+        let pool = Device::standard_command_pool(&self.device, family);
+        let kind = Kind::secondary(
+            KindOcclusionQuery::Forbidden,
+            QueryPipelineStatisticFlags::none(),
+        );
+        return unsafe { UnsafeCommandBufferBuilder::new(&pool, kind, Flags::OneTimeSubmit) }
+            .unwrap();
+    }
+
+    // Executes `raw` outside of a render pass.
+    fn execute_raw(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        raw: UnsafeCommandBufferBuilder<StandardCommandPoolBuilder>,
+        queries: Option<&FrameQueries>,
+    ) -> AutoCommandBufferBuilder {
+        let commands = RawCommands {
+            inner: raw.build().unwrap(),
+            _queries: queries.map(|queries| (queries.timestamps.clone(), queries.results.clone())),
+        };
+        return unsafe { builder.execute_commands(commands) }.unwrap();
+    }
+}
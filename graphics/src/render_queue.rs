@@ -0,0 +1,81 @@
+use crate::material::BlendMode;
+use polyengine_core::*;
+
+// How a window composites transparent surfaces.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransparencyMode {
+    // Drawn back to front, exact for non intersecting surfaces
+    Sorted,
+    // Weighted blended order independent transparency, no sorting and correct
+    // for intersecting surfaces but only approximates the blending order.
+    // Additive surfaces are still drawn directly.
+    WeightedBlended,
+}
+
+// Draw order of a frame, as indices into the frame draw list.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RenderQueues {
+    // Front to back, so hidden surfaces fail the depth test early
+    pub opaque: Vec<usize>,
+    // Opaque draws outside the camera frustum, only drawn after culling on the
+    // GPU
+    pub hidden_opaque: Vec<usize>,
+    // Back to front
+    pub transparent: Vec<usize>,
+}
+
+impl RenderQueues {
+    // `items` are the blend mode, camera distance and frustum visibility of every
+    // draw. Transparent draws outside the frustum are left out.
+    pub fn build<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = (BlendMode, FScalar, bool)>,
+    {
+        let mut opaque = Vec::new();
+        let mut hidden_opaque = Vec::new();
+        let mut transparent = Vec::new();
+        for (index, (blend_mode, distance, in_frustum)) in items.into_iter().enumerate() {
+            if !in_frustum {
+                if !blend_mode.is_transparent() {
+                    hidden_opaque.push(index);
+                }
+            } else if blend_mode.is_transparent() {
+                transparent.push((index, distance));
+            } else {
+                opaque.push((index, distance));
+            }
+        }
+        let by_distance = |a: &(usize, FScalar), b: &(usize, FScalar)| {
+            return a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal);
+        };
+        opaque.sort_by(by_distance);
+        transparent.sort_by(|a, b| by_distance(b, a));
+
+        return RenderQueues {
+            opaque: opaque.into_iter().map(|(index, _)| index).collect(),
+            hidden_opaque,
+            transparent: transparent.into_iter().map(|(index, _)| index).collect(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queues_test() {
+        let queues = RenderQueues::build(vec![
+            (BlendMode::AlphaBlend, 2.0, true),
+            (BlendMode::Opaque, 5.0, true),
+            (BlendMode::Additive, 7.0, true),
+            (BlendMode::AlphaTest(0.5), 1.0, true),
+            (BlendMode::Premultiplied, 3.0, true),
+            (BlendMode::Opaque, 4.0, false),
+            (BlendMode::AlphaBlend, 6.0, false),
+        ]);
+        assert_eq!(queues.opaque, vec![3, 1]);
+        assert_eq!(queues.hidden_opaque, vec![5]);
+        assert_eq!(queues.transparent, vec![2, 4, 0]);
+    }
+}
@@ -1,47 +1,261 @@
-use crate::vertex::Vertex;
-use std::sync::Arc;
+use crate::{
+    aa_pass::AaRenderer,
+    antialiasing::{jitter_matrix, jitter_offset, AntiAliasing},
+    capture_pass::{CaptureReadback, CaptureRenderer},
+    compute_pass::ComputeQueue,
+    config,
+    culling::DrawItem,
+    debug_draw::{self, DebugBatch, DebugRenderer},
+    debug_view::DebugView,
+    debug_view_pass::DebugViewRenderer,
+    decal::{Decal, DecalId, DecalTextureId, DEFAULT_DECAL_BUDGET},
+    decal_pass::DecalRenderer,
+    deferred_pass::DeferredRenderer,
+    environment::IblSettings,
+    environment_pass::{EnvironmentMap, EnvironmentRenderer},
+    frustum::Frustum,
+    geometry::{Geometry, GeometryId},
+    gpu_driven_pass::{GeometryArena, GpuDrivenRenderer, IndirectDraws},
+    light::{GpuLight, Light, LightId, LightKind},
+    material::{BlendMode, Material, MaterialId},
+    object::{ObjectId, RenderObject},
+    oit_pass::OitRenderer,
+    particles::{ParticleEmitter, ParticleEmitterId},
+    particles_pass::ParticleRenderer,
+    picking_pass::{PickReadback, PickingRenderer},
+    post_pass::PostRenderer,
+    profiling::ProfiledPass,
+    profiling_pass::{FrameQueries, GpuProfiler},
+    render_queue::{RenderQueues, TransparencyMode},
+    shadow::{plan_shadows, GpuShadowView, ShadowSettings},
+    shadow_pass::ShadowRenderer,
+    skinning::{SkinnedMeshId, SkinnedObject, SkinnedObjectId},
+    skinning_pass::{SkinnedMesh, SkinningRenderer},
+    sprite_pass::{SpriteAtlasImage, SpriteRenderer},
+    sprites::{
+        Camera2d,
+        Sprite,
+        SpriteAtlasId,
+        SpriteBatches,
+        SpriteId,
+        SpriteQuad,
+        TileMap,
+        TileMapId,
+    },
+    ssao_pass::SsaoRenderer,
+    terrain::TerrainId,
+    terrain_pass::{RenderTerrain, TerrainRenderer},
+    text::{screen_projection, FontId, Text, TextBatches, TextId},
+    text_pass::{FontImage, TextRenderer},
+    vertex::Vertex,
+    voxel_pass::{ChunkMesh, VoxelRenderer},
+    window::WindowContext,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, sync::Arc, time::Instant};
 use vulkano::{
-    device::Device,
+    buffer::{BufferUsage, CpuBufferPool},
+    command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState},
+    descriptor::{
+        descriptor_set::{DescriptorSetsCollection, PersistentDescriptorSet},
+        PipelineLayoutAbstract,
+    },
+    device::{Device, Queue},
+    format::{ClearValue, Format},
     framebuffer::{RenderPassAbstract, Subpass},
-    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract},
+    image::{ImageViewAccess, ImmutableImage},
+    pipeline::{
+        blend::{AttachmentBlend, BlendFactor},
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+    sync::GpuFuture,
 };
 
+pub mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/forward.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/forward.frag",
+        include: ["src/shaders"]
+    }
+}
+
+// Per draw data read by the scene shaders, layout has to match `DrawData` in
+// `draw_data.glsl`.
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct GpuDrawData {
+    pub model: [[f32; 4]; 4],
+    pub previous_model: [[f32; 4]; 4],
+    pub base_color: [f32; 4],
+    // x: metallic, y: roughness, z: ambient occlusion weight, w: alpha cutoff
+    pub material: [f32; 4],
+    // w: 1 for premultiplied alpha
+    pub emissive: [f32; 4],
+}
+
+impl GpuDrawData {
+    pub fn new(model: &Matrix4f, previous_model: &Matrix4f, material: &Material) -> Self {
+        let (c, e) = (material.base_color, material.emissive);
+        let occlusion = if material.blend_mode.is_transparent() {
+            0.0
+        } else {
+            1.0
+        };
+        let cutoff = match material.blend_mode {
+            BlendMode::AlphaTest(cutoff) => cutoff,
+            _ => 0.0,
+        };
+        let premultiplied = if material.blend_mode == BlendMode::Premultiplied {
+            1.0
+        } else {
+            0.0
+        };
+        return GpuDrawData {
+            model: (*model).into(),
+            previous_model: (*previous_model).into(),
+            base_color: [c.x, c.y, c.z, c.w],
+            material: [material.metallic, material.roughness, occlusion, cutoff],
+            emissive: [e.x, e.y, e.z, premultiplied],
+        };
+    }
+}
+
+// How a window shades the scene. Transparent objects are always shaded forward,
+// after opaque ones.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RenderPath {
+    // Lights are evaluated while drawing every object
+    Forward,
+    // Objects write a G-buffer, lights are evaluated once per pixel afterwards.
+    // Scales better with many overlapping lights.
+    Deferred,
+}
+
+// Scene state needed to record a single window frame.
+pub struct FrameInput<'a> {
+    pub objects: &'a HashMap<ObjectId, RenderObject>,
+    pub geometries: &'a HashMap<GeometryId, Geometry>,
+    pub materials: &'a HashMap<MaterialId, Material>,
+    pub lights: &'a HashMap<LightId, Light>,
+    pub ambient_light: Vector3f,
+    pub environment: Option<&'a EnvironmentMap>,
+    pub environment_intensity: FScalar,
+    pub draws: &'a [DrawItem],
+    pub debug_batch: &'a DebugBatch,
+    // Built while a window uses GPU driven rendering
+    pub geometry_arena: Option<&'a GeometryArena>,
+    pub particle_emitters: &'a HashMap<ParticleEmitterId, ParticleEmitter>,
+    pub sprites: &'a HashMap<SpriteId, Sprite>,
+    pub tile_maps: &'a HashMap<TileMapId, TileMap>,
+    pub sprite_atlases: &'a HashMap<SpriteAtlasId, SpriteAtlasImage>,
+    pub texts: &'a HashMap<TextId, Text>,
+    pub fonts: &'a HashMap<FontId, FontImage>,
+    pub skinned_objects: &'a HashMap<SkinnedObjectId, SkinnedObject>,
+    pub skinned_meshes: &'a HashMap<SkinnedMeshId, SkinnedMesh>,
+    pub chunk_meshes: &'a HashMap<Vector3i, ChunkMesh>,
+    // White until a block atlas is set
+    pub block_atlas: Option<&'a Arc<ImmutableImage<Format>>>,
+    pub block_material: Option<MaterialId>,
+    pub terrains: &'a HashMap<TerrainId, RenderTerrain>,
+    pub decals: &'a HashMap<DecalId, Decal>,
+    pub decal_textures: &'a HashMap<DecalTextureId, Arc<ImmutableImage<Format>>>,
+}
+
 pub struct Renderer {
+    device: Arc<Device>,
     pub pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-    #[allow(dead_code)] // TODO remove this
-    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    // Forward shading of transparent materials, one per blend mode
+    alpha_blend_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    additive_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    premultiplied_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // HDR colour, motion vectors and depth, resolved into the window by the
+    // post-process stack
+    scene_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    pub debug: DebugRenderer,
+    pub debug_views: DebugViewRenderer,
+    pub shadow: ShadowRenderer,
+    pub shadow_settings: ShadowSettings,
+    pub post: PostRenderer,
+    pub aa: AaRenderer,
+    pub deferred: DeferredRenderer,
+    pub decals: DecalRenderer,
+    // Most decals drawn per window and frame
+    pub decal_budget: usize,
+    pub ssao: SsaoRenderer,
+    pub oit: OitRenderer,
+    pub picking: PickingRenderer,
+    pub capture: CaptureRenderer,
+    pub gpu_driven: GpuDrivenRenderer,
+    pub particles: ParticleRenderer,
+    pub sprites: SpriteRenderer,
+    pub text: TextRenderer,
+    pub skinning: SkinningRenderer,
+    pub voxels: VoxelRenderer,
+    pub terrain: TerrainRenderer,
+    pub environment: EnvironmentRenderer,
+    pub profiler: GpuProfiler,
+    pub compute: ComputeQueue,
+    // Used for environments created afterwards
+    pub ibl_settings: IblSettings,
+
+    frame_data_pool: CpuBufferPool<vs::ty::FrameData>,
+    light_pool: CpuBufferPool<GpuLight>,
+    cluster_range_pool: CpuBufferPool<[u32; 2]>,
+    light_index_pool: CpuBufferPool<u32>,
+    shadow_view_pool: CpuBufferPool<GpuShadowView>,
+    draw_pool: CpuBufferPool<GpuDrawData>,
+    default_material: Material,
 }
 
 impl Renderer {
+    // `window_render_pass` draws into the swapchain images, after the scene was
+    // rendered into the HDR target.
     pub fn new(
         device: Arc<Device>,
-        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        queue: Arc<Queue>,
+        compute_queue: Arc<Queue>,
+        window_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
     ) -> Self {
-        mod vs {
-            vulkano_shaders::shader! {
-                ty: "vertex",
-                src: "
-                    #version 450
-                    layout(location = 0) in vec3 position;
-                    void main() {
-                        gl_Position = vec4(position, 1.0);
-                    }
-                "
-            }
-        }
-
-        mod fs {
-            vulkano_shaders::shader! {
-                ty: "fragment",
-                src: "
-                    #version 450
-                    layout(location = 0) out vec4 f_color;
-                    void main() {
-                        f_color = vec4(1.0, 0.0, 0.0, 1.0);
+        let scene_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    },
+                    velocity: {
+                        load: Clear,
+                        store: Store,
+                        format: config::VELOCITY_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
                     }
-                "
-            }
-        }
+                },
+                pass: {
+                    color: [color, velocity],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
 
         let vs = vs::Shader::load(device.clone()).unwrap();
         let fs = fs::Shader::load(device.clone()).unwrap();
@@ -63,18 +277,1387 @@ impl Renderer {
                 .viewports_dynamic_scissors_irrelevant(1)
                 // See `vertex_shader`.
                 .fragment_shader(fs.main_entry_point(), ())
+                // Equal depth passes, so the pass also works after the depth prepass.
+                .depth_stencil(DepthStencil {
+                    depth_compare: Compare::LessOrEqual,
+                    ..DepthStencil::simple_depth_test()
+                })
                 // We have to indicate which subpass of which render pass this pipeline is going to
                 // be used in. The pipeline will only be usable from this particular
                 // subpass.
-                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .render_pass(Subpass::from(scene_render_pass.clone(), 0).unwrap())
                 // Now that our builder is filled, we call `build()` to obtain an actual pipeline.
                 .build(device.clone())
                 .unwrap(),
         );
 
+        // Motion vectors of transparent surfaces are ignored, TAA reprojects what is
+        // behind them.
+        let velocity_blend = AttachmentBlend {
+            mask_red: false,
+            mask_green: false,
+            mask_blue: false,
+            mask_alpha: false,
+            ..AttachmentBlend::pass_through()
+        };
+        let transparent_pipeline = |color_blend: AttachmentBlend| {
+            return Arc::new(
+                GraphicsPipeline::start()
+                    .vertex_input_single_buffer::<Vertex>()
+                    .vertex_shader(vs.main_entry_point(), ())
+                    .triangle_list()
+                    .viewports_dynamic_scissors_irrelevant(1)
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .depth_stencil(DepthStencil {
+                        depth_write: false,
+                        ..DepthStencil::simple_depth_test()
+                    })
+                    .blend_individual(vec![color_blend, velocity_blend.clone()].into_iter())
+                    .render_pass(Subpass::from(scene_render_pass.clone(), 0).unwrap())
+                    .build(device.clone())
+                    .unwrap(),
+            ) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
+        };
+        let alpha_blend_pipeline = transparent_pipeline(AttachmentBlend::alpha_blending());
+        let additive_pipeline = transparent_pipeline(AttachmentBlend {
+            color_source: BlendFactor::SrcAlpha,
+            color_destination: BlendFactor::One,
+            alpha_source: BlendFactor::Zero,
+            alpha_destination: BlendFactor::One,
+            ..AttachmentBlend::alpha_blending()
+        });
+        let premultiplied_pipeline = transparent_pipeline(AttachmentBlend {
+            color_source: BlendFactor::One,
+            alpha_source: BlendFactor::One,
+            ..AttachmentBlend::alpha_blending()
+        });
+
+        // Debug primitives are drawn after the composite so they keep their colours.
+        let debug = DebugRenderer::new(device.clone(), window_render_pass.clone());
+        let debug_views = DebugViewRenderer::new(device.clone(), window_render_pass.clone());
+        let shadow = ShadowRenderer::new(device.clone());
+        let post = PostRenderer::new(device.clone(), queue.clone(), window_render_pass.clone());
+        let aa = AaRenderer::new(device.clone(), window_render_pass.clone());
+        let sprites =
+            SpriteRenderer::new(device.clone(), queue.clone(), window_render_pass.clone());
+        let text = TextRenderer::new(device.clone(), queue.clone(), window_render_pass);
+        let deferred = DeferredRenderer::new(device.clone());
+        let decals = DecalRenderer::new(device.clone(), queue.clone());
+        let ssao = SsaoRenderer::new(device.clone(), queue.clone());
+        let oit = OitRenderer::new(device.clone());
+        let picking = PickingRenderer::new(device.clone());
+        let capture = CaptureRenderer::new(device.clone());
+        let gpu_driven = GpuDrivenRenderer::new(device.clone(), queue.clone());
+        // Geometry shaded forward in both render paths still fills the ambient
+        // occlusion prepass and the G-buffer.
+        let skinning = SkinningRenderer::new(
+            device.clone(),
+            scene_render_pass.clone(),
+            ssao.prepass_render_pass.clone(),
+            deferred.gbuffer_render_pass.clone(),
+        );
+        let voxels = VoxelRenderer::new(
+            device.clone(),
+            queue.clone(),
+            scene_render_pass.clone(),
+            ssao.prepass_render_pass.clone(),
+            deferred.gbuffer_render_pass.clone(),
+        );
+        let terrain = TerrainRenderer::new(
+            device.clone(),
+            queue.clone(),
+            scene_render_pass.clone(),
+            ssao.prepass_render_pass.clone(),
+            deferred.gbuffer_render_pass.clone(),
+        );
+        let particles =
+            ParticleRenderer::new(device.clone(), queue.clone(), scene_render_pass.clone());
+        let profiler = GpuProfiler::new(device.clone(), &queue);
+        let compute = ComputeQueue::new(device.clone(), compute_queue, queue.clone());
+        let environment =
+            EnvironmentRenderer::new(device.clone(), queue, scene_render_pass.clone());
+        let storage_usage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::none()
+        };
+
         return Renderer {
+            device: device.clone(),
             pipeline,
-            render_pass,
+            alpha_blend_pipeline,
+            additive_pipeline,
+            premultiplied_pipeline,
+            scene_render_pass,
+            debug,
+            debug_views,
+            shadow,
+            shadow_settings: ShadowSettings::default(),
+            post,
+            aa,
+            deferred,
+            decals,
+            decal_budget: DEFAULT_DECAL_BUDGET,
+            ssao,
+            oit,
+            picking,
+            capture,
+            gpu_driven,
+            particles,
+            sprites,
+            text,
+            skinning,
+            voxels,
+            terrain,
+            environment,
+            profiler,
+            compute,
+            ibl_settings: IblSettings::default(),
+            frame_data_pool: CpuBufferPool::uniform_buffer(device.clone()),
+            light_pool: CpuBufferPool::new(device.clone(), storage_usage),
+            cluster_range_pool: CpuBufferPool::new(device.clone(), storage_usage),
+            light_index_pool: CpuBufferPool::new(device.clone(), storage_usage),
+            shadow_view_pool: CpuBufferPool::new(device.clone(), storage_usage),
+            draw_pool: CpuBufferPool::new(device, storage_usage),
+            default_material: Material::default(),
         };
     }
+
+    // Updates per window resources depending on the camera and renderer settings.
+    // Has to be called before `render`. Time based effects advance to `now`.
+    pub fn prepare_window(
+        &self,
+        window: &mut WindowContext,
+        particle_emitters: &HashMap<ParticleEmitterId, ParticleEmitter>,
+        now: Instant,
+    ) {
+        // 2D windows draw straight into the swapchain images
+        if window.camera_2d.is_some() {
+            return;
+        }
+        window.update_cluster_volumes();
+        let view_projection = window.camera.view_projection_matrix(window.aspect_ratio());
+        window.temporal.advance(view_projection);
+
+        let atlas_size = self.shadow_settings.atlas_size;
+        let up_to_date = match &window.shadow_target {
+            Some(target) => target.size == atlas_size,
+            None => false,
+        };
+        if !up_to_date {
+            window.shadow_target = Some(self.shadow.create_target(self.device.clone(), atlas_size));
+        }
+
+        let depth_buffer = &window.render_target.depth_buffer;
+        let up_to_date = match &window.post_targets {
+            Some(targets) => targets.matches(depth_buffer, &window.post_process),
+            None => false,
+        };
+        if !up_to_date {
+            window.post_targets = Some(self.post.create_targets(
+                self.scene_render_pass.clone(),
+                depth_buffer.clone(),
+                &window.post_process,
+            ));
+        }
+
+        let up_to_date = match (&window.deferred_targets, &window.post_targets) {
+            (Some(deferred), Some(post)) => deferred.matches(post),
+            _ => false,
+        };
+        if window.render_path == RenderPath::Deferred && !up_to_date {
+            window.deferred_targets = Some(
+                self.deferred
+                    .create_targets(window.post_targets.as_ref().unwrap(), depth_buffer.clone()),
+            );
+        } else if window.render_path == RenderPath::Forward {
+            window.deferred_targets = None;
+        }
+        let up_to_date = match (&window.decal_targets, &window.post_targets) {
+            (Some(decals), Some(post)) => decals.matches(post, window.deferred_targets.as_ref()),
+            _ => false,
+        };
+        if !up_to_date {
+            window.decal_targets = Some(self.decals.create_targets(
+                window.post_targets.as_ref().unwrap(),
+                window.deferred_targets.as_ref(),
+                depth_buffer.clone(),
+            ));
+        }
+        let up_to_date = match (&window.ssao_targets, &window.post_targets) {
+            (Some(ssao), Some(post)) => ssao.matches(post),
+            _ => false,
+        };
+        if window.ssao.is_some() && !up_to_date {
+            window.ssao_targets = Some(
+                self.ssao
+                    .create_targets(window.post_targets.as_ref().unwrap(), depth_buffer.clone()),
+            );
+        } else if window.ssao.is_none() {
+            window.ssao_targets = None;
+        }
+        let up_to_date = match (&window.oit_targets, &window.post_targets) {
+            (Some(oit), Some(post)) => oit.matches(post),
+            _ => false,
+        };
+        if window.transparency == TransparencyMode::WeightedBlended && !up_to_date {
+            window.oit_targets = Some(
+                self.oit
+                    .create_targets(window.post_targets.as_ref().unwrap(), depth_buffer.clone()),
+            );
+        } else if window.transparency == TransparencyMode::Sorted {
+            window.oit_targets = None;
+        }
+
+        let targets = window.post_targets.as_mut().unwrap();
+        if let Some(upload) = self.post.prepare(targets, &window.post_process, now) {
+            let previous = window.previous_frame_end.take().unwrap();
+            window.previous_frame_end = Some(Box::new(previous.join(upload)));
+        }
+
+        let up_to_date = match &window.aa_targets {
+            Some(targets) => targets.matches(depth_buffer, &window.anti_aliasing),
+            None => false,
+        };
+        if !up_to_date {
+            window.aa_targets = Some(self.aa.create_targets(
+                depth_buffer.clone(),
+                &window.anti_aliasing,
+                self.post.ldr_render_pass.clone(),
+            ));
+        }
+        window.aa_targets.as_mut().unwrap().begin_frame();
+
+        // Only created once something is picked in the window.
+        let up_to_date = match &window.picking_targets {
+            Some(targets) => targets.matches(depth_buffer),
+            None => false,
+        };
+        if !window.pick_requests.is_empty() && !up_to_date {
+            window.picking_targets = Some(self.picking.create_targets(depth_buffer.clone()));
+        }
+
+        // New hierarchical depth is only valid once the previous frame has built it.
+        let up_to_date = match &window.hiz_targets {
+            Some(targets) => targets.matches(depth_buffer),
+            None => false,
+        };
+        if window.gpu_driven && !up_to_date {
+            window.hiz_targets = Some(self.gpu_driven.create_hiz_targets(depth_buffer.clone()));
+        } else if window.gpu_driven {
+            window.hiz_targets.as_mut().unwrap().valid = true;
+        } else {
+            window.hiz_targets = None;
+        }
+
+        if let Some(upload) = self.particles.prepare(
+            &mut window.particle_targets,
+            depth_buffer,
+            particle_emitters,
+            now,
+        ) {
+            let previous = window.previous_frame_end.take().unwrap();
+            window.previous_frame_end = Some(Box::new(previous.join(upload)));
+        }
+    }
+
+    // Records all rendering commands for the window image. Pick and capture
+    // requests of the window are recorded too, their results and the render stats
+    // of the returned queries can be read once the returned command buffer has
+    // finished.
+    // `recycled_queries` are reused for the profiling queries when given.
+    pub fn render(
+        &self,
+        window: &WindowContext,
+        image_num: usize,
+        frame: &FrameInput,
+        recycled_queries: Option<FrameQueries>,
+    ) -> (
+        AutoCommandBuffer,
+        Vec<PickReadback>,
+        Vec<CaptureReadback>,
+        Option<FrameQueries>,
+    ) {
+        if let Some(camera_2d) = &window.camera_2d {
+            return self.render_2d(window, image_num, frame, camera_2d, recycled_queries);
+        }
+        let camera = &window.camera;
+        let temporal = &window.temporal;
+        let camera_position = camera.position();
+        let camera_forward = camera.transform.rotation * -Vector3f::z();
+        let ambient = frame.ambient_light;
+        let [width, height] = window.swapchain.dimensions();
+        // Temporal anti-aliasing moves the image by a sub-pixel offset every frame,
+        // motion vectors and debug primitives use the unjittered matrix.
+        let view_projection = match window.anti_aliasing {
+            AntiAliasing::Taa(settings) => {
+                let offset = jitter_offset(temporal.frame_index, settings.jitter_samples);
+                jitter_matrix(&offset, [width, height]) * temporal.view_projection
+            }
+            _ => temporal.view_projection,
+        };
+
+        // Directional lights go first, they are not binned into clusters.
+        let (mut lights, local_lights): (Vec<Light>, Vec<Light>) = frame
+            .lights
+            .values()
+            .cloned()
+            .partition(|light| light.kind == LightKind::Directional);
+        let directional_count = lights.len() as u32;
+        lights.extend(local_lights);
+
+        let cluster_volumes = window
+            .cluster_volumes
+            .as_ref()
+            .expect("cluster volumes not prepared for the window");
+        let clusters = cluster_volumes.assign_lights(camera, &lights);
+        let shadow_target = window
+            .shadow_target
+            .as_ref()
+            .expect("shadow atlas not prepared for the window");
+        let post_targets = window
+            .post_targets
+            .as_ref()
+            .expect("post-process targets not prepared for the window");
+        let aa_targets = window
+            .aa_targets
+            .as_ref()
+            .expect("anti-aliasing targets not prepared for the window");
+        let shadow_plan = plan_shadows(
+            &lights,
+            camera,
+            window.aspect_ratio(),
+            &self.shadow_settings,
+        );
+
+        // Storage buffers can't be empty, upload dummy entries when there is nothing to
+        // bind.
+        let mut gpu_lights: Vec<GpuLight> = lights
+            .iter()
+            .zip(&shadow_plan.light_params)
+            .map(|(light, params)| GpuLight::new(light, *params))
+            .collect();
+        if gpu_lights.is_empty() {
+            gpu_lights.push(GpuLight::default());
+        }
+        let mut shadow_views: Vec<GpuShadowView> = shadow_plan
+            .views
+            .iter()
+            .map(|view| GpuShadowView::new(view, shadow_target.size))
+            .collect();
+        if shadow_views.is_empty() {
+            shadow_views.push(GpuShadowView::default());
+        }
+        let mut light_indices = clusters.light_indices;
+        if light_indices.is_empty() {
+            light_indices.push(0);
+        }
+        let mut blend_modes = Vec::with_capacity(frame.draws.len());
+        let mut draws: Vec<GpuDrawData> = Vec::with_capacity(frame.draws.len());
+        for item in frame.draws.iter() {
+            let material = item
+                .material_id
+                .and_then(|id| frame.materials.get(&id))
+                .unwrap_or(&self.default_material);
+            draws.push(GpuDrawData::new(
+                &item.model,
+                &item.previous_model,
+                material,
+            ));
+            blend_modes.push((material.blend_mode, item.distance, item.in_frustum));
+        }
+        let queues = RenderQueues::build(blend_modes.iter().cloned());
+        // Skinned objects follow the regular draws, they are always drawn opaque
+        let skinned = self
+            .skinning
+            .prepare(frame.skinned_objects, frame.skinned_meshes);
+        let first_skinned_draw = draws.len() as u32;
+        for item in &skinned.draws {
+            let material = item
+                .material_id
+                .and_then(|id| frame.materials.get(&id))
+                .unwrap_or(&self.default_material);
+            draws.push(GpuDrawData::new(
+                &item.model,
+                &item.previous_model,
+                material,
+            ));
+        }
+        // Visible chunks follow, they are static so the previous model matches
+        let frustum = Frustum::from_matrix(&temporal.view_projection);
+        let chunks = self.voxels.visible_chunks(frame.chunk_meshes, &frustum);
+        let first_chunk_draw = draws.len() as u32;
+        let block_material = frame
+            .block_material
+            .and_then(|id| frame.materials.get(&id))
+            .unwrap_or(&self.default_material);
+        for chunk in &chunks {
+            draws.push(GpuDrawData::new(&chunk.model, &chunk.model, block_material));
+        }
+        // One draw per terrain shared by its patches
+        let terrain_draws = self
+            .terrain
+            .prepare(frame.terrains, &camera_position, &frustum);
+        let first_terrain_draw = draws.len() as u32;
+        for item in &terrain_draws {
+            let material = item
+                .material_id
+                .and_then(|id| frame.materials.get(&id))
+                .unwrap_or(&self.default_material);
+            let model = Matrix4f::identity();
+            draws.push(GpuDrawData::new(&model, &model, material));
+        }
+        let decals = self.decals.prepare(
+            frame.decals,
+            frame.decal_textures,
+            frame.materials,
+            &self.default_material,
+            &frustum,
+            self.decal_budget,
+        );
+        if draws.is_empty() {
+            draws.push(GpuDrawData::default());
+        }
+
+        let frame_data = self
+            .frame_data_pool
+            .next(vs::ty::FrameData {
+                view_projection: view_projection.into(),
+                unjittered_view_projection: temporal.view_projection.into(),
+                previous_view_projection: temporal.previous_view_projection.into(),
+                inverse_view_projection: view_projection
+                    .try_inverse()
+                    .unwrap_or_else(Matrix4f::identity)
+                    .into(),
+                camera_position: [camera_position.x, camera_position.y, camera_position.z, 1.0],
+                camera_forward: [camera_forward.x, camera_forward.y, camera_forward.z, 0.0],
+                ambient: [ambient.x, ambient.y, ambient.z, 0.0],
+                cluster_params: [
+                    clusters.slice_scale,
+                    clusters.slice_bias,
+                    width as f32,
+                    height as f32,
+                ],
+                light_info: [directional_count, 0, 0, 0],
+                cascade_splits: shadow_plan.cascade_splits,
+                shadow_params: [
+                    self.shadow_settings.pcf_radius as f32,
+                    1.0 / shadow_target.size as f32,
+                    0.0,
+                    0.0,
+                ],
+                environment_params: [
+                    frame.environment_intensity,
+                    if frame.environment.is_some() {
+                        1.0
+                    } else {
+                        0.0
+                    },
+                    0.0,
+                    0.0,
+                ],
+            })
+            .unwrap();
+        let light_buffer = self.light_pool.chunk(gpu_lights).unwrap();
+        let cluster_range_buffer = self.cluster_range_pool.chunk(clusters.ranges).unwrap();
+        let light_index_buffer = self.light_index_pool.chunk(light_indices).unwrap();
+        let shadow_view_buffer = self.shadow_view_pool.chunk(shadow_views).unwrap();
+        let draw_buffer = self.draw_pool.chunk(draws).unwrap();
+        let (irradiance, specular_levels) = self.environment.lighting_images(frame.environment);
+        let ssao = match (window.ssao, &window.ssao_targets) {
+            (Some(settings), Some(targets)) => Some((settings, targets)),
+            _ => None,
+        };
+        let occlusion: Arc<dyn ImageViewAccess + Send + Sync> = match ssao {
+            Some((_, targets)) => targets.ao_image.clone(),
+            None => self.ssao.fallback.clone(),
+        };
+
+        // The deferred lighting pass uses the layout of the forward pass, without the
+        // per draw data at binding 6.
+        macro_rules! frame_set {
+            ($pipeline:expr, $set:ident => $draws:expr) => {{
+                let $set = PersistentDescriptorSet::start(
+                    $pipeline.descriptor_set_layout(0).unwrap().clone(),
+                )
+                .add_buffer(frame_data.clone())
+                .unwrap()
+                .add_buffer(light_buffer.clone())
+                .unwrap()
+                .add_buffer(cluster_range_buffer.clone())
+                .unwrap()
+                .add_buffer(light_index_buffer.clone())
+                .unwrap()
+                .add_sampled_image(shadow_target.atlas.clone(), self.shadow.sampler.clone())
+                .unwrap()
+                .add_buffer(shadow_view_buffer.clone())
+                .unwrap();
+                Arc::new(
+                    $draws
+                        .add_sampled_image(irradiance.clone(), self.environment.sampler.clone())
+                        .unwrap()
+                        // Every array element changes the builder type, so the levels can't be
+                        // added in a loop.
+                        .enter_array()
+                        .unwrap()
+                        .add_sampled_image(
+                            specular_levels[0].clone(),
+                            self.environment.sampler.clone(),
+                        )
+                        .unwrap()
+                        .add_sampled_image(
+                            specular_levels[1].clone(),
+                            self.environment.sampler.clone(),
+                        )
+                        .unwrap()
+                        .add_sampled_image(
+                            specular_levels[2].clone(),
+                            self.environment.sampler.clone(),
+                        )
+                        .unwrap()
+                        .add_sampled_image(
+                            specular_levels[3].clone(),
+                            self.environment.sampler.clone(),
+                        )
+                        .unwrap()
+                        .add_sampled_image(
+                            specular_levels[4].clone(),
+                            self.environment.sampler.clone(),
+                        )
+                        .unwrap()
+                        .leave_array()
+                        .unwrap()
+                        .add_sampled_image(
+                            self.environment.brdf_lut.clone(),
+                            self.environment.sampler.clone(),
+                        )
+                        .unwrap()
+                        .add_sampled_image(occlusion.clone(), self.ssao.sampler.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                )
+            }};
+        }
+        let frame_set =
+            frame_set!(self.pipeline, set => set.add_buffer(draw_buffer.clone()).unwrap());
+        // Set of the pipelines only running the vertex shader logic, the depth prepass
+        // and the G-buffer pass.
+        macro_rules! draw_set {
+            ($pipeline:expr) => {
+                Arc::new(
+                    PersistentDescriptorSet::start(
+                        $pipeline.descriptor_set_layout(0).unwrap().clone(),
+                    )
+                    .add_buffer(frame_data.clone())
+                    .unwrap()
+                    .add_empty()
+                    .unwrap()
+                    .add_empty()
+                    .unwrap()
+                    .add_empty()
+                    .unwrap()
+                    .add_empty()
+                    .unwrap()
+                    .add_empty()
+                    .unwrap()
+                    .add_buffer(draw_buffer.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+                )
+            };
+        }
+
+        // Skinned objects, chunks and terrains are shaded forward in both render
+        // paths, their depth pipelines still fill the ambient occlusion prepass or
+        // the G-buffer. Evaluates to the recorded draw calls.
+        macro_rules! record_prepass {
+            ($builder:ident, $pipeline:ident) => {{
+                let (skinned_builder, skinned_draws) = self.skinning.record_prepass(
+                    $builder,
+                    &self.skinning.$pipeline,
+                    &skinned,
+                    draw_set!(self.skinning.$pipeline),
+                    first_skinned_draw,
+                    &window.dynamic_state,
+                );
+                let (voxel_builder, voxel_draws) = self.voxels.record_prepass(
+                    skinned_builder,
+                    &self.voxels.$pipeline,
+                    &chunks,
+                    draw_set!(self.voxels.$pipeline),
+                    first_chunk_draw,
+                    &window.dynamic_state,
+                );
+                let (terrain_builder, terrain_draw_calls) = self.terrain.record_prepass(
+                    voxel_builder,
+                    &self.terrain.$pipeline,
+                    &terrain_draws,
+                    frame.terrains,
+                    draw_set!(self.terrain.$pipeline),
+                    first_terrain_draw,
+                    &window.dynamic_state,
+                );
+                $builder = terrain_builder;
+                skinned_draws + voxel_draws + terrain_draw_calls
+            }};
+        }
+
+        // Specify the color to clear the framebuffer with i.e. blue
+        let background = [0.0, 0.0, 1.0, 1.0];
+        let window_clear_values = vec![ClearValue::None, ClearValue::None];
+
+        let builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            window.queue().family(),
+        )
+        .unwrap();
+        let (builder, mut queries) = self.profiler.begin_frame(builder, recycled_queries);
+        let mut builder = self
+            .profiler
+            .begin_pass(builder, &mut queries, ProfiledPass::Shadows);
+        let (shadow_builder, shadow_draws) = self.shadow.record(
+            builder,
+            shadow_target,
+            &shadow_plan,
+            frame.objects,
+            frame.geometries,
+            &skinned,
+            frame.chunk_meshes,
+            &camera_position,
+        );
+        builder = self
+            .profiler
+            .end_pass(shadow_builder, &mut queries, shadow_draws);
+        // Opaque draws are culled on the GPU against the frustum and the depth of the
+        // previous frame, transparent draws stay on the CPU.
+        let gpu_driven = match (frame.geometry_arena, &window.hiz_targets) {
+            (Some(arena), Some(hiz)) if window.gpu_driven => Some((arena, hiz)),
+            _ => None,
+        };
+        let mut indirect_draws = None;
+        let mut opaque_count = queues.opaque.len();
+        if let Some((arena, hiz)) = gpu_driven {
+            let mut opaque = queues.opaque.clone();
+            opaque.extend(&queues.hidden_opaque);
+            opaque_count = opaque.len();
+            builder = self
+                .profiler
+                .begin_pass(builder, &mut queries, ProfiledPass::GpuCulling);
+            let (cull_builder, culled) = self.gpu_driven.record_cull(
+                builder,
+                hiz,
+                arena,
+                &temporal.view_projection,
+                &temporal.previous_view_projection,
+                frame.draws,
+                &opaque,
+                frame.objects,
+                frame.geometries,
+            );
+            builder = self.profiler.end_pass(cull_builder, &mut queries, 0);
+            indirect_draws = Some(culled);
+        }
+        // Particles collide with the depth of the previous frame, so they are
+        // simulated before anything clears it.
+        let particle_targets = window
+            .particle_targets
+            .as_ref()
+            .expect("particles not prepared for the window");
+        let mut sorted_particles = Vec::new();
+        if !particle_targets.is_empty() {
+            builder = self
+                .profiler
+                .begin_pass(builder, &mut queries, ProfiledPass::Particles);
+            builder = self.particles.record_simulation(
+                builder,
+                particle_targets,
+                frame.particle_emitters,
+                &temporal.previous_view_projection,
+            );
+            let (sort_builder, sorted) =
+                self.particles
+                    .record_sort(builder, particle_targets, &camera_position);
+            builder = self.profiler.end_pass(sort_builder, &mut queries, 0);
+            sorted_particles = sorted;
+        }
+        let opaque_draws = match &indirect_draws {
+            Some(_) => self.gpu_driven.draw_calls(opaque_count),
+            None => opaque_count as u32,
+        };
+        // Transparent draws in the scene pass, depends on the transparency mode
+        let transparent_draws = queues.transparent.len() as u32;
+        let mut scene_draws = transparent_draws;
+        let decal_targets = window
+            .decal_targets
+            .as_ref()
+            .expect("decals not prepared for the window");
+
+        match window.render_path {
+            RenderPath::Forward => {
+                match ssao {
+                    Some((settings, targets)) => {
+                        // Occlusion has to be ready before shading, so depth is drawn first.
+                        builder = self.profiler.begin_pass(
+                            builder,
+                            &mut queries,
+                            ProfiledPass::DepthPrepass,
+                        );
+                        builder = builder
+                            .begin_render_pass(
+                                targets.prepass_framebuffer.clone(),
+                                false,
+                                vec![1f32.into()],
+                            )
+                            .unwrap();
+                        builder = self.record_opaque_draws(
+                            builder,
+                            &self.ssao.prepass_pipeline,
+                            draw_set!(self.ssao.prepass_pipeline),
+                            indirect_draws.as_ref(),
+                            &queues.opaque,
+                            frame,
+                            &window.dynamic_state,
+                        );
+                        let prepass_draws = record_prepass!(builder, prepass_pipeline);
+                        builder = builder.end_render_pass().unwrap();
+                        builder = self.profiler.end_pass(
+                            builder,
+                            &mut queries,
+                            opaque_draws + prepass_draws,
+                        );
+                        builder = self.profiler.begin_pass(
+                            builder,
+                            &mut queries,
+                            ProfiledPass::AmbientOcclusion,
+                        );
+                        builder = self.ssao.record(
+                            builder,
+                            targets,
+                            frame_data.clone(),
+                            None,
+                            &settings,
+                            &window.dynamic_state,
+                        );
+                        builder =
+                            self.profiler
+                                .end_pass(builder, &mut queries, settings.draw_calls());
+                        builder =
+                            self.profiler
+                                .begin_pass(builder, &mut queries, ProfiledPass::Scene);
+                        let clear_values =
+                            vec![background.into(), [0.0, 0.0].into(), ClearValue::None];
+                        builder = builder
+                            .begin_render_pass(
+                                targets.scene_framebuffer.clone(),
+                                false,
+                                clear_values,
+                            )
+                            .unwrap();
+                    }
+                    None => {
+                        builder =
+                            self.profiler
+                                .begin_pass(builder, &mut queries, ProfiledPass::Scene);
+                        let clear_values = vec![background.into(), [0.0, 0.0].into(), 1f32.into()];
+                        builder = builder
+                            .begin_render_pass(
+                                post_targets.scene_framebuffer.clone(),
+                                false,
+                                clear_values,
+                            )
+                            .unwrap();
+                    }
+                }
+                builder = self.record_opaque_draws(
+                    builder,
+                    &self.pipeline,
+                    frame_set.clone(),
+                    indirect_draws.as_ref(),
+                    &queues.opaque,
+                    frame,
+                    &window.dynamic_state,
+                );
+                scene_draws += opaque_draws;
+            }
+            RenderPath::Deferred => {
+                let targets = window
+                    .deferred_targets
+                    .as_ref()
+                    .expect("G-buffer not prepared for the window");
+                let gbuffer_set = draw_set!(self.deferred.gbuffer_pipeline);
+                let clear_values = vec![
+                    background.into(),
+                    [0.0, 0.0].into(),
+                    [0.0, 0.0, 0.0, 0.0].into(),
+                    [0.0, 0.0, 0.0, 0.0].into(),
+                    [0.0, 0.0, 0.0, 0.0].into(),
+                    1f32.into(),
+                ];
+                builder = self
+                    .profiler
+                    .begin_pass(builder, &mut queries, ProfiledPass::GBuffer);
+                builder = builder
+                    .begin_render_pass(targets.gbuffer_framebuffer.clone(), false, clear_values)
+                    .unwrap();
+                builder = self.record_opaque_draws(
+                    builder,
+                    &self.deferred.gbuffer_pipeline,
+                    gbuffer_set,
+                    indirect_draws.as_ref(),
+                    &queues.opaque,
+                    frame,
+                    &window.dynamic_state,
+                );
+                let gbuffer_draws = record_prepass!(builder, gbuffer_pipeline);
+                builder = builder.end_render_pass().unwrap();
+                builder =
+                    self.profiler
+                        .end_pass(builder, &mut queries, opaque_draws + gbuffer_draws);
+                if let Some((settings, ssao_targets)) = ssao {
+                    builder = self.profiler.begin_pass(
+                        builder,
+                        &mut queries,
+                        ProfiledPass::AmbientOcclusion,
+                    );
+                    builder = self.ssao.record(
+                        builder,
+                        ssao_targets,
+                        frame_data.clone(),
+                        Some(targets.normal.clone()),
+                        &settings,
+                        &window.dynamic_state,
+                    );
+                    builder = self
+                        .profiler
+                        .end_pass(builder, &mut queries, settings.draw_calls());
+                }
+                // Decals change the G-buffer, so they are lit like the surfaces below
+                if !decals.is_empty() {
+                    builder = self
+                        .profiler
+                        .begin_pass(builder, &mut queries, ProfiledPass::Decals);
+                    builder = self.decals.record_gbuffer(
+                        builder,
+                        decal_targets,
+                        &decals,
+                        frame_data.clone(),
+                        &window.dynamic_state,
+                    );
+                    builder = self
+                        .profiler
+                        .end_pass(builder, &mut queries, decals.len() as u32);
+                }
+
+                let lighting_set =
+                    frame_set!(self.deferred.lighting_pipeline, set => set.add_empty().unwrap());
+                builder = self
+                    .profiler
+                    .begin_pass(builder, &mut queries, ProfiledPass::Lighting);
+                builder = self.deferred.record_lighting(
+                    builder,
+                    targets,
+                    lighting_set,
+                    &window.dynamic_state,
+                );
+                builder = self.profiler.end_pass(builder, &mut queries, 1);
+                builder = self
+                    .profiler
+                    .begin_pass(builder, &mut queries, ProfiledPass::Scene);
+                builder = builder
+                    .begin_render_pass(
+                        targets.forward_framebuffer.clone(),
+                        false,
+                        vec![ClearValue::None, ClearValue::None, ClearValue::None],
+                    )
+                    .unwrap();
+            }
+        }
+
+        // Skinned objects aren't in picking, they are shaded forward in both render
+        // paths.
+        let (skinned_builder, skinned_draws) = self.skinning.record(
+            builder,
+            &skinned,
+            frame_set.clone(),
+            first_skinned_draw,
+            &window.dynamic_state,
+        );
+        builder = skinned_builder;
+        scene_draws += skinned_draws;
+        // Chunks too, their faces darken the ambient occlusion further
+        let (voxel_builder, voxel_draws) = self.voxels.record(
+            builder,
+            &chunks,
+            frame.block_atlas,
+            frame_set.clone(),
+            first_chunk_draw,
+            &window.dynamic_state,
+        );
+        builder = voxel_builder;
+        scene_draws += voxel_draws;
+        // Terrains too, they receive shadows but don't cast them
+        let (terrain_builder, terrain_draw_calls) = self.terrain.record(
+            builder,
+            &terrain_draws,
+            frame.terrains,
+            frame_set.clone(),
+            first_terrain_draw,
+            &window.dynamic_state,
+        );
+        builder = terrain_builder;
+        scene_draws += terrain_draw_calls;
+        // Forward decals need the depth of all opaque surfaces, the scene pass is
+        // interrupted while they are drawn
+        if window.render_path == RenderPath::Forward && !decals.is_empty() {
+            builder = builder.end_render_pass().unwrap();
+            builder =
+                self.profiler
+                    .end_pass(builder, &mut queries, scene_draws - transparent_draws);
+            scene_draws = transparent_draws;
+            builder = self
+                .profiler
+                .begin_pass(builder, &mut queries, ProfiledPass::Decals);
+            let decal_set =
+                frame_set!(self.decals.forward_pipeline, set => set.add_empty().unwrap());
+            builder = self.decals.record_forward(
+                builder,
+                decal_targets,
+                &decals,
+                decal_set,
+                &window.dynamic_state,
+            );
+            builder = self
+                .profiler
+                .end_pass(builder, &mut queries, decals.len() as u32);
+            builder = self
+                .profiler
+                .begin_pass(builder, &mut queries, ProfiledPass::Scene);
+            builder = builder
+                .begin_render_pass(
+                    decal_targets.resume_framebuffer.clone(),
+                    false,
+                    vec![ClearValue::None, ClearValue::None, ClearValue::None],
+                )
+                .unwrap();
+        }
+        if let Some(environment) = frame.environment {
+            builder = self.environment.record_skybox(
+                builder,
+                environment,
+                frame_data.clone(),
+                &window.dynamic_state,
+            );
+            scene_draws += 1;
+        }
+        // Sorted among themselves, drawn behind other transparent surfaces
+        if !sorted_particles.is_empty() {
+            let (particle_builder, particle_draws) = self.particles.record_draws(
+                builder,
+                particle_targets,
+                &sorted_particles,
+                frame.particle_emitters,
+                frame_data.clone(),
+                &window.dynamic_state,
+            );
+            builder = particle_builder;
+            scene_draws += particle_draws;
+        }
+        match &window.oit_targets {
+            Some(targets) => {
+                // Additive blending doesn't depend on order, it stays in the scene pass.
+                let (additive, blended): (Vec<usize>, Vec<usize>) =
+                    queues.transparent.iter().cloned().partition(|&index| {
+                        return blend_modes[index].0 == BlendMode::Additive;
+                    });
+                builder = self.record_draws(
+                    builder,
+                    &self.additive_pipeline,
+                    frame_set.clone(),
+                    &additive,
+                    frame,
+                    &window.dynamic_state,
+                );
+                scene_draws -= blended.len() as u32;
+                builder = builder.end_render_pass().unwrap();
+                builder = self.profiler.end_pass(builder, &mut queries, scene_draws);
+
+                builder =
+                    self.profiler
+                        .begin_pass(builder, &mut queries, ProfiledPass::Transparency);
+
+                builder = builder
+                    .begin_render_pass(
+                        targets.accumulation_framebuffer.clone(),
+                        false,
+                        self.oit.clear_values(),
+                    )
+                    .unwrap();
+                builder = self.record_draws(
+                    builder,
+                    &self.oit.accumulation_pipeline,
+                    frame_set,
+                    &blended,
+                    frame,
+                    &window.dynamic_state,
+                );
+                builder = builder.end_render_pass().unwrap();
+                builder = self
+                    .oit
+                    .record_composite(builder, targets, &window.dynamic_state);
+                builder = self
+                    .profiler
+                    .end_pass(builder, &mut queries, blended.len() as u32 + 1);
+            }
+            None => {
+                for &index in &queues.transparent {
+                    let pipeline = match blend_modes[index].0 {
+                        BlendMode::Additive => &self.additive_pipeline,
+                        BlendMode::Premultiplied => &self.premultiplied_pipeline,
+                        _ => &self.alpha_blend_pipeline,
+                    };
+                    builder = self.record_draws(
+                        builder,
+                        pipeline,
+                        frame_set.clone(),
+                        &[index],
+                        frame,
+                        &window.dynamic_state,
+                    );
+                }
+                builder = builder.end_render_pass().unwrap();
+                builder = self.profiler.end_pass(builder, &mut queries, scene_draws);
+            }
+        }
+        // Depth of this frame culls the next one
+        if let Some((_, hiz)) = gpu_driven {
+            builder = self
+                .profiler
+                .begin_pass(builder, &mut queries, ProfiledPass::DepthPyramid);
+            builder = self.gpu_driven.record_hiz(builder, hiz);
+            builder = self.profiler.end_pass(builder, &mut queries, 0);
+        }
+        let mut readbacks = Vec::new();
+        if !window.pick_requests.is_empty() {
+            builder = self
+                .profiler
+                .begin_pass(builder, &mut queries, ProfiledPass::Picking);
+            let (picking_builder, picked) = self.picking.record(
+                builder,
+                window.picking_targets.as_ref().unwrap(),
+                draw_set!(self.picking.pipeline),
+                frame.draws,
+                frame.geometries,
+                &window.pick_requests,
+                &window.dynamic_state,
+            );
+            let picking_draws = frame.draws.iter().filter(|item| item.in_frustum).count();
+            builder = self
+                .profiler
+                .end_pass(picking_builder, &mut queries, picking_draws as u32);
+            readbacks = picked;
+        }
+
+        builder = self
+            .profiler
+            .begin_pass(builder, &mut queries, ProfiledPass::PostProcess);
+        let mut post_draws = 0;
+        let mut scene = post_targets.hdr_image.clone();
+        if let AntiAliasing::Taa(settings) = window.anti_aliasing {
+            let (taa_builder, resolved) = self.aa.record_taa(
+                builder,
+                aa_targets,
+                scene,
+                post_targets.velocity_image.clone(),
+                settings.feedback,
+                temporal.frame_index,
+            );
+            builder = taa_builder;
+            scene = resolved;
+            post_draws += 1;
+        }
+        let (post_builder, effect_draws) =
+            self.post
+                .record_effects(builder, post_targets, &scene, &window.post_process);
+        builder = self
+            .profiler
+            .end_pass(post_builder, &mut queries, post_draws + effect_draws);
+
+        // Composite, FXAA, text and debug drawing
+        builder = self
+            .profiler
+            .begin_pass(builder, &mut queries, ProfiledPass::Window);
+        let mut window_draws = 1;
+
+        // FXAA works on the tonemapped image, the composite goes into an offscreen
+        // target first.
+        if let Some(fxaa) = &aa_targets.fxaa {
+            builder = builder
+                .begin_render_pass(fxaa.framebuffer.clone(), false, vec![ClearValue::None])
+                .unwrap();
+            builder = self
+                .post
+                .record_composite(
+                    builder,
+                    post_targets,
+                    &scene,
+                    &window.post_process,
+                    &window.dynamic_state,
+                    window.aspect_ratio(),
+                    true,
+                )
+                .end_render_pass()
+                .unwrap()
+                .begin_render_pass(
+                    window.render_target.framebuffers[image_num].clone(),
+                    false,
+                    window_clear_values,
+                )
+                .unwrap();
+            builder = self
+                .aa
+                .record_fxaa(builder, aa_targets, &window.dynamic_state);
+            window_draws += 1;
+        } else {
+            builder = builder
+                .begin_render_pass(
+                    window.render_target.framebuffers[image_num].clone(),
+                    false,
+                    window_clear_values,
+                )
+                .unwrap();
+            builder = self.post.record_composite(
+                builder,
+                post_targets,
+                &scene,
+                &window.post_process,
+                &window.dynamic_state,
+                window.aspect_ratio(),
+                false,
+            );
+        }
+
+        // Debug views replace the shaded surfaces, the debug primitives stay on top.
+        if window.debug_view != DebugView::None {
+            let (debug_view_builder, debug_view_draws) = self.debug_views.record(
+                builder,
+                window.debug_view,
+                draw_set!(self.debug_views.surface_pipeline),
+                frame.draws,
+                frame.geometries,
+                &window.camera,
+                &window.dynamic_state,
+            );
+            builder = debug_view_builder;
+            window_draws += debug_view_draws;
+        }
+
+        let mut text_batches = TextBatches::default();
+        text_batches.add_scene(
+            frame.texts,
+            |font_id| frame.fonts.get(&font_id).map(|font| &font.metrics),
+            Some(&camera.transform),
+        );
+        let (text_builder, text_draws) = self.text.record(
+            builder,
+            &text_batches,
+            frame.fonts,
+            Some(&temporal.view_projection),
+            &screen_projection([width, height]),
+            &window.dynamic_state,
+        );
+        builder = text_builder;
+        window_draws += text_draws;
+
+        let debug_batches = [
+            (
+                &frame.debug_batch.depth_tested,
+                &self.debug.depth_tested_pipeline,
+            ),
+            (&frame.debug_batch.overlay, &self.debug.overlay_pipeline),
+        ];
+        for (vertex_buffer, pipeline) in debug_batches.iter() {
+            if let Some(vertex_buffer) = vertex_buffer {
+                let push_constants = debug_draw::vs::ty::PushConstants {
+                    view_projection: temporal.view_projection.into(),
+                };
+                builder = builder
+                    .draw(
+                        (*pipeline).clone(),
+                        &window.dynamic_state,
+                        vec![vertex_buffer.clone()],
+                        (),
+                        push_constants,
+                    )
+                    .unwrap();
+                window_draws += 1;
+            }
+        }
+        builder = builder.end_render_pass().unwrap();
+        builder = self.profiler.end_pass(builder, &mut queries, window_draws);
+        builder = self.profiler.end_frame(builder, &queries);
+
+        let (builder, captures) = self.capture.record(
+            builder,
+            &window.capture_requests,
+            &window.images[image_num],
+            Some(&scene),
+        );
+        return (builder.build().unwrap(), readbacks, captures, queries);
+    }
+
+    // Draws the sprites, tile maps and screen text of the frame over the
+    // background colour of the 2D camera.
+    fn render_2d(
+        &self,
+        window: &WindowContext,
+        image_num: usize,
+        frame: &FrameInput,
+        camera: &Camera2d,
+        recycled_queries: Option<FrameQueries>,
+    ) -> (
+        AutoCommandBuffer,
+        Vec<PickReadback>,
+        Vec<CaptureReadback>,
+        Option<FrameQueries>,
+    ) {
+        let viewport = window.swapchain.dimensions();
+        let (min, max) = camera.visible_area(viewport);
+        let mut batches = SpriteBatches::default();
+        // The window render pass doesn't clear, the background is the first quad
+        let background = &camera.background;
+        batches.add_quad(
+            None,
+            SpriteQuad {
+                corners: [
+                    min,
+                    Vector2f::new(max.x, min.y),
+                    Vector2f::new(min.x, max.y),
+                    max,
+                ],
+                uv_min: Vector2f::zeros(),
+                uv_max: Vector2f::new(1.0, 1.0),
+                color: Vector4f::new(background.x, background.y, background.z, 1.0),
+            },
+        );
+        batches.add_scene(
+            frame.sprites,
+            frame.tile_maps,
+            |atlas_id| frame.sprite_atlases.get(&atlas_id).map(|atlas| atlas.size),
+            (min, max),
+        );
+
+        let builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            window.queue().family(),
+        )
+        .unwrap();
+        let (builder, mut queries) = self.profiler.begin_frame(builder, recycled_queries);
+        let builder = self
+            .profiler
+            .begin_pass(builder, &mut queries, ProfiledPass::Sprites)
+            .begin_render_pass(
+                window.render_target.framebuffers[image_num].clone(),
+                false,
+                vec![ClearValue::None, ClearValue::None],
+            )
+            .unwrap();
+        let (builder, sprite_draws) = self.sprites.record(
+            builder,
+            &batches,
+            frame.sprite_atlases,
+            &camera.view_projection(viewport),
+            &window.dynamic_state,
+        );
+        // Only screen text, there is no camera to place world text with
+        let mut text_batches = TextBatches::default();
+        text_batches.add_scene(
+            frame.texts,
+            |font_id| frame.fonts.get(&font_id).map(|font| &font.metrics),
+            None,
+        );
+        let (builder, text_draws) = self.text.record(
+            builder,
+            &text_batches,
+            frame.fonts,
+            None,
+            &screen_projection(viewport),
+            &window.dynamic_state,
+        );
+        let draw_calls = sprite_draws + text_draws;
+        let builder = builder.end_render_pass().unwrap();
+        let builder = self.profiler.end_pass(builder, &mut queries, draw_calls);
+        let builder = self.profiler.end_frame(builder, &queries);
+
+        // There is no HDR scene, EXR captures store the window image
+        let (builder, captures) = self.capture.record(
+            builder,
+            &window.capture_requests,
+            &window.images[image_num],
+            None,
+        );
+        return (builder.build().unwrap(), Vec::new(), captures, queries);
+    }
+
+    // Draws the objects at `indices` of the frame draw list, `set` has to contain
+    // the per draw data at binding 6.
+    fn record_draws<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        set: S,
+        indices: &[usize],
+        frame: &FrameInput,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        S: DescriptorSetsCollection + Clone,
+    {
+        for &index in indices {
+            let geometry = &frame.geometries[&frame.draws[index].geometry_id];
+            let push_constants = vs::ty::PushConstants {
+                draw: [index as u32, 0, 0, 0],
+            };
+            builder = builder
+                .draw(
+                    pipeline.clone(),
+                    dynamic_state,
+                    geometry.vertex_buffer.clone(),
+                    set.clone(),
+                    push_constants,
+                )
+                .unwrap();
+        }
+        return builder;
+    }
+
+    // Draws the opaque queue, with the culled indirect commands when the window is
+    // GPU driven.
+    fn record_opaque_draws<S>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        set: S,
+        indirect_draws: Option<&IndirectDraws>,
+        indices: &[usize],
+        frame: &FrameInput,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        S: DescriptorSetsCollection + Clone,
+    {
+        match (indirect_draws, frame.geometry_arena) {
+            (Some(draws), Some(arena)) => {
+                return self.gpu_driven.record_draws(
+                    builder,
+                    pipeline,
+                    set,
+                    draws,
+                    arena,
+                    dynamic_state,
+                );
+            }
+            _ => return self.record_draws(builder, pipeline, set, indices, frame, dynamic_state),
+        }
+    }
 }
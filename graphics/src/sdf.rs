@@ -0,0 +1,399 @@
+use crate::font::{Outline, OutlineSegment};
+use polyengine_core::*;
+
+// Channels an edge contributes to.
+const RED: u8 = 0x1;
+const GREEN: u8 = 0x2;
+const BLUE: u8 = 0x4;
+const CYAN: u8 = GREEN | BLUE;
+const MAGENTA: u8 = RED | BLUE;
+const YELLOW: u8 = RED | GREEN;
+const WHITE: u8 = RED | GREEN | BLUE;
+
+// Sine of the smallest turn between two edges treated as a sharp corner, about
+// 8 degrees.
+const CORNER_THRESHOLD: FScalar = 0.14;
+
+// Line pieces per curve, curves of glyphs at atlas sizes are a few dozen
+// pixels long at most.
+const QUADRATIC_STEPS: usize = 8;
+const CUBIC_STEPS: usize = 12;
+
+// Outline segment flattened into a polyline in pixel space, with the channels
+// it contributes to.
+#[derive(Debug, Clone, PartialEq)]
+struct Edge {
+    points: Vec<Vector2f>,
+    color: u8,
+}
+
+impl Edge {
+    fn start_direction(&self) -> Vector2f { return self.points[1] - self.points[0]; }
+
+    fn end_direction(&self) -> Vector2f {
+        let n = self.points.len();
+        return self.points[n - 1] - self.points[n - 2];
+    }
+
+    // Splits the edge in three edges of equal point count.
+    fn split_in_thirds(&self) -> [Edge; 3] {
+        let mut points = Vec::with_capacity((self.points.len() - 1) * 3 + 1);
+        for pair in self.points.windows(2) {
+            for i in 0..3 {
+                points.push(pair[0] + (pair[1] - pair[0]) * (i as FScalar / 3.0));
+            }
+        }
+        points.push(self.points[self.points.len() - 1]);
+        let third = self.points.len() - 1;
+        let piece = |i: usize| Edge {
+            points: points[i * third..=(i + 1) * third].to_vec(),
+            color: self.color,
+        };
+        return [piece(0), piece(1), piece(2)];
+    }
+}
+
+// Distance of a point to an edge.
+#[derive(Debug, Copy, Clone)]
+struct EdgeDistance {
+    // Unsigned distance to the closest point
+    distance: FScalar,
+    // Cosine between the edge and the direction to the point, breaks ties of
+    // edges meeting at the closest point
+    alignment: FScalar,
+    // Distance to the edge extended along its end tangents, positive on the
+    // inner side
+    pseudo_distance: FScalar,
+}
+
+impl EdgeDistance {
+    fn closer_than(&self, other: &EdgeDistance) -> bool {
+        if (self.distance - other.distance).abs() > 1e-4 {
+            return self.distance < other.distance;
+        }
+        return self.alignment < other.alignment;
+    }
+}
+
+fn cross(a: &Vector2f, b: &Vector2f) -> FScalar { return a.x * b.y - a.y * b.x; }
+
+fn flatten(segment: &OutlineSegment, transform: &dyn Fn(Vector2f) -> Vector2f) -> Vec<Vector2f> {
+    let steps = match segment {
+        OutlineSegment::Line(..) => 1,
+        OutlineSegment::Quadratic(..) => QUADRATIC_STEPS,
+        OutlineSegment::Cubic(..) => CUBIC_STEPS,
+    };
+    return (0..=steps)
+        .map(|i| transform(segment.point(i as FScalar / steps as FScalar)))
+        .collect();
+}
+
+fn edge_distance(edge: &Edge, point: &Vector2f, orientation: FScalar) -> EdgeDistance {
+    let last = edge.points.len() - 2;
+    let mut closest = EdgeDistance {
+        distance: FScalar::MAX,
+        alignment: 1.0,
+        pseudo_distance: 0.0,
+    };
+    for (i, pair) in edge.points.windows(2).enumerate() {
+        let (a, b) = (pair[0], pair[1]);
+        let direction = b - a;
+        let length_squared = direction.norm_squared().max(1e-12);
+        let t = (point - a).dot(&direction) / length_squared;
+        let to_point = point - (a + direction * t.max(0.0).min(1.0));
+        let distance = to_point.norm();
+        let side = cross(&direction, &(point - a)).signum() * orientation;
+        let alignment = match distance > 1e-6 {
+            true => (direction.dot(&to_point) / (length_squared.sqrt() * distance)).abs(),
+            false => 0.0,
+        };
+        let candidate = EdgeDistance {
+            distance,
+            alignment,
+            pseudo_distance: side * distance,
+        };
+        if !candidate.closer_than(&closest) {
+            continue;
+        }
+        closest = candidate;
+        // Past the ends the edge continues along its tangent, so corners of
+        // differently coloured edges stay sharp
+        if (i == 0 && t < 0.0) || (i == last && t > 1.0) {
+            let perpendicular = cross(&direction, &(point - a)) / length_squared.sqrt();
+            if perpendicular.abs() <= distance {
+                closest.pseudo_distance = perpendicular * orientation;
+            }
+        }
+    }
+    return closest;
+}
+
+fn is_corner(a: &Vector2f, b: &Vector2f) -> bool {
+    let (a, b) = (a.normalize(), b.normalize());
+    return a.dot(&b) <= 0.0 || cross(&a, &b).abs() > CORNER_THRESHOLD;
+}
+
+// Colours the edges so that the two edges meeting at a corner share only one
+// channel. Contours without corners use all channels.
+fn color_contour(edges: &mut Vec<Edge>) {
+    let n = edges.len();
+    let corners: Vec<usize> = (0..n)
+        .filter(|&i| {
+            is_corner(
+                &edges[(i + n - 1) % n].end_direction(),
+                &edges[i].start_direction(),
+            )
+        })
+        .collect();
+    match corners.len() {
+        0 => {
+            for edge in edges.iter_mut() {
+                edge.color = WHITE;
+            }
+        }
+        // Teardrop, the single corner is split between three colours
+        1 => {
+            edges.rotate_left(corners[0]);
+            if edges.len() < 3 {
+                *edges = edges
+                    .iter()
+                    .flat_map(|edge| edge.split_in_thirds().to_vec())
+                    .collect();
+            }
+            let colors = [CYAN, WHITE, YELLOW];
+            let m = edges.len();
+            for (j, edge) in edges.iter_mut().enumerate() {
+                edge.color = colors[(j * 3 / m).min(2)];
+            }
+        }
+        _ => {
+            let colors = [CYAN, MAGENTA, YELLOW];
+            let splines = corners.len();
+            let mut previous = 0;
+            for (k, &corner) in corners.iter().enumerate() {
+                let mut color = colors[k % 3];
+                // The last spline also touches the first one
+                if k == splines - 1 && color == colors[0] {
+                    color = *colors
+                        .iter()
+                        .find(|&&c| c != previous && c != colors[0])
+                        .unwrap();
+                }
+                let end = corners.get(k + 1).cloned().unwrap_or(corners[0] + n);
+                for i in corner..end {
+                    edges[i % n].color = color;
+                }
+                previous = color;
+            }
+        }
+    }
+}
+
+// Non-zero winding of the polylines around the point.
+fn winding(contours: &[Vec<Edge>], point: &Vector2f) -> i32 {
+    let mut winding = 0;
+    for edge in contours.iter().flatten() {
+        for pair in edge.points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let side = cross(&(b - a), &(point - a));
+            if a.y <= point.y && b.y > point.y && side > 0.0 {
+                winding += 1;
+            } else if b.y <= point.y && a.y > point.y && side < 0.0 {
+                winding -= 1;
+            }
+        }
+    }
+    return winding;
+}
+
+fn encode(distance: FScalar, range: FScalar) -> u8 {
+    return ((distance / range + 0.5).max(0.0).min(1.0) * 255.0).round() as u8;
+}
+
+fn median(a: u8, b: u8, c: u8) -> u8 { return a.min(b).max(a.max(b).min(c)); }
+
+// Multi-channel signed distance field of the outline, with the true signed
+// distance in the alpha channel. `transform` maps font units to pixels with Y
+// pointing down. Values are 0.5 on the outline, 1 at `range / 2` pixels inside
+// and 0 at `range / 2` pixels outside.
+pub fn generate_mtsdf<F>(
+    outline: &Outline,
+    width: u32,
+    height: u32,
+    transform: F,
+    range: FScalar,
+) -> Vec<[u8; 4]>
+where
+    F: Fn(Vector2f) -> Vector2f,
+{
+    let mut contours: Vec<Vec<Edge>> = outline
+        .iter()
+        .map(|contour| {
+            return contour
+                .iter()
+                .map(|segment| Edge {
+                    points: flatten(segment, &transform),
+                    color: WHITE,
+                })
+                .filter(|edge| edge.points.windows(2).any(|p| p[0] != p[1]))
+                .collect::<Vec<_>>();
+        })
+        .filter(|edges: &Vec<Edge>| !edges.is_empty())
+        .collect();
+    for edges in contours.iter_mut() {
+        color_contour(edges);
+    }
+
+    // Filled areas are on the inner side of the outer contours, which turn
+    // the same way as the whole outline
+    let area: FScalar = contours
+        .iter()
+        .flatten()
+        .flat_map(|edge| edge.points.windows(2))
+        .map(|p| cross(&p[0], &p[1]))
+        .sum();
+    let orientation = match area < 0.0 {
+        true => -1.0,
+        false => 1.0,
+    };
+
+    let mut texels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let point = Vector2f::new(x as FScalar + 0.5, y as FScalar + 0.5);
+            let mut channels: [Option<EdgeDistance>; 3] = [None; 3];
+            let mut closest: Option<EdgeDistance> = None;
+            for edge in contours.iter().flatten() {
+                let distance = edge_distance(edge, &point, orientation);
+                for (channel, best) in channels.iter_mut().enumerate() {
+                    if edge.color & (1 << channel) == 0 {
+                        continue;
+                    }
+                    if best.map(|b| distance.closer_than(&b)).unwrap_or(true) {
+                        *best = Some(distance);
+                    }
+                }
+                if closest
+                    .map(|b| distance.distance < b.distance)
+                    .unwrap_or(true)
+                {
+                    closest = Some(distance);
+                }
+            }
+
+            let inside = winding(&contours, &point) != 0;
+            let true_distance = match (closest, inside) {
+                (Some(closest), true) => closest.distance,
+                (Some(closest), false) => -closest.distance,
+                (None, _) => -range,
+            };
+            let alpha = encode(true_distance, range);
+            let mut texel = [alpha; 4];
+            for (channel, best) in channels.iter().enumerate() {
+                if let Some(best) = best {
+                    texel[channel] = encode(best.pseudo_distance, range);
+                }
+            }
+            // Where the channels disagree with the outline, the plain distance
+            // is used instead
+            let median_inside = median(texel[0], texel[1], texel[2]) >= 128;
+            if median_inside != inside {
+                texel = [alpha; 4];
+            }
+            texels.push(texel);
+        }
+    }
+    return texels;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Outline {
+        let corners = [
+            Vector2f::new(0.0, 0.0),
+            Vector2f::new(8.0, 0.0),
+            Vector2f::new(8.0, 8.0),
+            Vector2f::new(0.0, 8.0),
+        ];
+        return vec![(0..4)
+            .map(|i| OutlineSegment::Line(corners[i], corners[(i + 1) % 4]))
+            .collect()];
+    }
+
+    #[test]
+    fn coloring_test() {
+        let mut edges: Vec<Edge> = square()[0]
+            .iter()
+            .map(|segment| Edge {
+                points: flatten(segment, &|p| p),
+                color: WHITE,
+            })
+            .collect();
+        color_contour(&mut edges);
+        for i in 0..4 {
+            let shared = edges[i].color & edges[(i + 1) % 4].color;
+            assert_eq!(shared.count_ones(), 1);
+        }
+
+        // Circle without corners
+        let circle: Vec<OutlineSegment> = (0..4)
+            .map(|i| {
+                let angle = |i: usize| i as FScalar * std::f32::consts::FRAC_PI_2;
+                let point = |a: FScalar| Vector2f::new(a.cos(), a.sin());
+                let control = point(angle(i) + std::f32::consts::FRAC_PI_4) * 2.0f32.sqrt();
+                return OutlineSegment::Quadratic(point(angle(i)), control, point(angle(i + 1)));
+            })
+            .collect();
+        let mut edges: Vec<Edge> = circle
+            .iter()
+            .map(|segment| Edge {
+                points: flatten(segment, &|p| p),
+                color: 0,
+            })
+            .collect();
+        color_contour(&mut edges);
+        assert!(edges.iter().all(|edge| edge.color == WHITE));
+    }
+
+    #[test]
+    fn teardrop_test() {
+        let mut edges = vec![Edge {
+            points: vec![
+                Vector2f::new(0.0, 0.0),
+                Vector2f::new(4.0, 2.0),
+                Vector2f::new(0.0, 4.0),
+                Vector2f::new(0.0, 0.0),
+            ],
+            color: WHITE,
+        }];
+        color_contour(&mut edges);
+        assert_eq!(edges.len(), 3);
+        assert_eq!(edges[0].points[0], Vector2f::new(0.0, 0.0));
+        assert_eq!(edges[2].points[3], Vector2f::new(0.0, 0.0));
+        assert_eq!((edges[0].color & edges[2].color).count_ones(), 1);
+    }
+
+    #[test]
+    fn mtsdf_test() {
+        // 8 pixel square with 4 pixels of padding
+        let texels = generate_mtsdf(&square(), 16, 16, |p| p + Vector2f::new(4.0, 4.0), 4.0);
+        let texel = |x: usize, y: usize| texels[y * 16 + x];
+        let median = |t: [u8; 4]| median(t[0], t[1], t[2]);
+        // Center is 4 pixels inside, the range saturates
+        assert_eq!(texel(8, 8), [255; 4]);
+        assert_eq!(median(texel(0, 8)), 0);
+        assert_eq!(texel(0, 8)[3], 0);
+        // Half a pixel inside of the left edge
+        assert_eq!(median(texel(4, 8)), 159);
+        assert_eq!(texel(4, 8)[3], 159);
+        // Diagonally outside of the corner the median keeps the corner sharp,
+        // the alpha is rounded
+        let corner = texel(3, 3);
+        assert!(median(corner) < 128);
+        assert!(corner[3] < median(corner));
+        // Just inside of the corner
+        assert!(median(texel(4, 4)) > 128);
+    }
+}
@@ -0,0 +1,8 @@
+// Screen space ambient occlusion of the opaque geometry, white when disabled.
+// Requires frame_data.glsl.
+
+layout(set = 0, binding = 10) uniform sampler2D ambient_occlusion_map;
+
+float ambient_occlusion(vec2 frag_coord) {
+    return texture(ambient_occlusion_map, frag_coord / frame.cluster_params.zw).r;
+}
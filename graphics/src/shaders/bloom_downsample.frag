@@ -0,0 +1,16 @@
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    vec3 color = 0.25 * (
+        texture(source, v_uv + texel * vec2(-1.0, -1.0)).rgb +
+        texture(source, v_uv + texel * vec2(1.0, -1.0)).rgb +
+        texture(source, v_uv + texel * vec2(-1.0, 1.0)).rgb +
+        texture(source, v_uv + texel * vec2(1.0, 1.0)).rgb);
+    f_color = vec4(color, 1.0);
+}
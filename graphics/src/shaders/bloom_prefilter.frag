@@ -0,0 +1,34 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "color.glsl"
+
+// Downsamples the scene into the first bloom level keeping only bright pixels.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+layout(push_constant) uniform PushConstants {
+    // x: threshold, y: soft knee width
+    vec4 threshold;
+} pc;
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    vec3 color = 0.25 * (
+        texture(source, v_uv + texel * vec2(-1.0, -1.0)).rgb +
+        texture(source, v_uv + texel * vec2(1.0, -1.0)).rgb +
+        texture(source, v_uv + texel * vec2(-1.0, 1.0)).rgb +
+        texture(source, v_uv + texel * vec2(1.0, 1.0)).rgb);
+
+    // Quadratic curve around the threshold avoids a hard cut off.
+    float threshold = pc.threshold.x;
+    float knee = pc.threshold.y;
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-5);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 1e-5);
+
+    f_color = vec4(color * contribution, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+// Blurs the smaller level with a 3x3 tent filter, the result is added to the
+// destination level by blending.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    vec3 color = vec3(0.0);
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            float weight = (2.0 - abs(float(x))) * (2.0 - abs(float(y))) / 16.0;
+            color += texture(source, v_uv + texel * vec2(x, y)).rgb * weight;
+        }
+    }
+    f_color = vec4(color, 1.0);
+}
@@ -0,0 +1,45 @@
+// Clustered light lookup. Requires `frame_data.glsl`, `lighting.glsl` and
+// `shadows.glsl`.
+// Cluster grid has to match constants in `clusters.rs`.
+
+const uint CLUSTER_TILES_X = 16;
+const uint CLUSTER_TILES_Y = 9;
+const uint CLUSTER_SLICES = 24;
+
+layout(set = 0, binding = 1) readonly buffer Lights {
+    Light lights[];
+};
+
+// Offset into `light_indices` and light count for every cluster
+layout(set = 0, binding = 2) readonly buffer ClusterRanges {
+    uvec2 cluster_ranges[];
+};
+
+layout(set = 0, binding = 3) readonly buffer LightIndices {
+    uint light_indices[];
+};
+
+uint cluster_index(vec2 frag_coord, vec3 world_position) {
+    float depth = dot(world_position - frame.camera_position.xyz, frame.camera_forward.xyz);
+    float slice = log(max(depth, 1e-4)) * frame.cluster_params.x + frame.cluster_params.y;
+    uvec3 cluster = uvec3(
+        min(uint(frag_coord.x / frame.cluster_params.z * CLUSTER_TILES_X), CLUSTER_TILES_X - 1),
+        min(uint(frag_coord.y / frame.cluster_params.w * CLUSTER_TILES_Y), CLUSTER_TILES_Y - 1),
+        uint(clamp(slice, 0.0, float(CLUSTER_SLICES - 1))));
+    return (cluster.z * CLUSTER_TILES_Y + cluster.y) * CLUSTER_TILES_X + cluster.x;
+}
+
+// Sum of all lights affecting the surface. Directional lights are placed at the
+// beginning of the light list and are always evaluated.
+vec3 evaluate_clustered_lights(vec2 frag_coord, SurfaceData s) {
+    vec3 color = vec3(0.0);
+    for (uint i = 0; i < frame.light_info.x; ++i) {
+        color += evaluate_shadowed_light(lights[i], s);
+    }
+
+    uvec2 range = cluster_ranges[cluster_index(frag_coord, s.position)];
+    for (uint i = 0; i < range.y; ++i) {
+        color += evaluate_shadowed_light(lights[light_indices[range.x + i]], s);
+    }
+    return color;
+}
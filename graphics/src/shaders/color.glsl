@@ -0,0 +1,39 @@
+// Colour space conversions and tonemapping operators.
+// Tonemapper ids have to match `tonemapper_id` in `post_pass.rs`.
+
+const uint TONEMAPPER_NONE = 0;
+const uint TONEMAPPER_REINHARD = 1;
+const uint TONEMAPPER_ACES = 2;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+vec3 tonemap_reinhard(vec3 color) {
+    return color / (color + vec3(1.0));
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+vec3 tonemap_aces(vec3 color) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), 0.0, 1.0);
+}
+
+vec3 tonemap(vec3 color, uint tonemapper) {
+    if (tonemapper == TONEMAPPER_REINHARD) {
+        return tonemap_reinhard(color);
+    } else if (tonemapper == TONEMAPPER_ACES) {
+        return tonemap_aces(color);
+    }
+    return clamp(color, 0.0, 1.0);
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
@@ -0,0 +1,59 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "color.glsl"
+
+// Resolves the HDR scene into the window image.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+layout(set = 0, binding = 1) uniform sampler2D bloom;
+layout(set = 0, binding = 2) uniform sampler2D grading_lut;
+
+layout(set = 0, binding = 3) readonly buffer Exposure {
+    // x: adapted luminance, y: exposure
+    vec4 exposure;
+};
+
+layout(push_constant) uniform PushConstants {
+    // x: manual exposure, y: auto exposure when positive, z: compensation
+    // multiplier, w: tonemapper
+    vec4 exposure;
+    // x: bloom intensity, y: LUT size, z: colour grading when positive
+    vec4 effects;
+    // x: intensity, y: radius, z: smoothness, w: aspect ratio
+    vec4 vignette;
+} pc;
+
+// Looks up the 3D table stored as a strip of blue slices.
+vec3 apply_lut(vec3 color, float size) {
+    vec3 c = clamp(color, 0.0, 1.0) * (size - 1.0);
+    float slice = min(floor(c.b), size - 2.0);
+    float t = c.b - slice;
+    vec2 uv = vec2((slice * size + c.r + 0.5) / (size * size), (c.g + 0.5) / size);
+    vec2 next = vec2(1.0 / size, 0.0);
+    return mix(texture(grading_lut, uv).rgb, texture(grading_lut, uv + next).rgb, t);
+}
+
+void main() {
+    vec3 color = texture(scene, v_uv).rgb;
+    color += texture(bloom, v_uv).rgb * pc.effects.x;
+
+    float exposure_value = pc.exposure.y > 0.0 ? exposure.y : pc.exposure.x;
+    color *= exposure_value * pc.exposure.z;
+
+    color = linear_to_srgb(tonemap(color, uint(pc.exposure.w)));
+    if (pc.effects.z > 0.0) {
+        color = apply_lut(color, pc.effects.y);
+    }
+
+    if (pc.vignette.x > 0.0) {
+        vec2 scale = vec2(pc.vignette.w, 1.0);
+        float distance = length((v_uv - 0.5) * scale) / length(0.5 * scale);
+        float falloff = smoothstep(pc.vignette.y, pc.vignette.y + pc.vignette.z, distance);
+        color *= 1.0 - falloff * pc.vignette.x;
+    }
+
+    f_color = vec4(color, 1.0);
+}
@@ -0,0 +1,21 @@
+// Cubemap texel directions. Faces are ordered +X, -X, +Y, -Y, +Z, -Z and
+// oriented as sampled by Vulkan.
+
+vec3 cube_direction(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    vec3 direction;
+    switch (face) {
+        case 0: direction = vec3(1.0, -st.y, -st.x); break;
+        case 1: direction = vec3(-1.0, -st.y, st.x); break;
+        case 2: direction = vec3(st.x, 1.0, st.y); break;
+        case 3: direction = vec3(st.x, -1.0, -st.y); break;
+        case 4: direction = vec3(st.x, -st.y, 1.0); break;
+        default: direction = vec3(-st.x, -st.y, -1.0); break;
+    }
+    return normalize(direction);
+}
+
+// Direction of the texel center at `id` (x, y, face) of a `size` sized face.
+vec3 cube_texel_direction(uvec3 id, uint size) {
+    return cube_direction(id.z, (vec2(id.xy) + 0.5) / float(size));
+}
@@ -0,0 +1,49 @@
+#version 450
+
+// Asset diagnostic views, drawn over the composited image.
+const uint VIEW_WIREFRAME = 1;
+const uint VIEW_NORMALS = 2;
+const uint VIEW_UV_CHECKER = 3;
+const uint VIEW_OVERDRAW = 4;
+const uint VIEW_DEPTH = 5;
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec2 v_uv;
+layout(location = 2) in float v_view_depth;
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform PushConstants {
+    uvec4 draw;
+    vec4 params;
+} pc;
+
+void main() {
+    vec3 color = vec3(0.0);
+    switch (pc.draw.y) {
+    case VIEW_WIREFRAME:
+        color = vec3(0.1, 1.0, 0.3);
+        break;
+    case VIEW_NORMALS:
+        color = normalize(v_normal) * 0.5 + 0.5;
+        break;
+    case VIEW_UV_CHECKER:
+        vec2 cell = floor(v_uv * 8.0);
+        float checker = mod(cell.x + cell.y, 2.0);
+        // Tinted by the coordinates so flipped and stretched mappings are visible
+        color = mix(vec3(0.15), vec3(0.9), checker) * (vec3(fract(v_uv), 0.5) * 0.5 + 0.5);
+        break;
+    case VIEW_OVERDRAW:
+        // Added up for every fragment, saturates after about 10 layers
+        color = vec3(0.1, 0.04, 0.01);
+        break;
+    case VIEW_DEPTH:
+        // Logarithmic so near and far detail are both visible
+        float near = pc.params.z;
+        float far = pc.params.w;
+        float t = log(max(v_view_depth, near) / near) / log(far / near);
+        color = vec3(1.0 - clamp(t, 0.0, 1.0));
+        break;
+    }
+    f_color = vec4(color, 1.0);
+}
@@ -0,0 +1,33 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "draw_data.glsl"
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+
+// Shared with debug_view.frag.
+layout(push_constant) uniform PushConstants {
+    // x: index into `draws`, y: debug view, see debug_view.frag
+    uvec4 draw;
+    // x: clip space depth offset towards the camera, z: camera near plane,
+    // w: camera far plane
+    vec4 params;
+} pc;
+
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec2 v_uv;
+layout(location = 2) out float v_view_depth;
+
+void main() {
+    DrawData data = draws[pc.draw.x];
+    vec4 world_position = data.model * vec4(position, 1.0);
+    v_normal = mat3(data.model) * normal;
+    v_uv = uv;
+    v_view_depth = dot(world_position.xyz - frame.camera_position.xyz, frame.camera_forward.xyz);
+
+    gl_Position = frame.view_projection * world_position;
+    gl_Position.z -= pc.params.x * gl_Position.w;
+}
@@ -0,0 +1,11 @@
+// Decal parameters shared by decal.vert and the decal fragment shaders.
+
+layout(push_constant) uniform PushConstants {
+    // World to decal space, the box spans -0.5 to 0.5 along every axis
+    mat4 inverse_model;
+    // Linear RGB base colour and opacity
+    vec4 color;
+    // x: metallic, y: roughness, z: cosine the angle fade starts at, w: cosine
+    // the decal is gone at
+    vec4 params;
+} pc;
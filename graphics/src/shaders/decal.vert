@@ -0,0 +1,37 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "decal.glsl"
+
+// Corners of the unit cube by face, a corner index holds the X, Y and Z side in
+// its first three bits. Faces are in the order +X, -X, +Y, -Y, +Z, -Z.
+const uint CUBE_CORNERS[36] = uint[](
+    1, 3, 7, 1, 7, 5,
+    0, 4, 6, 0, 6, 2,
+    2, 6, 7, 2, 7, 3,
+    0, 1, 5, 0, 5, 4,
+    4, 5, 7, 4, 7, 6,
+    0, 2, 3, 0, 3, 1
+);
+const vec3 FACE_NORMALS[6] = vec3[](
+    vec3(1.0, 0.0, 0.0),
+    vec3(-1.0, 0.0, 0.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(0.0, -1.0, 0.0),
+    vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 0.0, -1.0)
+);
+
+// Box of the decal, drawn without vertex buffers.
+layout(location = 0) out vec3 v_position;
+layout(location = 1) flat out vec3 v_face_normal;
+
+void main() {
+    uint corner = CUBE_CORNERS[gl_VertexIndex];
+    vec3 position = vec3(corner & 1u, (corner >> 1) & 1u, (corner >> 2) & 1u) - 0.5;
+    vec4 world_position = inverse(pc.inverse_model) * vec4(position, 1.0);
+    v_position = world_position.xyz;
+    v_face_normal = transpose(mat3(pc.inverse_model)) * FACE_NORMALS[gl_VertexIndex / 6];
+    gl_Position = frame.view_projection * world_position;
+}
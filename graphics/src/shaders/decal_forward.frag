@@ -0,0 +1,49 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "lighting.glsl"
+#include "shadows.glsl"
+#include "clusters.glsl"
+#include "ibl.glsl"
+#include "ambient_occlusion.glsl"
+#include "decal.glsl"
+#include "decal_projection.glsl"
+
+// Lights the decal and blends it over the shaded surfaces of the forward path.
+// Uses the frame set of the forward pass without draw data.
+layout(set = 1, binding = 0) uniform sampler2D decal_texture;
+layout(set = 2, binding = 0) uniform sampler2D scene_depth;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    vec4 position = scene_position(scene_depth);
+    // There is no normal buffer, it's reconstructed from the depth of the
+    // neighbouring pixels before any of them are discarded
+    vec3 normal = normalize(cross(dFdx(position.xyz), dFdy(position.xyz)));
+    vec3 view = normalize(frame.camera_position.xyz - position.xyz);
+    if (dot(normal, view) < 0.0) {
+        normal = -normal;
+    }
+    if (facing_camera() || position.w == 0.0) {
+        discard;
+    }
+    vec4 decal = project_decal(position.xyz, normal, decal_texture);
+    if (decal.a <= 0.0) {
+        discard;
+    }
+
+    SurfaceData s;
+    s.position = position.xyz;
+    s.normal = normal;
+    s.view = view;
+    s.albedo = decal.rgb;
+    s.metallic = pc.params.x;
+    s.roughness = clamp(pc.params.y, 0.04, 1.0);
+
+    vec3 color = (frame.ambient.rgb * s.albedo + evaluate_environment(s)) *
+                 ambient_occlusion(gl_FragCoord.xy);
+    color += evaluate_clustered_lights(gl_FragCoord.xy, s);
+    f_color = vec4(color, decal.a);
+}
@@ -0,0 +1,33 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "decal.glsl"
+#include "decal_projection.glsl"
+
+// Blends the decal into the G-buffer before lighting, so it's lit like the
+// surface it lies on.
+layout(set = 1, binding = 0) uniform sampler2D decal_texture;
+layout(set = 2, binding = 0) uniform sampler2D scene_depth;
+layout(set = 2, binding = 1) uniform sampler2D gbuffer_normal;
+
+layout(location = 0) out vec4 f_albedo;
+// x: metallic, y: roughness
+layout(location = 1) out vec4 f_material;
+
+void main() {
+    if (facing_camera()) {
+        discard;
+    }
+    vec4 position = scene_position(scene_depth);
+    if (position.w == 0.0) {
+        discard;
+    }
+    vec3 normal = normalize(texelFetch(gbuffer_normal, ivec2(gl_FragCoord.xy), 0).xyz);
+    vec4 decal = project_decal(position.xyz, normal, decal_texture);
+    if (decal.a <= 0.0) {
+        discard;
+    }
+    f_albedo = decal;
+    f_material = vec4(pc.params.xy, 0.0, decal.a);
+}
@@ -0,0 +1,36 @@
+// Projection of decals onto the depth buffer, for the decal fragment shaders.
+// Needs frame_data.glsl and decal.glsl.
+
+layout(location = 0) in vec3 v_position;
+layout(location = 1) flat in vec3 v_face_normal;
+
+// Only the faces of the box turned away from the camera are drawn, so every
+// pixel is covered once even with the camera inside of the box.
+bool facing_camera() {
+    return dot(v_face_normal, frame.camera_position.xyz - v_position) > 0.0;
+}
+
+// World position of the surface behind the fragment, w is 0 where nothing was
+// drawn.
+vec4 scene_position(sampler2D scene_depth) {
+    float depth = texelFetch(scene_depth, ivec2(gl_FragCoord.xy), 0).r;
+    if (depth >= 1.0) {
+        return vec4(0.0);
+    }
+    vec2 ndc = gl_FragCoord.xy / frame.cluster_params.zw * 2.0 - 1.0;
+    vec4 world = frame.inverse_view_projection * vec4(ndc, depth, 1.0);
+    return vec4(world.xyz / world.w, 1.0);
+}
+
+// Colour and opacity the decal projects onto a surface, transparent outside of
+// the box and on surfaces turned away from the projection.
+vec4 project_decal(vec3 position, vec3 normal, sampler2D decal_texture) {
+    vec3 local = (pc.inverse_model * vec4(position, 1.0)).xyz;
+    if (any(greaterThan(abs(local), vec3(0.5)))) {
+        return vec4(0.0);
+    }
+    vec3 up = normalize(transpose(mat3(pc.inverse_model))[1]);
+    float fade = smoothstep(pc.params.w, pc.params.z, dot(normal, up));
+    vec4 color = pc.color * texture(decal_texture, local.xz + 0.5);
+    return vec4(color.rgb, color.a * fade);
+}
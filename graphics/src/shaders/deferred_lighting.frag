@@ -0,0 +1,43 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "lighting.glsl"
+#include "shadows.glsl"
+#include "clusters.glsl"
+#include "ibl.glsl"
+#include "ambient_occlusion.glsl"
+
+// Lights every G-buffer texel once, added on top of the emissive light already
+// in the HDR target. Uses the frame set of the forward pass without draw data.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 1, binding = 0) uniform sampler2D gbuffer_albedo;
+layout(set = 1, binding = 1) uniform sampler2D gbuffer_normal;
+layout(set = 1, binding = 2) uniform sampler2D gbuffer_material;
+layout(set = 1, binding = 3) uniform sampler2D gbuffer_depth;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(gbuffer_depth, texel, 0).r;
+    // Nothing was drawn, the skybox is drawn there later.
+    if (depth >= 1.0) {
+        discard;
+    }
+    vec4 world = frame.inverse_view_projection * vec4(v_uv * 2.0 - 1.0, depth, 1.0);
+    vec4 material = texelFetch(gbuffer_material, texel, 0);
+
+    SurfaceData s;
+    s.position = world.xyz / world.w;
+    s.normal = normalize(texelFetch(gbuffer_normal, texel, 0).xyz);
+    s.view = normalize(frame.camera_position.xyz - s.position);
+    s.albedo = texelFetch(gbuffer_albedo, texel, 0).rgb;
+    s.metallic = material.x;
+    s.roughness = material.y;
+
+    vec3 color = (frame.ambient.rgb * s.albedo + evaluate_environment(s)) *
+                 ambient_occlusion(gl_FragCoord.xy);
+    color += evaluate_clustered_lights(gl_FragCoord.xy, s);
+    f_color = vec4(color, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// Depth prepass, only the depth attachment is written.
+layout(location = 3) flat in vec4 v_base_color;
+layout(location = 4) flat in vec4 v_material;
+
+void main() {
+    // Alpha test, w: cutoff
+    if (v_base_color.a < v_material.w) {
+        discard;
+    }
+}
@@ -0,0 +1,22 @@
+// Per draw data of the scene shaders.
+
+// Push constant draw index of indirect draws, the index is the instance index
+// instead. Has to match `INSTANCED_DRAW` in `gpu_driven.rs`.
+const uint INSTANCED_DRAW = 0xffffffffu;
+
+// Layout has to match `GpuDrawData` in `renderer.rs`.
+struct DrawData {
+    mat4 model;
+    // Model matrix of the previous frame, for motion vectors
+    mat4 previous_model;
+    vec4 base_color;
+    // x: metallic, y: roughness, z: how much ambient occlusion applies, transparent
+    // surfaces aren't in the depth buffer it is computed from, w: alpha test cutoff
+    vec4 material;
+    // w: 1 when the base colour is premultiplied by alpha
+    vec4 emissive;
+};
+
+layout(set = 0, binding = 6) readonly buffer Draws {
+    DrawData draws[];
+};
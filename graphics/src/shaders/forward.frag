@@ -0,0 +1,15 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "forward_shading.glsl"
+#include "velocity.glsl"
+
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec2 f_velocity;
+
+void main() {
+    // Linear HDR output, resolved by the post-process stack.
+    f_color = shade_forward();
+
+    f_velocity = motion_vector(v_clip_position, v_previous_clip_position);
+}
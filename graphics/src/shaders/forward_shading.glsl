@@ -0,0 +1,44 @@
+// Forward lighting of surfaces drawn with forward.vert, shared by the forward and
+// order independent transparency passes.
+
+#include "frame_data.glsl"
+#include "lighting.glsl"
+#include "shadows.glsl"
+#include "clusters.glsl"
+#include "ibl.glsl"
+#include "ambient_occlusion.glsl"
+
+layout(location = 0) in vec3 v_position;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) in vec2 v_uv;
+layout(location = 3) flat in vec4 v_base_color;
+layout(location = 4) flat in vec4 v_material;
+layout(location = 5) flat in vec4 v_emissive;
+layout(location = 6) in vec4 v_clip_position;
+layout(location = 7) in vec4 v_previous_clip_position;
+
+// Linear HDR colour and alpha of a surface with `base_color`, fragments failing
+// the alpha test are discarded. `vertex_occlusion` darkens the ambient light on
+// top of the screen space ambient occlusion.
+vec4 shade_surface(vec4 base_color, float vertex_occlusion) {
+    if (base_color.a < v_material.w) {
+        discard;
+    }
+
+    SurfaceData s;
+    s.position = v_position;
+    s.normal = normalize(v_normal);
+    s.view = normalize(frame.camera_position.xyz - v_position);
+    s.albedo = base_color.rgb;
+    s.metallic = v_material.x;
+    s.roughness = clamp(v_material.y, 0.04, 1.0);
+
+    float occlusion =
+        mix(1.0, ambient_occlusion(gl_FragCoord.xy), v_material.z) * vertex_occlusion;
+    vec3 color = (frame.ambient.rgb * s.albedo + evaluate_environment(s)) * occlusion;
+    color += v_emissive.rgb;
+    color += evaluate_clustered_lights(gl_FragCoord.xy, s);
+    return vec4(color, base_color.a);
+}
+
+vec4 shade_forward() { return shade_surface(v_base_color, 1.0); }
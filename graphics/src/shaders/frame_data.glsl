@@ -0,0 +1,25 @@
+// Per frame data shared by the scene shaders.
+// Layout has to match `FrameData` filled in `renderer.rs`.
+
+layout(set = 0, binding = 0) uniform FrameData {
+    // Includes the temporal anti-aliasing jitter
+    mat4 view_projection;
+    mat4 unjittered_view_projection;
+    mat4 previous_view_projection;
+    // Inverse of the jittered `view_projection`
+    mat4 inverse_view_projection;
+    vec4 camera_position;
+    // xyz: camera forward direction
+    vec4 camera_forward;
+    vec4 ambient;
+    // x: cluster slice scale, y: cluster slice bias, zw: framebuffer size
+    vec4 cluster_params;
+    // x: directional light count
+    uvec4 light_info;
+    // Far view depth of every directional light cascade
+    vec4 cascade_splits;
+    // x: PCF radius in texels, y: atlas texel size in UV units
+    vec4 shadow_params;
+    // x: environment intensity, y: environment lighting enabled when positive
+    vec4 environment_params;
+} frame;
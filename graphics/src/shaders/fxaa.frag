@@ -0,0 +1,50 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "color.glsl"
+
+// Fast approximate anti-aliasing of the tonemapped image. Blurs along the local
+// edge direction estimated from luma of the diagonal neighbours.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+
+const float FXAA_REDUCE_MIN = 1.0 / 128.0;
+const float FXAA_REDUCE_MUL = 1.0 / 8.0;
+const float FXAA_SPAN_MAX = 8.0;
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    float luma_nw = luminance(texture(source, v_uv + vec2(-1.0, -1.0) * texel).rgb);
+    float luma_ne = luminance(texture(source, v_uv + vec2(1.0, -1.0) * texel).rgb);
+    float luma_sw = luminance(texture(source, v_uv + vec2(-1.0, 1.0) * texel).rgb);
+    float luma_se = luminance(texture(source, v_uv + vec2(1.0, 1.0) * texel).rgb);
+    vec3 color_m = texture(source, v_uv).rgb;
+    float luma_m = luminance(color_m);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 direction = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL,
+        FXAA_REDUCE_MIN);
+    float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * texel;
+
+    vec3 color_a = 0.5 * (
+        texture(source, v_uv + direction * (1.0 / 3.0 - 0.5)).rgb +
+        texture(source, v_uv + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 color_b = color_a * 0.5 + 0.25 * (
+        texture(source, v_uv - direction * 0.5).rgb +
+        texture(source, v_uv + direction * 0.5).rgb);
+
+    float luma_b = luminance(color_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        f_color = vec4(color_a, 1.0);
+    } else {
+        f_color = vec4(color_b, 1.0);
+    }
+}
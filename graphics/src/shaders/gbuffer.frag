@@ -0,0 +1,35 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "velocity.glsl"
+
+// Surface attributes for the deferred lighting pass. Emissive light goes
+// straight into the HDR target, lighting is added on top of it.
+layout(location = 0) in vec3 v_position;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) in vec2 v_uv;
+layout(location = 3) flat in vec4 v_base_color;
+layout(location = 4) flat in vec4 v_material;
+layout(location = 5) flat in vec4 v_emissive;
+layout(location = 6) in vec4 v_clip_position;
+layout(location = 7) in vec4 v_previous_clip_position;
+
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec2 f_velocity;
+layout(location = 2) out vec4 f_albedo;
+// xyz: world space normal
+layout(location = 3) out vec4 f_normal;
+// x: metallic, y: roughness
+layout(location = 4) out vec4 f_material;
+
+void main() {
+    // Alpha test, w: cutoff
+    if (v_base_color.a < v_material.w) {
+        discard;
+    }
+    f_color = vec4(v_emissive.rgb, 1.0);
+    f_velocity = motion_vector(v_clip_position, v_previous_clip_position);
+    f_albedo = vec4(v_base_color.rgb, 1.0);
+    f_normal = vec4(normalize(v_normal), 0.0);
+    f_material = vec4(v_material.x, clamp(v_material.y, 0.04, 1.0), 0.0, 0.0);
+}
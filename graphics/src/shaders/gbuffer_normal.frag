@@ -0,0 +1,19 @@
+#version 450
+
+// G-buffer pass of surfaces shaded forward later, only depth and the normal
+// are written so they take part in ambient occlusion. The other attachments
+// are masked.
+layout(location = 1) in vec3 v_normal;
+layout(location = 3) flat in vec4 v_base_color;
+layout(location = 4) flat in vec4 v_material;
+
+// xyz: world space normal
+layout(location = 3) out vec4 f_normal;
+
+void main() {
+    // Alpha test, w: cutoff
+    if (v_base_color.a < v_material.w) {
+        discard;
+    }
+    f_normal = vec4(normalize(v_normal), 0.0);
+}
@@ -0,0 +1,51 @@
+// Image based ambient lighting from the environment cubemaps. Requires
+// `frame_data.glsl` and `lighting.glsl`.
+// Level count has to match `SPECULAR_LEVELS` in `environment.rs`.
+
+#define SPECULAR_LEVELS 5
+
+layout(set = 0, binding = 7) uniform samplerCube irradiance_map;
+layout(set = 0, binding = 8) uniform samplerCube specular_maps[SPECULAR_LEVELS];
+layout(set = 0, binding = 9) uniform sampler2D brdf_lut;
+
+// Levels are separate images, indexed with constants so dynamic indexing of
+// sampler arrays isn't required.
+vec3 sample_specular_level(int level, vec3 direction) {
+    switch (level) {
+        case 0: return textureLod(specular_maps[0], direction, 0.0).rgb;
+        case 1: return textureLod(specular_maps[1], direction, 0.0).rgb;
+        case 2: return textureLod(specular_maps[2], direction, 0.0).rgb;
+        case 3: return textureLod(specular_maps[3], direction, 0.0).rgb;
+        default: return textureLod(specular_maps[4], direction, 0.0).rgb;
+    }
+}
+
+vec3 sample_specular(vec3 direction, float roughness) {
+    float level = roughness * float(SPECULAR_LEVELS - 1);
+    int lower = int(floor(level));
+    int upper = min(lower + 1, SPECULAR_LEVELS - 1);
+    return mix(
+        sample_specular_level(lower, direction),
+        sample_specular_level(upper, direction),
+        level - float(lower));
+}
+
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+vec3 evaluate_environment(SurfaceData s) {
+    if (frame.environment_params.y <= 0.0) {
+        return vec3(0.0);
+    }
+    float n_dot_v = max(dot(s.normal, s.view), 1e-4);
+    vec3 f0 = mix(vec3(0.04), s.albedo, s.metallic);
+    vec3 f = fresnel_schlick_roughness(n_dot_v, f0, s.roughness);
+
+    vec3 diffuse = (1.0 - f) * (1.0 - s.metallic) * s.albedo
+        * texture(irradiance_map, s.normal).rgb;
+    vec3 r = reflect(-s.view, s.normal);
+    vec2 brdf = texture(brdf_lut, vec2(n_dot_v, s.roughness)).rg;
+    vec3 specular = sample_specular(r, s.roughness) * (f0 * brdf.x + brdf.y);
+    return (diffuse + specular) * frame.environment_params.x;
+}
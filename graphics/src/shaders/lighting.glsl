@@ -0,0 +1,91 @@
+// Shared physically based lighting code.
+// Light layout has to match `GpuLight` in `light.rs`.
+
+const float PI = 3.14159265359;
+
+const uint LIGHT_DIRECTIONAL = 0;
+const uint LIGHT_POINT = 1;
+const uint LIGHT_SPOT = 2;
+
+struct Light {
+    vec4 position_range;
+    vec4 color_intensity;
+    vec4 direction_type;
+    vec4 spot_angles;
+    // x: first shadow view or -1, y: depth bias, z: normal bias, w: view count
+    vec4 shadow_params;
+};
+
+struct SurfaceData {
+    vec3 position;
+    vec3 normal;
+    vec3 view;
+    vec3 albedo;
+    float metallic;
+    float roughness;
+};
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Inverse square falloff smoothly windowed to zero at the light range.
+float range_attenuation(float distance, float range) {
+    float ratio = distance / range;
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
+
+vec3 evaluate_light(Light light, SurfaceData s) {
+    uint type = uint(light.direction_type.w);
+    vec3 l;
+    float attenuation = 1.0;
+
+    if (type == LIGHT_DIRECTIONAL) {
+        l = -normalize(light.direction_type.xyz);
+    } else {
+        vec3 to_light = light.position_range.xyz - s.position;
+        float distance = length(to_light);
+        l = to_light / max(distance, 1e-4);
+        attenuation = range_attenuation(distance, light.position_range.w);
+
+        if (type == LIGHT_SPOT) {
+            float cos_angle = dot(-l, normalize(light.direction_type.xyz));
+            attenuation *= smoothstep(light.spot_angles.y, light.spot_angles.x, cos_angle);
+        }
+    }
+
+    float n_dot_l = max(dot(s.normal, l), 0.0);
+    if (n_dot_l <= 0.0 || attenuation <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 h = normalize(s.view + l);
+    float n_dot_v = max(dot(s.normal, s.view), 1e-4);
+    float n_dot_h = max(dot(s.normal, h), 0.0);
+    vec3 f0 = mix(vec3(0.04), s.albedo, s.metallic);
+
+    vec3 f = fresnel_schlick(max(dot(h, s.view), 0.0), f0);
+    float d = distribution_ggx(n_dot_h, s.roughness);
+    float g = geometry_smith(n_dot_v, n_dot_l, s.roughness);
+    vec3 specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 1e-4);
+    vec3 diffuse = (vec3(1.0) - f) * (1.0 - s.metallic) * s.albedo / PI;
+
+    vec3 radiance = light.color_intensity.rgb * light.color_intensity.w * attenuation;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
@@ -0,0 +1,21 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "forward_shading.glsl"
+
+// Weighted blended order independent transparency (McGuire and Bavoil 2013).
+// Premultiplied colours are summed with a depth based weight, the product of
+// (1 - alpha) is accumulated separately.
+layout(location = 0) out vec4 f_accumulation;
+layout(location = 1) out float f_revealage;
+
+void main() {
+    vec4 color = shade_forward();
+    // w: 1 when the base colour is already premultiplied by alpha
+    vec3 premultiplied = v_emissive.w > 0.0 ? color.rgb : color.rgb * color.a;
+
+    float depth = abs(dot(v_position - frame.camera_position.xyz, frame.camera_forward.xyz));
+    float weight = color.a * clamp(10.0 / (1e-5 + pow(depth / 5.0, 2.0) + pow(depth / 200.0, 6.0)), 1e-2, 3e3);
+    f_accumulation = vec4(premultiplied, color.a) * weight;
+    f_revealage = color.a;
+}
@@ -0,0 +1,20 @@
+#version 450
+
+// Resolves the weighted average of the transparent surfaces over the scene.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D accumulation_map;
+layout(set = 0, binding = 1) uniform sampler2D revealage_map;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float revealage = texelFetch(revealage_map, texel, 0).r;
+    // No transparent surface covers the pixel.
+    if (revealage >= 1.0) {
+        discard;
+    }
+    vec4 accumulation = texelFetch(accumulation_map, texel, 0);
+    vec3 average = accumulation.rgb / max(accumulation.a, 1e-5);
+    f_color = vec4(average, 1.0 - revealage);
+}
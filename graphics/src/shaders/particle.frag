@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec4 v_color;
+layout(location = 1) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+// Written for the attachment layout only, masked by the blend state
+layout(location = 1) out vec2 f_velocity;
+
+layout(set = 0, binding = 4) uniform sampler2D atlas;
+
+void main() {
+    f_color = v_color * texture(atlas, v_uv);
+    f_velocity = vec2(0.0);
+}
@@ -0,0 +1,72 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// Camera facing quad of a particle, instances are drawn in sorted order. Dead
+// particles are moved outside of the clip volume.
+#include "frame_data.glsl"
+#include "particles.glsl"
+
+layout(set = 0, binding = 1) readonly buffer Particles {
+    Particle particles[];
+};
+
+layout(set = 0, binding = 2) readonly buffer Keys {
+    uvec2 keys[];
+};
+
+layout(set = 0, binding = 3) uniform EmitterParams {
+    // Linear RGB colour with alpha over the normalised age
+    vec4 colors[CURVE_SAMPLES];
+    // Quad size over the normalised age, four samples per element
+    vec4 sizes[CURVE_SAMPLES / 4];
+    // x: atlas columns, y: atlas rows
+    uvec4 atlas;
+} emitter;
+
+layout(location = 0) out vec4 v_color;
+layout(location = 1) out vec2 v_uv;
+
+// Two triangles covering the quad
+const vec2 CORNERS[6] = vec2[](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0),
+    vec2(0.0, 1.0), vec2(1.0, 0.0), vec2(1.0, 1.0)
+);
+
+float sample_size(float t) {
+    float x = t * float(CURVE_SAMPLES - 1);
+    uint i = min(uint(x), CURVE_SAMPLES - 2);
+    float a = emitter.sizes[i / 4][i % 4];
+    float b = emitter.sizes[(i + 1) / 4][(i + 1) % 4];
+    return mix(a, b, x - float(i));
+}
+
+vec4 sample_color(float t) {
+    float x = t * float(CURVE_SAMPLES - 1);
+    uint i = min(uint(x), CURVE_SAMPLES - 2);
+    return mix(emitter.colors[i], emitter.colors[i + 1], x - float(i));
+}
+
+void main() {
+    Particle particle = particles[keys[gl_InstanceIndex].y];
+    if (!is_alive(particle)) {
+        gl_Position = vec4(0.0, 0.0, -2.0, 1.0);
+        return;
+    }
+
+    vec2 corner = CORNERS[gl_VertexIndex];
+    float t = clamp(particle.position.w / particle.velocity.w, 0.0, 1.0);
+
+    vec3 forward = normalize(particle.position.xyz - frame.camera_position.xyz);
+    vec3 helper = abs(forward.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 right = normalize(cross(forward, helper));
+    vec3 up = cross(right, forward);
+    vec2 offset = (corner - 0.5) * sample_size(t);
+    vec3 position = particle.position.xyz + right * offset.x + up * offset.y;
+    gl_Position = frame.view_projection * vec4(position, 1.0);
+
+    uint frames = emitter.atlas.x * emitter.atlas.y;
+    uint frame_index = min(uint(t * float(frames)), frames - 1);
+    vec2 cell = vec2(frame_index % emitter.atlas.x, frame_index / emitter.atlas.x);
+    v_uv = (cell + vec2(corner.x, 1.0 - corner.y)) / vec2(emitter.atlas.xy);
+    v_color = sample_color(t);
+}
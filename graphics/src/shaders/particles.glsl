@@ -0,0 +1,15 @@
+// Particle layout shared by the particle shaders.
+// Layout has to match `GpuParticle` in `particles_pass.rs`.
+
+const uint CURVE_SAMPLES = 32;
+
+struct Particle {
+    // xyz: world space position, w: age in seconds
+    vec4 position;
+    // xyz: velocity, w: lifetime in seconds, dead once the age exceeds it
+    vec4 velocity;
+};
+
+bool is_alive(Particle particle) {
+    return particle.position.w < particle.velocity.w;
+}
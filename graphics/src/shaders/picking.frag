@@ -0,0 +1,20 @@
+#version 450
+
+// Writes the encoded object ID of the surface, see `encode_object_id`.
+layout(location = 3) flat in vec4 v_base_color;
+layout(location = 4) flat in vec4 v_material;
+
+layout(location = 0) out uint f_object_id;
+
+layout(push_constant) uniform PushConstants {
+    // x: index into the draw data, y: encoded object ID
+    uvec4 draw;
+} pc;
+
+void main() {
+    // Alpha test, w: cutoff
+    if (v_base_color.a < v_material.w) {
+        discard;
+    }
+    f_object_id = pc.draw.y;
+}
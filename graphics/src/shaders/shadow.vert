@@ -0,0 +1,11 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model_view_projection;
+} pc;
+
+void main() {
+    gl_Position = pc.model_view_projection * vec4(position, 1.0);
+}
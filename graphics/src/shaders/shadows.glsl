@@ -0,0 +1,88 @@
+// Shadow atlas lookup. Requires `frame_data.glsl` and `lighting.glsl`.
+// View layout has to match `GpuShadowView` in `shadow.rs`.
+
+struct ShadowView {
+    mat4 view_projection;
+    // Tile offset and size in atlas UV space
+    vec4 atlas_rect;
+};
+
+layout(set = 0, binding = 4) uniform sampler2D shadow_atlas;
+
+layout(set = 0, binding = 5) readonly buffer ShadowViews {
+    ShadowView shadow_views[];
+};
+
+// Fraction of the PCF kernel not occluded in the given shadow view.
+float sample_shadow_view(uint index, vec3 position, float depth_bias) {
+    ShadowView view = shadow_views[index];
+    vec4 clip = view.view_projection * vec4(position, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    if (clip.w <= 0.0 || any(greaterThan(abs(ndc.xy), vec2(1.0))) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    float texel = frame.shadow_params.y;
+    vec2 uv = view.atlas_rect.xy + (ndc.xy * 0.5 + 0.5) * view.atlas_rect.zw;
+    // Keep the kernel inside of the tile so neighbouring maps don't bleed in.
+    vec2 tile_min = view.atlas_rect.xy + vec2(texel * 0.5);
+    vec2 tile_max = view.atlas_rect.xy + view.atlas_rect.zw - vec2(texel * 0.5);
+
+    int radius = int(frame.shadow_params.x);
+    float lit = 0.0;
+    for (int y = -radius; y <= radius; ++y) {
+        for (int x = -radius; x <= radius; ++x) {
+            vec2 sample_uv = clamp(uv + vec2(x, y) * texel, tile_min, tile_max);
+            float occluder = texture(shadow_atlas, sample_uv).r;
+            lit += ndc.z - depth_bias <= occluder ? 1.0 : 0.0;
+        }
+    }
+    float kernel = float(2 * radius + 1);
+    return lit / (kernel * kernel);
+}
+
+// Shadow factor of the light at the surface, 1 when fully lit.
+float light_shadow(Light light, SurfaceData s) {
+    int first = int(light.shadow_params.x);
+    if (first < 0) {
+        return 1.0;
+    }
+
+    uint type = uint(light.direction_type.w);
+    uint view = uint(first);
+    if (type == LIGHT_DIRECTIONAL) {
+        // Cascades are selected by view depth, past the last one there is no shadow.
+        float depth = dot(s.position - frame.camera_position.xyz, frame.camera_forward.xyz);
+        uint count = uint(light.shadow_params.w);
+        uint cascade = 0;
+        while (cascade < count && depth > frame.cascade_splits[cascade]) {
+            ++cascade;
+        }
+        if (cascade >= count) {
+            return 1.0;
+        }
+        view += cascade;
+    } else if (type == LIGHT_POINT) {
+        // Faces are stored in +X, -X, +Y, -Y, +Z, -Z order.
+        vec3 d = s.position - light.position_range.xyz;
+        vec3 a = abs(d);
+        if (a.x >= a.y && a.x >= a.z) {
+            view += d.x > 0.0 ? 0 : 1;
+        } else if (a.y >= a.z) {
+            view += d.y > 0.0 ? 2 : 3;
+        } else {
+            view += d.z > 0.0 ? 4 : 5;
+        }
+    }
+
+    vec3 biased = s.position + s.normal * light.shadow_params.z;
+    return sample_shadow_view(view, biased, light.shadow_params.y);
+}
+
+vec3 evaluate_shadowed_light(Light light, SurfaceData s) {
+    vec3 color = evaluate_light(light, s);
+    if (color == vec3(0.0)) {
+        return color;
+    }
+    return color * light_shadow(light, s);
+}
@@ -0,0 +1,61 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "draw_data.glsl"
+
+#define SKINNING_SET 1
+#define SKINNING_BINDING 0
+#include "skinning.glsl"
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in uvec4 joint_indices;
+layout(location = 4) in vec4 joint_weights;
+
+layout(push_constant) uniform PushConstants {
+    // x: index into `draws`, y: palette offset of the current pose, z: joint
+    // count, w: skinning method
+    uvec4 draw;
+} pc;
+
+// Same outputs as forward.vert, shaded by forward.frag.
+layout(location = 0) out vec3 v_position;
+layout(location = 1) out vec3 v_normal;
+layout(location = 2) out vec2 v_uv;
+layout(location = 3) flat out vec4 v_base_color;
+layout(location = 4) flat out vec4 v_material;
+layout(location = 5) flat out vec4 v_emissive;
+layout(location = 6) out vec4 v_clip_position;
+layout(location = 7) out vec4 v_previous_clip_position;
+
+void main() {
+    DrawData data = draws[pc.draw.x];
+    vec3 skinned_position = position;
+    vec3 skinned_normal = normal;
+    skin(pc.draw.y, pc.draw.w, joint_indices, joint_weights, skinned_position, skinned_normal);
+    vec3 previous_position = position;
+    vec3 previous_normal = normal;
+    skin(
+        pc.draw.y + pc.draw.z,
+        pc.draw.w,
+        joint_indices,
+        joint_weights,
+        previous_position,
+        previous_normal
+    );
+
+    vec4 world_position = data.model * vec4(skinned_position, 1.0);
+    v_position = world_position.xyz;
+    v_normal = mat3(data.model) * skinned_normal;
+    v_uv = uv;
+    v_base_color = data.base_color;
+    v_material = data.material;
+    v_emissive = data.emissive;
+
+    v_clip_position = frame.unjittered_view_projection * world_position;
+    v_previous_clip_position =
+        frame.previous_view_projection * data.previous_model * vec4(previous_position, 1.0);
+    gl_Position = frame.view_projection * world_position;
+}
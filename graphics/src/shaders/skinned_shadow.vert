@@ -0,0 +1,24 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#define SKINNING_SET 0
+#define SKINNING_BINDING 0
+#include "skinning.glsl"
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 3) in uvec4 joint_indices;
+layout(location = 4) in vec4 joint_weights;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model_view_projection;
+    // x: palette offset of the current pose, y: skinning method
+    uvec4 skin;
+} pc;
+
+void main() {
+    vec3 skinned_position = position;
+    vec3 skinned_normal = normal;
+    skin(pc.skin.x, pc.skin.y, joint_indices, joint_weights, skinned_position, skinned_normal);
+    gl_Position = pc.model_view_projection * vec4(skinned_position, 1.0);
+}
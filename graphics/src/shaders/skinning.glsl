@@ -0,0 +1,64 @@
+// Skinning by the joint palette of the drawn object. The including shader
+// defines `SKINNING_SET` and `SKINNING_BINDING` of the palette buffer.
+
+// Has to match `SkinningMethod::gpu_index` in `skinning.rs`.
+const uint SKINNING_LINEAR_BLEND = 0;
+const uint SKINNING_DUAL_QUATERNION = 1;
+
+// Layout has to match `GpuJoint` in `skinning.rs`.
+struct Joint {
+    mat4 matrix;
+    // Unit dual quaternion of the same transform, xyz is the vector part
+    vec4 real;
+    vec4 dual;
+};
+
+// Palettes of all skinned objects of the frame, the previous pose of an object
+// follows its current one.
+layout(set = SKINNING_SET, binding = SKINNING_BINDING) readonly buffer Joints {
+    Joint joints[];
+};
+
+vec3 rotate(vec4 q, vec3 v) {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+// Moves the model space position and normal by the joints starting at
+// `palette_offset`.
+void skin(
+    uint palette_offset,
+    uint method,
+    uvec4 indices,
+    vec4 weights,
+    inout vec3 position,
+    inout vec3 normal
+) {
+    if (method == SKINNING_DUAL_QUATERNION) {
+        vec4 first = joints[palette_offset + indices.x].real;
+        vec4 real = vec4(0.0);
+        vec4 dual = vec4(0.0);
+        for (int i = 0; i < 4; i++) {
+            Joint joint = joints[palette_offset + indices[i]];
+            // q and -q are the same rotation, blending has to stay in one hemisphere
+            float weight = dot(joint.real, first) < 0.0 ? -weights[i] : weights[i];
+            real += weight * joint.real;
+            dual += weight * joint.dual;
+        }
+        float norm = length(real);
+        real /= norm;
+        dual /= norm;
+        vec3 translation =
+            2.0 * (real.w * dual.xyz - dual.w * real.xyz + cross(real.xyz, dual.xyz));
+        position = rotate(real, position) + translation;
+        normal = rotate(real, normal);
+        return;
+    }
+
+    mat4 transform = weights.x * joints[palette_offset + indices.x].matrix
+        + weights.y * joints[palette_offset + indices.y].matrix
+        + weights.z * joints[palette_offset + indices.z].matrix
+        + weights.w * joints[palette_offset + indices.w].matrix;
+    position = (transform * vec4(position, 1.0)).xyz;
+    // Joints are rigid, scaling only comes from the blending
+    normal = mat3(transform) * normal;
+}
@@ -0,0 +1,22 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+
+layout(location = 0) in vec2 v_ndc;
+
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec2 f_velocity;
+
+layout(set = 0, binding = 1) uniform samplerCube environment;
+
+void main() {
+    vec4 far_point = frame.inverse_view_projection * vec4(v_ndc, 1.0, 1.0);
+    vec3 direction = normalize(far_point.xyz / far_point.w - frame.camera_position.xyz);
+    f_color = vec4(textureLod(environment, direction, 0.0).rgb * frame.environment_params.x, 1.0);
+
+    // The sky is infinitely far away, only camera rotation moves it.
+    vec4 current = frame.unjittered_view_projection * vec4(direction, 0.0);
+    vec4 previous = frame.previous_view_projection * vec4(direction, 0.0);
+    f_velocity = (current.xy / current.w - previous.xy / previous.w) * 0.5;
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// Fills the viewport with a single colour.
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform PushConstants {
+    vec4 color;
+} pc;
+
+void main() {
+    f_color = pc.color;
+}
@@ -0,0 +1,18 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "color.glsl"
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_color;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D atlas;
+
+void main() {
+    // 2D windows skip the post-process stack, so the sRGB encoding happens here
+    // and sprites blend like in image editors.
+    vec4 color = v_color * texture(atlas, v_uv);
+    f_color = vec4(linear_to_srgb(color.rgb), color.a);
+}
@@ -0,0 +1,85 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "ssao_common.glsl"
+
+// Hemisphere sampling around the surface normal, the kernel is rotated per pixel
+// and the noise is removed by the blur pass.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out float f_occlusion;
+
+layout(set = 1, binding = 0) uniform sampler2D depth_map;
+// Only read when `pc.info.y` is set, normals are reconstructed from depth otherwise
+layout(set = 1, binding = 1) uniform sampler2D normal_map;
+// Layout has to match `ssao_kernel` uploaded in `ssao_pass.rs`.
+layout(set = 1, binding = 2) uniform Kernel {
+    // xyz: tangent space offset within the unit hemisphere
+    vec4 samples[64];
+} kernel;
+
+layout(push_constant) uniform PushConstants {
+    // x: radius, y: intensity, z: bias
+    vec4 params;
+    // x: sample count, y: normals come from `normal_map`
+    uvec4 info;
+} pc;
+
+vec3 position_at(vec2 uv) {
+    return world_position(uv, textureLod(depth_map, uv, 0.0).r);
+}
+
+// Uses the neighbours with the smaller depth difference, so edges don't bend the
+// normal.
+vec3 reconstruct_normal(vec2 uv, vec3 position) {
+    vec2 texel = 1.0 / vec2(textureSize(depth_map, 0));
+    vec3 right = position_at(uv + vec2(texel.x, 0.0)) - position;
+    vec3 left = position - position_at(uv - vec2(texel.x, 0.0));
+    vec3 down = position_at(uv + vec2(0.0, texel.y)) - position;
+    vec3 up = position - position_at(uv - vec2(0.0, texel.y));
+    vec3 dx = length(right) < length(left) ? right : left;
+    vec3 dy = length(down) < length(up) ? down : up;
+    vec3 normal = normalize(cross(dx, dy));
+    if (dot(normal, frame.camera_position.xyz - position) < 0.0) {
+        normal = -normal;
+    }
+    return normal;
+}
+
+void main() {
+    float depth = textureLod(depth_map, v_uv, 0.0).r;
+    if (depth >= 1.0) {
+        f_occlusion = 1.0;
+        return;
+    }
+    vec3 position = world_position(v_uv, depth);
+    vec3 normal = pc.info.y != 0 ? normalize(textureLod(normal_map, v_uv, 0.0).xyz)
+                                 : reconstruct_normal(v_uv, position);
+
+    // Interleaved gradient noise rotates the kernel around the normal.
+    float angle = 6.2831853 * fract(52.9829189 * fract(dot(gl_FragCoord.xy, vec2(0.06711056, 0.00583715))));
+    vec3 axis = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(axis, normal));
+    tangent = cos(angle) * tangent + sin(angle) * cross(normal, tangent);
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float radius = pc.params.x;
+    float center_depth = view_depth(position);
+    float occlusion = 0.0;
+    for (uint i = 0; i < pc.info.x; i++) {
+        vec3 sample_position = position + tbn * kernel.samples[i].xyz * radius;
+        vec4 clip = frame.view_projection * vec4(sample_position, 1.0);
+        vec2 uv = clip.xy / clip.w * 0.5 + 0.5;
+        if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+            continue;
+        }
+        float scene_depth = view_depth(position_at(uv));
+        // Geometry far in front of the surface doesn't occlude it.
+        float range = smoothstep(0.0, 1.0, radius / max(abs(center_depth - scene_depth), 1e-4));
+        if (scene_depth <= view_depth(sample_position) - pc.params.z) {
+            occlusion += range;
+        }
+    }
+    occlusion /= float(max(pc.info.x, 1u));
+    f_occlusion = clamp(1.0 - occlusion * pc.params.y, 0.0, 1.0);
+}
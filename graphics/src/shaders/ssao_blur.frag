@@ -0,0 +1,39 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "ssao_common.glsl"
+
+// Bilateral blur of the raw occlusion, texels at a different depth are ignored
+// so occlusion doesn't bleed over edges.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out float f_occlusion;
+
+layout(set = 1, binding = 0) uniform sampler2D occlusion_map;
+layout(set = 1, binding = 1) uniform sampler2D depth_map;
+
+const int BLUR_RADIUS = 2;
+
+float depth_at(ivec2 texel) {
+    vec2 uv = (vec2(texel) + 0.5) / vec2(textureSize(depth_map, 0));
+    return view_depth(world_position(uv, texelFetch(depth_map, texel, 0).r));
+}
+
+void main() {
+    ivec2 center = ivec2(gl_FragCoord.xy);
+    ivec2 last = textureSize(occlusion_map, 0) - 1;
+    float center_depth = depth_at(center);
+
+    float total = 0.0;
+    float weights = 0.0;
+    for (int y = -BLUR_RADIUS; y <= BLUR_RADIUS; y++) {
+        for (int x = -BLUR_RADIUS; x <= BLUR_RADIUS; x++) {
+            ivec2 texel = clamp(center + ivec2(x, y), ivec2(0), last);
+            float difference = abs(depth_at(texel) - center_depth);
+            float weight = 1.0 / (1e-3 + difference / max(abs(center_depth) * 0.05, 1e-3));
+            total += texelFetch(occlusion_map, texel, 0).r * weight;
+            weights += weight;
+        }
+    }
+    f_occlusion = total / weights;
+}
@@ -0,0 +1,11 @@
+// Depth buffer helpers of the ambient occlusion passes. Requires frame_data.glsl.
+
+vec3 world_position(vec2 uv, float depth) {
+    vec4 world = frame.inverse_view_projection * vec4(uv * 2.0 - 1.0, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Distance from the camera plane, linear unlike the depth buffer.
+float view_depth(vec3 position) {
+    return dot(position - frame.camera_position.xyz, frame.camera_forward.xyz);
+}
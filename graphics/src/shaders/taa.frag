@@ -0,0 +1,69 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "color.glsl"
+
+// Blends the jittered frame with the reprojected history. History is clamped to
+// the colour range of the current neighbourhood to reject stale samples.
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D current;
+layout(set = 0, binding = 1) uniform sampler2D history;
+layout(set = 0, binding = 2) uniform sampler2D velocity;
+
+layout(push_constant) uniform PushConstants {
+    // x: history feedback, y: history valid when positive
+    vec4 params;
+} pc;
+
+vec3 rgb_to_ycocg(vec3 c) {
+    return vec3(
+        0.25 * c.r + 0.5 * c.g + 0.25 * c.b,
+        0.5 * c.r - 0.5 * c.b,
+        -0.25 * c.r + 0.5 * c.g - 0.25 * c.b);
+}
+
+vec3 ycocg_to_rgb(vec3 c) {
+    return vec3(c.x + c.y - c.z, c.x + c.z, c.x - c.y - c.z);
+}
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(current, 0));
+    vec3 color = texture(current, v_uv).rgb;
+
+    // Motion of the closest surface in the neighbourhood keeps edges of moving
+    // objects reprojected with the object.
+    vec3 neighbourhood_min = rgb_to_ycocg(color);
+    vec3 neighbourhood_max = neighbourhood_min;
+    vec2 motion = texture(velocity, v_uv).xy;
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            vec2 offset = vec2(x, y) * texel;
+            vec3 sample_color = rgb_to_ycocg(texture(current, v_uv + offset).rgb);
+            neighbourhood_min = min(neighbourhood_min, sample_color);
+            neighbourhood_max = max(neighbourhood_max, sample_color);
+            vec2 sample_motion = texture(velocity, v_uv + offset).xy;
+            if (dot(sample_motion, sample_motion) > dot(motion, motion)) {
+                motion = sample_motion;
+            }
+        }
+    }
+
+    vec2 history_uv = v_uv - motion;
+    bool outside = any(lessThan(history_uv, vec2(0.0))) || any(greaterThan(history_uv, vec2(1.0)));
+    if (pc.params.y <= 0.0 || outside) {
+        f_color = vec4(color, 1.0);
+        return;
+    }
+
+    vec3 previous = rgb_to_ycocg(texture(history, history_uv).rgb);
+    previous = ycocg_to_rgb(clamp(previous, neighbourhood_min, neighbourhood_max));
+
+    // Luminance weighting keeps bright samples from dominating the blend.
+    float current_weight = (1.0 - pc.params.x) / (1.0 + luminance(color));
+    float history_weight = pc.params.x / (1.0 + luminance(previous));
+    vec3 resolved = (color * current_weight + previous * history_weight)
+        / (current_weight + history_weight);
+    f_color = vec4(resolved, 1.0);
+}
@@ -0,0 +1,28 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "forward_shading.glsl"
+#include "velocity.glsl"
+#include "terrain.glsl"
+
+layout(set = 1, binding = 1) uniform sampler2D splat_map;
+layout(set = 1, binding = 2) uniform sampler2DArray layers;
+
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec2 f_velocity;
+
+void main() {
+    vec4 weights = texture(splat_map, v_uv);
+    vec2 position = v_position.xz - pc.origin.xz;
+    vec4 albedo = vec4(0.0);
+    float total = 0.0;
+    for (uint i = 0; i < min(pc.draw.y, MAX_TERRAIN_LAYERS); i++) {
+        albedo += weights[i] * texture(layers, vec3(position / pc.tiling[i], float(i)));
+        total += weights[i];
+    }
+    // Unpainted areas and terrains without layers show the material colour
+    vec4 base_color = v_base_color * (total > 0.0 ? albedo / total : vec4(1.0));
+    f_color = shade_surface(base_color, 1.0);
+
+    f_velocity = motion_vector(v_clip_position, v_previous_clip_position);
+}
@@ -0,0 +1,17 @@
+// Terrain parameters shared by terrain.vert and terrain.frag.
+
+const uint MAX_TERRAIN_LAYERS = 4;
+
+layout(push_constant) uniform PushConstants {
+    // xyz: world position of the first sample, w: distance between samples
+    vec4 origin;
+    // xy: first sample of the patch, z: samples between vertices
+    vec4 patch_params;
+    // x: distance the vertices start morphing into the next level at, y: where
+    // they match it
+    vec4 morph;
+    // World units covered by one repetition of every layer
+    vec4 tiling;
+    // x: index into `draws`, y: layer count
+    uvec4 draw;
+} pc;
@@ -0,0 +1,78 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "draw_data.glsl"
+#include "terrain.glsl"
+
+layout(location = 0) in vec2 grid;
+
+layout(set = 1, binding = 0) uniform sampler2D heightmap;
+
+// Outputs of forward.vert, `v_uv` spans the whole terrain. Shaded by
+// terrain.frag.
+layout(location = 0) out vec3 v_position;
+layout(location = 1) out vec3 v_normal;
+layout(location = 2) out vec2 v_uv;
+layout(location = 3) flat out vec4 v_base_color;
+layout(location = 4) flat out vec4 v_material;
+layout(location = 5) flat out vec4 v_emissive;
+layout(location = 6) out vec4 v_clip_position;
+layout(location = 7) out vec4 v_previous_clip_position;
+
+float sample_height(ivec2 sample_index) {
+    ivec2 last = textureSize(heightmap, 0) - 1;
+    return texelFetch(heightmap, clamp(sample_index, ivec2(0), last), 0).r;
+}
+
+// Bilinear height between samples, morphing vertices move between them.
+float height_at(vec2 position) {
+    ivec2 cell = ivec2(floor(position));
+    vec2 f = position - vec2(cell);
+    float h0 = mix(sample_height(cell), sample_height(cell + ivec2(1, 0)), f.x);
+    float h1 = mix(sample_height(cell + ivec2(0, 1)), sample_height(cell + ivec2(1, 1)), f.x);
+    return mix(h0, h1, f.y);
+}
+
+// Central differences, has to match `Terrain::sample_normal`.
+vec3 normal_at(vec2 position) {
+    return normalize(vec3(
+        height_at(position - vec2(1.0, 0.0)) - height_at(position + vec2(1.0, 0.0)),
+        2.0 * pc.origin.w,
+        height_at(position - vec2(0.0, 1.0)) - height_at(position + vec2(0.0, 1.0))
+    ));
+}
+
+vec3 world_position(vec2 position) {
+    return pc.origin.xyz + vec3(position.x, 0.0, position.y) * pc.origin.w
+        + vec3(0.0, height_at(position), 0.0);
+}
+
+void main() {
+    DrawData data = draws[pc.draw.x];
+    vec2 last = vec2(textureSize(heightmap, 0) - 1);
+    float step_size = pc.patch_params.z;
+    // Patches at the edge extend past the heightmap, their vertices collapse
+    // onto it
+    vec2 position = min(pc.patch_params.xy + grid * step_size, last);
+
+    // Odd vertices slide onto their even neighbours and match the grid of the
+    // next level at the end of the range, so coarser neighbours leave no cracks
+    float distance_to_camera = distance(frame.camera_position.xyz, world_position(position));
+    float morph = clamp((distance_to_camera - pc.morph.x) / (pc.morph.y - pc.morph.x), 0.0, 1.0);
+    vec2 morphed_grid = grid - fract(grid * 0.5) * 2.0 * morph;
+    position = min(pc.patch_params.xy + morphed_grid * step_size, last);
+
+    vec4 world = data.model * vec4(world_position(position), 1.0);
+    v_position = world.xyz;
+    v_normal = normal_at(position);
+    v_uv = position / last;
+    v_base_color = data.base_color;
+    v_material = data.material;
+    v_emissive = data.emissive;
+
+    // Terrains don't move, only the camera does
+    v_clip_position = frame.unjittered_view_projection * world;
+    v_previous_clip_position = frame.previous_view_projection * world;
+    gl_Position = frame.view_projection * world;
+}
@@ -0,0 +1,49 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "color.glsl"
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_color;
+layout(location = 2) in vec4 v_outline_color;
+// x: outline width, y: edge softness, both in em
+layout(location = 3) in vec2 v_effect;
+layout(location = 4) flat in vec2 v_font;
+
+layout(location = 0) out vec4 f_color;
+
+// Multi-channel distance in RGB, true distance in alpha
+layout(set = 0, binding = 0) uniform sampler2D atlas;
+
+float median(float r, float g, float b) {
+    return max(min(r, g), min(max(r, g), b));
+}
+
+void main() {
+    vec4 field = texture(atlas, v_uv);
+    float distance_range = v_font.x;
+    float em_size = v_font.y;
+    // Edges stay one pixel wide at any size and angle
+    vec2 texels_per_pixel = fwidth(v_uv) * vec2(textureSize(atlas, 0));
+    float pixels_per_em = em_size / max(0.5 * (texels_per_pixel.x + texels_per_pixel.y), 1e-4);
+
+    // Signed distances in em, positive inside of the glyph
+    float to_em = distance_range / em_size;
+    float distance = (median(field.r, field.g, field.b) - 0.5) * to_em;
+    float fill = clamp(distance * pixels_per_em + 0.5, 0.0, 1.0);
+    float outline = v_effect.x;
+    float softness = v_effect.y;
+    float coverage;
+    if (softness > 0.0) {
+        // The true distance stays smooth away from the outline, unlike the median
+        float true_distance = (field.a - 0.5) * to_em;
+        coverage = smoothstep(-softness, softness, true_distance + outline);
+    } else {
+        coverage = clamp((distance + outline) * pixels_per_em + 0.5, 0.0, 1.0);
+    }
+
+    // Text is drawn after the post-process stack, so the sRGB encoding happens
+    // here like for sprites.
+    vec4 color = mix(v_outline_color, v_color, fill);
+    f_color = vec4(linear_to_srgb(color.rgb), color.a * coverage);
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
+layout(location = 3) in vec4 outline_color;
+layout(location = 4) in vec2 effect;
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec4 v_color;
+layout(location = 2) out vec4 v_outline_color;
+layout(location = 3) out vec2 v_effect;
+layout(location = 4) flat out vec2 v_font;
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_projection;
+    // x: distance range in atlas texels, y: atlas texels per em
+    vec2 font;
+} pc;
+
+void main() {
+    v_uv = uv;
+    v_color = color;
+    v_outline_color = outline_color;
+    v_effect = effect;
+    v_font = pc.font;
+    gl_Position = pc.view_projection * vec4(position, 1.0);
+}
@@ -0,0 +1,6 @@
+// Screen space motion since the previous frame, in UV units.
+vec2 motion_vector(vec4 clip_position, vec4 previous_clip_position) {
+    vec2 current = clip_position.xy / clip_position.w;
+    vec2 previous = previous_clip_position.xy / previous_clip_position.w;
+    return (current - previous) * 0.5;
+}
@@ -0,0 +1,20 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "forward_shading.glsl"
+#include "velocity.glsl"
+
+layout(location = 8) flat in uint v_layer;
+layout(location = 9) in float v_occlusion;
+
+layout(set = 1, binding = 0) uniform sampler2DArray blocks;
+
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec2 f_velocity;
+
+void main() {
+    vec4 base_color = v_base_color * texture(blocks, vec3(v_uv, float(v_layer)));
+    f_color = shade_surface(base_color, v_occlusion);
+
+    f_velocity = motion_vector(v_clip_position, v_previous_clip_position);
+}
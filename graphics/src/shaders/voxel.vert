@@ -0,0 +1,48 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "frame_data.glsl"
+#include "draw_data.glsl"
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in uint layer;
+layout(location = 4) in float occlusion;
+
+layout(push_constant) uniform PushConstants {
+    // x: index into `draws`
+    uvec4 draw;
+} pc;
+
+// Outputs of forward.vert with the block texture layer and vertex occlusion,
+// shaded by voxel.frag.
+layout(location = 0) out vec3 v_position;
+layout(location = 1) out vec3 v_normal;
+layout(location = 2) out vec2 v_uv;
+layout(location = 3) flat out vec4 v_base_color;
+layout(location = 4) flat out vec4 v_material;
+layout(location = 5) flat out vec4 v_emissive;
+layout(location = 6) out vec4 v_clip_position;
+layout(location = 7) out vec4 v_previous_clip_position;
+layout(location = 8) flat out uint v_layer;
+layout(location = 9) out float v_occlusion;
+
+void main() {
+    DrawData data = draws[pc.draw.x];
+    vec4 world_position = data.model * vec4(position, 1.0);
+    v_position = world_position.xyz;
+    // Chunks are only translated
+    v_normal = normal;
+    v_uv = uv;
+    v_base_color = data.base_color;
+    v_material = data.material;
+    v_emissive = data.emissive;
+    v_layer = layer;
+    v_occlusion = occlusion;
+
+    v_clip_position = frame.unjittered_view_projection * world_position;
+    v_previous_clip_position =
+        frame.previous_view_projection * data.previous_model * vec4(position, 1.0);
+    gl_Position = frame.view_projection * world_position;
+}
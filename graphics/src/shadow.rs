@@ -0,0 +1,482 @@
+use crate::{
+    bounds::BoundingSphere,
+    camera::{orthographic_matrix, perspective_matrix, Camera},
+    frustum::Frustum,
+    light::{Light, LightKind},
+};
+use polyengine_core::*;
+
+pub const MAX_CASCADES: usize = 4;
+
+// Distance behind a cascade (towards the light) still rendered into it, so
+// casters outside of the view still throw shadows into it.
+const CASCADE_CASTER_MARGIN: FScalar = 50.0;
+// Near plane of spot and point light shadow projections.
+const LOCAL_LIGHT_NEAR: FScalar = 0.05;
+
+// Global shadow configuration, shared by all windows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowSettings {
+    // Size of the square shadow atlas holding all shadow maps
+    pub atlas_size: u32,
+    // Number of directional light cascades, up to `MAX_CASCADES`
+    pub cascade_count: usize,
+    // Blend between uniform (0) and logarithmic (1) cascade splits
+    pub cascade_split_lambda: FScalar,
+    // Distance from the camera covered by directional light cascades
+    pub shadow_distance: FScalar,
+    // Snaps cascades to shadow map texels to prevent shimmering edges when the
+    // camera moves, at the cost of some resolution
+    pub stabilize_cascades: bool,
+    pub cascade_resolution: u32,
+    pub spot_resolution: u32,
+    pub point_resolution: u32,
+    // Radius of the PCF kernel in texels, 0 disables filtering
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        return ShadowSettings {
+            atlas_size: 4096,
+            cascade_count: 4,
+            cascade_split_lambda: 0.75,
+            shadow_distance: 100.0,
+            stabilize_cascades: true,
+            cascade_resolution: 1024,
+            spot_resolution: 512,
+            point_resolution: 256,
+            pcf_radius: 1,
+        };
+    }
+}
+
+// Per light shadow parameters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LightShadow {
+    // Offset subtracted from the compared depth, in shadow map depth units
+    pub depth_bias: FScalar,
+    // Offset of the shaded point along its normal, in world units
+    pub normal_bias: FScalar,
+}
+
+impl Default for LightShadow {
+    fn default() -> Self {
+        return LightShadow {
+            depth_bias: 0.0005,
+            normal_bias: 0.02,
+        };
+    }
+}
+
+// Single shadow map rendered into the atlas.
+#[derive(Debug, Copy, Clone)]
+pub struct ShadowView {
+    pub view_projection: Matrix4f,
+    // Top left corner of the tile in the atlas, in texels
+    pub origin: [u32; 2],
+    pub size: u32,
+}
+
+// Matches `ShadowView` in shadows.glsl.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct GpuShadowView {
+    pub view_projection: [[f32; 4]; 4],
+    // Tile offset and size in atlas UV space
+    pub atlas_rect: [f32; 4],
+}
+
+impl GpuShadowView {
+    pub fn new(view: &ShadowView, atlas_size: u32) -> Self {
+        let scale = 1.0 / atlas_size as f32;
+        return GpuShadowView {
+            view_projection: view.view_projection.into(),
+            atlas_rect: [
+                view.origin[0] as f32 * scale,
+                view.origin[1] as f32 * scale,
+                view.size as f32 * scale,
+                view.size as f32 * scale,
+            ],
+        };
+    }
+}
+
+// Shadow views of a frame.
+pub struct ShadowPlan {
+    pub views: Vec<ShadowView>,
+    // `GpuLight::shadow_params` for every light, in order of the input list
+    pub light_params: Vec<[f32; 4]>,
+    // Far distance of every cascade, unused entries are infinite
+    pub cascade_splits: [f32; MAX_CASCADES],
+}
+
+// Far distances of the cascades using the practical split scheme.
+pub fn cascade_splits(
+    z_near: FScalar,
+    distance: FScalar,
+    count: usize,
+    lambda: FScalar,
+) -> Vec<FScalar> {
+    return (1..=count)
+        .map(|i| {
+            let p = i as FScalar / count as FScalar;
+            let logarithmic = z_near * (distance / z_near).powf(p);
+            let uniform = z_near + (distance - z_near) * p;
+            return lambda * logarithmic + (1.0 - lambda) * uniform;
+        })
+        .collect();
+}
+
+// Packs square tiles into the atlas row by row. Tiles are expected to be
+// allocated from the largest to the smallest.
+struct AtlasAllocator {
+    size: u32,
+    x: u32,
+    y: u32,
+    row_height: u32,
+}
+
+impl AtlasAllocator {
+    fn new(size: u32) -> Self {
+        return AtlasAllocator {
+            size,
+            x: 0,
+            y: 0,
+            row_height: 0,
+        };
+    }
+
+    fn allocate(&mut self, tile: u32) -> Option<[u32; 2]> {
+        if self.x + tile > self.size {
+            self.x = 0;
+            self.y += self.row_height;
+            self.row_height = 0;
+        }
+        if self.x + tile > self.size || self.y + tile > self.size {
+            return None;
+        }
+
+        let origin = [self.x, self.y];
+        self.x += tile;
+        self.row_height = self.row_height.max(tile);
+        return Some(origin);
+    }
+}
+
+fn up_vector(direction: &Vector3f) -> Vector3f {
+    if direction.y.abs() > 0.99 {
+        return Vector3f::z();
+    }
+    return Vector3f::y();
+}
+
+// Orthographic view covering the camera frustum slice between `near` and `far`.
+fn cascade_view_projection(
+    direction: &Vector3f,
+    camera: &Camera,
+    aspect: FScalar,
+    near: FScalar,
+    far: FScalar,
+    resolution: u32,
+    stabilize: bool,
+) -> Matrix4f {
+    let tan_y = (camera.fov_y * 0.5).tan();
+    let tan_x = tan_y * aspect;
+    let mut corners = Vec::with_capacity(8);
+    for depth in &[near, far] {
+        for sx in &[-1.0, 1.0] {
+            for sy in &[-1.0, 1.0] {
+                let local = na::Point3::new(sx * tan_x * depth, sy * tan_y * depth, -depth);
+                corners.push((camera.transform * local).coords);
+            }
+        }
+    }
+
+    // Light space rotation doesn't depend on the camera, so texel snapping in it
+    // stays consistent between frames.
+    let light_view = Isometry3::look_at_rh(
+        &na::Point3::origin(),
+        &na::Point3::from(*direction),
+        &up_vector(direction),
+    );
+    let light_corners: Vec<Vector3f> = corners
+        .iter()
+        .map(|c| (light_view * na::Point3::from(*c)).coords)
+        .collect();
+
+    let (min_x, max_x, min_y, max_y);
+    if stabilize {
+        // Bounding sphere keeps the projection size constant when the camera rotates.
+        let center = corners.iter().fold(Vector3f::zeros(), |acc, c| acc + c) / 8.0;
+        let radius = corners
+            .iter()
+            .map(|c| (c - center).norm())
+            .fold(0.0, FScalar::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+        let texel = 2.0 * radius / resolution as FScalar;
+        let light_center = (light_view * na::Point3::from(center)).coords;
+        let cx = (light_center.x / texel).floor() * texel;
+        let cy = (light_center.y / texel).floor() * texel;
+        min_x = cx - radius;
+        max_x = cx + radius;
+        min_y = cy - radius;
+        max_y = cy + radius;
+    } else {
+        min_x = light_corners
+            .iter()
+            .map(|c| c.x)
+            .fold(FScalar::INFINITY, FScalar::min);
+        max_x = light_corners
+            .iter()
+            .map(|c| c.x)
+            .fold(FScalar::NEG_INFINITY, FScalar::max);
+        min_y = light_corners
+            .iter()
+            .map(|c| c.y)
+            .fold(FScalar::INFINITY, FScalar::min);
+        max_y = light_corners
+            .iter()
+            .map(|c| c.y)
+            .fold(FScalar::NEG_INFINITY, FScalar::max);
+    }
+
+    // Light looks down -Z, distances along the view direction are -z.
+    let z_near = light_corners
+        .iter()
+        .map(|c| -c.z)
+        .fold(FScalar::INFINITY, FScalar::min)
+        - CASCADE_CASTER_MARGIN;
+    let z_far = light_corners
+        .iter()
+        .map(|c| -c.z)
+        .fold(FScalar::NEG_INFINITY, FScalar::max);
+
+    let projection = orthographic_matrix(min_x, max_x, min_y, max_y, z_near, z_far);
+    return projection * light_view.to_homogeneous();
+}
+
+fn local_view_projection(
+    position: &Vector3f,
+    direction: &Vector3f,
+    fov: FScalar,
+    range: FScalar,
+) -> Matrix4f {
+    let eye = na::Point3::from(*position);
+    let view = Isometry3::look_at_rh(&eye, &(eye + direction), &up_vector(direction));
+    let projection = perspective_matrix(fov, 1.0, LOCAL_LIGHT_NEAR, range);
+    return projection * view.to_homogeneous();
+}
+
+// Point light faces in the +X, -X, +Y, -Y, +Z, -Z order expected by the shader.
+fn point_view_projections(light: &Light) -> Vec<Matrix4f> {
+    let directions = [
+        Vector3f::x(),
+        -Vector3f::x(),
+        Vector3f::y(),
+        -Vector3f::y(),
+        Vector3f::z(),
+        -Vector3f::z(),
+    ];
+    return directions
+        .iter()
+        .map(|d| {
+            local_view_projection(&light.position, d, std::f32::consts::FRAC_PI_2, light.range)
+        })
+        .collect();
+}
+
+// Computes shadow views for all shadow casting lights and packs them into the
+// atlas. Lights that don't fit or are outside of the view cast no shadows.
+pub fn plan_shadows(
+    lights: &[Light],
+    camera: &Camera,
+    aspect: FScalar,
+    settings: &ShadowSettings,
+) -> ShadowPlan {
+    let frustum: Frustum = camera.frustum(aspect);
+    let cascade_count = settings.cascade_count.min(MAX_CASCADES).max(1);
+    let distance = settings.shadow_distance.min(camera.z_far);
+    let splits = cascade_splits(
+        camera.z_near,
+        distance,
+        cascade_count,
+        settings.cascade_split_lambda,
+    );
+
+    let mut cascade_splits = [FScalar::INFINITY; MAX_CASCADES];
+    cascade_splits[..cascade_count].copy_from_slice(&splits);
+
+    let mut plan = ShadowPlan {
+        views: Vec::new(),
+        light_params: vec![[-1.0, 0.0, 0.0, 0.0]; lights.len()],
+        cascade_splits,
+    };
+    let mut atlas = AtlasAllocator::new(settings.atlas_size);
+
+    // Larger tiles go first so the rows pack tightly.
+    let mut order: Vec<(usize, u32)> = lights
+        .iter()
+        .enumerate()
+        .filter(|(_, light)| light.shadow.is_some())
+        .map(|(index, light)| {
+            let resolution = match light.kind {
+                LightKind::Directional => settings.cascade_resolution,
+                LightKind::Spot { .. } => settings.spot_resolution,
+                LightKind::Point => settings.point_resolution,
+            };
+            return (index, resolution);
+        })
+        .collect();
+    order.sort_by(|a, b| b.1.cmp(&a.1));
+
+    for (index, resolution) in order {
+        let light = &lights[index];
+        let shadow = light.shadow.unwrap();
+
+        let matrices = match light.kind {
+            LightKind::Directional => {
+                let mut near = camera.z_near;
+                let mut matrices = Vec::with_capacity(cascade_count);
+                for &far in &splits {
+                    matrices.push(cascade_view_projection(
+                        &light.direction,
+                        camera,
+                        aspect,
+                        near,
+                        far,
+                        resolution,
+                        settings.stabilize_cascades,
+                    ));
+                    near = far;
+                }
+                matrices
+            }
+            LightKind::Spot { outer_angle, .. } => {
+                let bounds = BoundingSphere::new(light.position, light.range);
+                if !frustum.intersects_sphere(&bounds) {
+                    continue;
+                }
+                vec![local_view_projection(
+                    &light.position,
+                    &light.direction,
+                    2.0 * outer_angle,
+                    light.range,
+                )]
+            }
+            LightKind::Point => {
+                let bounds = BoundingSphere::new(light.position, light.range);
+                if !frustum.intersects_sphere(&bounds) {
+                    continue;
+                }
+                point_view_projections(light)
+            }
+        };
+
+        let tiles: Option<Vec<[u32; 2]>> = matrices
+            .iter()
+            .map(|_| atlas.allocate(resolution))
+            .collect();
+        let tiles = match tiles {
+            Some(tiles) => tiles,
+            None => {
+                log::debug!("Shadow atlas is full, skipping shadows of light {}", index);
+                continue;
+            }
+        };
+
+        plan.light_params[index] = [
+            plan.views.len() as f32,
+            shadow.depth_bias,
+            shadow.normal_bias,
+            matrices.len() as f32,
+        ];
+        for (view_projection, origin) in matrices.into_iter().zip(tiles) {
+            plan.views.push(ShadowView {
+                view_projection,
+                origin,
+                size: resolution,
+            });
+        }
+    }
+
+    return plan;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascade_splits_test() {
+        let uniform = cascade_splits(1.0, 101.0, 4, 0.0);
+        assert_eq!(uniform, vec![26.0, 51.0, 76.0, 101.0]);
+
+        let logarithmic = cascade_splits(1.0, 1000.0, 3, 1.0);
+        assert!((logarithmic[0] - 10.0).abs() < 1e-3);
+        assert!((logarithmic[1] - 100.0).abs() < 1e-2);
+        assert!((logarithmic[2] - 1000.0).abs() < 1e-1);
+    }
+
+    #[test]
+    fn atlas_allocator_test() {
+        let mut atlas = AtlasAllocator::new(1024);
+        assert_eq!(atlas.allocate(512), Some([0, 0]));
+        assert_eq!(atlas.allocate(512), Some([512, 0]));
+        assert_eq!(atlas.allocate(256), Some([0, 512]));
+        assert_eq!(atlas.allocate(512), Some([256, 512]));
+        assert_eq!(atlas.allocate(512), None);
+    }
+
+    #[test]
+    fn stable_cascade_test() {
+        let direction = Vector3f::new(-1.0, -1.0, 0.0).normalize();
+        let camera = Camera::default();
+        let a = cascade_view_projection(&direction, &camera, 1.0, 0.1, 20.0, 1024, true);
+
+        // Moving the camera by a fraction of a texel shouldn't move the projection.
+        let mut moved = camera;
+        moved.transform.translation.vector += Vector3f::new(0.0, 0.0, 0.001);
+        let b = cascade_view_projection(&direction, &moved, 1.0, 0.1, 20.0, 1024, true);
+        assert_eq!(a.column(3).xy(), b.column(3).xy());
+    }
+
+    #[test]
+    fn plan_test() {
+        let lights = [
+            Light::directional(-Vector3f::y(), Vector3f::repeat(1.0), 1.0)
+                .with_shadow(LightShadow::default()),
+            Light::point(
+                Vector3f::new(0.0, 0.0, -5.0),
+                Vector3f::repeat(1.0),
+                1.0,
+                2.0,
+            )
+            .with_shadow(LightShadow::default()),
+            Light::point(
+                Vector3f::new(0.0, 0.0, 50.0),
+                Vector3f::repeat(1.0),
+                1.0,
+                2.0,
+            )
+            .with_shadow(LightShadow::default()),
+            Light::point(
+                Vector3f::new(0.0, 0.0, -5.0),
+                Vector3f::repeat(1.0),
+                1.0,
+                2.0,
+            ),
+        ];
+        let plan = plan_shadows(&lights, &Camera::default(), 1.0, &ShadowSettings::default());
+
+        assert_eq!(plan.views.len(), 4 + 6);
+        assert_eq!(plan.light_params[0][0], 0.0);
+        assert_eq!(plan.light_params[0][3], 4.0);
+        assert_eq!(plan.light_params[1][0], 4.0);
+        assert_eq!(plan.light_params[1][3], 6.0);
+        // Behind the camera
+        assert_eq!(plan.light_params[2][0], -1.0);
+        // No shadow settings
+        assert_eq!(plan.light_params[3][0], -1.0);
+    }
+}
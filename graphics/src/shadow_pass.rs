@@ -0,0 +1,283 @@
+use crate::{
+    config,
+    culling::cull_shadow_casters,
+    debug_utils::set_image_name,
+    frustum::Frustum,
+    geometry::{Geometry, GeometryId},
+    object::{ObjectId, RenderObject},
+    shadow::ShadowPlan,
+    skinning_pass::SkinnedFrame,
+    vertex::{SkinnedVertex, Vertex, VoxelVertex},
+    voxel_pass::ChunkMesh,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::PersistentDescriptorSet,
+    device::Device,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{AttachmentImage, ImageUsage},
+    pipeline::{viewport::Viewport, GraphicsPipeline, GraphicsPipelineAbstract},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/shadow.vert"
+    }
+}
+
+mod skinned_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/skinned_shadow.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/shadow.frag"
+    }
+}
+
+// Per window shadow atlas. Windows record their frames independently, so they
+// can't share a single atlas image.
+pub struct ShadowTarget {
+    pub atlas: Arc<AttachmentImage>,
+    pub framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    pub size: u32,
+}
+
+pub struct ShadowRenderer {
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    skinned_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // Same shaders as `pipeline`, only the position of the voxel vertices is read
+    voxel_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub sampler: Arc<Sampler>,
+}
+
+impl ShadowRenderer {
+    pub fn new(device: Arc<Device>) -> Self {
+        let render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                // Every shadow map is a separate viewport in the atlas
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                // Both faces are rendered so open meshes still cast shadows, acne is
+                // handled by the per light biases.
+                .cull_mode_disabled()
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let voxel_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<VoxelVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .cull_mode_disabled()
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let skinned_vs = skinned_vs::Shader::load(device.clone()).unwrap();
+        let skinned_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<SkinnedVertex>()
+                .vertex_shader(skinned_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .cull_mode_disabled()
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        // Filtering is done manually, linear filtering of depth isn't universally
+        // supported.
+        let sampler = Sampler::new(
+            device,
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        return ShadowRenderer {
+            render_pass,
+            pipeline,
+            skinned_pipeline,
+            voxel_pipeline,
+            sampler,
+        };
+    }
+
+    pub fn create_target(&self, device: Arc<Device>, size: u32) -> ShadowTarget {
+        let usage = ImageUsage {
+            depth_stencil_attachment: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+        let atlas =
+            AttachmentImage::with_usage(device, [size, size], config::DEFAULT_DEPTH_FORMAT, usage)
+                .unwrap();
+        set_image_name(&atlas, "shadow atlas");
+        let framebuffer = Arc::new(
+            Framebuffer::start(self.render_pass.clone())
+                .add(atlas.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        return ShadowTarget {
+            atlas,
+            framebuffer,
+            size,
+        };
+    }
+
+    // Renders all shadow views into the atlas and returns the number of draws.
+    // The atlas is cleared even when there is nothing to render, as the lighting
+    // pass always samples it. Skinned objects aren't culled, their bounds depend
+    // on the pose.
+    pub fn record(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        target: &ShadowTarget,
+        plan: &ShadowPlan,
+        objects: &HashMap<ObjectId, RenderObject>,
+        geometries: &HashMap<GeometryId, Geometry>,
+        skinned: &SkinnedFrame,
+        chunks: &HashMap<Vector3i, ChunkMesh>,
+        lod_origin: &Vector3f,
+    ) -> (AutoCommandBufferBuilder, u32) {
+        builder = builder
+            .begin_render_pass(target.framebuffer.clone(), false, vec![1f32.into()])
+            .unwrap();
+        let mut draw_calls = 0;
+        let palette_set = skinned.palette.as_ref().map(|palette| {
+            return Arc::new(
+                PersistentDescriptorSet::start(
+                    self.skinned_pipeline
+                        .descriptor_set_layout(0)
+                        .unwrap()
+                        .clone(),
+                )
+                .add_buffer(palette.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+            );
+        });
+
+        for view in &plan.views {
+            let dynamic_state = DynamicState {
+                viewports: Some(vec![Viewport {
+                    origin: [view.origin[0] as f32, view.origin[1] as f32],
+                    dimensions: [view.size as f32, view.size as f32],
+                    depth_range: 0.0..1.0,
+                }]),
+                ..DynamicState::none()
+            };
+
+            let frustum = Frustum::from_matrix(&view.view_projection);
+            for caster in cull_shadow_casters(objects, geometries, &frustum, lod_origin) {
+                let push_constants = vs::ty::PushConstants {
+                    model_view_projection: (view.view_projection * caster.model).into(),
+                };
+                builder = builder
+                    .draw(
+                        self.pipeline.clone(),
+                        &dynamic_state,
+                        geometries[&caster.geometry_id].vertex_buffer.clone(),
+                        (),
+                        push_constants,
+                    )
+                    .unwrap();
+                draw_calls += 1;
+            }
+            for chunk in chunks.values() {
+                if !frustum.intersects_aabb(&chunk.bounds) {
+                    continue;
+                }
+                let push_constants = vs::ty::PushConstants {
+                    model_view_projection: (view.view_projection * chunk.model).into(),
+                };
+                builder = builder
+                    .draw(
+                        self.voxel_pipeline.clone(),
+                        &dynamic_state,
+                        chunk.vertex_buffer.clone(),
+                        (),
+                        push_constants,
+                    )
+                    .unwrap();
+                draw_calls += 1;
+            }
+            if let Some(palette_set) = &palette_set {
+                for draw in &skinned.draws {
+                    let push_constants = skinned_vs::ty::PushConstants {
+                        model_view_projection: (view.view_projection * draw.model).into(),
+                        skin: [draw.palette_offset, draw.method.gpu_index(), 0, 0],
+                    };
+                    builder = builder
+                        .draw(
+                            self.skinned_pipeline.clone(),
+                            &dynamic_state,
+                            draw.vertex_buffer.clone(),
+                            palette_set.clone(),
+                            push_constants,
+                        )
+                        .unwrap();
+                    draw_calls += 1;
+                }
+            }
+        }
+
+        return (builder.end_render_pass().unwrap(), draw_calls);
+    }
+}
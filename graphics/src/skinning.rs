@@ -0,0 +1,315 @@
+use crate::material::MaterialId;
+use polyengine_core::*;
+
+pub type SkinnedMeshId = u32;
+pub type SkinnedObjectId = u32;
+
+// Joints influencing a single vertex
+pub const MAX_JOINT_INFLUENCES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+    // Index of the parent joint, which has to come first in the skeleton
+    pub parent: Option<usize>,
+    // Moves the mesh from model space into the space of the joint in bind pose
+    pub inverse_bind: Isometry3,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+// Transform of a joint relative to its parent, or to the model for root joints.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct JointPose {
+    pub rotation: UnitQuaternion,
+    pub translation: Translation3f,
+}
+
+impl Default for JointPose {
+    fn default() -> Self {
+        return JointPose {
+            rotation: UnitQuaternion::identity(),
+            translation: Translation3f::identity(),
+        };
+    }
+}
+
+impl JointPose {
+    pub fn new(rotation: UnitQuaternion, translation: Translation3f) -> Self {
+        return JointPose {
+            rotation,
+            translation,
+        };
+    }
+
+    pub fn to_isometry(&self) -> Isometry3 {
+        return Isometry3::from_parts(self.translation, self.rotation);
+    }
+}
+
+impl Skeleton {
+    // Whether every parent comes before its children.
+    pub fn is_valid(&self) -> bool {
+        return self
+            .joints
+            .iter()
+            .enumerate()
+            .all(|(i, joint)| joint.parent.map_or(true, |parent| parent < i));
+    }
+
+    // Local poses of the bind pose, a starting point for animations.
+    pub fn bind_pose(&self) -> Vec<JointPose> {
+        let globals: Vec<Isometry3> = self
+            .joints
+            .iter()
+            .map(|joint| joint.inverse_bind.inverse())
+            .collect();
+        return self
+            .joints
+            .iter()
+            .enumerate()
+            .map(|(i, joint)| {
+                let local = match joint.parent {
+                    Some(parent) => globals[parent].inverse() * globals[i],
+                    None => globals[i],
+                };
+                return JointPose::new(local.rotation, local.translation);
+            })
+            .collect();
+    }
+
+    // Model space transforms moving vertices from the bind pose into `poses`.
+    // Joints without a pose keep their bind pose.
+    pub fn skinning_transforms(&self, poses: &[JointPose]) -> Vec<Isometry3> {
+        let bind_pose = self.bind_pose();
+        let mut globals: Vec<Isometry3> = Vec::with_capacity(self.joints.len());
+        for (i, joint) in self.joints.iter().enumerate() {
+            let local = poses.get(i).unwrap_or(&bind_pose[i]).to_isometry();
+            let global = match joint.parent {
+                Some(parent) => globals[parent] * local,
+                None => local,
+            };
+            globals.push(global);
+        }
+        return globals
+            .iter()
+            .zip(&self.joints)
+            .map(|(global, joint)| global * joint.inverse_bind)
+            .collect();
+    }
+}
+
+// How the joint transforms influencing a vertex are blended.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SkinningMethod {
+    // Weighted sum of the joint matrices, cheap but volume collapses around
+    // twisting joints
+    LinearBlend,
+    // Weighted sum of dual quaternions, keeps the volume of twisted and bent
+    // joints
+    DualQuaternion,
+}
+
+impl Default for SkinningMethod {
+    fn default() -> Self { return SkinningMethod::LinearBlend; }
+}
+
+impl SkinningMethod {
+    // Has to match the `SKINNING_*` constants in `skinning.glsl`.
+    pub fn gpu_index(&self) -> u32 {
+        match self {
+            SkinningMethod::LinearBlend => return 0,
+            SkinningMethod::DualQuaternion => return 1,
+        }
+    }
+}
+
+// Joint palette entry, layout has to match `Joint` in `skinning.glsl`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct GpuJoint {
+    pub matrix: [[f32; 4]; 4],
+    // Unit dual quaternion of the same transform, vector part first
+    pub real: [f32; 4],
+    pub dual: [f32; 4],
+}
+
+impl Default for GpuJoint {
+    fn default() -> Self { return GpuJoint::new(&Isometry3::identity()); }
+}
+
+impl GpuJoint {
+    pub fn new(transform: &Isometry3) -> Self {
+        let real = transform.rotation.into_inner();
+        let translation = na::Quaternion::from_imag(transform.translation.vector);
+        let dual = translation * real * 0.5;
+        return GpuJoint {
+            matrix: transform.to_homogeneous().into(),
+            real: real.coords.into(),
+            dual: dual.coords.into(),
+        };
+    }
+}
+
+// Instance of a skinned mesh placed in the world, posed by an animation system.
+pub struct SkinnedObject {
+    pub mesh: SkinnedMeshId,
+    pub transform: Similarity3,
+    // Uses the default material when not set
+    pub material: Option<MaterialId>,
+    pub method: SkinningMethod,
+    // Palette of the current pose
+    pub joints: Vec<GpuJoint>,
+    // Palette and model matrix of the previous frame, for motion vectors
+    pub previous_joints: Vec<GpuJoint>,
+    pub previous_model: Matrix4f,
+}
+
+impl SkinnedObject {
+    // Starts in the bind pose.
+    pub fn new(mesh: SkinnedMeshId, joint_count: usize, transform: Similarity3) -> Self {
+        let joints = vec![GpuJoint::default(); joint_count];
+        return SkinnedObject {
+            mesh,
+            transform,
+            material: None,
+            method: SkinningMethod::default(),
+            previous_joints: joints.clone(),
+            joints,
+            previous_model: transform.to_homogeneous(),
+        };
+    }
+
+    pub fn set_pose(&mut self, skeleton: &Skeleton, poses: &[JointPose]) {
+        self.joints = skeleton
+            .skinning_transforms(poses)
+            .iter()
+            .map(GpuJoint::new)
+            .collect();
+    }
+
+    // Keeps the pose and transform of the rendered frame for the motion vectors
+    // of the next one.
+    pub fn end_frame(&mut self) {
+        self.previous_joints.clone_from(&self.joints);
+        self.previous_model = self.transform.to_homogeneous();
+    }
+}
+
+// Scales the weights to sum up to 1, vertices without weights follow the first
+// joint.
+pub fn normalize_weights(weights: [f32; MAX_JOINT_INFLUENCES]) -> [f32; MAX_JOINT_INFLUENCES] {
+    let sum: f32 = weights.iter().sum();
+    if sum <= FScalar::EPSILON {
+        return [1.0, 0.0, 0.0, 0.0];
+    }
+    return [
+        weights[0] / sum,
+        weights[1] / sum,
+        weights[2] / sum,
+        weights[3] / sum,
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two joint arm along X, the elbow is one unit from the shoulder.
+    fn arm() -> Skeleton {
+        let shoulder = Isometry3::translation(1.0, 0.0, 0.0);
+        let elbow = Isometry3::translation(2.0, 0.0, 0.0);
+        return Skeleton {
+            joints: vec![
+                Joint {
+                    parent: None,
+                    inverse_bind: shoulder.inverse(),
+                },
+                Joint {
+                    parent: Some(0),
+                    inverse_bind: elbow.inverse(),
+                },
+            ],
+        };
+    }
+
+    #[test]
+    fn bind_pose_test() {
+        let skeleton = arm();
+        assert!(skeleton.is_valid());
+        let bind_pose = skeleton.bind_pose();
+        assert!((bind_pose[1].translation.vector - Vector3f::x()).norm() < 1e-6);
+        for transform in skeleton.skinning_transforms(&bind_pose) {
+            assert!((transform.to_homogeneous() - Matrix4f::identity()).norm() < 1e-6);
+        }
+        // Missing poses fall back to the bind pose
+        assert_eq!(
+            skeleton.skinning_transforms(&[]),
+            skeleton.skinning_transforms(&bind_pose)
+        );
+
+        let invalid = Skeleton {
+            joints: vec![Joint {
+                parent: Some(0),
+                inverse_bind: Isometry3::identity(),
+            }],
+        };
+        assert!(!invalid.is_valid());
+    }
+
+    #[test]
+    fn pose_test() {
+        let skeleton = arm();
+        let mut poses = skeleton.bind_pose();
+        // Bend the elbow by 90 degrees around Z
+        poses[1].rotation =
+            UnitQuaternion::from_euler_angles(0.0, 0.0, std::f32::consts::FRAC_PI_2);
+        let transforms = skeleton.skinning_transforms(&poses);
+
+        // The shoulder and the elbow stay, the hand moves from X to Y
+        let elbow = na::Point3::new(2.0, 0.0, 0.0);
+        let hand = na::Point3::new(3.0, 0.0, 0.0);
+        assert!((transforms[0] * hand - hand).norm() < 1e-6);
+        assert!((transforms[1] * elbow - elbow).norm() < 1e-6);
+        assert!((transforms[1] * hand - na::Point3::new(2.0, 1.0, 0.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn dual_quaternion_test() {
+        let transform = Isometry3::from_parts(
+            Translation3f::new(1.0, -2.0, 3.0),
+            UnitQuaternion::from_euler_angles(0.3, -0.7, 1.1),
+        );
+        let joint = GpuJoint::new(&transform);
+        // Translation is recovered from 2 * dual * conjugate(real), like the shader
+        let real = na::Quaternion::from(Vector4f::from(joint.real));
+        let dual = na::Quaternion::from(Vector4f::from(joint.dual));
+        let translation = (dual * real.conjugate() * 2.0).imag();
+        assert!((translation - transform.translation.vector).norm() < 1e-5);
+        assert!((Matrix4f::from(joint.matrix) - transform.to_homogeneous()).norm() < 1e-6);
+    }
+
+    #[test]
+    fn weights_test() {
+        assert_eq!(
+            normalize_weights([2.0, 1.0, 1.0, 0.0]),
+            [0.5, 0.25, 0.25, 0.0]
+        );
+        assert_eq!(normalize_weights([0.0; 4]), [1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn previous_frame_test() {
+        let skeleton = arm();
+        let mut object = SkinnedObject::new(0, 2, Similarity3::identity());
+        let mut poses = skeleton.bind_pose();
+        poses[0].translation = Translation3f::new(0.0, 1.0, 0.0);
+        object.set_pose(&skeleton, &poses);
+        assert_eq!(object.previous_joints[0], GpuJoint::default());
+        assert_ne!(object.joints[0], GpuJoint::default());
+        object.end_frame();
+        assert_eq!(object.previous_joints, object.joints);
+    }
+}
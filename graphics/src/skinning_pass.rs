@@ -0,0 +1,297 @@
+use crate::{
+    deferred_pass::gbuffer_normal_blend,
+    material::MaterialId,
+    skinning::{GpuJoint, Skeleton, SkinnedMeshId, SkinnedObject, SkinnedObjectId, SkinningMethod},
+    vertex::SkinnedVertex,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/skinned.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/forward.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod depth_only_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/depth_only.frag"
+    }
+}
+
+mod normal_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/gbuffer_normal.frag"
+    }
+}
+
+// Skinned geometry with the skeleton its vertices are bound to.
+pub struct SkinnedMesh {
+    pub vertex_buffer: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+    pub skeleton: Skeleton,
+}
+
+impl SkinnedMesh {
+    pub fn new(device: Arc<Device>, vertices: &[SkinnedVertex], skeleton: Skeleton) -> Self {
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage::vertex_buffer(),
+            false,
+            vertices.iter().cloned(),
+        )
+        .unwrap();
+        return SkinnedMesh {
+            vertex_buffer: vec![vertex_buffer],
+            skeleton,
+        };
+    }
+}
+
+// Skinned object drawn in a frame.
+pub struct SkinnedDraw {
+    pub vertex_buffer: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+    pub material_id: Option<MaterialId>,
+    pub model: Matrix4f,
+    pub previous_model: Matrix4f,
+    // First joint of the current pose in the palette buffer, the previous pose
+    // follows
+    pub palette_offset: u32,
+    pub joint_count: u32,
+    pub method: SkinningMethod,
+}
+
+// Skinned objects of a frame with the joint palettes of all of them.
+#[derive(Default)]
+pub struct SkinnedFrame {
+    pub draws: Vec<SkinnedDraw>,
+    // None when there is nothing to draw
+    pub palette: Option<Arc<dyn BufferAccess + Send + Sync>>,
+}
+
+pub struct SkinningRenderer {
+    // Shaded by the forward fragment shader, uses the frame set of the forward
+    // pipeline and the palette in set 1
+    pub pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // Depth of the ambient occlusion prepass and depth with normals of the
+    // G-buffer, so skinned objects occlude in both render paths
+    pub prepass_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub gbuffer_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    palette_pool: CpuBufferPool<GpuJoint>,
+}
+
+impl SkinningRenderer {
+    pub fn new(
+        device: Arc<Device>,
+        scene_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        prepass_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        gbuffer_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+        let depth_only_fs = depth_only_fs::Shader::load(device.clone()).unwrap();
+        let normal_fs = normal_fs::Shader::load(device.clone()).unwrap();
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<SkinnedVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil(DepthStencil {
+                    depth_compare: Compare::LessOrEqual,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .render_pass(Subpass::from(scene_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let prepass_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<SkinnedVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(depth_only_fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(prepass_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let gbuffer_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<SkinnedVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(normal_fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .blend_individual(gbuffer_normal_blend().into_iter())
+                .render_pass(Subpass::from(gbuffer_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        return SkinningRenderer {
+            pipeline,
+            prepass_pipeline,
+            gbuffer_pipeline,
+            palette_pool: CpuBufferPool::new(
+                device,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::none()
+                },
+            ),
+        };
+    }
+
+    // Uploads the palettes of all objects, sorted by ID. Objects of removed
+    // meshes are skipped.
+    pub fn prepare(
+        &self,
+        objects: &HashMap<SkinnedObjectId, SkinnedObject>,
+        meshes: &HashMap<SkinnedMeshId, SkinnedMesh>,
+    ) -> SkinnedFrame {
+        let mut ids: Vec<SkinnedObjectId> = objects.keys().cloned().collect();
+        ids.sort();
+        let mut draws = Vec::with_capacity(ids.len());
+        let mut palette = Vec::new();
+        for object_id in ids {
+            let object = &objects[&object_id];
+            let mesh = match meshes.get(&object.mesh) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            draws.push(SkinnedDraw {
+                vertex_buffer: mesh.vertex_buffer.clone(),
+                material_id: object.material,
+                model: object.transform.to_homogeneous(),
+                previous_model: object.previous_model,
+                palette_offset: palette.len() as u32,
+                joint_count: object.joints.len() as u32,
+                method: object.method,
+            });
+            palette.extend_from_slice(&object.joints);
+            palette.extend_from_slice(&object.previous_joints);
+        }
+        // Storage buffers can't be empty
+        if palette.is_empty() {
+            return SkinnedFrame::default();
+        }
+        let palette = Arc::new(self.palette_pool.chunk(palette).unwrap());
+        return SkinnedFrame {
+            draws,
+            palette: Some(palette),
+        };
+    }
+
+    // Draws the skinned objects in the scene pass. `frame_set` is the set of the
+    // forward pipeline, whose draw data contains the skinned draws from
+    // `first_draw` on. Returns the recorded draw calls.
+    pub fn record<S>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        frame: &SkinnedFrame,
+        frame_set: S,
+        first_draw: u32,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        return self.record_with(
+            builder,
+            &self.pipeline,
+            frame,
+            frame_set,
+            first_draw,
+            dynamic_state,
+        );
+    }
+
+    // Draws the skinned objects with `prepass_pipeline` or `gbuffer_pipeline`,
+    // `set` contains the frame data and draw data.
+    pub fn record_prepass<S>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        frame: &SkinnedFrame,
+        set: S,
+        first_draw: u32,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        return self.record_with(builder, pipeline, frame, set, first_draw, dynamic_state);
+    }
+
+    fn record_with<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        frame: &SkinnedFrame,
+        set: S,
+        first_draw: u32,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        let palette = match &frame.palette {
+            Some(palette) => palette,
+            None => return (builder, 0),
+        };
+        let palette_set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.descriptor_set_layout(1).unwrap().clone())
+                .add_buffer(palette.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        for (i, draw) in frame.draws.iter().enumerate() {
+            let push_constants = vs::ty::PushConstants {
+                draw: [
+                    first_draw + i as u32,
+                    draw.palette_offset,
+                    draw.joint_count,
+                    draw.method.gpu_index(),
+                ],
+            };
+            builder = builder
+                .draw(
+                    pipeline.clone(),
+                    dynamic_state,
+                    draw.vertex_buffer.clone(),
+                    (set.clone(), palette_set.clone()),
+                    push_constants,
+                )
+                .unwrap();
+        }
+        return (builder, frame.draws.len() as u32);
+    }
+}
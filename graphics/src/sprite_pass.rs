@@ -0,0 +1,213 @@
+use crate::{
+    sprites::{SpriteAtlas, SpriteAtlasId, SpriteBatches, SpriteQuad},
+    vertex::SpriteVertex,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, iter, sync::Arc};
+use vulkano::{
+    buffer::{BufferSlice, BufferUsage, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::PersistentDescriptorSet,
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::{Dimensions, ImmutableImage},
+    pipeline::{blend::AttachmentBlend, GraphicsPipeline, GraphicsPipelineAbstract},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/sprite.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/sprite.frag",
+        include: ["src/shaders"]
+    }
+}
+
+// Uploaded sprite atlas.
+pub struct SpriteAtlasImage {
+    pub image: Arc<ImmutableImage<Format>>,
+    pub size: [u32; 2],
+}
+
+pub struct SpriteRenderer {
+    queue: Arc<Queue>,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    // Bound for sprites without an atlas
+    white: Arc<ImmutableImage<Format>>,
+    vertex_pool: CpuBufferPool<SpriteVertex>,
+}
+
+impl SpriteRenderer {
+    // Sprites are drawn straight into the swapchain images with
+    // `window_render_pass`, without depth testing.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        window_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<SpriteVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .blend_collective(AttachmentBlend::alpha_blending())
+                .render_pass(Subpass::from(window_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        // Pixel art stays sharp at integer zoom levels.
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let (white, upload) = ImmutableImage::from_iter(
+            iter::once([255u8; 4]),
+            Dimensions::Dim2d {
+                width: 1,
+                height: 1,
+            },
+            Format::R8G8B8A8Srgb,
+            queue.clone(),
+        )
+        .unwrap();
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        return SpriteRenderer {
+            queue,
+            pipeline,
+            sampler,
+            white,
+            vertex_pool: CpuBufferPool::new(device, BufferUsage::vertex_buffer()),
+        };
+    }
+
+    // Uploads the atlas texels. Blocks until the GPU is done, atlases are
+    // expected to be loaded rarely.
+    pub fn create_atlas(&self, atlas: &SpriteAtlas) -> SpriteAtlasImage {
+        let (image, upload) = ImmutableImage::from_iter(
+            atlas.texels.iter().cloned(),
+            Dimensions::Dim2d {
+                width: atlas.width,
+                height: atlas.height,
+            },
+            Format::R8G8B8A8Srgb,
+            self.queue.clone(),
+        )
+        .unwrap();
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        return SpriteAtlasImage {
+            image,
+            size: [atlas.width, atlas.height],
+        };
+    }
+
+    // Draws the batches with a single vertex upload, has to be recorded inside of
+    // the window render pass. Returns the recorded draw calls.
+    pub fn record(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        batches: &SpriteBatches,
+        atlases: &HashMap<SpriteAtlasId, SpriteAtlasImage>,
+        view_projection: &Matrix4f,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32) {
+        if batches.quads.is_empty() {
+            return (builder, 0);
+        }
+        // Chunks need the vertex count up front
+        let vertices: Vec<_> = batches.quads.iter().flat_map(quad_vertices).collect();
+        let vertices = self.vertex_pool.chunk(vertices).unwrap();
+        let vertices = Arc::new(vertices);
+        let push_constants = vs::ty::PushConstants {
+            view_projection: (*view_projection).into(),
+        };
+
+        let mut draw_calls = 0;
+        for batch in &batches.batches {
+            let image = match batch.atlas {
+                Some(atlas_id) => match atlases.get(&atlas_id) {
+                    Some(atlas) => atlas.image.clone(),
+                    None => continue,
+                },
+                None => self.white.clone(),
+            };
+            let set = Arc::new(
+                PersistentDescriptorSet::start(
+                    self.pipeline.descriptor_set_layout(0).unwrap().clone(),
+                )
+                .add_sampled_image(image, self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+            );
+            let first = batch.first_quad * 6;
+            let vertex_slice = BufferSlice::from_typed_buffer_access(vertices.clone())
+                .slice(first..first + batch.quad_count * 6)
+                .unwrap();
+            builder = builder
+                .draw(
+                    self.pipeline.clone(),
+                    dynamic_state,
+                    vec![Arc::new(vertex_slice)],
+                    set,
+                    push_constants,
+                )
+                .unwrap();
+            draw_calls += 1;
+        }
+        return (builder, draw_calls);
+    }
+}
+
+// Two triangles per quad from the corners in top left, top right, bottom left,
+// bottom right order.
+fn quad_vertices(quad: &SpriteQuad) -> Vec<SpriteVertex> {
+    let uvs = [
+        [quad.uv_min.x, quad.uv_min.y],
+        [quad.uv_max.x, quad.uv_min.y],
+        [quad.uv_min.x, quad.uv_max.y],
+        [quad.uv_max.x, quad.uv_max.y],
+    ];
+    let c = quad.color;
+    return [0, 1, 2, 2, 1, 3]
+        .iter()
+        .map(|&i| SpriteVertex {
+            position: [quad.corners[i].x, quad.corners[i].y],
+            uv: uvs[i],
+            color: [c.x, c.y, c.z, c.w],
+        })
+        .collect();
+}
@@ -0,0 +1,558 @@
+use crate::camera::orthographic_matrix;
+use polyengine_core::*;
+use std::collections::HashMap;
+
+pub type SpriteAtlasId = u32;
+pub type SpriteId = u32;
+pub type TileMapId = u32;
+
+// Texture sprites and tiles are cut from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteAtlas {
+    pub width: u32,
+    pub height: u32,
+    // sRGB texels with straight alpha, row by row
+    pub texels: Vec<[u8; 4]>,
+}
+
+impl SpriteAtlas {
+    // Returns None when the data doesn't match the size.
+    pub fn new(width: u32, height: u32, texels: Vec<[u8; 4]>) -> Option<Self> {
+        if width == 0 || height == 0 || texels.len() != (width * height) as usize {
+            return None;
+        }
+        return Some(SpriteAtlas {
+            width,
+            height,
+            texels,
+        });
+    }
+}
+
+// Rectangle of an atlas in texels.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct SpriteRegion {
+    pub offset: [u32; 2],
+    pub size: [u32; 2],
+}
+
+// Borders of a region in texels that keep their size when the sprite is
+// stretched, only the middle is scaled.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct NineSlice {
+    pub left: FScalar,
+    pub right: FScalar,
+    pub top: FScalar,
+    pub bottom: FScalar,
+}
+
+// Textured or solid coloured rectangle of a 2D window. Positions and sizes are
+// in pixels, Y points down.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sprite {
+    // Sprites without an atlas are filled with their colour
+    pub atlas: Option<SpriteAtlasId>,
+    pub region: SpriteRegion,
+    pub position: Vector2f,
+    pub size: Vector2f,
+    // Point the sprite is positioned and rotated around, as a fraction of its size
+    pub pivot: Vector2f,
+    // Clockwise rotation in radians
+    pub rotation: FScalar,
+    // Linear RGB colour with alpha, multiplied with the atlas
+    pub color: Vector4f,
+    // Higher layers are drawn on top. Sprites of a layer are grouped by atlas,
+    // their order within the layer is only kept for the same atlas.
+    pub layer: i32,
+    pub nine_slice: Option<NineSlice>,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Sprite {
+    // Sprite showing the whole region at its texel size.
+    pub fn new(atlas: SpriteAtlasId, region: SpriteRegion, position: Vector2f) -> Self {
+        return Sprite {
+            atlas: Some(atlas),
+            region,
+            position,
+            size: Vector2f::new(region.size[0] as FScalar, region.size[1] as FScalar),
+            pivot: Vector2f::zeros(),
+            rotation: 0.0,
+            color: Vector4f::new(1.0, 1.0, 1.0, 1.0),
+            layer: 0,
+            nine_slice: None,
+            flip_x: false,
+            flip_y: false,
+        };
+    }
+
+    pub fn solid(position: Vector2f, size: Vector2f, color: Vector4f) -> Self {
+        return Sprite {
+            atlas: None,
+            size,
+            color,
+            ..Sprite::new(0, SpriteRegion::default(), position)
+        };
+    }
+}
+
+// Grid of tiles cut from an atlas. Tiles are numbered row by row from the top
+// left tile of the atlas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileMap {
+    pub atlas: SpriteAtlasId,
+    // Size of a tile in the atlas, in texels
+    pub tile_size: [u32; 2],
+    // Size of a drawn tile, in pixels
+    pub cell_size: Vector2f,
+    // Top left corner, in pixels
+    pub position: Vector2f,
+    pub columns: u32,
+    pub rows: u32,
+    // Row by row, None for empty cells
+    pub tiles: Vec<Option<u32>>,
+    pub color: Vector4f,
+    // Tile maps are drawn below the sprites of their layer
+    pub layer: i32,
+}
+
+impl TileMap {
+    // Empty map, drawing tiles at their texel size.
+    pub fn new(atlas: SpriteAtlasId, tile_size: [u32; 2], columns: u32, rows: u32) -> Self {
+        return TileMap {
+            atlas,
+            tile_size,
+            cell_size: Vector2f::new(tile_size[0] as FScalar, tile_size[1] as FScalar),
+            position: Vector2f::zeros(),
+            columns,
+            rows,
+            tiles: vec![None; (columns * rows) as usize],
+            color: Vector4f::new(1.0, 1.0, 1.0, 1.0),
+            layer: 0,
+        };
+    }
+
+    pub fn tile(&self, column: u32, row: u32) -> Option<u32> {
+        if column >= self.columns || row >= self.rows {
+            return None;
+        }
+        return self.tiles[(row * self.columns + column) as usize];
+    }
+
+    // Returns false when the cell is outside of the map.
+    pub fn set_tile(&mut self, column: u32, row: u32, tile: Option<u32>) -> bool {
+        if column >= self.columns || row >= self.rows {
+            return false;
+        }
+        self.tiles[(row * self.columns + column) as usize] = tile;
+        return true;
+    }
+}
+
+// Orthographic camera of a 2D window, one world unit is one pixel at zoom 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera2d {
+    // World position shown in the top left corner of the window
+    pub position: Vector2f,
+    pub zoom: FScalar,
+    // Linear RGB colour the window is cleared with
+    pub background: Vector3f,
+}
+
+impl Default for Camera2d {
+    fn default() -> Self {
+        return Camera2d {
+            position: Vector2f::zeros(),
+            zoom: 1.0,
+            background: Vector3f::zeros(),
+        };
+    }
+}
+
+impl Camera2d {
+    // World rectangle shown in a window of `viewport` pixels, as its top left and
+    // bottom right corner.
+    pub fn visible_area(&self, viewport: [u32; 2]) -> (Vector2f, Vector2f) {
+        let size = Vector2f::new(viewport[0] as FScalar, viewport[1] as FScalar) / self.zoom;
+        return (self.position, self.position + size);
+    }
+
+    pub fn view_projection(&self, viewport: [u32; 2]) -> Matrix4f {
+        let (min, max) = self.visible_area(viewport);
+        // Y points down, so the top edge is the smaller coordinate
+        return orthographic_matrix(min.x, max.x, max.y, min.y, -1.0, 1.0);
+    }
+}
+
+// Rectangle with its corners in world space, ordered top left, top right,
+// bottom left, bottom right of the source image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpriteQuad {
+    pub corners: [Vector2f; 4],
+    // Atlas coordinates of the top left and bottom right corner
+    pub uv_min: Vector2f,
+    pub uv_max: Vector2f,
+    pub color: Vector4f,
+}
+
+// Consecutive quads drawn with the same atlas.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpriteBatch {
+    pub atlas: Option<SpriteAtlasId>,
+    pub first_quad: usize,
+    pub quad_count: usize,
+}
+
+// Quads of a frame in drawing order, merged into as few batches as possible.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SpriteBatches {
+    pub quads: Vec<SpriteQuad>,
+    pub batches: Vec<SpriteBatch>,
+}
+
+impl SpriteBatches {
+    pub fn add_quad(&mut self, atlas: Option<SpriteAtlasId>, quad: SpriteQuad) {
+        match self.batches.last_mut() {
+            Some(batch) if batch.atlas == atlas => batch.quad_count += 1,
+            _ => self.batches.push(SpriteBatch {
+                atlas,
+                first_quad: self.quads.len(),
+                quad_count: 1,
+            }),
+        }
+        self.quads.push(quad);
+    }
+
+    // Adds the sprites and tiles overlapping the visible area by layer.
+    // `atlas_size` returns the texel size of an atlas, sprites of unknown atlases
+    // are skipped.
+    pub fn add_scene<F>(
+        &mut self,
+        sprites: &HashMap<SpriteId, Sprite>,
+        tile_maps: &HashMap<TileMapId, TileMap>,
+        atlas_size: F,
+        visible_area: (Vector2f, Vector2f),
+    ) where
+        F: Fn(SpriteAtlasId) -> Option<[u32; 2]>,
+    {
+        // Layer, tile maps before sprites, atlas and ID
+        let mut order: Vec<(i32, bool, Option<SpriteAtlasId>, u32)> = tile_maps
+            .iter()
+            .map(|(&id, map)| (map.layer, false, Some(map.atlas), id))
+            .chain(
+                sprites
+                    .iter()
+                    .map(|(&id, sprite)| (sprite.layer, true, sprite.atlas, id)),
+            )
+            .collect();
+        order.sort_unstable();
+
+        for (_, is_sprite, atlas, id) in order {
+            let size = match atlas {
+                Some(atlas) => match atlas_size(atlas) {
+                    Some(size) => Vector2f::new(size[0] as FScalar, size[1] as FScalar),
+                    None => continue,
+                },
+                None => Vector2f::new(1.0, 1.0),
+            };
+            if is_sprite {
+                self.add_sprite(&sprites[&id], size, visible_area);
+            } else {
+                self.add_tile_map(&tile_maps[&id], size, visible_area);
+            }
+        }
+    }
+
+    fn add_sprite(
+        &mut self,
+        sprite: &Sprite,
+        atlas_size: Vector2f,
+        visible_area: (Vector2f, Vector2f),
+    ) {
+        let region_offset = Vector2f::new(
+            sprite.region.offset[0] as FScalar,
+            sprite.region.offset[1] as FScalar,
+        );
+        let region_size = Vector2f::new(
+            sprite.region.size[0] as FScalar,
+            sprite.region.size[1] as FScalar,
+        );
+        let origin = sprite.position - sprite.size.component_mul(&sprite.pivot);
+        let (sin, cos) = sprite.rotation.sin_cos();
+        let transform = |local: Vector2f| {
+            let p = origin + local - sprite.position;
+            return sprite.position + Vector2f::new(p.x * cos - p.y * sin, p.x * sin + p.y * cos);
+        };
+
+        let cells = match sprite.nine_slice {
+            Some(slice) => nine_slice_cells(&slice, sprite.size, region_size),
+            None => vec![(
+                Vector2f::zeros(),
+                sprite.size,
+                Vector2f::zeros(),
+                region_size,
+            )],
+        };
+        for (position, size, source_offset, source_size) in cells {
+            let corners = [
+                transform(position),
+                transform(position + Vector2f::new(size.x, 0.0)),
+                transform(position + Vector2f::new(0.0, size.y)),
+                transform(position + size),
+            ];
+            if !overlaps(&corners, visible_area) {
+                continue;
+            }
+            // Flipping mirrors the source rectangle inside of the region
+            let mut uv_min = source_offset;
+            let mut uv_max = source_offset + source_size;
+            if sprite.flip_x {
+                uv_min.x = region_size.x - uv_min.x;
+                uv_max.x = region_size.x - uv_max.x;
+            }
+            if sprite.flip_y {
+                uv_min.y = region_size.y - uv_min.y;
+                uv_max.y = region_size.y - uv_max.y;
+            }
+            let quad = SpriteQuad {
+                corners,
+                uv_min: (region_offset + uv_min).component_div(&atlas_size),
+                uv_max: (region_offset + uv_max).component_div(&atlas_size),
+                color: sprite.color,
+            };
+            self.add_quad(sprite.atlas, quad);
+        }
+    }
+
+    fn add_tile_map(
+        &mut self,
+        map: &TileMap,
+        atlas_size: Vector2f,
+        visible_area: (Vector2f, Vector2f),
+    ) {
+        let (min, max) = visible_area;
+        if map.cell_size.x <= 0.0 || map.cell_size.y <= 0.0 {
+            return;
+        }
+        // Only cells overlapping the visible area
+        let first = (min - map.position).component_div(&map.cell_size);
+        let last = (max - map.position).component_div(&map.cell_size);
+        let columns =
+            first.x.floor().max(0.0) as u32..(last.x.ceil().max(0.0) as u32).min(map.columns);
+        let rows = first.y.floor().max(0.0) as u32..(last.y.ceil().max(0.0) as u32).min(map.rows);
+        let tile_size = Vector2f::new(map.tile_size[0] as FScalar, map.tile_size[1] as FScalar);
+        let atlas_columns = (atlas_size.x / tile_size.x).floor().max(1.0) as u32;
+
+        for row in rows {
+            for column in columns.clone() {
+                let tile = match map.tile(column, row) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+                let source = Vector2f::new(
+                    (tile % atlas_columns) as FScalar,
+                    (tile / atlas_columns) as FScalar,
+                )
+                .component_mul(&tile_size);
+                let position = map.position
+                    + Vector2f::new(column as FScalar, row as FScalar)
+                        .component_mul(&map.cell_size);
+                let quad = SpriteQuad {
+                    corners: [
+                        position,
+                        position + Vector2f::new(map.cell_size.x, 0.0),
+                        position + Vector2f::new(0.0, map.cell_size.y),
+                        position + map.cell_size,
+                    ],
+                    uv_min: source.component_div(&atlas_size),
+                    uv_max: (source + tile_size).component_div(&atlas_size),
+                    color: map.color,
+                };
+                self.add_quad(Some(map.atlas), quad);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn draw_calls(&self) -> u32 { return self.batches.len() as u32; }
+}
+
+// Position and size of the nine cells of a sliced sprite of `size`, with
+// their source rectangle in a region of `region_size`. Borders shrink evenly
+// when the sprite is smaller than them.
+pub fn nine_slice_cells(
+    slice: &NineSlice,
+    size: Vector2f,
+    region_size: Vector2f,
+) -> Vec<(Vector2f, Vector2f, Vector2f, Vector2f)> {
+    let scale_x = (size.x / (slice.left + slice.right)).min(1.0);
+    let scale_y = (size.y / (slice.top + slice.bottom)).min(1.0);
+    let xs = [
+        0.0,
+        slice.left * scale_x,
+        size.x - slice.right * scale_x,
+        size.x,
+    ];
+    let ys = [
+        0.0,
+        slice.top * scale_y,
+        size.y - slice.bottom * scale_y,
+        size.y,
+    ];
+    let source_xs = [0.0, slice.left, region_size.x - slice.right, region_size.x];
+    let source_ys = [0.0, slice.top, region_size.y - slice.bottom, region_size.y];
+
+    let mut cells = Vec::with_capacity(9);
+    for row in 0..3 {
+        for column in 0..3 {
+            let position = Vector2f::new(xs[column], ys[row]);
+            let cell_size = Vector2f::new(xs[column + 1] - xs[column], ys[row + 1] - ys[row]);
+            if cell_size.x <= 0.0 || cell_size.y <= 0.0 {
+                continue;
+            }
+            let source = Vector2f::new(source_xs[column], source_ys[row]);
+            let source_size = Vector2f::new(
+                source_xs[column + 1] - source_xs[column],
+                source_ys[row + 1] - source_ys[row],
+            );
+            cells.push((position, cell_size, source, source_size));
+        }
+    }
+    return cells;
+}
+
+fn overlaps(corners: &[Vector2f; 4], (min, max): (Vector2f, Vector2f)) -> bool {
+    let below = |axis: usize, bound: FScalar| corners.iter().all(|c| c[axis] < bound);
+    let above = |axis: usize, bound: FScalar| corners.iter().all(|c| c[axis] > bound);
+    return !(below(0, min.x) || below(1, min.y) || above(0, max.x) || above(1, max.y));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn visible() -> (Vector2f, Vector2f) {
+        return (Vector2f::zeros(), Vector2f::new(100.0, 100.0));
+    }
+
+    #[test]
+    fn camera_2d_test() {
+        let camera = Camera2d {
+            position: Vector2f::new(10.0, 20.0),
+            zoom: 2.0,
+            ..Camera2d::default()
+        };
+        let (min, max) = camera.visible_area([200, 100]);
+        assert_eq!(min, Vector2f::new(10.0, 20.0));
+        assert_eq!(max, Vector2f::new(110.0, 70.0));
+
+        let view_projection = camera.view_projection([200, 100]);
+        let clip = |x: FScalar, y: FScalar| view_projection * Vector4f::new(x, y, 0.0, 1.0);
+        // Top left of the window is -1, -1 in Vulkan clip space
+        assert!((clip(10.0, 20.0).xy() - Vector2f::new(-1.0, -1.0)).norm() < 1e-5);
+        assert!((clip(110.0, 70.0).xy() - Vector2f::new(1.0, 1.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn batching_test() {
+        let mut sprites = HashMap::new();
+        let region = SpriteRegion {
+            offset: [0, 0],
+            size: [8, 8],
+        };
+        sprites.insert(0, Sprite::new(1, region, Vector2f::new(0.0, 0.0)));
+        sprites.insert(1, Sprite::new(2, region, Vector2f::new(10.0, 0.0)));
+        sprites.insert(2, Sprite::new(1, region, Vector2f::new(20.0, 0.0)));
+        sprites.insert(
+            3,
+            Sprite {
+                layer: 1,
+                ..Sprite::new(1, region, Vector2f::new(30.0, 0.0))
+            },
+        );
+        // Unknown atlas and outside of the view
+        sprites.insert(4, Sprite::new(3, region, Vector2f::new(0.0, 0.0)));
+        sprites.insert(5, Sprite::new(1, region, Vector2f::new(500.0, 0.0)));
+
+        let mut batches = SpriteBatches::default();
+        let atlas_size = |atlas| if atlas < 3 { Some([16, 16]) } else { None };
+        batches.add_scene(&sprites, &HashMap::new(), atlas_size, visible());
+        let summary: Vec<(Option<SpriteAtlasId>, usize)> = batches
+            .batches
+            .iter()
+            .map(|batch| (batch.atlas, batch.quad_count))
+            .collect();
+        assert_eq!(summary, vec![(Some(1), 2), (Some(2), 1), (Some(1), 1)]);
+        assert_eq!(batches.quads[0].uv_max, Vector2f::new(0.5, 0.5));
+        assert_eq!(batches.quads[3].corners[0], Vector2f::new(30.0, 0.0));
+    }
+
+    #[test]
+    fn sprite_transform_test() {
+        let mut batches = SpriteBatches::default();
+        let sprite = Sprite {
+            pivot: Vector2f::new(0.5, 0.5),
+            rotation: std::f32::consts::FRAC_PI_2,
+            flip_x: true,
+            ..Sprite::solid(
+                Vector2f::new(50.0, 50.0),
+                Vector2f::new(20.0, 10.0),
+                Vector4f::zeros(),
+            )
+        };
+        batches.add_sprite(&sprite, Vector2f::new(1.0, 1.0), visible());
+        let quad = batches.quads[0];
+        // Top left corner rotated clockwise around the center
+        assert!((quad.corners[0] - Vector2f::new(55.0, 40.0)).norm() < 1e-4);
+        assert!((quad.corners[3] - Vector2f::new(45.0, 60.0)).norm() < 1e-4);
+        assert_eq!(batches.batches[0].atlas, None);
+    }
+
+    #[test]
+    fn nine_slice_test() {
+        let slice = NineSlice {
+            left: 2.0,
+            right: 2.0,
+            top: 3.0,
+            bottom: 1.0,
+        };
+        let cells = nine_slice_cells(&slice, Vector2f::new(20.0, 10.0), Vector2f::new(8.0, 8.0));
+        assert_eq!(cells.len(), 9);
+        // Center cell stretches, its source stays inside of the borders
+        let (position, size, source, source_size) = cells[4];
+        assert_eq!(position, Vector2f::new(2.0, 3.0));
+        assert_eq!(size, Vector2f::new(16.0, 6.0));
+        assert_eq!(source, Vector2f::new(2.0, 3.0));
+        assert_eq!(source_size, Vector2f::new(4.0, 4.0));
+
+        // Too narrow for the borders, the middle column disappears
+        let cells = nine_slice_cells(&slice, Vector2f::new(2.0, 10.0), Vector2f::new(8.0, 8.0));
+        assert_eq!(cells.len(), 6);
+        assert_eq!(cells[0].1, Vector2f::new(1.0, 3.0));
+    }
+
+    #[test]
+    fn tile_map_test() {
+        let mut map = TileMap::new(7, [8, 8], 100, 2);
+        map.position = Vector2f::new(-16.0, 0.0);
+        assert!(map.set_tile(0, 0, Some(0)));
+        assert!(map.set_tile(3, 0, Some(5)));
+        assert!(map.set_tile(99, 1, Some(1)));
+        assert!(!map.set_tile(100, 0, Some(1)));
+        assert_eq!(map.tile(3, 0), Some(5));
+
+        let mut tile_maps = HashMap::new();
+        tile_maps.insert(0, map);
+        let mut batches = SpriteBatches::default();
+        batches.add_scene(&HashMap::new(), &tile_maps, |_| Some([32, 16]), visible());
+        // The first tile and the last column are outside of the view
+        assert_eq!(batches.quads.len(), 1);
+        let quad = batches.quads[0];
+        assert_eq!(quad.corners[0], Vector2f::new(8.0, 0.0));
+        // Tile 5 is the second tile of the second atlas row
+        assert_eq!(quad.uv_min, Vector2f::new(0.25, 0.5));
+        assert_eq!(quad.uv_max, Vector2f::new(0.5, 1.0));
+        assert_eq!(batches.draw_calls(), 1);
+    }
+}
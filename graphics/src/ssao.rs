@@ -0,0 +1,91 @@
+use crate::antialiasing::halton;
+use polyengine_core::*;
+
+// Size of the sample kernel array in the shader.
+pub const MAX_SSAO_SAMPLES: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SsaoSettings {
+    // World space radius of the sampled hemisphere
+    pub radius: FScalar,
+    // Strength of the darkening, 0 disables it
+    pub intensity: FScalar,
+    // Samples per pixel, at most `MAX_SSAO_SAMPLES`
+    pub sample_count: usize,
+    // Depth offset avoiding self occlusion on flat surfaces, in world units
+    pub bias: FScalar,
+    // Depth aware blur removing the noise of the rotated kernel
+    pub blur: bool,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        return SsaoSettings {
+            radius: 0.5,
+            intensity: 1.0,
+            sample_count: 16,
+            bias: 0.025,
+            blur: true,
+        };
+    }
+}
+
+impl SsaoSettings {
+    pub fn clamped_sample_count(&self) -> usize {
+        return self.sample_count.max(1).min(MAX_SSAO_SAMPLES);
+    }
+
+    // Fullscreen draws of the pass, the occlusion and the optional blur.
+    pub fn draw_calls(&self) -> u32 {
+        if self.blur {
+            return 2;
+        }
+        return 1;
+    }
+}
+
+// Sample offsets in the unit hemisphere around +Z, in tangent space. Samples
+// are spread with low discrepancy sequences and get denser close to the center,
+// where occlusion matters most.
+pub fn ssao_kernel(sample_count: usize) -> Vec<Vector3f> {
+    return (0..sample_count)
+        .map(|i| {
+            let index = i as u64 + 1;
+            let phi = 2.0 * std::f32::consts::PI * halton(index, 2);
+            let cos_theta = halton(index, 3);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let direction = Vector3f::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+
+            let t = (i as FScalar + 0.5) / sample_count as FScalar;
+            let scale = 0.1 + 0.9 * t * t;
+            return direction * halton(index, 5).max(0.1) * scale;
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_test() {
+        let kernel = ssao_kernel(32);
+        assert_eq!(kernel.len(), 32);
+        for sample in &kernel {
+            assert!(sample.z >= 0.0);
+            assert!(sample.norm() <= 1.0);
+            assert!(sample.norm() > 0.0);
+        }
+        // Deterministic, so the noise pattern doesn't change between frames.
+        assert_eq!(kernel, ssao_kernel(32));
+    }
+
+    #[test]
+    fn sample_count_test() {
+        let mut settings = SsaoSettings::default();
+        settings.sample_count = 0;
+        assert_eq!(settings.clamped_sample_count(), 1);
+        settings.sample_count = 1000;
+        assert_eq!(settings.clamped_sample_count(), MAX_SSAO_SAMPLES);
+    }
+}
@@ -0,0 +1,442 @@
+use crate::{
+    common::BufferlessPipeline,
+    config,
+    post_pass::PostTargets,
+    ssao::{ssao_kernel, SsaoSettings, MAX_SSAO_SAMPLES},
+    vertex::Vertex,
+};
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferAccess, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, PipelineLayoutAbstract},
+    device::{Device, Queue},
+    format::{ClearValue, Format},
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{AttachmentImage, Dimensions, ImageUsage, ImmutableImage},
+    pipeline::{
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/forward.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod depth_only_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/depth_only.frag"
+    }
+}
+
+mod fullscreen_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/fullscreen.vert"
+    }
+}
+
+mod ssao_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/ssao.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod blur_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/ssao_blur.frag",
+        include: ["src/shaders"]
+    }
+}
+
+// Ambient occlusion images of a window, built around its HDR scene targets.
+pub struct SsaoTargets {
+    hdr_image: Arc<AttachmentImage>,
+    depth_buffer: Arc<AttachmentImage>,
+    // Noisy occlusion, input of the blur
+    raw: Arc<AttachmentImage>,
+    raw_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    // Occlusion read by the lighting shaders
+    pub ao_image: Arc<AttachmentImage>,
+    ao_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    // Depth only, the forward path fills depth before shading
+    pub prepass_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    // Forward scene pass keeping the prepass depth
+    pub scene_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+impl SsaoTargets {
+    // Whether the targets were created for the current scene targets.
+    pub fn matches(&self, post_targets: &PostTargets) -> bool {
+        return Arc::ptr_eq(&self.hdr_image, &post_targets.hdr_image);
+    }
+}
+
+pub struct SsaoRenderer {
+    device: Arc<Device>,
+    pub prepass_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    scene_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    occlusion_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    pub prepass_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    ssao_pipeline: Arc<BufferlessPipeline>,
+    blur_pipeline: Arc<BufferlessPipeline>,
+    kernel_pool: CpuBufferPool<ssao_fs::ty::Kernel>,
+    pub sampler: Arc<Sampler>,
+    // White, bound when ambient occlusion is disabled
+    pub fallback: Arc<ImmutableImage<Format>>,
+}
+
+impl SsaoRenderer {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        let prepass_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+        // Compatible with the forward scene pass, so forward pipelines can be used in
+        // it.
+        let scene_render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: config::HDR_FORMAT,
+                        samples: 1,
+                    },
+                    velocity: {
+                        load: Clear,
+                        store: Store,
+                        format: config::VELOCITY_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Load,
+                        store: Store,
+                        format: config::DEFAULT_DEPTH_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color, velocity],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+        let occlusion_render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    occlusion: {
+                        load: DontCare,
+                        store: Store,
+                        format: config::AMBIENT_OCCLUSION_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [occlusion],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let depth_only_fs = depth_only_fs::Shader::load(device.clone()).unwrap();
+        let fullscreen_vs = fullscreen_vs::Shader::load(device.clone()).unwrap();
+        let ssao_fs = ssao_fs::Shader::load(device.clone()).unwrap();
+        let blur_fs = blur_fs::Shader::load(device.clone()).unwrap();
+
+        let prepass_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(depth_only_fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(prepass_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let ssao_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(fullscreen_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(ssao_fs.main_entry_point(), ())
+                .render_pass(Subpass::from(occlusion_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let blur_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition {})
+                .vertex_shader(fullscreen_vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(blur_fs.main_entry_point(), ())
+                .render_pass(Subpass::from(occlusion_render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+
+        // Depth and occlusion are read at texel centers.
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let (fallback, upload) = ImmutableImage::from_iter(
+            std::iter::once(255u8),
+            Dimensions::Dim2d {
+                width: 1,
+                height: 1,
+            },
+            config::AMBIENT_OCCLUSION_FORMAT,
+            queue,
+        )
+        .unwrap();
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        return SsaoRenderer {
+            device: device.clone(),
+            prepass_render_pass,
+            scene_render_pass,
+            occlusion_render_pass,
+            prepass_pipeline,
+            ssao_pipeline,
+            blur_pipeline,
+            kernel_pool: CpuBufferPool::uniform_buffer(device),
+            sampler,
+            fallback,
+        };
+    }
+
+    pub fn create_targets(
+        &self,
+        post_targets: &PostTargets,
+        depth_buffer: Arc<AttachmentImage>,
+    ) -> SsaoTargets {
+        let dimensions = depth_buffer.dimensions();
+        let usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+        let attachment = || {
+            let image = AttachmentImage::with_usage(
+                self.device.clone(),
+                dimensions,
+                config::AMBIENT_OCCLUSION_FORMAT,
+                usage,
+            )
+            .unwrap();
+            let framebuffer = Arc::new(
+                Framebuffer::start(self.occlusion_render_pass.clone())
+                    .add(image.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn FramebufferAbstract + Send + Sync>;
+            return (image, framebuffer);
+        };
+        let (raw, raw_framebuffer) = attachment();
+        let (ao_image, ao_framebuffer) = attachment();
+
+        let prepass_framebuffer = Arc::new(
+            Framebuffer::start(self.prepass_render_pass.clone())
+                .add(depth_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let scene_framebuffer = Arc::new(
+            Framebuffer::start(self.scene_render_pass.clone())
+                .add(post_targets.hdr_image.clone())
+                .unwrap()
+                .add(post_targets.velocity_image.clone())
+                .unwrap()
+                .add(depth_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        return SsaoTargets {
+            hdr_image: post_targets.hdr_image.clone(),
+            depth_buffer,
+            raw,
+            raw_framebuffer,
+            ao_image,
+            ao_framebuffer,
+            prepass_framebuffer,
+            scene_framebuffer,
+        };
+    }
+
+    // Computes `targets.ao_image` from the depth buffer, which has to be filled
+    // already. Without `normals` they are reconstructed from depth.
+    pub fn record<B>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        targets: &SsaoTargets,
+        frame_data: B,
+        normals: Option<Arc<AttachmentImage>>,
+        settings: &SsaoSettings,
+        dynamic_state: &DynamicState,
+    ) -> AutoCommandBufferBuilder
+    where
+        B: BufferAccess + Clone + Send + Sync + 'static,
+    {
+        let sample_count = settings.clamped_sample_count();
+        let mut kernel = ssao_fs::ty::Kernel {
+            samples: [[0.0; 4]; MAX_SSAO_SAMPLES],
+        };
+        for (sample, offset) in kernel.samples.iter_mut().zip(ssao_kernel(sample_count)) {
+            *sample = [offset.x, offset.y, offset.z, 0.0];
+        }
+        let kernel_buffer = self.kernel_pool.next(kernel).unwrap();
+
+        let use_normals = normals.is_some();
+        let frame_set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.ssao_pipeline.descriptor_set_layout(0).unwrap().clone(),
+            )
+            .add_buffer(frame_data.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        let input_set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.ssao_pipeline.descriptor_set_layout(1).unwrap().clone(),
+            )
+            .add_sampled_image(targets.depth_buffer.clone(), self.sampler.clone())
+            .unwrap()
+            // Never read without normals, any image of the right type works.
+            .add_sampled_image(
+                normals.unwrap_or_else(|| targets.depth_buffer.clone()),
+                self.sampler.clone(),
+            )
+            .unwrap()
+            .add_buffer(kernel_buffer)
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        let push_constants = ssao_fs::ty::PushConstants {
+            params: [settings.radius, settings.intensity, settings.bias, 0.0],
+            info: [sample_count as u32, use_normals as u32, 0, 0],
+        };
+        let output = if settings.blur {
+            targets.raw_framebuffer.clone()
+        } else {
+            targets.ao_framebuffer.clone()
+        };
+
+        let builder = builder
+            .begin_render_pass(output, false, vec![ClearValue::None])
+            .unwrap()
+            .draw(
+                self.ssao_pipeline.clone(),
+                dynamic_state,
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                (frame_set, input_set),
+                push_constants,
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+        if !settings.blur {
+            return builder;
+        }
+
+        let frame_set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.blur_pipeline.descriptor_set_layout(0).unwrap().clone(),
+            )
+            .add_buffer(frame_data)
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        let input_set = Arc::new(
+            PersistentDescriptorSet::start(
+                self.blur_pipeline.descriptor_set_layout(1).unwrap().clone(),
+            )
+            .add_sampled_image(targets.raw.clone(), self.sampler.clone())
+            .unwrap()
+            .add_sampled_image(targets.depth_buffer.clone(), self.sampler.clone())
+            .unwrap()
+            .build()
+            .unwrap(),
+        );
+        return builder
+            .begin_render_pass(
+                targets.ao_framebuffer.clone(),
+                false,
+                vec![ClearValue::None],
+            )
+            .unwrap()
+            .draw(
+                self.blur_pipeline.clone(),
+                dynamic_state,
+                BufferlessVertices {
+                    vertices: 3,
+                    instances: 1,
+                },
+                (frame_set, input_set),
+                (),
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+}
@@ -1,14 +1,50 @@
 use crate::{
+    antialiasing::AntiAliasing,
+    camera::Camera,
+    capture::{CaptureFormat, CaptureId, CaptureWriter, FrameSequence, SequenceRecording},
+    capture_pass::CaptureRequest,
+    compute_pass::ComputeQueue,
     context::RenderContext,
+    culling::cull_objects,
+    debug_draw::DebugDraw,
+    debug_utils::create_instance,
+    debug_view::DebugView,
+    decal::{Decal, DecalId, DecalTexture, DecalTextureId},
+    environment::{EnvironmentId, HdrImage, IblSettings},
     error::RenderingError,
-    renderer::Renderer,
-    vertex::Vertex,
+    font_atlas::{FontSettings, SdfFont},
+    geometry::Geometry,
+    light::{Light, LightId},
+    lod::LodGroup,
+    material::{Material, MaterialId},
+    particles::{ParticleEmitter, ParticleEmitterId},
+    picking::{PickId, PickRegion},
+    post::PostProcessSettings,
+    profiling::RenderStats,
+    render_queue::TransparencyMode,
+    renderer::{FrameInput, RenderPath, Renderer},
+    shadow::ShadowSettings,
+    skinning::{JointPose, Skeleton, SkinnedMeshId, SkinnedObjectId, SkinningMethod},
+    skinning_pass::SkinnedMesh,
+    sprites::{Camera2d, Sprite, SpriteAtlas, SpriteAtlasId, SpriteId, TileMap, TileMapId},
+    ssao::SsaoSettings,
+    terrain::{Terrain, TerrainId},
+    text::{FontId, Text, TextId},
+    vertex::{SkinnedVertex, Vertex},
+    voxel::{BlockAtlas, BlockId, BlockTextures, Chunk},
     GeometryId,
+    ObjectId,
+};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+    vec::Vec,
 };
-use std::{sync::Arc, vec::Vec};
 use vulkano::{
     buffer::{BufferUsage, CpuAccessibleBuffer},
-    instance::Instance,
+    instance::{debug::DebugCallback, Instance},
 };
 use winit::{dpi::PhysicalSize, event_loop::EventLoopWindowTarget, window::WindowId};
 
@@ -17,8 +53,21 @@ use polyengine_core::*;
 pub struct RenderingSystem {
     #[allow(dead_code)] // TODO remove this
     instance: Arc<Instance>,
+    // Logs validation messages while alive, only created in debug mode
+    #[allow(dead_code)]
+    debug_callback: Option<DebugCallback>,
     context: RenderContext,
     renderer: Renderer,
+    debug_draw: DebugDraw,
+    ambient_light: Vector3f,
+    environment: Option<EnvironmentId>,
+    environment_intensity: FScalar,
+    // Material of all chunks, tinting the block textures
+    block_material: Option<MaterialId>,
+    log_render_stats: bool,
+    capture_writer: CaptureWriter,
+    // Window whose frames are written, time advances at a fixed rate meanwhile
+    frame_sequence: Option<(WindowId, SequenceRecording)>,
 
     // TEMPORARY
     #[allow(dead_code)]
@@ -26,14 +75,24 @@ pub struct RenderingSystem {
 }
 
 impl RenderingSystem {
-    pub fn new(elwt: &EventLoopWindowTarget<()>) -> Self {
-        // Instance
-        let instance = Instance::new(None, &vulkano_win::required_extensions(), None)
-            .expect("failed to create instance");
+    pub fn new(elwt: &EventLoopWindowTarget<()>) -> Self { return Self::create(elwt, false); }
+
+    // Same as `new` with the Vulkan validation layer, whose messages are logged at
+    // their severity. Images and passes are named for graphics debuggers like
+    // RenderDoc. Validation is skipped with a warning when the layer isn't
+    // installed.
+    pub fn with_debug_mode(elwt: &EventLoopWindowTarget<()>) -> Self {
+        return Self::create(elwt, true);
+    }
+
+    fn create(elwt: &EventLoopWindowTarget<()>, debug: bool) -> Self {
+        let (instance, debug_callback) = create_instance(debug);
 
         let context = RenderContext::new(elwt, instance.clone());
         let renderer = Renderer::new(
             context.device.clone(),
+            context.queue.clone(),
+            context.compute_queue.clone(),
             context.default_window_render_pass.clone(),
         );
 
@@ -45,12 +104,15 @@ impl RenderingSystem {
             [
                 Vertex {
                     position: [-0.5, -0.25, 0.0],
+                    ..Vertex::default()
                 },
                 Vertex {
                     position: [0.0, 0.5, 0.0],
+                    ..Vertex::default()
                 },
                 Vertex {
                     position: [0.25, -0.1, 0.0],
+                    ..Vertex::default()
                 },
             ]
             .iter()
@@ -61,8 +123,17 @@ impl RenderingSystem {
 
         return RenderingSystem {
             instance,
+            debug_callback,
             context,
             renderer,
+            debug_draw: DebugDraw::new(),
+            ambient_light: Vector3f::new(0.03, 0.03, 0.03),
+            environment: None,
+            environment_intensity: 1.0,
+            block_material: None,
+            log_render_stats: false,
+            capture_writer: CaptureWriter::default(),
+            frame_sequence: None,
 
             vertex_buffer: vec![vertex_buffer],
         };
@@ -81,6 +152,9 @@ impl RenderingSystem {
     }
 
     pub fn close_window(&mut self, window_id: WindowId) -> bool {
+        if self.frame_sequence.as_ref().map(|(id, _)| *id) == Some(window_id) {
+            self.frame_sequence = None;
+        }
         return match self.context.close_window(window_id) {
             Ok(_) => self.context.window_count() == 0,
             Err(_) => false,
@@ -91,21 +165,804 @@ impl RenderingSystem {
         return self.context.create_geometry(data);
     }
 
-    pub fn end_frame(&mut self) {
+    pub fn create_geometry_from_vertices(&mut self, vertices: &[Vertex]) -> GeometryId {
+        return self.context.add_geometry(Geometry::from_vertices(
+            self.context.device.clone(),
+            vertices,
+        ));
+    }
+
+    pub fn create_object(
+        &mut self,
+        geometry_id: GeometryId,
+        transform: Similarity3,
+    ) -> Result<ObjectId, RenderingError> {
+        return self
+            .context
+            .create_object(LodGroup::single(geometry_id), transform);
+    }
+
+    pub fn create_lod_object(
+        &mut self,
+        lod_group: LodGroup,
+        transform: Similarity3,
+    ) -> Result<ObjectId, RenderingError> {
+        return self.context.create_object(lod_group, transform);
+    }
+
+    pub fn set_object_transform(
+        &mut self,
+        object_id: ObjectId,
+        transform: Similarity3,
+    ) -> Result<(), RenderingError> {
+        return self.context.set_object_transform(object_id, transform);
+    }
+
+    pub fn remove_object(&mut self, object_id: ObjectId) -> Result<(), RenderingError> {
+        return self.context.remove_object(object_id);
+    }
+
+    pub fn set_object_material(
+        &mut self,
+        object_id: ObjectId,
+        material_id: MaterialId,
+    ) -> Result<(), RenderingError> {
+        return self.context.set_object_material(object_id, material_id);
+    }
+
+    pub fn create_material(&mut self, material: Material) -> MaterialId {
+        return self.context.create_material(material);
+    }
+
+    pub fn update_material(
+        &mut self,
+        material_id: MaterialId,
+        material: Material,
+    ) -> Result<(), RenderingError> {
+        return self.context.update_material(material_id, material);
+    }
+
+    pub fn create_light(&mut self, light: Light) -> LightId {
+        return self.context.create_light(light);
+    }
+
+    pub fn update_light(&mut self, light_id: LightId, light: Light) -> Result<(), RenderingError> {
+        return self.context.update_light(light_id, light);
+    }
+
+    pub fn remove_light(&mut self, light_id: LightId) -> Result<(), RenderingError> {
+        return self.context.remove_light(light_id);
+    }
+
+    // Particles are simulated separately in every window, colliding with what
+    // the window shows.
+    pub fn create_particle_emitter(&mut self, emitter: ParticleEmitter) -> ParticleEmitterId {
+        return self.context.create_particle_emitter(emitter);
+    }
+
+    // Live particles are kept unless the maximum particle count changes.
+    pub fn update_particle_emitter(
+        &mut self,
+        emitter_id: ParticleEmitterId,
+        emitter: ParticleEmitter,
+    ) -> Result<(), RenderingError> {
+        return self.context.update_particle_emitter(emitter_id, emitter);
+    }
+
+    pub fn remove_particle_emitter(
+        &mut self,
+        emitter_id: ParticleEmitterId,
+    ) -> Result<(), RenderingError> {
+        return self.context.remove_particle_emitter(emitter_id);
+    }
+
+    // Uploads the atlas texels. Blocks until the GPU is done.
+    pub fn create_sprite_atlas(&mut self, atlas: &SpriteAtlas) -> SpriteAtlasId {
+        let atlas = self.renderer.sprites.create_atlas(atlas);
+        return self.context.add_sprite_atlas(atlas);
+    }
+
+    // Sprites and tile maps still using the atlas are skipped until they are
+    // updated.
+    pub fn remove_sprite_atlas(&mut self, atlas_id: SpriteAtlasId) -> Result<(), RenderingError> {
+        return self.context.remove_sprite_atlas(atlas_id);
+    }
+
+    // Sprites are drawn in all windows with a 2D camera.
+    pub fn create_sprite(&mut self, sprite: Sprite) -> SpriteId {
+        return self.context.create_sprite(sprite);
+    }
+
+    pub fn update_sprite(
+        &mut self,
+        sprite_id: SpriteId,
+        sprite: Sprite,
+    ) -> Result<(), RenderingError> {
+        return self.context.update_sprite(sprite_id, sprite);
+    }
+
+    pub fn remove_sprite(&mut self, sprite_id: SpriteId) -> Result<(), RenderingError> {
+        return self.context.remove_sprite(sprite_id);
+    }
+
+    pub fn create_tile_map(&mut self, tile_map: TileMap) -> TileMapId {
+        return self.context.create_tile_map(tile_map);
+    }
+
+    pub fn update_tile_map(
+        &mut self,
+        tile_map_id: TileMapId,
+        tile_map: TileMap,
+    ) -> Result<(), RenderingError> {
+        return self.context.update_tile_map(tile_map_id, tile_map);
+    }
+
+    pub fn remove_tile_map(&mut self, tile_map_id: TileMapId) -> Result<(), RenderingError> {
+        return self.context.remove_tile_map(tile_map_id);
+    }
+
+    // Parses TTF or OTF data and uploads a signed distance field atlas of its
+    // glyphs. Generating the atlas is slow, with a cache directory in the
+    // settings it only happens once. Blocks until the GPU is done.
+    pub fn load_font(
+        &mut self,
+        data: &[u8],
+        settings: &FontSettings,
+    ) -> Result<FontId, RenderingError> {
+        let font = self
+            .renderer
+            .text
+            .create_font(SdfFont::load(data, settings)?);
+        return Ok(self.context.add_font(font));
+    }
+
+    // Texts still using the font are skipped until they are updated.
+    pub fn remove_font(&mut self, font_id: FontId) -> Result<(), RenderingError> {
+        return self.context.remove_font(font_id);
+    }
+
+    // Screen text is drawn in every window, world text only in windows with a
+    // 3D camera.
+    pub fn create_text(&mut self, text: Text) -> TextId { return self.context.create_text(text); }
+
+    pub fn update_text(&mut self, text_id: TextId, text: Text) -> Result<(), RenderingError> {
+        return self.context.update_text(text_id, text);
+    }
+
+    pub fn remove_text(&mut self, text_id: TextId) -> Result<(), RenderingError> {
+        return self.context.remove_text(text_id);
+    }
+
+    // Width and height of the text once laid out, in the units of its size.
+    pub fn measure_text(&self, text: &Text) -> Result<Vector2f, RenderingError> {
+        let font = match self.context.fonts.get(&text.font) {
+            Some(font) => font,
+            None => return Err(RenderingError::FontNotFound),
+        };
+        return Ok(text.layout(&font.metrics).size * text.size);
+    }
+
+    // Skinned vertices reference joints of the skeleton by index.
+    pub fn create_skinned_mesh(
+        &mut self,
+        vertices: &[SkinnedVertex],
+        skeleton: Skeleton,
+    ) -> Result<SkinnedMeshId, RenderingError> {
+        let joint_count = skeleton.joints.len() as u32;
+        let joints_valid = vertices
+            .iter()
+            .all(|vertex| vertex.joints.iter().all(|&joint| joint < joint_count));
+        if !skeleton.is_valid() || !joints_valid {
+            return Err(RenderingError::InvalidSkin);
+        }
+        let mesh = SkinnedMesh::new(self.context.device.clone(), vertices, skeleton);
+        return Ok(self.context.add_skinned_mesh(mesh));
+    }
+
+    // Skinned objects still using the mesh are skipped.
+    pub fn remove_skinned_mesh(&mut self, mesh_id: SkinnedMeshId) -> Result<(), RenderingError> {
+        return self.context.remove_skinned_mesh(mesh_id);
+    }
+
+    // The object starts in the bind pose of the mesh skeleton.
+    pub fn create_skinned_object(
+        &mut self,
+        mesh_id: SkinnedMeshId,
+        transform: Similarity3,
+    ) -> Result<SkinnedObjectId, RenderingError> {
+        return self.context.create_skinned_object(mesh_id, transform);
+    }
+
+    // Local joint poses in skeleton order, typically sampled from an animation.
+    // Joints without a pose keep their bind pose.
+    pub fn set_skinned_object_pose(
+        &mut self,
+        object_id: SkinnedObjectId,
+        poses: &[JointPose],
+    ) -> Result<(), RenderingError> {
+        return self.context.set_skinned_pose(object_id, poses);
+    }
+
+    pub fn set_skinned_object_transform(
+        &mut self,
+        object_id: SkinnedObjectId,
+        transform: Similarity3,
+    ) -> Result<(), RenderingError> {
+        self.context.skinned_object_mut(object_id)?.transform = transform;
+        return Ok(());
+    }
+
+    pub fn set_skinned_object_material(
+        &mut self,
+        object_id: SkinnedObjectId,
+        material_id: MaterialId,
+    ) -> Result<(), RenderingError> {
+        self.context.skinned_object_mut(object_id)?.material = Some(material_id);
+        return Ok(());
+    }
+
+    pub fn set_skinning_method(
+        &mut self,
+        object_id: SkinnedObjectId,
+        method: SkinningMethod,
+    ) -> Result<(), RenderingError> {
+        self.context.skinned_object_mut(object_id)?.method = method;
+        return Ok(());
+    }
+
+    pub fn remove_skinned_object(
+        &mut self,
+        object_id: SkinnedObjectId,
+    ) -> Result<(), RenderingError> {
+        return self.context.remove_skinned_object(object_id);
+    }
+
+    // Blocks are placed in a world of chunks drawn in every window with a 3D
+    // camera. Changed chunks are remeshed at the end of the frame.
+    pub fn set_block(&mut self, position: &Vector3i, block: BlockId) {
+        self.context.voxel_world.set_block(position, block);
+    }
+
+    pub fn block(&self, position: &Vector3i) -> BlockId {
+        return self.context.voxel_world.block(position);
+    }
+
+    // Replaces a whole chunk, faster than setting its blocks one by one.
+    pub fn set_chunk(&mut self, chunk_position: Vector3i, chunk: Chunk) {
+        self.context.voxel_world.set_chunk(chunk_position, chunk);
+    }
+
+    pub fn remove_chunk(&mut self, chunk_position: &Vector3i) -> Option<Chunk> {
+        return self.context.voxel_world.remove_chunk(chunk_position);
+    }
+
+    // Layers of the block atlas used by the faces of the block. Remeshes every
+    // chunk.
+    pub fn set_block_textures(&mut self, block: BlockId, textures: BlockTextures) {
+        self.context.voxel_world.set_block_textures(block, textures);
+    }
+
+    // Uploads the texture array of the block faces. Blocks until the GPU is done.
+    pub fn set_block_atlas(&mut self, atlas: &BlockAtlas) {
+        self.context.block_atlas = Some(self.renderer.voxels.create_atlas(atlas));
+    }
+
+    // Chunks use the default material when not set or the material is missing.
+    pub fn set_block_material(&mut self, material_id: Option<MaterialId>) {
+        self.block_material = material_id;
+    }
+
+    // Builds the level of detail quadtree and uploads the images of the terrain.
+    // Blocks until the GPU is done. Terrains receive shadows but don't cast them.
+    pub fn create_terrain(&mut self, terrain: Terrain) -> Result<TerrainId, RenderingError> {
+        if !terrain.is_valid() {
+            return Err(RenderingError::InvalidTerrain);
+        }
+        let terrain = self.renderer.terrain.create_terrain(terrain);
+        return Ok(self.context.add_terrain(terrain));
+    }
+
+    pub fn remove_terrain(&mut self, terrain_id: TerrainId) -> Result<(), RenderingError> {
+        return self.context.remove_terrain(terrain_id);
+    }
+
+    pub fn terrain(&self, terrain_id: TerrainId) -> Option<&Terrain> {
+        return self
+            .context
+            .terrains
+            .get(&terrain_id)
+            .map(|terrain| &terrain.terrain);
+    }
+
+    // Height of the terrain above the world position on the XZ plane, None outside
+    // of the terrain or when it doesn't exist.
+    pub fn terrain_height(&self, terrain_id: TerrainId, position: &Vector2f) -> Option<FScalar> {
+        return self.terrain(terrain_id)?.height_at(position);
+    }
+
+    // Surface normal of the terrain at the world position on the XZ plane.
+    pub fn terrain_normal(&self, terrain_id: TerrainId, position: &Vector2f) -> Option<Vector3f> {
+        return self.terrain(terrain_id)?.normal_at(position);
+    }
+
+    // Uploads the texels of a decal texture. Blocks until the GPU is done.
+    pub fn create_decal_texture(&mut self, texture: &DecalTexture) -> DecalTextureId {
+        let texture = self.renderer.decals.create_texture(texture);
+        return self.context.add_decal_texture(texture);
+    }
+
+    // Decals still using the texture are skipped until they are updated.
+    pub fn remove_decal_texture(
+        &mut self,
+        texture_id: DecalTextureId,
+    ) -> Result<(), RenderingError> {
+        return self.context.remove_decal_texture(texture_id);
+    }
+
+    // Decals are drawn in all windows with a 3D camera. In the deferred path they
+    // only project onto surfaces in the G-buffer, skinned objects, chunks and
+    // terrains are shaded later.
+    pub fn create_decal(&mut self, decal: Decal) -> Result<DecalId, RenderingError> {
+        if !decal.is_valid() {
+            return Err(RenderingError::InvalidDecal);
+        }
+        return Ok(self.context.create_decal(decal));
+    }
+
+    pub fn update_decal(&mut self, decal_id: DecalId, decal: Decal) -> Result<(), RenderingError> {
+        if !decal.is_valid() {
+            return Err(RenderingError::InvalidDecal);
+        }
+        return self.context.update_decal(decal_id, decal);
+    }
+
+    pub fn remove_decal(&mut self, decal_id: DecalId) -> Result<(), RenderingError> {
+        return self.context.remove_decal(decal_id);
+    }
+
+    // Limits the decals drawn per window and frame, the lowest ordered and then
+    // the oldest visible decals are left out first.
+    pub fn set_decal_budget(&mut self, budget: usize) { self.renderer.decal_budget = budget; }
+
+    // Constant light added to every surface, in linear RGB.
+    pub fn set_ambient_light(&mut self, color: Vector3f) { self.ambient_light = color; }
+
+    // Precomputes the skybox and image based lighting cubemaps of an
+    // equirectangular HDR image. Blocks until the GPU is done.
+    pub fn create_environment(&mut self, image: &HdrImage) -> EnvironmentId {
+        let environment = self
+            .renderer
+            .environment
+            .create_environment(image, &self.renderer.ibl_settings);
+        return self.context.add_environment(environment);
+    }
+
+    pub fn remove_environment(
+        &mut self,
+        environment_id: EnvironmentId,
+    ) -> Result<(), RenderingError> {
+        if self.environment == Some(environment_id) {
+            self.environment = None;
+        }
+        return self.context.remove_environment(environment_id);
+    }
+
+    // Environment drawn as the skybox and lighting the scene on top of the
+    // ambient light, None disables both.
+    pub fn set_environment(
+        &mut self,
+        environment_id: Option<EnvironmentId>,
+    ) -> Result<(), RenderingError> {
+        if let Some(id) = environment_id {
+            if !self.context.environments.contains_key(&id) {
+                return Err(RenderingError::EnvironmentNotFound);
+            }
+        }
+        self.environment = environment_id;
+        return Ok(());
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: FScalar) {
+        self.environment_intensity = intensity;
+    }
+
+    // Cubemap resolutions of environments created afterwards.
+    pub fn set_ibl_settings(&mut self, settings: IblSettings) {
+        self.renderer.ibl_settings = settings;
+    }
+
+    // Sets the camera used to render given window.
+    pub fn set_camera(
+        &mut self,
+        window_id: WindowId,
+        camera: Camera,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.camera = camera;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    // Renders the sprites and tile maps of the scene in given window instead of
+    // the 3D scene, None switches back to the 3D camera.
+    pub fn set_camera_2d(
+        &mut self,
+        window_id: WindowId,
+        camera: Option<Camera2d>,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.camera_2d = camera;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    pub fn shadow_settings(&self) -> &ShadowSettings { return &self.renderer.shadow_settings; }
+
+    // Shadow atlas size, cascades and filtering, applied to all windows.
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.renderer.shadow_settings = settings;
+    }
+
+    // Post-process stack used to resolve the HDR image of the window.
+    pub fn set_post_process(
+        &mut self,
+        window_id: WindowId,
+        settings: PostProcessSettings,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.post_process = settings;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    // Screen space anti-aliasing of the window.
+    pub fn set_anti_aliasing(
+        &mut self,
+        window_id: WindowId,
+        anti_aliasing: AntiAliasing,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.anti_aliasing = anti_aliasing;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    // Forward or deferred shading of the window.
+    pub fn set_render_path(
+        &mut self,
+        window_id: WindowId,
+        render_path: RenderPath,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.render_path = render_path;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    // Screen space ambient occlusion of the window, None disables it.
+    pub fn set_ssao(
+        &mut self,
+        window_id: WindowId,
+        settings: Option<SsaoSettings>,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.ssao = settings;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    // Replaces the shading of the window with a debug visualisation.
+    pub fn set_debug_view(
+        &mut self,
+        window_id: WindowId,
+        view: DebugView,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.debug_view = view;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    // Culls and draws opaque objects of the window on the GPU with indirect
+    // draws, the CPU records the same commands regardless of the object count.
+    pub fn set_gpu_driven(
+        &mut self,
+        window_id: WindowId,
+        enabled: bool,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.gpu_driven = enabled;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    // Logs the render stats of every finished frame.
+    pub fn set_render_stats_logging(&mut self, enabled: bool) { self.log_render_stats = enabled; }
+
+    // How transparent surfaces of the window are composited.
+    pub fn set_transparency_mode(
+        &mut self,
+        window_id: WindowId,
+        transparency: TransparencyMode,
+    ) -> Result<(), RenderingError> {
+        match self.context.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.transparency = transparency;
+                return Ok(());
+            }
+            None => return Err(RenderingError::WindowNotFound),
+        }
+    }
+
+    // Immediate mode debug primitives, drawn in all windows.
+    pub fn debug_draw(&mut self) -> &mut DebugDraw { return &mut self.debug_draw; }
+
+    // Queue for compute work outside of the renderer, waited for by the next
+    // rendered frame.
+    pub fn compute(&mut self) -> &mut ComputeQueue { return &mut self.renderer.compute; }
+
+    // Requests the object visible at the pixel. Picking doesn't wait for the GPU,
+    // the result is available from `pick_result` a few frames later.
+    pub fn pick(&mut self, window_id: WindowId, pixel: [u32; 2]) -> Result<PickId, RenderingError> {
+        return self
+            .context
+            .request_pick(window_id, PickRegion::Pixel(pixel));
+    }
+
+    // Requests all objects visible in the rectangle between both corners, see
+    // `pick`.
+    pub fn pick_rectangle(
+        &mut self,
+        window_id: WindowId,
+        corner_a: [u32; 2],
+        corner_b: [u32; 2],
+    ) -> Result<PickId, RenderingError> {
+        return self
+            .context
+            .request_pick(window_id, PickRegion::Rectangle(corner_a, corner_b));
+    }
+
+    // Picked objects, sorted by ID, or None while the request is still pending.
+    // Pixel picks return at most one object. The result is removed once taken.
+    pub fn pick_result(&mut self, pick_id: PickId) -> Option<Vec<ObjectId>> {
+        return self.context.pick_results.remove(&pick_id);
+    }
+
+    // Requests a screenshot of the window, saved as PNG or EXR depending on the
+    // extension of the path. Doesn't wait for the GPU or the disk, the result is
+    // available from `capture_result` a few frames later.
+    pub fn capture(
+        &mut self,
+        window_id: WindowId,
+        path: &Path,
+    ) -> Result<CaptureId, RenderingError> {
+        let format = match CaptureFormat::from_path(path) {
+            Some(format) => format,
+            None => return Err(RenderingError::UnsupportedCaptureFormat),
+        };
+        return self
+            .context
+            .request_capture(window_id, path.to_path_buf(), format);
+    }
+
+    // Whether the capture was written, or None while it is still pending. The
+    // result is removed once taken.
+    pub fn capture_result(&mut self, capture_id: CaptureId) -> Option<Result<(), RenderingError>> {
+        return self.context.capture_results.remove(&capture_id);
+    }
+
+    // Writes every frame of the window to numbered files until
+    // `stop_frame_sequence`. Replaces the sequence recorded before. Meanwhile
+    // rendering time advances by the timestep of the sequence per frame, see
+    // `fixed_timestep`.
+    pub fn start_frame_sequence(
+        &mut self,
+        window_id: WindowId,
+        sequence: FrameSequence,
+    ) -> Result<(), RenderingError> {
+        if !self.context.windows.contains_key(&window_id) {
+            return Err(RenderingError::WindowNotFound);
+        }
+        self.frame_sequence = Some((window_id, SequenceRecording::new(sequence, Instant::now())));
+        return Ok(());
+    }
+
+    pub fn stop_frame_sequence(&mut self) { self.frame_sequence = None; }
+
+    // Timestep of the recorded frame sequence. Games should advance their
+    // simulation by exactly this much per frame while it is set.
+    pub fn fixed_timestep(&self) -> Option<Duration> {
+        return self
+            .frame_sequence
+            .as_ref()
+            .map(|(_, recording)| recording.sequence.timestep);
+    }
+
+    // Renders all windows. Returns the render stats of the latest frame of every
+    // window the GPU finished since the last call, frames in flight are reported
+    // by later calls.
+    pub fn end_frame(&mut self) -> HashMap<WindowId, RenderStats> {
+        if self
+            .context
+            .windows
+            .values()
+            .any(|window| window.gpu_driven)
+        {
+            self.context.update_geometry_arena();
+        }
+        self.context.update_chunk_meshes();
+        let pick_results = &mut self.context.pick_results;
+        let capture_writer = &self.capture_writer;
+        // Recorded sequences advance at a fixed rate instead of the wall clock
+        let now = match &self.frame_sequence {
+            Some((_, recording)) => recording.frame_time(),
+            None => Instant::now(),
+        };
+        let mut render_stats = HashMap::new();
+        // Compute work of the frame, waited for by the first window submission
+        let mut compute_future = self.renderer.compute.take_pending();
         for window in self.context.windows.values_mut() {
             let (image_num, acquire_future) = match window.acquire_next_image() {
                 Ok(r) => r,
                 Err(RenderingError::ImageAcquireFailed) => {
-                    return;
+                    continue;
                 }
                 Err(RenderingError::RecreateSwapchainFailed) => {
-                    return;
+                    continue;
                 }
                 Err(e) => panic!("Acquire failed! {:?}", e),
             };
+            // Finished frames were cleaned up while acquiring, so their buffers can be
+            // read.
+            window
+                .pick_readbacks
+                .retain(|readback| match readback.try_read() {
+                    Some(object_ids) => {
+                        pick_results.insert(readback.pick_id, object_ids);
+                        return false;
+                    }
+                    None => return true,
+                });
+            window
+                .capture_readbacks
+                .retain(|readback| match readback.try_read() {
+                    Some(image) => {
+                        let request = &readback.request;
+                        capture_writer.write(
+                            request.capture_id,
+                            request.path.clone(),
+                            request.format,
+                            image,
+                        );
+                        return false;
+                    }
+                    None => return true,
+                });
+            // Frames finish in submission order
+            while let Some(stats) = window.pending_queries.first().and_then(|q| q.try_read()) {
+                if self.log_render_stats {
+                    log::info!("Render stats of {:?}: {}", window.id(), stats);
+                }
+                render_stats.insert(window.id(), stats);
+                let queries = window.pending_queries.remove(0);
+                window.spare_queries.push(queries);
+            }
+
+            // 2D windows draw no objects, so there is nothing to pick
+            let draws = if window.camera_2d.is_some() {
+                for (pick_id, _) in window.pick_requests.drain(..) {
+                    pick_results.insert(pick_id, Vec::new());
+                }
+                Vec::new()
+            } else {
+                cull_objects(
+                    &self.context.objects,
+                    &self.context.geometries,
+                    &window.camera,
+                    window.aspect_ratio(),
+                    window.gpu_driven,
+                    &mut window.culling_state,
+                )
+            };
+            let debug_batch = self
+                .renderer
+                .debug
+                .prepare(&self.debug_draw, &window.camera);
+            // The sequence clock only moves on when its window renders a frame
+            if let Some((window_id, recording)) = &mut self.frame_sequence {
+                if *window_id == window.id() {
+                    window.capture_requests.push(CaptureRequest {
+                        capture_id: None,
+                        path: recording.advance(),
+                        format: recording.sequence.format,
+                    });
+                }
+            }
+            self.renderer
+                .prepare_window(window, &self.context.particle_emitters, now);
+            let frame = FrameInput {
+                objects: &self.context.objects,
+                geometries: &self.context.geometries,
+                materials: &self.context.materials,
+                lights: &self.context.lights,
+                ambient_light: self.ambient_light,
+                environment: match self.environment {
+                    Some(id) => self.context.environments.get(&id),
+                    None => None,
+                },
+                environment_intensity: self.environment_intensity,
+                draws: &draws,
+                debug_batch: &debug_batch,
+                geometry_arena: self.context.geometry_arena.as_ref(),
+                particle_emitters: &self.context.particle_emitters,
+                sprites: &self.context.sprites,
+                tile_maps: &self.context.tile_maps,
+                sprite_atlases: &self.context.sprite_atlases,
+                texts: &self.context.texts,
+                fonts: &self.context.fonts,
+                skinned_objects: &self.context.skinned_objects,
+                skinned_meshes: &self.context.skinned_meshes,
+                chunk_meshes: &self.context.chunk_meshes,
+                block_atlas: self.context.block_atlas.as_ref(),
+                block_material: self.block_material,
+                terrains: &self.context.terrains,
+                decals: &self.context.decals,
+                decal_textures: &self.context.decal_textures,
+            };
+            let recycled_queries = window.spare_queries.pop();
+            let (command_buffer, readbacks, captures, queries) =
+                self.renderer
+                    .render(window, image_num, &frame, recycled_queries);
+            window.pick_readbacks.extend(readbacks);
+            window.capture_readbacks.extend(captures);
+            window.pending_queries.extend(queries);
+            window.pick_requests.clear();
+            window.capture_requests.clear();
+            window.present(
+                image_num,
+                acquire_future,
+                command_buffer,
+                compute_future.take(),
+            );
+        }
 
-            let g = &self.context.geometries.values().next().unwrap();
-            window.draw(image_num, acquire_future, &self.renderer, g);
+        // No window was rendered, the work is waited for by the next frame
+        if let Some(future) = compute_future {
+            self.renderer.compute.restore_pending(future);
+        }
+
+        for (capture_id, result) in self.capture_writer.finished() {
+            self.context.capture_results.insert(capture_id, result);
+        }
+        // Poses of this frame become the previous poses of the next one
+        for object in self.context.skinned_objects.values_mut() {
+            object.end_frame();
         }
+        self.debug_draw.end_frame(now);
+        return render_stats;
     }
 }
@@ -1,8 +1,13 @@
-use vulkano::framebuffer::{FramebufferAbstract, RenderPassAbstract};
+use vulkano::{
+    framebuffer::{FramebufferAbstract, RenderPassAbstract},
+    image::AttachmentImage,
+};
 
 use std::{sync::Arc, vec::Vec};
 
 pub struct RenderTarget {
     pub framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
     pub render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    // Scene depth, shared with the HDR scene framebuffer
+    pub depth_buffer: Arc<AttachmentImage>,
 }
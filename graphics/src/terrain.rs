@@ -0,0 +1,480 @@
+use crate::{bounds::Aabb, frustum::Frustum, material::MaterialId};
+use polyengine_core::*;
+
+pub type TerrainId = u32;
+
+// Quads along each side of a full patch
+pub const PATCH_RESOLUTION: u32 = 32;
+// Layers blended by the RGBA channels of the splat map
+pub const MAX_TERRAIN_LAYERS: usize = 4;
+// Fraction of the range of a level where its vertices start morphing into the
+// next level
+const MORPH_START: FScalar = 0.7;
+// Finest range in leaf patch sizes, lower ranges could place patches more than
+// one level apart next to each other and open cracks
+const MIN_LOD_DISTANCE: FScalar = 4.0;
+
+// Grid of heights in world units, row by row along +Z.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    pub width: u32,
+    pub depth: u32,
+    pub heights: Vec<FScalar>,
+}
+
+impl Heightmap {
+    // Returns None when the data doesn't match the size or there are less than 2
+    // samples along a side.
+    pub fn new(width: u32, depth: u32, heights: Vec<FScalar>) -> Option<Self> {
+        if width < 2 || depth < 2 || heights.len() != (width * depth) as usize {
+            return None;
+        }
+        return Some(Heightmap {
+            width,
+            depth,
+            heights,
+        });
+    }
+
+    // Samples outside of the heightmap are clamped to its edge.
+    pub fn height(&self, x: i64, z: i64) -> FScalar {
+        let x = x.max(0).min(self.width as i64 - 1) as usize;
+        let z = z.max(0).min(self.depth as i64 - 1) as usize;
+        return self.heights[x + z * self.width as usize];
+    }
+}
+
+// Weights of the terrain layers, stretched across the whole terrain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplatMap {
+    pub width: u32,
+    pub depth: u32,
+    // Weight of the first to fourth layer in the RGBA channels, row by row along
+    // +Z. Weights are normalized when shading.
+    pub weights: Vec<[u8; 4]>,
+}
+
+impl SplatMap {
+    // Returns None when the data doesn't match the size.
+    pub fn new(width: u32, depth: u32, weights: Vec<[u8; 4]>) -> Option<Self> {
+        if width == 0 || depth == 0 || weights.len() != (width * depth) as usize {
+            return None;
+        }
+        return Some(SplatMap {
+            width,
+            depth,
+            weights,
+        });
+    }
+}
+
+// Texture tiled across the terrain where the splat map selects it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerrainLayer {
+    // Side of the square texture, every layer of a terrain has the same size
+    pub size: u32,
+    // sRGB texels, row by row
+    pub texels: Vec<[u8; 4]>,
+    // World units covered by one repetition of the texture
+    pub tile_size: FScalar,
+}
+
+impl TerrainLayer {
+    // Returns None when the data doesn't match the size.
+    pub fn new(size: u32, texels: Vec<[u8; 4]>, tile_size: FScalar) -> Option<Self> {
+        if size == 0 || texels.len() != (size * size) as usize || tile_size <= 0.0 {
+            return None;
+        }
+        return Some(TerrainLayer {
+            size,
+            texels,
+            tile_size,
+        });
+    }
+}
+
+// Heightmap terrain. The first sample is at `origin`, the terrain extends along
+// +X and +Z and heights are added to the origin. +Y is up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Terrain {
+    pub heightmap: Heightmap,
+    pub origin: Vector3f,
+    // Distance between neighbouring samples
+    pub spacing: FScalar,
+    // The first layer covers the terrain when not set
+    pub splat_map: Option<SplatMap>,
+    pub layers: Vec<TerrainLayer>,
+    // Multiplied with the layers, uses the default material when not set
+    pub material: Option<MaterialId>,
+    // Distance up to which the full detail is drawn, doubling with every level.
+    // Raised to at least 4 full patch sizes.
+    pub lod_distance: FScalar,
+}
+
+impl Terrain {
+    pub fn new(heightmap: Heightmap, origin: Vector3f, spacing: FScalar) -> Self {
+        return Terrain {
+            heightmap,
+            origin,
+            spacing,
+            splat_map: None,
+            layers: Vec::new(),
+            material: None,
+            lod_distance: 0.0,
+        };
+    }
+
+    // Whether the spacing is positive and the layers fit and share a size.
+    pub fn is_valid(&self) -> bool {
+        let same_size = self
+            .layers
+            .windows(2)
+            .all(|layers| layers[0].size == layers[1].size);
+        return self.spacing > 0.0 && self.layers.len() <= MAX_TERRAIN_LAYERS && same_size;
+    }
+
+    // Horizontal size in world units.
+    pub fn size(&self) -> Vector2f {
+        return Vector2f::new(
+            (self.heightmap.width - 1) as FScalar,
+            (self.heightmap.depth - 1) as FScalar,
+        ) * self.spacing;
+    }
+
+    // Height of the surface at full detail above the world position on the XZ
+    // plane, None outside of the terrain.
+    pub fn height_at(&self, position: &Vector2f) -> Option<FScalar> {
+        let (x, z, fx, fz) = self.cell(position)?;
+        let h = |dx, dz| self.heightmap.height(x + dx, z + dz);
+        // Cells are split along the diagonal from the first to the last sample,
+        // like the grid mesh
+        let height = if fx >= fz {
+            h(0, 0) + fx * (h(1, 0) - h(0, 0)) + fz * (h(1, 1) - h(1, 0))
+        } else {
+            h(0, 0) + fz * (h(0, 1) - h(0, 0)) + fx * (h(1, 1) - h(0, 1))
+        };
+        return Some(self.origin.y + height);
+    }
+
+    // Smooth surface normal at the world position on the XZ plane, as shaded.
+    // None outside of the terrain.
+    pub fn normal_at(&self, position: &Vector2f) -> Option<Vector3f> {
+        let (x, z, fx, fz) = self.cell(position)?;
+        let n = |dx, dz| self.sample_normal(x + dx, z + dz);
+        let normal = (n(0, 0) * (1.0 - fx) + n(1, 0) * fx) * (1.0 - fz)
+            + (n(0, 1) * (1.0 - fx) + n(1, 1) * fx) * fz;
+        return Some(normal.normalize());
+    }
+
+    // Normal of a sample from central differences, has to match terrain.vert.
+    fn sample_normal(&self, x: i64, z: i64) -> Vector3f {
+        let h = |x, z| self.heightmap.height(x, z);
+        return Vector3f::new(
+            h(x - 1, z) - h(x + 1, z),
+            2.0 * self.spacing,
+            h(x, z - 1) - h(x, z + 1),
+        )
+        .normalize();
+    }
+
+    // Cell containing the position and the position in it.
+    fn cell(&self, position: &Vector2f) -> Option<(i64, i64, FScalar, FScalar)> {
+        let local = (position - self.origin.xz()) / self.spacing;
+        let max_x = (self.heightmap.width - 1) as FScalar;
+        let max_z = (self.heightmap.depth - 1) as FScalar;
+        if local.x < 0.0 || local.y < 0.0 || local.x > max_x || local.y > max_z {
+            return None;
+        }
+        // The last row and column belong to the cells before them
+        let x = local.x.floor().min(max_x - 1.0);
+        let z = local.y.floor().min(max_z - 1.0);
+        return Some((x as i64, z as i64, local.x - x, local.y - z));
+    }
+
+    fn lod_distance(&self) -> FScalar {
+        let minimum = MIN_LOD_DISTANCE * PATCH_RESOLUTION as FScalar * self.spacing;
+        return self.lod_distance.max(minimum);
+    }
+
+    // Distances where vertices of patches of the level start and finish morphing
+    // into the next level.
+    pub fn morph_range(&self, level: u32) -> [FScalar; 2] {
+        let end = self.lod_distance() * (1 << level) as FScalar;
+        return [end * MORPH_START, end];
+    }
+}
+
+// Part of the terrain drawn with a grid mesh.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TerrainPatch {
+    // First covered sample
+    pub origin: [u32; 2],
+    // Covered samples along each side
+    pub size: u32,
+    // Vertices are 2^level samples apart
+    pub level: u32,
+}
+
+impl TerrainPatch {
+    // Quads along each side, `PATCH_RESOLUTION` or half of it for quarters of a
+    // patch drawn at the level of their parent.
+    pub fn resolution(&self) -> u32 { return self.size >> self.level; }
+}
+
+// Quadtree of the terrain with the height range of every node, selecting
+// patches by the distance to the camera like CDLOD. Patches next to each other
+// are at most one level apart and vertices morph into the next level before
+// reaching the coarser patch, so there are no cracks.
+#[derive(Debug, Clone)]
+pub struct TerrainLod {
+    levels: u32,
+    // Minimum and maximum height of the nodes of every level, finest first and
+    // row by row
+    heights: Vec<Vec<(FScalar, FScalar)>>,
+}
+
+impl TerrainLod {
+    pub fn new(heightmap: &Heightmap) -> Self {
+        let quads = (heightmap.width - 1).max(heightmap.depth - 1);
+        let mut levels = 1;
+        while PATCH_RESOLUTION << (levels - 1) < quads {
+            levels += 1;
+        }
+
+        let leaves = 1usize << (levels - 1);
+        let mut level_heights = Vec::with_capacity(leaves * leaves);
+        for z in 0..leaves {
+            for x in 0..leaves {
+                let mut range = (FScalar::MAX, FScalar::MIN);
+                let first = [x as u32 * PATCH_RESOLUTION, z as u32 * PATCH_RESOLUTION];
+                // Leaves outside of the heightmap keep an empty range
+                if first[0] < heightmap.width - 1 && first[1] < heightmap.depth - 1 {
+                    let last = [
+                        (first[0] + PATCH_RESOLUTION).min(heightmap.width - 1),
+                        (first[1] + PATCH_RESOLUTION).min(heightmap.depth - 1),
+                    ];
+                    for sz in first[1]..=last[1] {
+                        for sx in first[0]..=last[0] {
+                            let h = heightmap.height(sx as i64, sz as i64);
+                            range = (range.0.min(h), range.1.max(h));
+                        }
+                    }
+                }
+                level_heights.push(range);
+            }
+        }
+        let mut heights = vec![level_heights];
+        for level in 1..levels as usize {
+            let nodes = leaves >> level;
+            let children = &heights[level - 1];
+            let mut level_heights = Vec::with_capacity(nodes * nodes);
+            for z in 0..nodes {
+                for x in 0..nodes {
+                    let mut range = (FScalar::MAX, FScalar::MIN);
+                    for (cx, cz) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let child = children[2 * x + cx + (2 * z + cz) * nodes * 2];
+                        range = (range.0.min(child.0), range.1.max(child.1));
+                    }
+                    level_heights.push(range);
+                }
+            }
+            heights.push(level_heights);
+        }
+        return TerrainLod { levels, heights };
+    }
+
+    // Patches covering the terrain in the frustum, finer closer to the camera.
+    pub fn select(
+        &self,
+        terrain: &Terrain,
+        camera_position: &Vector3f,
+        frustum: Option<&Frustum>,
+    ) -> Vec<TerrainPatch> {
+        let mut patches = Vec::new();
+        let root = self.levels - 1;
+        if !self.select_node(
+            terrain,
+            camera_position,
+            frustum,
+            root,
+            [0, 0],
+            &mut patches,
+        ) {
+            // Beyond the coarsest range, the coarsest level is used anyway
+            patches.push(self.patch(root, [0, 0], root));
+        }
+        return patches;
+    }
+
+    // Returns false when the node is visible but out of the range of its level,
+    // its parent covers it instead.
+    fn select_node(
+        &self,
+        terrain: &Terrain,
+        camera_position: &Vector3f,
+        frustum: Option<&Frustum>,
+        level: u32,
+        node: [u32; 2],
+        patches: &mut Vec<TerrainPatch>,
+    ) -> bool {
+        let aabb = match self.bounds(terrain, level, node) {
+            Some(aabb) => aabb,
+            // Nothing to draw outside of the heightmap
+            None => return true,
+        };
+        if frustum.map_or(false, |frustum| !frustum.intersects_aabb(&aabb)) {
+            return true;
+        }
+        let range = |level: u32| terrain.morph_range(level)[1];
+        if !sphere_intersects_aabb(camera_position, range(level), &aabb) {
+            return false;
+        }
+        if level == 0 || !sphere_intersects_aabb(camera_position, range(level - 1), &aabb) {
+            patches.push(self.patch(level, node, level));
+            return true;
+        }
+        for (x, z) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let child = [node[0] * 2 + x, node[1] * 2 + z];
+            if !self.select_node(terrain, camera_position, frustum, level - 1, child, patches) {
+                // Quarter of this node at its level
+                if self.bounds(terrain, level - 1, child).is_some() {
+                    patches.push(self.patch(level - 1, child, level));
+                }
+            }
+        }
+        return true;
+    }
+
+    // Patch covering the node of `node_level` with vertices of `level`.
+    fn patch(&self, node_level: u32, node: [u32; 2], level: u32) -> TerrainPatch {
+        let size = PATCH_RESOLUTION << node_level;
+        return TerrainPatch {
+            origin: [node[0] * size, node[1] * size],
+            size,
+            level,
+        };
+    }
+
+    // World bounds of the node, None when it lies outside of the heightmap.
+    fn bounds(&self, terrain: &Terrain, level: u32, node: [u32; 2]) -> Option<Aabb> {
+        let size = PATCH_RESOLUTION << level;
+        let first = [node[0] * size, node[1] * size];
+        let heightmap = &terrain.heightmap;
+        if first[0] >= heightmap.width - 1 || first[1] >= heightmap.depth - 1 {
+            return None;
+        }
+        let last = [
+            (first[0] + size).min(heightmap.width - 1),
+            (first[1] + size).min(heightmap.depth - 1),
+        ];
+        let nodes = 1 << (self.levels - 1 - level);
+        let (min, max) = self.heights[level as usize][(node[0] + node[1] * nodes) as usize];
+        let spacing = terrain.spacing;
+        return Some(Aabb::new(
+            terrain.origin
+                + Vector3f::new(
+                    first[0] as FScalar * spacing,
+                    min,
+                    first[1] as FScalar * spacing,
+                ),
+            terrain.origin
+                + Vector3f::new(
+                    last[0] as FScalar * spacing,
+                    max,
+                    last[1] as FScalar * spacing,
+                ),
+        ));
+    }
+}
+
+fn sphere_intersects_aabb(center: &Vector3f, radius: FScalar, aabb: &Aabb) -> bool {
+    let closest = center.zip_zip_map(&aabb.min, &aabb.max, |c, min, max| c.max(min).min(max));
+    return (closest - center).norm_squared() <= radius * radius;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slope(width: u32, depth: u32) -> Terrain {
+        let mut heights = Vec::new();
+        for _ in 0..depth {
+            for x in 0..width {
+                heights.push(x as FScalar * 0.5);
+            }
+        }
+        let heightmap = Heightmap::new(width, depth, heights).unwrap();
+        return Terrain::new(heightmap, Vector3f::new(-10.0, 5.0, 0.0), 2.0);
+    }
+
+    #[test]
+    fn heightmap_test() {
+        assert!(Heightmap::new(2, 2, vec![0.0; 4]).is_some());
+        assert!(Heightmap::new(1, 2, vec![0.0; 2]).is_none());
+        assert!(Heightmap::new(2, 2, vec![0.0; 3]).is_none());
+
+        let mut terrain = slope(3, 3);
+        assert!(terrain.is_valid());
+        terrain.layers = vec![TerrainLayer::new(1, vec![[0; 4]], 1.0).unwrap(); 5];
+        assert!(!terrain.is_valid());
+    }
+
+    #[test]
+    fn query_test() {
+        let terrain = slope(5, 5);
+        assert_eq!(terrain.size(), Vector2f::new(8.0, 8.0));
+        // Two units along X are one sample, which rises by 0.5
+        let height = terrain.height_at(&Vector2f::new(-7.0, 3.0)).unwrap();
+        assert!((height - 5.75).abs() < 1e-5);
+        let height = terrain.height_at(&Vector2f::new(-2.0, 8.0)).unwrap();
+        assert!((height - 7.0).abs() < 1e-5);
+        assert_eq!(terrain.height_at(&Vector2f::new(-11.0, 3.0)), None);
+        assert_eq!(terrain.height_at(&Vector2f::new(-7.0, 8.5)), None);
+
+        let normal = terrain.normal_at(&Vector2f::new(-5.0, 3.0)).unwrap();
+        assert!((normal - Vector3f::new(-0.25, 1.0, 0.0).normalize()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn lod_selection_test() {
+        let terrain = slope(257, 257);
+        let lod = TerrainLod::new(&terrain.heightmap);
+        let camera = terrain.origin + Vector3f::new(10.0, 100.0, 10.0);
+        let patches = lod.select(&terrain, &camera, None);
+
+        // Patches cover every cell once
+        let mut levels = vec![None; 256 * 256];
+        for patch in &patches {
+            assert!(
+                patch.resolution() == PATCH_RESOLUTION
+                    || patch.resolution() * 2 == PATCH_RESOLUTION
+            );
+            for z in patch.origin[1]..patch.origin[1] + patch.size {
+                for x in patch.origin[0]..patch.origin[0] + patch.size {
+                    let cell = &mut levels[(x + z * 256) as usize];
+                    assert_eq!(*cell, None);
+                    *cell = Some(patch.level as i64);
+                }
+            }
+        }
+        assert!(levels.iter().all(|level| level.is_some()));
+        assert_eq!(levels[0], Some(0));
+        assert!(patches.iter().any(|patch| patch.level > 0));
+
+        // Neighbours are at most one level apart
+        for z in 0..256 {
+            for x in 0..255 {
+                let (a, b) = (
+                    levels[x + z * 256].unwrap(),
+                    levels[x + 1 + z * 256].unwrap(),
+                );
+                assert!((a - b).abs() <= 1);
+                let (a, b) = (
+                    levels[z + x * 256].unwrap(),
+                    levels[z + (x + 1) * 256].unwrap(),
+                );
+                assert!((a - b).abs() <= 1);
+            }
+        }
+    }
+}
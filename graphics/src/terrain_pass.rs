@@ -0,0 +1,447 @@
+use crate::{
+    deferred_pass::gbuffer_normal_blend,
+    frustum::Frustum,
+    material::MaterialId,
+    terrain::{Terrain, TerrainId, TerrainLod, TerrainPatch, PATCH_RESOLUTION},
+    vertex::TerrainVertex,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, iter, sync::Arc};
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{
+        descriptor_set::{DescriptorSetsCollection, PersistentDescriptorSet},
+        DescriptorSet,
+    },
+    device::{Device, Queue},
+    format::{AcceptsPixels, Format},
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::{Dimensions, ImmutableImage},
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/terrain.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/terrain.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod depth_only_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/depth_only.frag"
+    }
+}
+
+mod normal_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/gbuffer_normal.frag"
+    }
+}
+
+// Terrain with its level of detail quadtree and uploaded images.
+pub struct RenderTerrain {
+    pub terrain: Terrain,
+    pub lod: TerrainLod,
+    heightmap: Arc<ImmutableImage<Format>>,
+    splat_map: Arc<ImmutableImage<Format>>,
+    layers: Arc<ImmutableImage<Format>>,
+}
+
+// Terrain drawn in a frame with the patches selected for the camera.
+pub struct TerrainDraw {
+    pub terrain_id: TerrainId,
+    pub material_id: Option<MaterialId>,
+    pub patches: Vec<TerrainPatch>,
+}
+
+pub struct TerrainRenderer {
+    queue: Arc<Queue>,
+    // Shaded like the forward pipeline with the splatted layers, uses its frame
+    // set and the terrain images in set 1
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // Depth of the ambient occlusion prepass and depth with normals of the
+    // G-buffer, with only the heightmap in set 1
+    pub prepass_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub gbuffer_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // Grid meshes of full patches and of quarters drawn at their parent level
+    full_grid: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+    quarter_grid: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+    sampler: Arc<Sampler>,
+    // The heightmap is fetched and the splat map stretched over the terrain
+    clamp_sampler: Arc<Sampler>,
+}
+
+impl TerrainRenderer {
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        scene_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        prepass_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        gbuffer_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+        let depth_only_fs = depth_only_fs::Shader::load(device.clone()).unwrap();
+        let normal_fs = normal_fs::Shader::load(device.clone()).unwrap();
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<TerrainVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil(DepthStencil {
+                    depth_compare: Compare::LessOrEqual,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .render_pass(Subpass::from(scene_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let prepass_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<TerrainVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(depth_only_fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(prepass_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let gbuffer_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<TerrainVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(normal_fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .blend_individual(gbuffer_normal_blend().into_iter())
+                .render_pass(Subpass::from(gbuffer_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let sampler = |address_mode| {
+            return Sampler::new(
+                device.clone(),
+                Filter::Linear,
+                Filter::Linear,
+                MipmapMode::Nearest,
+                address_mode,
+                address_mode,
+                address_mode,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+            )
+            .unwrap();
+        };
+
+        return TerrainRenderer {
+            queue,
+            pipeline,
+            prepass_pipeline,
+            gbuffer_pipeline,
+            full_grid: vec![grid_buffer(device.clone(), PATCH_RESOLUTION)],
+            quarter_grid: vec![grid_buffer(device.clone(), PATCH_RESOLUTION / 2)],
+            sampler: sampler(SamplerAddressMode::Repeat),
+            clamp_sampler: sampler(SamplerAddressMode::ClampToEdge),
+        };
+    }
+
+    // Builds the quadtree and uploads the heightmap, splat map and layers. Blocks
+    // until the GPU is done.
+    pub fn create_terrain(&self, terrain: Terrain) -> RenderTerrain {
+        let heightmap = self.upload(
+            terrain.heightmap.heights.iter().cloned(),
+            Dimensions::Dim2d {
+                width: terrain.heightmap.width,
+                height: terrain.heightmap.depth,
+            },
+            Format::R32Sfloat,
+        );
+        let splat_map = match &terrain.splat_map {
+            Some(splat_map) => self.upload(
+                splat_map.weights.iter().cloned(),
+                Dimensions::Dim2d {
+                    width: splat_map.width,
+                    height: splat_map.depth,
+                },
+                Format::R8G8B8A8Unorm,
+            ),
+            None => self.upload(
+                iter::once([255u8, 0, 0, 0]),
+                Dimensions::Dim2d {
+                    width: 1,
+                    height: 1,
+                },
+                Format::R8G8B8A8Unorm,
+            ),
+        };
+        let (texels, size) = match terrain.layers.first() {
+            Some(first) => (
+                terrain
+                    .layers
+                    .iter()
+                    .flat_map(|layer| layer.texels.iter().cloned())
+                    .collect(),
+                first.size,
+            ),
+            None => (vec![[255u8; 4]], 1),
+        };
+        let layers = self.upload(
+            texels.into_iter(),
+            Dimensions::Dim2dArray {
+                width: size,
+                height: size,
+                array_layers: terrain.layers.len().max(1) as u32,
+            },
+            Format::R8G8B8A8Srgb,
+        );
+        return RenderTerrain {
+            lod: TerrainLod::new(&terrain.heightmap),
+            terrain,
+            heightmap,
+            splat_map,
+            layers,
+        };
+    }
+
+    fn upload<P, I>(
+        &self,
+        texels: I,
+        dimensions: Dimensions,
+        format: Format,
+    ) -> Arc<ImmutableImage<Format>>
+    where
+        P: Send + Sync + Clone + 'static,
+        I: ExactSizeIterator<Item = P>,
+        Format: AcceptsPixels<P>,
+    {
+        let (image, upload) =
+            ImmutableImage::from_iter(texels, dimensions, format, self.queue.clone()).unwrap();
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        return image;
+    }
+
+    // Selects the patches of every terrain, sorted by ID. Terrains outside of the
+    // frustum are left out.
+    pub fn prepare(
+        &self,
+        terrains: &HashMap<TerrainId, RenderTerrain>,
+        camera_position: &Vector3f,
+        frustum: &Frustum,
+    ) -> Vec<TerrainDraw> {
+        let mut ids: Vec<TerrainId> = terrains.keys().cloned().collect();
+        ids.sort();
+        return ids
+            .into_iter()
+            .filter_map(|terrain_id| {
+                let render_terrain = &terrains[&terrain_id];
+                let terrain = &render_terrain.terrain;
+                let patches = render_terrain
+                    .lod
+                    .select(terrain, camera_position, Some(frustum));
+                if patches.is_empty() {
+                    return None;
+                }
+                return Some(TerrainDraw {
+                    terrain_id,
+                    material_id: terrain.material,
+                    patches,
+                });
+            })
+            .collect();
+    }
+
+    // Draws the patches in the scene pass. `frame_set` is the set of the forward
+    // pipeline, whose draw data contains one draw per terrain from `first_draw`
+    // on. Returns the recorded draw calls.
+    pub fn record<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        draws: &[TerrainDraw],
+        terrains: &HashMap<TerrainId, RenderTerrain>,
+        frame_set: S,
+        first_draw: u32,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        let mut draw_calls = 0;
+        for (i, draw) in draws.iter().enumerate() {
+            let render_terrain = &terrains[&draw.terrain_id];
+            let terrain_set = Arc::new(
+                PersistentDescriptorSet::start(
+                    self.pipeline.descriptor_set_layout(1).unwrap().clone(),
+                )
+                .add_sampled_image(render_terrain.heightmap.clone(), self.clamp_sampler.clone())
+                .unwrap()
+                .add_sampled_image(render_terrain.splat_map.clone(), self.clamp_sampler.clone())
+                .unwrap()
+                .add_sampled_image(render_terrain.layers.clone(), self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+            );
+            let (patch_builder, patch_draws) = self.record_patches(
+                builder,
+                &self.pipeline,
+                draw,
+                &render_terrain.terrain,
+                (frame_set.clone(), terrain_set),
+                first_draw + i as u32,
+                dynamic_state,
+            );
+            builder = patch_builder;
+            draw_calls += patch_draws;
+        }
+        return (builder, draw_calls);
+    }
+
+    // Draws the patches with `prepass_pipeline` or `gbuffer_pipeline`, `set`
+    // contains the frame data and draw data.
+    pub fn record_prepass<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        draws: &[TerrainDraw],
+        terrains: &HashMap<TerrainId, RenderTerrain>,
+        set: S,
+        first_draw: u32,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        let mut draw_calls = 0;
+        for (i, draw) in draws.iter().enumerate() {
+            let render_terrain = &terrains[&draw.terrain_id];
+            let heightmap_set = Arc::new(
+                PersistentDescriptorSet::start(pipeline.descriptor_set_layout(1).unwrap().clone())
+                    .add_sampled_image(render_terrain.heightmap.clone(), self.clamp_sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            let (patch_builder, patch_draws) = self.record_patches(
+                builder,
+                pipeline,
+                draw,
+                &render_terrain.terrain,
+                (set.clone(), heightmap_set),
+                first_draw + i as u32,
+                dynamic_state,
+            );
+            builder = patch_builder;
+            draw_calls += patch_draws;
+        }
+        return (builder, draw_calls);
+    }
+
+    fn record_patches<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        draw: &TerrainDraw,
+        terrain: &Terrain,
+        sets: S,
+        draw_index: u32,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSetsCollection + Clone,
+    {
+        let mut tiling = [1.0; 4];
+        for (tile_size, layer) in tiling.iter_mut().zip(&terrain.layers) {
+            *tile_size = layer.tile_size;
+        }
+        let origin = terrain.origin;
+        for patch in &draw.patches {
+            let [morph_start, morph_end] = terrain.morph_range(patch.level);
+            let push_constants = vs::ty::PushConstants {
+                origin: [origin.x, origin.y, origin.z, terrain.spacing],
+                patch_params: [
+                    patch.origin[0] as f32,
+                    patch.origin[1] as f32,
+                    (1 << patch.level) as f32,
+                    0.0,
+                ],
+                morph: [morph_start, morph_end, 0.0, 0.0],
+                tiling,
+                draw: [draw_index, terrain.layers.len() as u32, 0, 0],
+            };
+            let grid = if patch.resolution() == PATCH_RESOLUTION {
+                self.full_grid.clone()
+            } else {
+                self.quarter_grid.clone()
+            };
+            builder = builder
+                .draw(
+                    pipeline.clone(),
+                    dynamic_state,
+                    grid,
+                    sets.clone(),
+                    push_constants,
+                )
+                .unwrap();
+        }
+        return (builder, draw.patches.len() as u32);
+    }
+}
+
+// Triangle list of a grid with `resolution` quads along each side. Quads are
+// split along the diagonal from their first to their last vertex, like
+// `Terrain::height_at`.
+fn grid_buffer(device: Arc<Device>, resolution: u32) -> Arc<dyn BufferAccess + Send + Sync> {
+    let mut vertices = Vec::with_capacity((resolution * resolution * 6) as usize);
+    for z in 0..resolution {
+        for x in 0..resolution {
+            let corner = |dx: u32, dz: u32| TerrainVertex {
+                grid: [(x + dx) as f32, (z + dz) as f32],
+            };
+            vertices.extend_from_slice(&[
+                corner(0, 0),
+                corner(1, 1),
+                corner(1, 0),
+                corner(0, 0),
+                corner(0, 1),
+                corner(1, 1),
+            ]);
+        }
+    }
+    return CpuAccessibleBuffer::from_iter(
+        device,
+        BufferUsage::vertex_buffer(),
+        false,
+        vertices.into_iter(),
+    )
+    .unwrap();
+}
@@ -0,0 +1,524 @@
+use crate::{camera::orthographic_matrix, font_atlas::FontMetrics};
+use polyengine_core::*;
+use std::collections::HashMap;
+
+pub type FontId = u32;
+pub type TextId = u32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+// Where a text is drawn. Positions are of the top left corner of the text.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextSpace {
+    // Window pixels with Y pointing down, drawn on top of everything
+    Screen(Vector2f),
+    // Text on the XY plane facing +Z, hidden by the scene in front of it
+    World(Isometry3),
+    // World position, the text always faces the camera
+    Billboard(Vector3f),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextOutline {
+    // Fraction of the text size, at most half of the distance range of the font
+    pub width: FScalar,
+    // Linear RGB colour with alpha
+    pub color: Vector4f,
+}
+
+// Soft copy of the text drawn behind it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextShadow {
+    // Fractions of the text size, Y pointing down
+    pub offset: Vector2f,
+    // Blur radius as a fraction of the text size
+    pub softness: FScalar,
+    pub color: Vector4f,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text {
+    pub font: FontId,
+    // Lines are separated by '\n'
+    pub content: String,
+    pub space: TextSpace,
+    // Em size, in pixels for screen text and in world units otherwise
+    pub size: FScalar,
+    // Linear RGB colour with alpha
+    pub color: Vector4f,
+    // Lines are wrapped at spaces to stay narrower, in the units of `size`.
+    // Words longer than a line are broken.
+    pub max_width: Option<FScalar>,
+    // Aligns the lines within the maximum width, or the widest line without one
+    pub align: TextAlign,
+    // Multiplier of the line height of the font
+    pub line_spacing: FScalar,
+    pub outline: Option<TextOutline>,
+    pub shadow: Option<TextShadow>,
+}
+
+impl Text {
+    pub fn new(font: FontId, content: &str, space: TextSpace, size: FScalar) -> Self {
+        return Text {
+            font,
+            content: content.to_string(),
+            space,
+            size,
+            color: Vector4f::new(1.0, 1.0, 1.0, 1.0),
+            max_width: None,
+            align: TextAlign::Left,
+            line_spacing: 1.0,
+            outline: None,
+            shadow: None,
+        };
+    }
+
+    // Glyphs of the text with the font of `metrics`, in em.
+    pub fn layout(&self, metrics: &FontMetrics) -> TextLayout {
+        return layout_text(
+            metrics,
+            &self.content,
+            self.max_width.map(|width| width / self.size),
+            self.align,
+            self.line_spacing,
+        );
+    }
+
+    pub fn is_screen(&self) -> bool {
+        match self.space {
+            TextSpace::Screen(_) => return true,
+            _ => return false,
+        }
+    }
+}
+
+// Glyph quad placed by the layout, in em from the top left corner of the text
+// with Y pointing down.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LaidOutGlyph {
+    pub min: Vector2f,
+    pub max: Vector2f,
+    pub uv_min: Vector2f,
+    pub uv_max: Vector2f,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TextLayout {
+    pub glyphs: Vec<LaidOutGlyph>,
+    // Width of the widest line and height of all lines, in em
+    pub size: Vector2f,
+}
+
+// Line breaks of a paragraph, as ranges of its characters. Spaces a line was
+// wrapped at belong to neither line.
+fn wrap_paragraph(
+    metrics: &FontMetrics,
+    chars: &[char],
+    max_width: Option<FScalar>,
+) -> Vec<std::ops::Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    loop {
+        let mut x = 0.0;
+        let mut wrap_at = None;
+        let mut end = start;
+        while end < chars.len() {
+            let c = chars[end];
+            let mut advance = metrics.glyph(c).advance;
+            if end > start {
+                advance += metrics.kerning(chars[end - 1], c);
+            }
+            let too_wide = max_width.map(|w| x + advance > w).unwrap_or(false);
+            if c != ' ' && too_wide && end > start {
+                break;
+            }
+            // Lines end before the first space of a run
+            if c == ' ' && end > start && chars[end - 1] != ' ' {
+                wrap_at = Some(end);
+            }
+            x += advance;
+            end += 1;
+        }
+        if end == chars.len() {
+            lines.push(start..end);
+            return lines;
+        }
+        match wrap_at {
+            Some(space) => {
+                lines.push(start..space);
+                start = space + 1;
+            }
+            None => {
+                lines.push(start..end);
+                start = end;
+            }
+        }
+        while start < chars.len() && chars[start] == ' ' {
+            start += 1;
+        }
+        if start == chars.len() {
+            return lines;
+        }
+    }
+}
+
+// Width of the line without trailing spaces, in em.
+fn line_width(metrics: &FontMetrics, line: &[char]) -> FScalar {
+    let trimmed = line.len() - line.iter().rev().take_while(|&&c| c == ' ').count();
+    let mut width = 0.0;
+    for i in 0..trimmed {
+        width += metrics.glyph(line[i]).advance;
+        if i > 0 {
+            width += metrics.kerning(line[i - 1], line[i]);
+        }
+    }
+    return width;
+}
+
+// Places the glyphs of the text with kerning. `max_width` is in em.
+pub fn layout_text(
+    metrics: &FontMetrics,
+    content: &str,
+    max_width: Option<FScalar>,
+    align: TextAlign,
+    line_spacing: FScalar,
+) -> TextLayout {
+    let mut lines: Vec<Vec<char>> = Vec::new();
+    for paragraph in content.split('\n') {
+        let chars: Vec<char> = paragraph.chars().filter(|&c| c != '\r').collect();
+        for range in wrap_paragraph(metrics, &chars, max_width) {
+            lines.push(chars[range].to_vec());
+        }
+    }
+    let widths: Vec<FScalar> = lines.iter().map(|line| line_width(metrics, line)).collect();
+    let widest = widths.iter().cloned().fold(0.0, FScalar::max);
+    let align_width = max_width.unwrap_or(widest);
+    let line_height = metrics.line_height * line_spacing;
+
+    let atlas_size = Vector2f::new(
+        metrics.atlas_size[0] as FScalar,
+        metrics.atlas_size[1] as FScalar,
+    );
+    let mut glyphs = Vec::new();
+    for (index, (line, width)) in lines.iter().zip(&widths).enumerate() {
+        let mut x = match align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => (align_width - width) * 0.5,
+            TextAlign::Right => align_width - width,
+        };
+        let baseline = metrics.ascender + index as FScalar * line_height;
+        for (i, &c) in line.iter().enumerate() {
+            if i > 0 {
+                x += metrics.kerning(line[i - 1], c);
+            }
+            let glyph = metrics.glyph(c);
+            if let Some(quad) = &glyph.quad {
+                let offset = Vector2f::new(
+                    quad.atlas_offset[0] as FScalar,
+                    quad.atlas_offset[1] as FScalar,
+                );
+                let size =
+                    Vector2f::new(quad.atlas_size[0] as FScalar, quad.atlas_size[1] as FScalar);
+                // The atlas rows go down, the plane coordinates up
+                glyphs.push(LaidOutGlyph {
+                    min: Vector2f::new(x + quad.plane_min.x, baseline - quad.plane_max.y),
+                    max: Vector2f::new(x + quad.plane_max.x, baseline - quad.plane_min.y),
+                    uv_min: offset.component_div(&atlas_size),
+                    uv_max: (offset + size).component_div(&atlas_size),
+                });
+            }
+            x += glyph.advance;
+        }
+    }
+
+    let height = (lines.len() - 1) as FScalar * line_height + metrics.ascender - metrics.descender;
+    return TextLayout {
+        glyphs,
+        size: Vector2f::new(widest, height),
+    };
+}
+
+// Maps window pixels with Y pointing down to clip space.
+pub fn screen_projection(viewport: [u32; 2]) -> Matrix4f {
+    return orthographic_matrix(
+        0.0,
+        viewport[0] as FScalar,
+        viewport[1] as FScalar,
+        0.0,
+        -1.0,
+        1.0,
+    );
+}
+
+// Glyph rectangle with its corners in world or screen space, ordered top left,
+// top right, bottom left, bottom right.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextQuad {
+    pub corners: [Vector3f; 4],
+    pub uv_min: Vector2f,
+    pub uv_max: Vector2f,
+    pub color: Vector4f,
+    pub outline_color: Vector4f,
+    // Outline width and edge softness in em
+    pub outline: FScalar,
+    pub softness: FScalar,
+}
+
+// Consecutive quads drawn with the same font and projection.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextBatch {
+    pub font: FontId,
+    pub screen: bool,
+    pub first_quad: usize,
+    pub quad_count: usize,
+}
+
+// Glyph quads of a frame in drawing order, world text first.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TextBatches {
+    pub quads: Vec<TextQuad>,
+    pub batches: Vec<TextBatch>,
+}
+
+impl TextBatches {
+    fn add_quad(&mut self, font: FontId, screen: bool, quad: TextQuad) {
+        match self.batches.last_mut() {
+            Some(batch) if batch.font == font && batch.screen == screen => batch.quad_count += 1,
+            _ => self.batches.push(TextBatch {
+                font,
+                screen,
+                first_quad: self.quads.len(),
+                quad_count: 1,
+            }),
+        }
+        self.quads.push(quad);
+    }
+
+    // Adds the texts of the scene. World text is grouped by font, screen text is
+    // drawn in creation order. `camera` orients billboards, without it only
+    // screen text is added. Texts of unknown fonts are skipped.
+    pub fn add_scene<'a, F>(
+        &mut self,
+        texts: &HashMap<TextId, Text>,
+        font_metrics: F,
+        camera: Option<&Isometry3>,
+    ) where
+        F: Fn(FontId) -> Option<&'a FontMetrics>,
+    {
+        let mut order: Vec<(bool, FontId, TextId)> = texts
+            .iter()
+            .filter(|(_, text)| camera.is_some() || text.is_screen())
+            .map(|(&id, text)| match text.is_screen() {
+                true => (true, 0, id),
+                false => (false, text.font, id),
+            })
+            .collect();
+        order.sort_unstable();
+
+        for (screen, _, id) in order {
+            let text = &texts[&id];
+            let metrics = match font_metrics(text.font) {
+                Some(metrics) => metrics,
+                None => continue,
+            };
+            let layout = text.layout(metrics);
+            let size = text.size;
+            let transform = match text.space {
+                TextSpace::Screen(position) => Isometry3::translation(position.x, position.y, 0.0),
+                TextSpace::World(transform) => transform,
+                TextSpace::Billboard(position) => {
+                    Isometry3::from_parts(Translation3f::from(position), camera.unwrap().rotation)
+                }
+            };
+            // Layout Y points down like the screen, but up in the world
+            let y_sign = if screen { 1.0 } else { -1.0 };
+            let to_space = |p: Vector2f| {
+                let local = na::Point3::new(p.x * size, p.y * size * y_sign, 0.0);
+                return (transform * local).coords;
+            };
+
+            let outline = text.outline.map(|o| o.width).unwrap_or(0.0);
+            if let Some(shadow) = &text.shadow {
+                for glyph in &layout.glyphs {
+                    let quad = TextQuad {
+                        color: shadow.color,
+                        outline_color: shadow.color,
+                        outline,
+                        softness: shadow.softness,
+                        ..glyph_quad(glyph, shadow.offset, &to_space)
+                    };
+                    self.add_quad(text.font, screen, quad);
+                }
+            }
+            let outline_color = text.outline.map(|o| o.color).unwrap_or(text.color);
+            for glyph in &layout.glyphs {
+                let quad = TextQuad {
+                    color: text.color,
+                    outline_color,
+                    outline,
+                    ..glyph_quad(glyph, Vector2f::zeros(), &to_space)
+                };
+                self.add_quad(text.font, screen, quad);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn draw_calls(&self) -> u32 { return self.batches.len() as u32; }
+}
+
+// White quad of the glyph moved by `offset` em, without outline.
+fn glyph_quad<F>(glyph: &LaidOutGlyph, offset: Vector2f, to_space: &F) -> TextQuad
+where
+    F: Fn(Vector2f) -> Vector3f,
+{
+    let (min, max) = (glyph.min + offset, glyph.max + offset);
+    return TextQuad {
+        corners: [
+            to_space(min),
+            to_space(Vector2f::new(max.x, min.y)),
+            to_space(Vector2f::new(min.x, max.y)),
+            to_space(max),
+        ],
+        uv_min: glyph.uv_min,
+        uv_max: glyph.uv_max,
+        color: Vector4f::new(1.0, 1.0, 1.0, 1.0),
+        outline_color: Vector4f::new(1.0, 1.0, 1.0, 1.0),
+        outline: 0.0,
+        softness: 0.0,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_atlas::{GlyphQuad, SdfGlyph};
+
+    // Every glyph is 0.5 em wide, the space 0.25 em.
+    fn metrics() -> FontMetrics {
+        let quad = GlyphQuad {
+            plane_min: Vector2f::new(0.0, -0.25),
+            plane_max: Vector2f::new(0.5, 0.75),
+            atlas_offset: [4, 0],
+            atlas_size: [4, 8],
+        };
+        let glyph = SdfGlyph {
+            advance: 0.5,
+            quad: Some(quad),
+        };
+        let mut glyphs = HashMap::new();
+        for c in "abcd".chars() {
+            glyphs.insert(c, glyph);
+        }
+        glyphs.insert(
+            ' ',
+            SdfGlyph {
+                advance: 0.25,
+                quad: None,
+            },
+        );
+        let mut kerning = HashMap::new();
+        kerning.insert(('a', 'b'), -0.125);
+        return FontMetrics {
+            em_size: 8,
+            distance_range: 2.0,
+            ascender: 0.75,
+            descender: -0.25,
+            line_height: 1.25,
+            atlas_size: [16, 8],
+            glyphs,
+            missing_glyph: glyph,
+            kerning,
+        };
+    }
+
+    #[test]
+    fn kerning_test() {
+        let layout = layout_text(&metrics(), "abc", None, TextAlign::Left, 1.0);
+        let xs: Vec<FScalar> = layout.glyphs.iter().map(|g| g.min.x).collect();
+        assert_eq!(xs, vec![0.0, 0.375, 0.875]);
+        assert_eq!(layout.size, Vector2f::new(1.375, 1.0));
+        // Top of the glyph is at the ascender above the first baseline
+        assert_eq!(layout.glyphs[0].min.y, 0.0);
+        assert_eq!(layout.glyphs[0].max.y, 1.0);
+        assert_eq!(layout.glyphs[0].uv_min, Vector2f::new(0.25, 0.0));
+        assert_eq!(layout.glyphs[0].uv_max, Vector2f::new(0.5, 1.0));
+    }
+
+    #[test]
+    fn wrap_test() {
+        let metrics = metrics();
+        let chars: Vec<char> = "cc cc  dddd".chars().collect();
+        let lines = wrap_paragraph(&metrics, &chars, Some(1.5));
+        assert_eq!(lines, vec![0..2, 3..5, 7..10, 10..11]);
+        // Every character fits
+        assert_eq!(wrap_paragraph(&metrics, &chars, None), vec![0..11]);
+
+        let layout = layout_text(&metrics, "cc cc\n\nd", Some(1.5), TextAlign::Left, 2.0);
+        let ys: Vec<FScalar> = layout.glyphs.iter().map(|g| g.min.y).collect();
+        assert_eq!(ys, vec![0.0, 0.0, 2.5, 2.5, 7.5]);
+        assert_eq!(layout.size, Vector2f::new(1.0, 8.5));
+    }
+
+    #[test]
+    fn align_test() {
+        let metrics = metrics();
+        let layout = layout_text(&metrics, "cccc\ncc ", None, TextAlign::Right, 1.0);
+        assert_eq!(layout.glyphs[4].min.x, 1.0);
+        let layout = layout_text(&metrics, "cc", Some(3.0), TextAlign::Center, 1.0);
+        assert_eq!(layout.glyphs[0].min.x, 1.0);
+    }
+
+    #[test]
+    fn batching_test() {
+        let metrics = metrics();
+        let mut texts = HashMap::new();
+        let mut screen = Text::new(0, "ab", TextSpace::Screen(Vector2f::new(10.0, 20.0)), 8.0);
+        screen.shadow = Some(TextShadow {
+            offset: Vector2f::new(0.125, 0.125),
+            softness: 0.1,
+            color: Vector4f::new(0.0, 0.0, 0.0, 0.5),
+        });
+        texts.insert(0, screen);
+        texts.insert(
+            1,
+            Text::new(1, "c", TextSpace::Billboard(Vector3f::zeros()), 2.0),
+        );
+        texts.insert(
+            2,
+            Text::new(0, "d", TextSpace::World(Isometry3::identity()), 2.0),
+        );
+        // Unknown font
+        texts.insert(
+            3,
+            Text::new(5, "d", TextSpace::World(Isometry3::identity()), 2.0),
+        );
+
+        let font_metrics = |font: FontId| if font < 2 { Some(&metrics) } else { None };
+        let mut batches = TextBatches::default();
+        batches.add_scene(&texts, font_metrics, Some(&Isometry3::identity()));
+        let summary: Vec<(FontId, bool, usize)> = batches
+            .batches
+            .iter()
+            .map(|batch| (batch.font, batch.screen, batch.quad_count))
+            .collect();
+        assert_eq!(summary, vec![(0, false, 1), (1, false, 1), (0, true, 4)]);
+
+        // World text is on the XY plane, Y pointing up
+        assert_eq!(batches.quads[0].corners[2], Vector3f::new(0.0, -2.0, 0.0));
+        // Shadows are drawn first, moved by the offset
+        assert_eq!(batches.quads[2].corners[0], Vector3f::new(11.0, 21.0, 0.0));
+        assert_eq!(batches.quads[2].softness, 0.1);
+        assert_eq!(batches.quads[4].corners[0], Vector3f::new(10.0, 20.0, 0.0));
+
+        // Without a camera only screen text is drawn
+        let mut batches = TextBatches::default();
+        batches.add_scene(&texts, font_metrics, None);
+        assert_eq!(batches.draw_calls(), 1);
+    }
+}
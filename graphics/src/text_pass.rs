@@ -0,0 +1,217 @@
+use crate::{
+    font_atlas::{FontMetrics, SdfFont},
+    text::{FontId, TextBatches, TextQuad},
+    vertex::TextVertex,
+};
+use polyengine_core::*;
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    buffer::{BufferSlice, BufferUsage, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::PersistentDescriptorSet,
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::{Dimensions, ImmutableImage},
+    pipeline::{
+        blend::AttachmentBlend,
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/text.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/text.frag",
+        include: ["src/shaders"]
+    }
+}
+
+// Uploaded font atlas with the metrics needed for layout.
+pub struct FontImage {
+    pub image: Arc<ImmutableImage<Format>>,
+    pub metrics: FontMetrics,
+}
+
+pub struct TextRenderer {
+    queue: Arc<Queue>,
+    // World text is hidden by the scene, screen text is drawn on top
+    depth_tested_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    overlay_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    vertex_pool: CpuBufferPool<TextVertex>,
+}
+
+impl TextRenderer {
+    // Text is drawn into the swapchain images with `window_render_pass`, after
+    // the composite.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        window_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+        let pipeline = |depth_stencil: DepthStencil| {
+            return Arc::new(
+                GraphicsPipeline::start()
+                    .vertex_input_single_buffer::<TextVertex>()
+                    .vertex_shader(vs.main_entry_point(), ())
+                    .triangle_list()
+                    .viewports_dynamic_scissors_irrelevant(1)
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .depth_stencil(depth_stencil)
+                    .blend_collective(AttachmentBlend::alpha_blending())
+                    .render_pass(Subpass::from(window_render_pass.clone(), 0).unwrap())
+                    .build(device.clone())
+                    .unwrap(),
+            ) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
+        };
+        let depth_tested_pipeline = pipeline(DepthStencil {
+            depth_write: false,
+            depth_compare: Compare::LessOrEqual,
+            ..DepthStencil::simple_depth_test()
+        });
+        let overlay_pipeline = pipeline(DepthStencil::disabled());
+
+        // Distance fields are interpolated linearly, the shader finds the edges.
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        return TextRenderer {
+            queue,
+            depth_tested_pipeline,
+            overlay_pipeline,
+            sampler,
+            vertex_pool: CpuBufferPool::new(device, BufferUsage::vertex_buffer()),
+        };
+    }
+
+    // Uploads the atlas of the font. Blocks until the GPU is done.
+    pub fn create_font(&self, font: SdfFont) -> FontImage {
+        let (image, upload) = ImmutableImage::from_iter(
+            font.texels.into_iter(),
+            Dimensions::Dim2d {
+                width: font.metrics.atlas_size[0],
+                height: font.metrics.atlas_size[1],
+            },
+            Format::R8G8B8A8Unorm,
+            self.queue.clone(),
+        )
+        .unwrap();
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        return FontImage {
+            image,
+            metrics: font.metrics,
+        };
+    }
+
+    // Draws the batches with a single vertex upload, has to be recorded inside of
+    // the window render pass. World text uses `world_view_projection` and is
+    // skipped without it, screen text uses `screen_view_projection`. Returns the
+    // recorded draw calls.
+    pub fn record(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        batches: &TextBatches,
+        fonts: &HashMap<FontId, FontImage>,
+        world_view_projection: Option<&Matrix4f>,
+        screen_view_projection: &Matrix4f,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32) {
+        if batches.quads.is_empty() {
+            return (builder, 0);
+        }
+        let vertices: Vec<_> = batches.quads.iter().flat_map(quad_vertices).collect();
+        let vertices = self.vertex_pool.chunk(vertices).unwrap();
+        let vertices = Arc::new(vertices);
+
+        let mut draw_calls = 0;
+        for batch in &batches.batches {
+            let font = match fonts.get(&batch.font) {
+                Some(font) => font,
+                None => continue,
+            };
+            let (pipeline, view_projection) = match (batch.screen, world_view_projection) {
+                (true, _) => (&self.overlay_pipeline, screen_view_projection),
+                (false, Some(view_projection)) => (&self.depth_tested_pipeline, view_projection),
+                (false, None) => continue,
+            };
+            let push_constants = vs::ty::PushConstants {
+                view_projection: (*view_projection).into(),
+                font: [font.metrics.distance_range, font.metrics.em_size as FScalar],
+            };
+            let set = Arc::new(
+                PersistentDescriptorSet::start(pipeline.descriptor_set_layout(0).unwrap().clone())
+                    .add_sampled_image(font.image.clone(), self.sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            let first = batch.first_quad * 6;
+            let vertex_slice = BufferSlice::from_typed_buffer_access(vertices.clone())
+                .slice(first..first + batch.quad_count * 6)
+                .unwrap();
+            builder = builder
+                .draw(
+                    pipeline.clone(),
+                    dynamic_state,
+                    vec![Arc::new(vertex_slice)],
+                    set,
+                    push_constants,
+                )
+                .unwrap();
+            draw_calls += 1;
+        }
+        return (builder, draw_calls);
+    }
+}
+
+// Two triangles per quad from the corners in top left, top right, bottom left,
+// bottom right order.
+fn quad_vertices(quad: &TextQuad) -> Vec<TextVertex> {
+    let uvs = [
+        [quad.uv_min.x, quad.uv_min.y],
+        [quad.uv_max.x, quad.uv_min.y],
+        [quad.uv_min.x, quad.uv_max.y],
+        [quad.uv_max.x, quad.uv_max.y],
+    ];
+    let (c, o) = (quad.color, quad.outline_color);
+    return [0, 1, 2, 2, 1, 3]
+        .iter()
+        .map(|&i| TextVertex {
+            position: quad.corners[i].into(),
+            uv: uvs[i],
+            color: [c.x, c.y, c.z, c.w],
+            outline_color: [o.x, o.y, o.z, o.w],
+            effect: [quad.outline, quad.softness],
+        })
+        .collect();
+}
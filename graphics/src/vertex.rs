@@ -2,5 +2,70 @@
 #[derive(Default, Debug, Clone)]
 pub struct Vertex {
     pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
 }
-vulkano::impl_vertex!(Vertex, position);
+vulkano::impl_vertex!(Vertex, position, normal, uv);
+
+// Debug primitive vertex
+#[derive(Default, Debug, Clone)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+vulkano::impl_vertex!(DebugVertex, position, color);
+
+// 2D sprite vertex
+#[derive(Default, Debug, Clone)]
+pub struct SpriteVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+vulkano::impl_vertex!(SpriteVertex, position, uv, color);
+
+// Glyph vertex of signed distance field text
+#[derive(Default, Debug, Clone)]
+pub struct TextVertex {
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+    pub outline_color: [f32; 4],
+    // Outline width and edge softness in em
+    pub effect: [f32; 2],
+}
+vulkano::impl_vertex!(TextVertex, position, uv, color, outline_color, effect);
+
+// Vertex of a skinned mesh, moved by up to four joints
+#[derive(Default, Debug, Clone)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub joints: [u32; 4],
+    // Should sum up to 1, see `normalize_weights`
+    pub weights: [f32; 4],
+}
+vulkano::impl_vertex!(SkinnedVertex, position, normal, uv, joints, weights);
+
+// Block face vertex of voxel chunks
+#[derive(Default, Debug, Clone)]
+pub struct VoxelVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    // In blocks, repeated across merged faces
+    pub uv: [f32; 2],
+    // Layer of the block texture array
+    pub layer: u32,
+    // Ambient light reaching the corner
+    pub occlusion: f32,
+}
+vulkano::impl_vertex!(VoxelVertex, position, normal, uv, layer, occlusion);
+
+// Terrain patch grid vertex
+#[derive(Default, Debug, Clone)]
+pub struct TerrainVertex {
+    // Vertex index along X and Z, the height is read from the heightmap
+    pub grid: [f32; 2],
+}
+vulkano::impl_vertex!(TerrainVertex, grid);
@@ -0,0 +1,494 @@
+use crate::vertex::VoxelVertex;
+use polyengine_core::*;
+use std::collections::{HashMap, HashSet};
+
+// Block type of a voxel, every block but air is an opaque cube
+pub type BlockId = u16;
+pub const AIR: BlockId = 0;
+
+// Edge length of the cubic chunks in blocks
+pub const CHUNK_SIZE: usize = 16;
+const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+// Chunk with the neighbouring blocks of the surrounding chunks
+const PADDED_SIZE: usize = CHUNK_SIZE + 2;
+
+// Ambient light reaching a face corner by the number of unoccluded neighbours
+const AMBIENT_OCCLUSION: [f32; 4] = [0.35, 0.55, 0.8, 1.0];
+
+// Texture array layers of the block faces in the order +X, -X, +Y, -Y, +Z, -Z.
+// +Y is up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct BlockTextures {
+    pub faces: [u32; 6],
+}
+
+impl BlockTextures {
+    pub fn uniform(layer: u32) -> Self { return BlockTextures { faces: [layer; 6] }; }
+
+    // Blocks like grass with different top and bottom faces.
+    pub fn top_side_bottom(top: u32, side: u32, bottom: u32) -> Self {
+        return BlockTextures {
+            faces: [side, side, top, bottom, side, side],
+        };
+    }
+}
+
+// Square textures of the block faces, uploaded as a texture array.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockAtlas {
+    pub size: u32,
+    pub layers: u32,
+    // sRGB texels, layer by layer and row by row
+    pub texels: Vec<[u8; 4]>,
+}
+
+impl BlockAtlas {
+    // Returns None when the data isn't a whole number of `size` sized layers.
+    pub fn new(size: u32, texels: Vec<[u8; 4]>) -> Option<Self> {
+        let layer_texels = (size * size) as usize;
+        if size == 0 || texels.is_empty() || texels.len() % layer_texels != 0 {
+            return None;
+        }
+        return Some(BlockAtlas {
+            size,
+            layers: (texels.len() / layer_texels) as u32,
+            texels,
+        });
+    }
+}
+
+// Cube of `CHUNK_SIZE` blocks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    blocks: Vec<BlockId>,
+}
+
+impl Default for Chunk {
+    fn default() -> Self { return Chunk::filled(AIR); }
+}
+
+impl Chunk {
+    pub fn filled(block: BlockId) -> Self {
+        return Chunk {
+            blocks: vec![block; CHUNK_VOLUME],
+        };
+    }
+
+    // Blocks are indexed by their position in the chunk, X varies fastest.
+    pub fn block(&self, local: [usize; 3]) -> BlockId { return self.blocks[chunk_index(local)]; }
+
+    pub fn set_block(&mut self, local: [usize; 3], block: BlockId) {
+        self.blocks[chunk_index(local)] = block;
+    }
+
+    pub fn is_empty(&self) -> bool { return self.blocks.iter().all(|&block| block == AIR); }
+}
+
+fn chunk_index(local: [usize; 3]) -> usize {
+    return local[0] + CHUNK_SIZE * (local[1] + CHUNK_SIZE * local[2]);
+}
+
+// Chunk containing a block and the position of the block in it.
+pub fn chunk_position(position: &Vector3i) -> (Vector3i, [usize; 3]) {
+    let size = CHUNK_SIZE as IScalar;
+    let chunk = position.map(|p| p.div_euclid(size));
+    let local = position.map(|p| p.rem_euclid(size) as usize);
+    return (chunk, [local.x, local.y, local.z]);
+}
+
+// Block world of chunks, tracking which chunk meshes are outdated.
+#[derive(Debug, Clone, Default)]
+pub struct VoxelWorld {
+    chunks: HashMap<Vector3i, Chunk>,
+    textures: HashMap<BlockId, BlockTextures>,
+    // Chunks whose meshes have to be rebuilt
+    dirty: HashSet<Vector3i>,
+}
+
+impl VoxelWorld {
+    pub fn new() -> Self { return VoxelWorld::default(); }
+
+    // Blocks without textures use layer 0 on every face.
+    pub fn set_block_textures(&mut self, block: BlockId, textures: BlockTextures) {
+        self.textures.insert(block, textures);
+        // Layers are baked into the meshes
+        self.dirty.extend(self.chunks.keys().cloned());
+    }
+
+    // Blocks of missing chunks are air.
+    pub fn block(&self, position: &Vector3i) -> BlockId {
+        let (chunk, local) = chunk_position(position);
+        return self
+            .chunks
+            .get(&chunk)
+            .map_or(AIR, |chunk| chunk.block(local));
+    }
+
+    // Creates the chunk when it is missing. Only the chunk and the neighbours
+    // touching the block are remeshed.
+    pub fn set_block(&mut self, position: &Vector3i, block: BlockId) {
+        let (chunk_position, local) = chunk_position(position);
+        let chunk = self.chunks.entry(chunk_position).or_default();
+        if chunk.block(local) == block {
+            return;
+        }
+        chunk.set_block(local, block);
+
+        // Faces and ambient occlusion of the neighbours depend on blocks next to
+        // the border
+        let offsets: Vec<[IScalar; 2]> = local
+            .iter()
+            .map(|&l| {
+                if l == 0 {
+                    return [0, -1];
+                } else if l == CHUNK_SIZE - 1 {
+                    return [0, 1];
+                }
+                return [0, 0];
+            })
+            .collect();
+        for &x in &offsets[0] {
+            for &y in &offsets[1] {
+                for &z in &offsets[2] {
+                    self.dirty.insert(chunk_position + Vector3i::new(x, y, z));
+                }
+            }
+        }
+    }
+
+    pub fn set_chunk(&mut self, chunk_position: Vector3i, chunk: Chunk) {
+        self.chunks.insert(chunk_position, chunk);
+        self.mark_neighbourhood(&chunk_position);
+    }
+
+    pub fn remove_chunk(&mut self, chunk_position: &Vector3i) -> Option<Chunk> {
+        let chunk = self.chunks.remove(chunk_position);
+        if chunk.is_some() {
+            self.mark_neighbourhood(chunk_position);
+        }
+        return chunk;
+    }
+
+    fn mark_neighbourhood(&mut self, chunk_position: &Vector3i) {
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    self.dirty.insert(chunk_position + Vector3i::new(x, y, z));
+                }
+            }
+        }
+    }
+
+    // Chunks changed since the last call, in a stable order. Meshes of chunks
+    // that no longer exist have to be removed.
+    pub fn take_dirty(&mut self) -> Vec<Vector3i> {
+        let mut dirty: Vec<Vector3i> = self.dirty.drain().collect();
+        dirty.sort_by_key(|p| (p.x, p.y, p.z));
+        return dirty;
+    }
+
+    // Triangle list of the visible block faces in chunk space. Faces hidden by
+    // other blocks are culled and coplanar faces with the same texture and
+    // ambient occlusion are merged into larger quads.
+    pub fn mesh_chunk(&self, chunk_position: &Vector3i) -> Vec<VoxelVertex> {
+        let mut vertices = Vec::new();
+        if !self.chunks.contains_key(chunk_position) {
+            return vertices;
+        }
+        let blocks = self.padded_blocks(chunk_position);
+        let solid = |p: [IScalar; 3]| -> bool {
+            return blocks[padded_index(p)] != AIR;
+        };
+
+        let size = CHUNK_SIZE as IScalar;
+        let mut mask: Vec<Option<FaceKey>> = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+        for face in 0..6 {
+            let axis = face / 2;
+            let sign: IScalar = if face % 2 == 0 { 1 } else { -1 };
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+            for slice in 0..size {
+                for j in 0..size {
+                    for i in 0..size {
+                        let mut p = [0; 3];
+                        p[axis] = slice;
+                        p[u] = i;
+                        p[v] = j;
+                        let mut air = p;
+                        air[axis] += sign;
+                        let index = (i + size * j) as usize;
+                        if !solid(p) || solid(air) {
+                            mask[index] = None;
+                            continue;
+                        }
+                        let block = blocks[padded_index(p)];
+                        let layer = self.textures.get(&block).map_or(0, |t| t.faces[face]);
+                        mask[index] = Some(FaceKey {
+                            layer,
+                            occlusion: corner_occlusion(&solid, air, u, v),
+                        });
+                    }
+                }
+                merge_faces(&mut mask, |key, origin, extent| {
+                    let mut base = [0; 3];
+                    base[axis] = slice + if sign > 0 { 1 } else { 0 };
+                    base[u] = origin[0] as IScalar;
+                    base[v] = origin[1] as IScalar;
+                    push_quad(&mut vertices, key, base, extent, axis, sign);
+                });
+            }
+        }
+        return vertices;
+    }
+
+    // Blocks of the chunk surrounded by a layer of blocks from its neighbours,
+    // indexed by `padded_index`.
+    fn padded_blocks(&self, chunk_position: &Vector3i) -> Vec<BlockId> {
+        let mut neighbours: [Option<&Chunk>; 27] = [None; 27];
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let index = (x + 1 + 3 * (y + 1) + 9 * (z + 1)) as usize;
+                    neighbours[index] = self.chunks.get(&(chunk_position + Vector3i::new(x, y, z)));
+                }
+            }
+        }
+        let size = CHUNK_SIZE as IScalar;
+        let split = |p: IScalar| -> (IScalar, usize) {
+            if p < 0 {
+                return (-1, (p + size) as usize);
+            } else if p >= size {
+                return (1, (p - size) as usize);
+            }
+            return (0, p as usize);
+        };
+        let mut blocks = vec![AIR; PADDED_SIZE * PADDED_SIZE * PADDED_SIZE];
+        for z in -1..=size {
+            for y in -1..=size {
+                for x in -1..=size {
+                    let (cx, lx) = split(x);
+                    let (cy, ly) = split(y);
+                    let (cz, lz) = split(z);
+                    let chunk = neighbours[(cx + 1 + 3 * (cy + 1) + 9 * (cz + 1)) as usize];
+                    if let Some(chunk) = chunk {
+                        blocks[padded_index([x, y, z])] = chunk.block([lx, ly, lz]);
+                    }
+                }
+            }
+        }
+        return blocks;
+    }
+}
+
+fn padded_index(p: [IScalar; 3]) -> usize {
+    return (p[0] + 1) as usize
+        + PADDED_SIZE * ((p[1] + 1) as usize + PADDED_SIZE * (p[2] + 1) as usize);
+}
+
+// Faces are only merged when everything interpolated across them matches.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct FaceKey {
+    layer: u32,
+    // Unoccluded neighbours of the corners (0, 0), (1, 0), (1, 1) and (0, 1) in
+    // face space, from 0 to 3
+    occlusion: [u8; 4],
+}
+
+// Ambient occlusion of the face corners from the blocks around the air block
+// in front of the face.
+fn corner_occlusion<F>(solid: &F, air: [IScalar; 3], u: usize, v: usize) -> [u8; 4]
+where
+    F: Fn([IScalar; 3]) -> bool,
+{
+    let neighbour = |du: IScalar, dv: IScalar| -> bool {
+        let mut p = air;
+        p[u] += du;
+        p[v] += dv;
+        return solid(p);
+    };
+    let corner = |du: IScalar, dv: IScalar| -> u8 {
+        let side_u = neighbour(du, 0);
+        let side_v = neighbour(0, dv);
+        // Both sides block the light that could reach the corner
+        if side_u && side_v {
+            return 0;
+        }
+        return 3 - side_u as u8 - side_v as u8 - neighbour(du, dv) as u8;
+    };
+    return [corner(-1, -1), corner(1, -1), corner(1, 1), corner(-1, 1)];
+}
+
+// Greedily merges equal faces of a slice into rectangles, first along u, then
+// along v. Calls `emit` with the face, origin and size of every rectangle and
+// clears the mask.
+fn merge_faces<F>(mask: &mut [Option<FaceKey>], mut emit: F)
+where
+    F: FnMut(FaceKey, [usize; 2], [usize; 2]),
+{
+    for j in 0..CHUNK_SIZE {
+        let mut i = 0;
+        while i < CHUNK_SIZE {
+            let key = match mask[i + CHUNK_SIZE * j] {
+                Some(key) => key,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let mut width = 1;
+            while i + width < CHUNK_SIZE && mask[i + width + CHUNK_SIZE * j] == Some(key) {
+                width += 1;
+            }
+            let mut height = 1;
+            while j + height < CHUNK_SIZE
+                && (i..i + width).all(|x| mask[x + CHUNK_SIZE * (j + height)] == Some(key))
+            {
+                height += 1;
+            }
+            for y in j..j + height {
+                for x in i..i + width {
+                    mask[x + CHUNK_SIZE * y] = None;
+                }
+            }
+            emit(key, [i, j], [width, height]);
+            i += width;
+        }
+    }
+}
+
+fn push_quad(
+    vertices: &mut Vec<VoxelVertex>,
+    key: FaceKey,
+    base: [IScalar; 3],
+    extent: [usize; 2],
+    axis: usize,
+    sign: IScalar,
+) {
+    let u = (axis + 1) % 3;
+    let v = (axis + 2) % 3;
+    let mut normal = [0.0; 3];
+    normal[axis] = sign as f32;
+    let corner = |du: usize, dv: usize, occlusion: u8| -> VoxelVertex {
+        let mut position = [base[0] as f32, base[1] as f32, base[2] as f32];
+        position[u] += (du * extent[0]) as f32;
+        position[v] += (dv * extent[1]) as f32;
+        // Side textures are upright, texture rows go down
+        let uv = match axis {
+            0 => [position[2], -position[1]],
+            1 => [position[0], position[2]],
+            _ => [position[0], -position[1]],
+        };
+        return VoxelVertex {
+            position,
+            normal,
+            uv,
+            layer: key.layer,
+            occlusion: AMBIENT_OCCLUSION[occlusion as usize],
+        };
+    };
+    let o = key.occlusion;
+    let corners = [
+        corner(0, 0, o[0]),
+        corner(1, 0, o[1]),
+        corner(1, 1, o[2]),
+        corner(0, 1, o[3]),
+    ];
+    // Splitting along the brighter diagonal keeps the occlusion symmetric,
+    // counter-clockwise seen from outside
+    let order: [usize; 6] = match (o[0] + o[2] >= o[1] + o[3], sign > 0) {
+        (true, true) => [0, 1, 2, 0, 2, 3],
+        (true, false) => [0, 2, 1, 0, 3, 2],
+        (false, true) => [1, 2, 3, 1, 3, 0],
+        (false, false) => [1, 3, 2, 1, 0, 3],
+    };
+    vertices.extend(order.iter().map(|&i| corners[i].clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quads(vertices: &[VoxelVertex]) -> usize { return vertices.len() / 6; }
+
+    #[test]
+    fn chunk_position_test() {
+        let (chunk, local) = chunk_position(&Vector3i::new(-1, 16, 5));
+        assert_eq!(chunk, Vector3i::new(-1, 1, 0));
+        assert_eq!(local, [15, 0, 5]);
+    }
+
+    #[test]
+    fn face_culling_test() {
+        let mut world = VoxelWorld::new();
+        world.set_block(&Vector3i::new(3, 3, 3), 1);
+        let vertices = world.mesh_chunk(&Vector3i::zeros());
+        assert_eq!(quads(&vertices), 6);
+        assert!(vertices.iter().all(|v| v.occlusion == 1.0));
+
+        // The faces between the blocks are hidden, the long sides are merged
+        world.set_block(&Vector3i::new(4, 3, 3), 1);
+        assert_eq!(quads(&world.mesh_chunk(&Vector3i::zeros())), 6);
+
+        // Faces against blocks of the neighbouring chunk are hidden as well
+        let mut world = VoxelWorld::new();
+        world.set_block(&Vector3i::new(15, 0, 0), 1);
+        world.set_block(&Vector3i::new(16, 0, 0), 1);
+        assert_eq!(quads(&world.mesh_chunk(&Vector3i::zeros())), 5);
+    }
+
+    #[test]
+    fn greedy_meshing_test() {
+        let mut world = VoxelWorld::new();
+        world.set_chunk(Vector3i::zeros(), Chunk::filled(1));
+        assert_eq!(quads(&world.mesh_chunk(&Vector3i::zeros())), 6);
+
+        // Different textures aren't merged
+        world.set_block_textures(2, BlockTextures::uniform(1));
+        world.set_block(&Vector3i::new(0, 15, 0), 2);
+        let vertices = world.mesh_chunk(&Vector3i::zeros());
+        assert!(vertices.iter().any(|v| v.layer == 1));
+        assert!(quads(&vertices) > 6);
+    }
+
+    #[test]
+    fn ambient_occlusion_test() {
+        let mut world = VoxelWorld::new();
+        world.set_block(&Vector3i::new(0, 0, 0), 1);
+        // Wall next to the top face, along its +X edge
+        world.set_block(&Vector3i::new(1, 1, 0), 1);
+        let vertices = world.mesh_chunk(&Vector3i::zeros());
+        for vertex in vertices.iter().filter(|v| v.normal == [0.0, 1.0, 0.0]) {
+            if vertex.position[1] != 1.0 || vertex.position[0] > 1.0 {
+                continue;
+            }
+            let expected = if vertex.position[0] == 1.0 {
+                AMBIENT_OCCLUSION[2]
+            } else {
+                AMBIENT_OCCLUSION[3]
+            };
+            assert_eq!(vertex.occlusion, expected);
+        }
+    }
+
+    #[test]
+    fn dirty_test() {
+        let mut world = VoxelWorld::new();
+        world.set_block(&Vector3i::new(5, 5, 5), 1);
+        assert_eq!(world.take_dirty(), vec![Vector3i::zeros()]);
+        assert!(world.take_dirty().is_empty());
+
+        // Setting the same block changes nothing
+        world.set_block(&Vector3i::new(5, 5, 5), 1);
+        assert!(world.take_dirty().is_empty());
+
+        // Corner blocks touch 7 neighbours
+        world.set_block(&Vector3i::new(0, 0, 0), 1);
+        let dirty = world.take_dirty();
+        assert_eq!(dirty.len(), 8);
+        assert!(dirty.contains(&Vector3i::new(-1, -1, -1)));
+
+        world.remove_chunk(&Vector3i::zeros());
+        assert_eq!(world.take_dirty().len(), 27);
+        assert!(world.mesh_chunk(&Vector3i::zeros()).is_empty());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{
+    bounds::Aabb,
+    deferred_pass::gbuffer_normal_blend,
+    frustum::Frustum,
+    vertex::VoxelVertex,
+    voxel::{BlockAtlas, CHUNK_SIZE},
+};
+use polyengine_core::*;
+use std::{collections::HashMap, iter, sync::Arc};
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::{Dimensions, ImmutableImage},
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline,
+        GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/voxel.vert",
+        include: ["src/shaders"]
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/voxel.frag",
+        include: ["src/shaders"]
+    }
+}
+
+mod depth_only_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/depth_only.frag"
+    }
+}
+
+mod normal_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/gbuffer_normal.frag"
+    }
+}
+
+// Uploaded mesh of a chunk with visible faces.
+pub struct ChunkMesh {
+    pub vertex_buffer: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+    // Translation to the chunk origin
+    pub model: Matrix4f,
+    // World space bounds
+    pub bounds: Aabb,
+}
+
+impl ChunkMesh {
+    pub fn new(device: Arc<Device>, chunk_position: &Vector3i, vertices: &[VoxelVertex]) -> Self {
+        let origin = chunk_position.map(|p| (p * CHUNK_SIZE as IScalar) as FScalar);
+        let mut bounds = Aabb::empty();
+        for v in vertices {
+            bounds.extend(&(origin + Vector3f::from(v.position)));
+        }
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage::vertex_buffer(),
+            false,
+            vertices.iter().cloned(),
+        )
+        .unwrap();
+        return ChunkMesh {
+            vertex_buffer: vec![vertex_buffer],
+            model: Matrix4f::new_translation(&origin),
+            bounds,
+        };
+    }
+}
+
+pub struct VoxelRenderer {
+    queue: Arc<Queue>,
+    // Shaded like the forward pipeline with the block textures, uses its frame
+    // set and the atlas in set 1
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    // Depth of the ambient occlusion prepass and depth with normals of the
+    // G-buffer, without the atlas
+    pub prepass_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub gbuffer_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    // Bound until a block atlas is set
+    white: Arc<ImmutableImage<Format>>,
+}
+
+impl VoxelRenderer {
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        scene_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        prepass_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        gbuffer_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Self {
+        let vs = vs::Shader::load(device.clone()).unwrap();
+        let fs = fs::Shader::load(device.clone()).unwrap();
+        let depth_only_fs = depth_only_fs::Shader::load(device.clone()).unwrap();
+        let normal_fs = normal_fs::Shader::load(device.clone()).unwrap();
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<VoxelVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil(DepthStencil {
+                    depth_compare: Compare::LessOrEqual,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .render_pass(Subpass::from(scene_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let prepass_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<VoxelVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(depth_only_fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .render_pass(Subpass::from(prepass_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        let gbuffer_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<VoxelVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(normal_fs.main_entry_point(), ())
+                .depth_stencil_simple_depth()
+                .blend_individual(gbuffer_normal_blend().into_iter())
+                .render_pass(Subpass::from(gbuffer_render_pass, 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        );
+        // Block textures are tiled across merged faces and stay sharp up close
+        let sampler = Sampler::new(
+            device,
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::Repeat,
+            SamplerAddressMode::Repeat,
+            SamplerAddressMode::Repeat,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+        let white = Self::upload(
+            &queue,
+            iter::once([255u8; 4]),
+            Dimensions::Dim2dArray {
+                width: 1,
+                height: 1,
+                array_layers: 1,
+            },
+        );
+
+        return VoxelRenderer {
+            queue,
+            pipeline,
+            prepass_pipeline,
+            gbuffer_pipeline,
+            sampler,
+            white,
+        };
+    }
+
+    // Uploads the atlas texels as a texture array. Blocks until the GPU is done.
+    pub fn create_atlas(&self, atlas: &BlockAtlas) -> Arc<ImmutableImage<Format>> {
+        return Self::upload(
+            &self.queue,
+            atlas.texels.iter().cloned(),
+            Dimensions::Dim2dArray {
+                width: atlas.size,
+                height: atlas.size,
+                array_layers: atlas.layers,
+            },
+        );
+    }
+
+    fn upload<I>(
+        queue: &Arc<Queue>,
+        texels: I,
+        dimensions: Dimensions,
+    ) -> Arc<ImmutableImage<Format>>
+    where
+        I: ExactSizeIterator<Item = [u8; 4]>,
+    {
+        let (image, upload) =
+            ImmutableImage::from_iter(texels, dimensions, Format::R8G8B8A8Srgb, queue.clone())
+                .unwrap();
+        upload
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        return image;
+    }
+
+    // Chunks intersecting the frustum, sorted by position so the draw order is
+    // stable.
+    pub fn visible_chunks<'a>(
+        &self,
+        chunks: &'a HashMap<Vector3i, ChunkMesh>,
+        frustum: &Frustum,
+    ) -> Vec<&'a ChunkMesh> {
+        let mut positions: Vec<&Vector3i> = chunks
+            .iter()
+            .filter(|(_, chunk)| frustum.intersects_aabb(&chunk.bounds))
+            .map(|(position, _)| position)
+            .collect();
+        positions.sort_by_key(|p| (p.x, p.y, p.z));
+        return positions.iter().map(|p| &chunks[p]).collect();
+    }
+
+    // Draws the chunks in the scene pass. `frame_set` is the set of the forward
+    // pipeline, whose draw data contains the chunk draws from `first_draw` on.
+    // Returns the recorded draw calls.
+    pub fn record<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        chunks: &[&ChunkMesh],
+        atlas: Option<&Arc<ImmutableImage<Format>>>,
+        frame_set: S,
+        first_draw: u32,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        if chunks.is_empty() {
+            return (builder, 0);
+        }
+        let atlas_set = Arc::new(
+            PersistentDescriptorSet::start(self.pipeline.descriptor_set_layout(1).unwrap().clone())
+                .add_sampled_image(atlas.unwrap_or(&self.white).clone(), self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        for (i, chunk) in chunks.iter().enumerate() {
+            let push_constants = vs::ty::PushConstants {
+                draw: [first_draw + i as u32, 0, 0, 0],
+            };
+            builder = builder
+                .draw(
+                    self.pipeline.clone(),
+                    dynamic_state,
+                    chunk.vertex_buffer.clone(),
+                    (frame_set.clone(), atlas_set.clone()),
+                    push_constants,
+                )
+                .unwrap();
+        }
+        return (builder, chunks.len() as u32);
+    }
+
+    // Draws the chunks with `prepass_pipeline` or `gbuffer_pipeline`, `set`
+    // contains the frame data and draw data.
+    pub fn record_prepass<S>(
+        &self,
+        mut builder: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        chunks: &[&ChunkMesh],
+        set: S,
+        first_draw: u32,
+        dynamic_state: &DynamicState,
+    ) -> (AutoCommandBufferBuilder, u32)
+    where
+        S: DescriptorSet + Send + Sync + Clone + 'static,
+    {
+        for (i, chunk) in chunks.iter().enumerate() {
+            let push_constants = vs::ty::PushConstants {
+                draw: [first_draw + i as u32, 0, 0, 0],
+            };
+            builder = builder
+                .draw(
+                    pipeline.clone(),
+                    dynamic_state,
+                    chunk.vertex_buffer.clone(),
+                    set.clone(),
+                    push_constants,
+                )
+                .unwrap();
+        }
+        return (builder, chunks.len() as u32);
+    }
+}
@@ -1,5 +1,5 @@
 use vulkano::{
-    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    command_buffer::{AutoCommandBuffer, DynamicState},
     device::Device,
     framebuffer::RenderPassAbstract,
     image::swapchain::SwapchainImage,
@@ -28,15 +28,36 @@ use winit::{
 use std::sync::Arc;
 
 use crate::{
+    aa_pass::AaTargets,
+    antialiasing::{AntiAliasing, TemporalState},
+    camera::Camera,
+    capture_pass::{CaptureReadback, CaptureRequest},
+    clusters::ClusterVolumes,
     common::*,
     config,
+    culling::CullingState,
+    debug_view::DebugView,
+    decal_pass::DecalTargets,
+    deferred_pass::DeferredTargets,
     error::RenderingError,
-    geometry::Geometry,
-    renderer::Renderer,
+    gpu_driven_pass::HizTargets,
+    oit_pass::OitTargets,
+    particles_pass::ParticleTargets,
+    picking::{PickId, PickRegion},
+    picking_pass::{PickReadback, PickingTargets},
+    post::PostProcessSettings,
+    post_pass::PostTargets,
+    profiling_pass::FrameQueries,
+    render_queue::TransparencyMode,
+    renderer::RenderPath,
+    shadow_pass::ShadowTarget,
+    sprites::Camera2d,
+    ssao::SsaoSettings,
+    ssao_pass::SsaoTargets,
     target::RenderTarget,
 };
 
-use polyengine_core::log;
+use polyengine_core::*;
 
 pub struct WindowContext {
     device: Arc<Device>,
@@ -49,6 +70,52 @@ pub struct WindowContext {
     pub dynamic_state: DynamicState,
     pub recreate_swapchain: bool,
     pub previous_frame_end: Option<Box<dyn GpuFuture>>,
+
+    pub camera: Camera,
+    pub culling_state: CullingState,
+    // Light cluster volumes for the current camera projection
+    pub cluster_volumes: Option<ClusterVolumes>,
+    pub shadow_target: Option<ShadowTarget>,
+    pub post_process: PostProcessSettings,
+    // HDR scene target and post-process state
+    pub post_targets: Option<PostTargets>,
+    pub anti_aliasing: AntiAliasing,
+    pub aa_targets: Option<AaTargets>,
+    // Camera matrices of the current and previous frame, for motion vectors
+    pub temporal: TemporalState,
+    pub render_path: RenderPath,
+    // G-buffer, only kept for the deferred path
+    pub deferred_targets: Option<DeferredTargets>,
+    // Framebuffers of the decals for the current render path
+    pub decal_targets: Option<DecalTargets>,
+    // Screen space ambient occlusion, disabled when None
+    pub ssao: Option<SsaoSettings>,
+    pub ssao_targets: Option<SsaoTargets>,
+    pub transparency: TransparencyMode,
+    // Only kept for weighted blended transparency
+    pub oit_targets: Option<OitTargets>,
+    // Recorded with the next frame
+    pub pick_requests: Vec<(PickId, PickRegion)>,
+    // Submitted, waiting for the GPU
+    pub pick_readbacks: Vec<PickReadback>,
+    pub picking_targets: Option<PickingTargets>,
+    // Recorded with the next frame
+    pub capture_requests: Vec<CaptureRequest>,
+    // Submitted, waiting for the GPU
+    pub capture_readbacks: Vec<CaptureReadback>,
+    pub debug_view: DebugView,
+    // Profiling queries of submitted frames, oldest first
+    pub pending_queries: Vec<FrameQueries>,
+    // Read queries, reused by the next frames
+    pub spare_queries: Vec<FrameQueries>,
+    // Opaque objects are culled and drawn with indirect commands
+    pub gpu_driven: bool,
+    // Only kept for GPU driven rendering
+    pub hiz_targets: Option<HizTargets>,
+    // Particles of every emitter, simulated for this window
+    pub particle_targets: Option<ParticleTargets>,
+    // Draws sprites and tile maps instead of the 3D scene when set
+    pub camera_2d: Option<Camera2d>,
 }
 
 impl WindowContext {
@@ -111,7 +178,7 @@ impl WindowContext {
             write_mask: None,
             reference: None,
         };
-        let framebuffers = window_size_dependent_setup(
+        let (framebuffers, depth_buffer) = window_size_dependent_setup(
             &images,
             default_window_render_pass.clone(),
             &mut dynamic_state,
@@ -122,6 +189,7 @@ impl WindowContext {
         let render_target = RenderTarget {
             render_pass: default_window_render_pass.clone(),
             framebuffers,
+            depth_buffer,
         };
 
         return WindowContext {
@@ -134,6 +202,34 @@ impl WindowContext {
             dynamic_state,
             recreate_swapchain,
             previous_frame_end,
+            camera: Camera::default(),
+            culling_state: CullingState::new(),
+            cluster_volumes: None,
+            shadow_target: None,
+            post_process: PostProcessSettings::default(),
+            post_targets: None,
+            anti_aliasing: AntiAliasing::None,
+            aa_targets: None,
+            temporal: TemporalState::default(),
+            render_path: RenderPath::Forward,
+            deferred_targets: None,
+            decal_targets: None,
+            ssao: None,
+            ssao_targets: None,
+            transparency: TransparencyMode::Sorted,
+            oit_targets: None,
+            pick_requests: Vec::new(),
+            pick_readbacks: Vec::new(),
+            picking_targets: None,
+            capture_requests: Vec::new(),
+            capture_readbacks: Vec::new(),
+            debug_view: DebugView::None,
+            pending_queries: Vec::new(),
+            spare_queries: Vec::new(),
+            gpu_driven: false,
+            hiz_targets: None,
+            particle_targets: None,
+            camera_2d: None,
         };
     }
 
@@ -141,6 +237,11 @@ impl WindowContext {
 
     pub fn on_resize(&mut self) { self.recreate_swapchain = true; }
 
+    pub fn aspect_ratio(&self) -> FScalar {
+        let dimensions = self.swapchain.dimensions();
+        return dimensions[0] as FScalar / dimensions[1].max(1) as FScalar;
+    }
+
     pub fn acquire_next_image(
         &mut self,
     ) -> Result<(usize, SwapchainAcquireFuture<Window>), RenderingError> {
@@ -166,11 +267,14 @@ impl WindowContext {
             self.swapchain = new_swapchain;
             // Because framebuffers contains an Arc on the old swapchain, we need to
             // recreate framebuffers as well.
-            self.render_target.framebuffers = window_size_dependent_setup(
+            let (framebuffers, depth_buffer) = window_size_dependent_setup(
                 &new_images,
                 self.render_target.render_pass.clone(),
                 &mut self.dynamic_state,
             );
+            self.images = new_images;
+            self.render_target.framebuffers = framebuffers;
+            self.render_target.depth_buffer = depth_buffer;
             self.recreate_swapchain = false;
         }
 
@@ -203,44 +307,34 @@ impl WindowContext {
         return Ok((image_num, acquire_future));
     }
 
-    pub fn draw(
+    // Rebuilds light cluster volumes when the camera projection changed.
+    pub fn update_cluster_volumes(&mut self) {
+        let aspect = self.aspect_ratio();
+        let up_to_date = match &self.cluster_volumes {
+            Some(volumes) => volumes.matches(&self.camera, aspect),
+            None => false,
+        };
+        if !up_to_date {
+            self.cluster_volumes = Some(ClusterVolumes::new(&self.camera, aspect));
+        }
+    }
+
+    pub fn queue(&self) -> &Arc<vulkano::device::Queue> { return &self.queue; }
+
+    // Submits the frame command buffer and presents the image. The command buffer
+    // waits for `compute_future` when given.
+    pub fn present(
         &mut self,
         image_num: usize,
         acquire_future: SwapchainAcquireFuture<Window>,
-        renderer: &Renderer,
-        geometry: &Geometry,
+        command_buffer: AutoCommandBuffer,
+        compute_future: Option<Box<dyn GpuFuture>>,
     ) {
-        // Specify the color to clear the framebuffer with i.e. blue
-        let clear_values = vec![[0.0, 0.0, 1.0, 1.0].into()];
-
-        let command_buffer = AutoCommandBufferBuilder::primary_one_time_submit(
-            self.device.clone(),
-            self.queue.family(),
-        )
-        .unwrap()
-        .begin_render_pass(
-            self.render_target.framebuffers[image_num].clone(),
-            false,
-            clear_values,
-        )
-        .unwrap()
-        .draw(
-            renderer.pipeline.clone(),
-            &self.dynamic_state,
-            geometry.vertex_buffer.clone(),
-            (),
-            (),
-        )
-        .unwrap()
-        .end_render_pass()
-        .unwrap()
-        .build()
-        .unwrap();
-
-        let future = self
-            .previous_frame_end
-            .take()
-            .unwrap()
+        let mut previous = self.previous_frame_end.take().unwrap();
+        if let Some(compute_future) = compute_future {
+            previous = Box::new(previous.join(compute_future));
+        }
+        let future = previous
             .join(acquire_future)
             .then_execute(self.queue.clone(), command_buffer)
             .unwrap()